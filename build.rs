@@ -0,0 +1,19 @@
+use std::process::Command;
+
+
+/**
+ * Records the git commit the assembler is built from so it can be reported by `--version` and embedded in
+ * the metadata of every output file.
+ */
+fn main() {
+    let git_hash = Command::new("git").args(["rev-parse", "--short", "HEAD"])
+                                      .output()
+                                      .ok()
+                                      .filter(|output| output.status.success())
+                                      .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                                      .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=S16_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::messages::{message, MessageId};
+use crate::object::{write_name, ObjectFile, ObjectFormatError, ObjectReader, ObjectWriteError};
+
+
+const ARCHIVE_MAGIC:&[u8] = b"SSA\0";
+const ARCHIVE_VERSION:u8 = 1;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveFormatError {
+    MissingMagicError,
+    UnsupportedVersionError(u8),
+    TruncatedError,
+    InvalidMemberError(String, ObjectFormatError)
+}
+
+impl Error for ArchiveFormatError {}
+
+impl fmt::Display for ArchiveFormatError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveFormatError::MissingMagicError => write!(f, "{}", message(MessageId::MissingArchiveMagic, &[])),
+            ArchiveFormatError::UnsupportedVersionError(version) => write!(f, "{}", message(MessageId::UnsupportedArchiveVersion, &[version])),
+            ArchiveFormatError::TruncatedError => write!(f, "{}", message(MessageId::TruncatedArchive, &[])),
+            ArchiveFormatError::InvalidMemberError(name, err) => write!(f, "{}", message(MessageId::InvalidArchiveMember, &[name, err]))
+        }
+    }
+}
+
+
+/**
+ * An object file stored in an archive, under the file name it was added with.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub object: ObjectFile
+}
+
+
+/**
+ * The contents of a .ssa static library: a sequence of object files which the linker pulls in only when
+ * they define a symbol the program needs.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Archive {
+    pub members: Vec<ArchiveMember>
+}
+
+impl Archive {
+    /**
+     * Serialises the archive. After the magic number and format version comes a 16-bit member count, then
+     * each member's name prefixed by an 8-bit length, and its object file prefixed by a 32-bit length.
+     */
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectWriteError> {
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.push(ARCHIVE_VERSION);
+
+        bytes.extend_from_slice(&(self.members.len() as u16).to_be_bytes());
+        for member in &self.members {
+            let object = member.object.to_bytes()?;
+            write_name(&mut bytes, &member.name)?;
+            bytes.extend_from_slice(&(object.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&object);
+        }
+
+        Ok(bytes)
+    }
+
+
+    /**
+     * Parses the bytes of a .ssa archive along with every object file in it.
+     */
+    pub fn parse(bytes:&[u8]) -> Result<Archive, ArchiveFormatError> {
+        let mut reader = ObjectReader::new(bytes);
+        if reader.take(ARCHIVE_MAGIC.len()).map_err(|_| ArchiveFormatError::MissingMagicError)? != ARCHIVE_MAGIC {
+            return Err(ArchiveFormatError::MissingMagicError);
+        }
+
+        let version = reader.read_u8().map_err(|_| ArchiveFormatError::TruncatedError)?;
+        if version != ARCHIVE_VERSION {
+            return Err(ArchiveFormatError::UnsupportedVersionError(version));
+        }
+
+        let mut members = vec![];
+        for _ in 0..reader.read_u16().map_err(|_| ArchiveFormatError::TruncatedError)? {
+            let name = reader.read_name().map_err(|_| ArchiveFormatError::TruncatedError)?;
+            let length = reader.read_u32().map_err(|_| ArchiveFormatError::TruncatedError)? as usize;
+            let object_bytes = reader.take(length).map_err(|_| ArchiveFormatError::TruncatedError)?;
+
+            match ObjectFile::parse(object_bytes) {
+                Ok(object) => members.push(ArchiveMember { name: name, object: object }),
+                Err(err) => return Err(ArchiveFormatError::InvalidMemberError(name, err))
+            }
+        }
+
+        Ok(Archive { members: members })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::object::{ObjectFile, ObjectFormatError, SectionKind, Symbol};
+    use super::*;
+
+
+    #[test]
+    fn test_archive_round_trip() {
+        let object = ObjectFile {
+            code: vec![0x07, 0xC1],
+            symbols: vec![Symbol { name: String::from("add_two"), section: SectionKind::Code, offset: 0, global: true }],
+            ..ObjectFile::default()
+        };
+
+        let archive = Archive {
+            members: vec![
+                ArchiveMember { name: String::from("add.sso"), object: object },
+                ArchiveMember { name: String::from("empty.sso"), object: ObjectFile::default() }
+            ]
+        };
+
+        assert_eq!(Archive::parse(&archive.to_bytes().unwrap()).unwrap(), archive);
+    }
+
+
+    #[test]
+    fn test_invalid_archive() {
+        assert_eq!(Archive::parse(b"SSO\0\x02"), Err(ArchiveFormatError::MissingMagicError));
+        assert_eq!(Archive::parse(b"SSA\0\x01\x00\x01"), Err(ArchiveFormatError::TruncatedError));
+        assert_eq!(Archive::parse(b"SSA\0\x01\x00\x01\x01a\x00\x00\x00\x01\x00"),
+                   Err(ArchiveFormatError::InvalidMemberError(String::from("a"), ObjectFormatError::MissingMagicError)));
+    }
+}
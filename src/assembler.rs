@@ -1,50 +1,372 @@
-use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
 
+use crate::diagnostic::is_print_directive;
+use crate::error::{AssemblerError, IoOperation};
+use crate::label_table::{get_section_marker, is_fill_directive, is_symbol_directive, LabelResolver, RecordingResolver, SymbolError};
+use crate::layout::MemoryLayout;
+use crate::metadata::is_metadata_directive;
+use crate::object::{Relocation, RelocationError, RelocationKind, SectionKind};
 use crate::repr::instruction::*;
+use crate::source::{find_label_end, to_source_lines, SourceLine};
+use crate::sse::{LineEntry, SseFile, SseSection, SseSectionKind};
+use crate::trace::SymbolTrace;
 use crate::validation::*;
 
 
+/**
+ * Reads the assembly source file at the given path and returns its numbered lines.
+ */
+pub fn read_source(path:&Path) -> Result<Vec<SourceLine>, AssemblerError> {
+    let source = fs::read_to_string(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    Ok(to_source_lines(&source))
+}
+
+
+/**
+ * The range of bytes in a section produced by a single line of source.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ByteOrigin {
+    pub section: SectionKind,
+    pub offset: usize,
+    pub length: usize,
+    pub source: SourceLine
+}
+
+
+/**
+ * The sections produced by assembling a source file, along with a relocation for each label reference if the
+ * labels were resolved by a relocation recorder, and the line each range of bytes came from. The named
+ * sections are indexed as in the memory layout, and any after the last one used are left out. The bss section
+ * holds no bytes, only the size of the zeroed space it reserves after the data section.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AssembledSections {
+    pub data: Vec<u8>,
+    pub code: Vec<u8>,
+    pub bss_size: usize,
+    pub custom: Vec<Vec<u8>>,
+    pub relocations: Vec<Relocation>,
+    pub origins: Vec<ByteOrigin>
+}
+
+impl AssembledSections {
+    pub fn section(&self, section:SectionKind) -> &[u8] {
+        match section {
+            SectionKind::Data => &self.data,
+            SectionKind::Code => &self.code,
+            SectionKind::Bss => &[],
+            SectionKind::Custom(index) => self.custom.get(index).map(|bytes| bytes.as_slice()).unwrap_or(&[])
+        }
+    }
+
+
+    fn section_mut(&mut self, section:SectionKind) -> &mut Vec<u8> {
+        match section {
+            SectionKind::Data => &mut self.data,
+            SectionKind::Code => &mut self.code,
+            SectionKind::Bss => unreachable!("the bss section holds no bytes"),
+            SectionKind::Custom(index) => {
+                if self.custom.len() <= index {
+                    self.custom.resize(index + 1, vec![]);
+                }
+                &mut self.custom[index]
+            }
+        }
+    }
+
+
+    /**
+     * Returns the size of every section which was assembled, to check against the layout.
+     */
+    pub fn sizes(&self) -> Vec<(SectionKind, usize)> {
+        let mut sizes = vec![(SectionKind::Data, self.data.len()), (SectionKind::Bss, self.bss_size), (SectionKind::Code, self.code.len())];
+        sizes.extend(self.custom.iter().enumerate().map(|(index, bytes)| (SectionKind::Custom(index), bytes.len())));
+        sizes
+    }
+
+
+    /**
+     * Returns the address the section is loaded at in the layout, where the bss section follows the data section.
+     */
+    pub fn address(&self, section:SectionKind, layout:&MemoryLayout) -> usize {
+        match section {
+            SectionKind::Bss => layout.data.origin + self.data.len(),
+            section => layout.region(section).origin
+        }
+    }
+
+
+    /**
+     * Builds the .sse file holding the sections at their load addresses in the layout. The bss section, which
+     * a loader zeroes before the program starts, is only recorded if the program reserves any, and execution
+     * starts at the start of the code section unless an `entry` address is given.
+     */
+    pub fn to_sse_file(&self, layout:&MemoryLayout, entry:Option<usize>, metadata:Vec<(String, String)>) -> SseFile {
+        let mut sections = vec![
+            SseSection::new(SseSectionKind::Data, layout.data.origin, self.data.to_owned()),
+            SseSection::new(SseSectionKind::Code, layout.code.origin, self.code.to_owned())
+        ];
+
+        if self.bss_size > 0 {
+            sections.push(SseSection { kind: SseSectionKind::Bss, address: self.address(SectionKind::Bss, layout), size: self.bss_size, contents: vec![] });
+        }
+
+        for ((name, region), contents) in layout.sections.iter().zip(&self.custom) {
+            sections.push(SseSection::new(SseSectionKind::Named(name.to_owned()), region.origin, contents.to_owned()));
+        }
+
+        SseFile {
+            entry: entry.unwrap_or(layout.code.origin),
+            sections: sections,
+            metadata: metadata,
+            lines: vec![],
+            symbols: vec![]
+        }
+    }
+
+
+    /**
+     * Returns the debug line table, mapping the address of the first byte produced by each line to the file and
+     * line it came from, sorted by address. Lines in the bss section are left out as they produce no bytes.
+     */
+    pub fn line_table(&self, layout:&MemoryLayout, source_path:&Path) -> Vec<LineEntry> {
+        let mut lines:Vec<LineEntry> = self.origins.iter().filter(|origin| origin.section != SectionKind::Bss && origin.length > 0).map(|origin| {
+            LineEntry {
+                address: self.address(origin.section, layout) + origin.offset,
+                file: origin.source.file.as_deref().unwrap_or(source_path).display().to_string(),
+                line: origin.source.line_num
+            }
+        }).collect();
+
+        lines.sort_by_key(|entry| entry.address);
+        lines
+    }
+
+
+    /**
+     * Rewrites the words the assembler encoded big-endian as little-endian. Each 16-bit word of an instruction
+     * has its bytes swapped, leaving the word holding the opcode first so a long instruction can still be
+     * decoded from its first word, and each `.word`, `.fixed` and `.long` value has its bytes reversed.
+     */
+    pub fn swap_to_little_endian(&mut self) {
+        let origins = std::mem::take(&mut self.origins);
+        for origin in &origins {
+            let text = origin.source.text.trim();
+            let directive = match text.split_once(':') {
+                Some((label, rest)) if !label.contains(char::is_whitespace) => rest,
+                _ => text
+            }.split_whitespace().next();
+
+            let bytes = match origin.section {
+                SectionKind::Bss => continue,
+                section => &mut self.section_mut(section)[origin.offset..origin.offset + origin.length]
+            };
+
+            match (origin.section, directive) {
+                (_, Some(".space")) | (_, Some(".fill")) => {},
+                (SectionKind::Code, _) => bytes.chunks_mut(2).for_each(|word| word.reverse()),
+                (_, Some(".word")) | (_, Some(".fixed")) => bytes.chunks_mut(2).for_each(|word| word.reverse()),
+                (_, Some(".long")) => bytes.chunks_mut(4).for_each(|long| long.reverse()),
+                _ => {}
+            }
+        }
+
+        self.origins = origins;
+    }
+}
+
+
+/**
+ * Works out where the label reference in an item lies once the item is appended to its section, returning
+ * an error if the reference is in a field a relocation cannot patch.
+ */
+fn get_relocation(item:&InstructionOrData, section:SectionKind, sections:&AssembledSections, label:String) -> Result<Relocation, Box<dyn Error>> {
+    let (section, offset, kind) = match item {
+        InstructionOrData::Instruction(instr, _) => match instr.operand_b {
+            Operand::LargeImmediate(_) => (SectionKind::Code, sections.code.len() + 2, RelocationKind::Absolute16),
+            _ => return Err(Box::new(RelocationError::UnrelocatableReferenceError(label)))
+        },
+
+        InstructionOrData::Data(data) => match data.bytes.len() {
+            1 => (section, sections.section(section).len(), RelocationKind::Absolute8),
+            2 => (section, sections.section(section).len(), RelocationKind::Absolute16),
+            4 => (section, sections.section(section).len(), RelocationKind::Absolute32),
+            _ => return Err(Box::new(RelocationError::UnrelocatableReferenceError(label)))
+        }
+    };
+
+    Ok(Relocation {
+        section: section,
+        offset: offset,
+        symbol: label,
+        kind: kind
+    })
+}
+
+
+/**
+ * Processes every line of a source file and encodes the results into the sections of the given layout. Each
+ * error is reported against its line number, and if `keep_going` is set every line is processed and errors are
+ * printed as they are found before failing with the total count. If a `trace` is given, every label reference
+ * is logged to it along with how it was resolved.
+ */
+pub fn assemble_lines(source_lines:&[SourceLine], labels:&impl LabelResolver, layout:&MemoryLayout, keep_going:bool, trace:Option<&SymbolTrace>)
+        -> Result<AssembledSections, AssemblerError> {
+    let labels = RecordingResolver::new(labels);
+    let mut sections = AssembledSections::default();
+    let mut section = SectionKind::Data;
+    let mut data_mode = true;
+    let mut error_count = 0;
+
+    for source_line in source_lines {
+        let line = match source_line.text.trim() {
+            "" => continue,
+            l => l
+        };
+
+        if let Some(name) = get_section_marker(line) {
+            section = layout.section_kind(name).ok_or_else(|| source_line.error(Box::new(SymbolError::UnplacedSectionError(name.to_string()))))?;
+        }
+
+        let result = process_line(line, &labels, &mut data_mode).and_then(|item| match (item, labels.take_relocation()) {
+            (Some(InstructionOrData::Data(data)), _) if section == SectionKind::Bss && data.bytes.iter().any(|byte| *byte != 0) => {
+                Err(Box::new(SymbolError::InitialisedBssError) as Box<dyn Error>)
+            },
+            (Some(item), Some(label)) => {
+                let relocation = get_relocation(&item, section, &sections, label)?;
+                Ok(Some((item, Some(relocation))))
+            },
+            (item, _) => Ok(item.map(|item| (item, None)))
+        });
+
+        let resolved = labels.take_resolved();
+        let (item, relocation) = match result {
+            Ok(Some(item)) => item,
+            Ok(None) => continue,
+            Err(err) => {
+                let err = source_line.error(err);
+                if !keep_going {
+                    return Err(err);
+                }
+
+                eprintln!("error: {}", err);
+                error_count += 1;
+                continue;
+            }
+        };
+
+        if let Some(trace) = trace {
+            for (label, value) in resolved {
+                trace.log_reference(source_line.line_num, &label, value, relocation.as_ref());
+            }
+        }
+
+        sections.relocations.extend(relocation);
+        let (section, offset) = match item {
+            InstructionOrData::Data(data) if section == SectionKind::Bss => {
+                sections.bss_size += data.bytes.len();
+                (section, sections.bss_size - data.bytes.len())
+            },
+
+            // only `.space` and `.fill` give data in the code section, where zeros decode as `nop`
+            InstructionOrData::Data(data) => {
+                let offset = sections.section(section).len();
+                sections.section_mut(section).extend_from_slice(&data.bytes);
+                (section, offset)
+            },
+
+            InstructionOrData::Instruction(instr, kind) => {
+                let offset = sections.code.len();
+                match instr.encode(&kind) {
+                    InstrType::Regular(reg) => sections.code.extend_from_slice(&reg.to_be_bytes()),
+                    InstrType::Long(long) => sections.code.extend_from_slice(&long.to_be_bytes())
+                }
+                (SectionKind::Code, offset)
+            }
+        };
+
+        let length = match section {
+            SectionKind::Bss => sections.bss_size - offset,
+            _ => sections.section(section).len() - offset
+        };
+
+        sections.origins.push(ByteOrigin {
+            section: section,
+            offset: offset,
+            length: length,
+            source: source_line.to_owned()
+        });
+    }
+
+    if error_count > 0 {
+        return Err(AssemblerError::AssemblyFailedError(error_count));
+    }
+
+    Ok(sections)
+}
+
+
 /**
  * Takes a line of S6 assembly and removes the label. Returns `None` if the line is just a label, otherwise
- * generates an `Instruction` for the line.
+ * generates an `Instruction` for the line, or returns an error if the line fails validation.
  */
-pub fn process_line(line:&str, label_table:&HashMap<String, usize>, data_mode:&mut bool) -> Option<InstructionOrData> {  
+pub fn process_line(line:&str, labels:&impl LabelResolver, data_mode:&mut bool) -> Result<Option<InstructionOrData>, Box<dyn Error>> {
     // this is a single-threaded assembler, therefore mutable static variable is ok
-    if line == ".code:" {
-        *data_mode = false;
+    if let Some(name) = get_section_marker(line) {
+        *data_mode = name != "code";
+        return Ok(None);
+    }
+
+    // a `.print` directive is reported once the labels are known, and its text may hold a ':'
+    if is_print_directive(line) {
+        return Ok(None);
     }
 
     // get the line excluding any labels ending in ":"
-    let mut line = match line.find(":") {
+    let mut line = match find_label_end(line) {
         None => line,
         Some(index) => (line[index + 1..]).trim()
     };
 
-    // if the line was just a label, return `None`
-    if line.is_empty() {
-        return None;
+    // if the line was just a label or a directive which takes up no space, return `None`
+    if line.is_empty() || is_symbol_directive(line) || is_metadata_directive(line) {
+        return Ok(None);
     }
 
-    // substitute a label for an absolute value
-    let new_line;
-    if let Some(index) = line.find("@")  {
-        let label = line[index + 1..].to_owned();
-        validate_label(&label).unwrap();
+    // substitute each label for an absolute value, which may be part of an expression such as `hi(@label)`
+    let mut new_line;
+    while let Some(index) = line.find('@') {
+        let rest = &line[index + 1..];
+        let label = &rest[..rest.find(|c:char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len())];
+        validate_label(label)?;
+
+        // a relocation patches a whole item, so a relocated label must be the only value of a data directive
+        let before = line[..index].trim_end();
+        let whole_operand = match *data_mode && !is_fill_directive(line) {
+            true => !before.contains(char::is_whitespace) && before.len() < index,
+            false => line[..index].ends_with(|c:char| c.is_whitespace() || c == ',')
+        } && rest[label.len()..].trim().is_empty();
+        if !whole_operand && labels.relocates() {
+            return Err(Box::new(RelocationError::UnrelocatableReferenceError(label.to_string())));
+        }
 
-        new_line = line.replace(&format!("@{}", label), &label_table[&label].to_string());
+        new_line = format!("{}{}{}", &line[..index], labels.resolve(label)?, &rest[label.len()..]);
         line = new_line.as_str();
     }
 
-    match data_mode {
+    match *data_mode || is_fill_directive(line) {
         true => {
-            let data = Data::from(line);
-            return Some(InstructionOrData::Data(data));
+            let data = Data::try_from(line)?;
+            return Ok(Some(InstructionOrData::Data(data)));
         }
 
         false => {
-            let instr = Instruction::from(line);
-            validate_instruction(&instr).unwrap();
-            return Some(InstructionOrData::Instruction(instr));
+            let instr = Instruction::try_from(line)?;
+            let kind = validate_instruction(&instr)?;
+            return Ok(Some(InstructionOrData::Instruction(instr, kind)));
         }
     }    
 }
@@ -54,29 +376,28 @@ pub fn process_line(line:&str, label_table:&HashMap<String, usize>, data_mode:&m
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use std::fs::OpenOptions;
-    use std::io::{BufRead, BufReader, Seek};
+    use std::path::Path;
 
-    use crate::label_table::get_label_table;
+    use crate::label_table::{get_label_definitions, get_label_table};
+    use crate::layout::MemoryLayout;
+    use crate::object::{RelocationRecorder, SectionKind};
     use crate::repr::instruction::{Instruction, InstructionOrData};
     use crate::repr::opcode::Opcode;
     use crate::repr::instruction::Operand;
     use crate::repr::register::Register;
-    use super::process_line;
+    use crate::source::to_source_lines;
+    use crate::sse::{LineEntry, SseSection, SseSectionKind};
+    use super::{assemble_lines, process_line, read_source, AssembledSections};
 
 
     fn load_input_lines(filename:&str) -> Vec<InstructionOrData> {
-        let mut input_file = OpenOptions::new().read(true)
-                                               .open(filename)
-                                               .unwrap();
-        
-        let label_table:HashMap<String, usize> = get_label_table(&input_file);
-        input_file.rewind().unwrap();
+        let source_lines = read_source(Path::new(filename)).unwrap();
+        let label_table:HashMap<String, usize> = get_label_table(&get_label_definitions(&source_lines, &MemoryLayout::default()));
 
         let mut data_mode = true;
-        BufReader::new(&input_file).lines().filter_map(|line| match line.unwrap().trim() {
+        source_lines.iter().filter_map(|line| match line.text.trim() {
             "" => None, 
-            l => process_line(l, &label_table, &mut data_mode)
+            l => process_line(l, &label_table, &mut data_mode).unwrap()
         }).collect()
     }
 
@@ -95,4 +416,148 @@ mod tests {
     fn test_mixed_code_data() {
         let _ = load_input_lines("test_files/test_mixed_code_data.asm");
     }
+
+
+    #[test]
+    fn test_label_expressions() {
+        let labels = HashMap::from([(String::from("table"), 0x9004)]);
+        let instr:Instruction = process_line("movi al, lo(@table)", &labels, &mut false).unwrap().unwrap().into();
+        assert_eq!(instr.operand_b, Operand::LargeImmediate(0x04));
+        let instr:Instruction = process_line("movi ah, hi(@table) + 1", &labels, &mut false).unwrap().unwrap().into();
+        assert_eq!(instr.operand_b, Operand::LargeImmediate(0x91));
+
+        let recorder = RelocationRecorder::new(&labels, &HashMap::new());
+        assert!(process_line("movi ax, @table", &recorder, &mut false).is_ok());
+        let err = process_line("movi ax, hi(@table)", &recorder, &mut false).unwrap_err();
+        assert_eq!(err.to_string(), "Label 'table' cannot be relocated in this position");
+    }
+
+
+    #[test]
+    fn test_jump_table() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".data:
+table: .word @first @second @third
+size: .word @third - @first
+                                            .code:
+first: nop
+second: nop
+third: movi ax, @third - @second");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+
+        let sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        assert_eq!(sections.data, vec![0x58, 0x00, 0x58, 0x02, 0x58, 0x04, 0x00, 0x04]);
+        assert_eq!(&sections.code[6..], &[0x00, 0x02]);
+
+        let recorder = RelocationRecorder::new(&label_table, &HashMap::new());
+        assert!(process_line(".word @first", &recorder, &mut true).is_ok());
+        let err = process_line(".word @first @second", &recorder, &mut true).unwrap_err();
+        assert_eq!(err.to_string(), "Label 'first' cannot be relocated in this position");
+        let err = process_line(".word 0 @second", &recorder, &mut true).unwrap_err();
+        assert_eq!(err.to_string(), "Label 'second' cannot be relocated in this position");
+    }
+
+
+    #[test]
+    fn test_interleaved_sections() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".data:\nlimit: .byte 3\n.code:\nstart: movi ax @limit\n.data:\ntable: .word 0x1234\n.code:\nmovi bx @table\nend: nop");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+        assert_eq!((label_table["limit"], label_table["table"]), (0x9000, 0x9001));
+        assert_eq!((label_table["start"], label_table["end"]), (0x5800, 0x5808));
+
+        let sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        assert_eq!(sections.data, vec![0x03, 0x12, 0x34]);
+        assert_eq!((sections.code.len(), &sections.code[6..8]), (10, &[0x90, 0x01][..]));
+    }
+
+
+    #[test]
+    fn test_little_endian() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".data:
+limit: .byte 3
+table: .word 0x1234
+.long 0x89ABCDEF
+msg: .asciiz `ab`
+.code:
+movi ax @table
+nop
+.space 3");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+
+        let mut sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        let code = sections.code.clone();
+        sections.swap_to_little_endian();
+        assert_eq!(sections.data, vec![0x03, 0x34, 0x12, 0xEF, 0xCD, 0xAB, 0x89, 0x61, 0x62, 0x00]);
+        assert_eq!(sections.code, vec![code[1], code[0], 0x01, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(sections.origins.len(), 7);
+    }
+
+
+    #[test]
+    fn test_little_endian_fixed() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".data:
+.fixed 1.5 -0.5
+.word 0x1234
+.fixed Q4.12 1.0625
+.code:
+halt");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+
+        let mut sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        sections.swap_to_little_endian();
+        assert_eq!(sections.data, vec![0x80, 0x01, 0x80, 0xFF, 0x34, 0x12, 0x00, 0x11]);
+    }
+
+
+    #[test]
+    fn test_named_sections() {
+        let layout = MemoryLayout::parse("region ROM 0x5800 0x3800\nregion RAM 0x9000 0x7000\nregion FAST 0x0100 0x100\n\
+                                          place code ROM\nplace data RAM\nplace fast_ram FAST").unwrap();
+        let source_lines = to_source_lines(".data:\ncount: .byte 1\n.section fast_ram\nbuffer: .word 0xBEEF\n.code:\nmovi ax @buffer\n.section fast_ram\nflag: .byte 7");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+        assert_eq!((label_table["count"], label_table["buffer"], label_table["flag"]), (0x9000, 0x0100, 0x0102));
+
+        let sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        assert_eq!((sections.data.clone(), sections.custom.clone()), (vec![0x01], vec![vec![0xBE, 0xEF, 0x07]]));
+        assert_eq!(&sections.code[2..4], &[0x01, 0x00]);
+        assert_eq!(sections.sizes()[3], (SectionKind::Custom(0), 3));
+
+        let err = assemble_lines(&to_source_lines(".section slow_ram"), &label_table, &layout, false, None).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: Section 'slow_ram' is not placed in the memory layout");
+    }
+
+
+    #[test]
+    fn test_bss_section() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".bss:\nbuffer: .space 16\ncount: .word 0\n.data:\nmsg: .asciiz `hi`\n.code:\nmovi ax @count");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+        assert_eq!((label_table["msg"], label_table["buffer"], label_table["count"]), (0x9000, 0x9003, 0x9013));
+
+        let sections = assemble_lines(&source_lines, &label_table, &layout, false, None).unwrap();
+        assert_eq!((sections.data.len(), sections.bss_size), (3, 18));
+        let file = sections.to_sse_file(&layout, Some(0x5804), vec![]);
+        assert_eq!(file.section(&SseSectionKind::Bss), Some(&SseSection { kind: SseSectionKind::Bss, address: 0x9003, size: 18, contents: vec![] }));
+        assert_eq!(file.entry, 0x5804);
+        assert_eq!(AssembledSections::default().to_sse_file(&layout, None, vec![]).section(&SseSectionKind::Bss), None);
+
+        // the bss section produces no bytes to map to source lines
+        assert_eq!(sections.line_table(&layout, Path::new("prog.asm")), vec![
+            LineEntry { address: 0x5800, file: String::from("prog.asm"), line: 7 },
+            LineEntry { address: 0x9000, file: String::from("prog.asm"), line: 5 }
+        ]);
+
+        let err = assemble_lines(&to_source_lines(".bss:\n.byte 1"), &label_table, &layout, false, None).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: The bss section only reserves space, so every value in it must be zero");
+    }
+
+
+    #[test]
+    fn test_missing_source_file() {
+        let err = read_source(Path::new("test_files/does_not_exist.asm")).unwrap_err();
+        assert_eq!(err.to_string(), "cannot read input 'test_files/does_not_exist.asm': no such file or directory");
+    }
 }
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::{evaluate, string_literal, unescape};
+use crate::messages::{message, MessageId};
+use crate::source::{find_label_end, SourceLine};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CharmapError {
+    InvalidCharmapError(String)
+}
+
+impl Error for CharmapError {}
+
+impl fmt::Display for CharmapError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CharmapError::InvalidCharmapError(operands) => write!(f, "{}", message(MessageId::InvalidCharmap, &[operands]))
+        }
+    }
+}
+
+
+/**
+ * Evaluates the operands of a `.charmap` directive, a character and the byte it is encoded as, each of which
+ * may be a constant expression such as `'A'` or `0x0A`.
+ */
+fn parse_charmap(operands:&str) -> Result<(u8, u8), Box<dyn Error>> {
+    let invalid = || CharmapError::InvalidCharmapError(operands.trim().to_string());
+    let (character, byte) = operands.rsplit_once(',').ok_or_else(invalid)?;
+    let character = u8::try_from(evaluate(character, &HashMap::new())?).map_err(|_| invalid())?;
+    let byte = u8::try_from(evaluate(byte, &HashMap::new())?).map_err(|_| invalid())?;
+
+    Ok((character, byte))
+}
+
+
+/**
+ * Takes the lines of a source file and applies the `.charmap 'A', 0x0A` directives in it, each of which sets
+ * the byte a character is encoded as in the strings of the `.ascii`, `.asciiz` and `.asciip` lines after it, so
+ * text can be stored in the encoding of a custom font. A string holding a mapped character is replaced with an
+ * `.array` of its encoded bytes, including the null byte of an `.asciiz` and the length of an `.asciip`, and
+ * the directive lines are removed.
+ */
+pub fn apply_charmaps(source_lines:Vec<SourceLine>) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut charmap:HashMap<u8, u8> = HashMap::new();
+    let mut output = vec![];
+
+    for line in source_lines {
+        let (label, contents) = match find_label_end(&line.text) {
+            Some(index) => (format!("{} ", line.text[..index + 1].trim()), line.text[index + 1..].trim()),
+            None => (String::new(), line.text.trim())
+        };
+
+        let directive = contents.split_whitespace().next().unwrap_or("");
+        if directive == ".charmap" {
+            let (character, byte) = parse_charmap(&contents[directive.len()..]).map_err(|err| line.error(err))?;
+            charmap.insert(character, byte);
+            continue;
+        }
+
+        // strings which are invalid or use no mapped character are left for the encoder as they are
+        let bytes = match directive {
+            ".ascii" | ".asciiz" | ".asciip" => string_literal(contents).and_then(|text| unescape(&text)).unwrap_or_default(),
+            _ => vec![]
+        };
+        if !bytes.iter().any(|byte| charmap.contains_key(byte)) {
+            output.push(line);
+            continue;
+        }
+
+        let mut encoded:Vec<u8> = bytes.iter().map(|byte| *charmap.get(byte).unwrap_or(byte)).collect();
+        match directive {
+            ".asciiz" => encoded.push(0x00),
+            ".asciip" => match u8::try_from(encoded.len()) {
+                Ok(length) => encoded.insert(0, length),
+                Err(_) => {
+                    output.push(line);
+                    continue;
+                }
+            },
+            _ => {}
+        }
+
+        let array:Vec<String> = encoded.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        output.push(SourceLine { text: format!("{}.array {}", label, array.join(" ")), ..line });
+    }
+
+    Ok(output)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    #[test]
+    fn test_apply_charmaps() {
+        let source = ".asciiz `AB`\n.charmap 'A', 0x0A\n.charmap 66, 'b' + 1\nmsg: .asciiz `AB:A`\n.ascii \"CA\"\n.asciip `AC`\n.ascii `C`";
+        let texts:Vec<String> = apply_charmaps(to_source_lines(source)).unwrap().into_iter().map(|line| line.text).collect();
+        assert_eq!(texts, vec![
+            ".asciiz `AB`", "msg: .array 0x0A 0x63 0x3A 0x0A 0x00", ".array 0x43 0x0A", ".array 0x02 0x0A 0x43", ".ascii `C`"
+        ]);
+
+        let err = apply_charmaps(to_source_lines(".charmap 'A'")).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '.charmap' expects a character and the byte it is encoded as, as in '.charmap 'A', 0x0A', found ''A''");
+
+        let err = apply_charmaps(to_source_lines(".charmap 'A', 256")).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '.charmap' expects a character and the byte it is encoded as, as in '.charmap 'A', 0x0A', found ''A', 256'");
+    }
+}
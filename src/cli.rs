@@ -0,0 +1,699 @@
+use std::path::PathBuf;
+
+use crate::emit::{Endian, OutputFormat};
+use crate::error::AssemblerError;
+use crate::expr::parse_integer;
+use crate::messages::{Language, MessageId};
+use crate::pseudo::SCRATCH_REGISTERS;
+use crate::repr::opcode::{is_mnemonic, resolve_mnemonic};
+use crate::version::{SUPPORTED_FORMATS, SUPPORTED_MARCH};
+
+
+/**
+ * The task the assembler has been asked to perform.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Assemble(Box<AssembleOptions>),
+    Inspect(PathBuf),
+    Dump(PathBuf),
+    Verify(PathBuf),
+    Link(LinkOptions),
+    Archive(ArchiveOptions),
+    Version
+}
+
+
+/**
+ * The options the assembler was invoked with. Every output is produced from the same assembly of the input.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleOptions {
+    pub input_path: PathBuf,
+    pub outputs: Vec<(OutputFormat, PathBuf)>,
+    pub keep_going: bool,
+    pub trace_symbols: bool,
+    pub debug_info: bool,
+    pub compat_numbers: bool,
+    pub align_data: bool,
+    pub strict: bool,
+    pub strip: bool,
+    pub keep_symbols: Vec<String>,
+    pub layout_path: Option<PathBuf>,
+    pub port_map_path: Option<PathBuf>,
+    pub check_ports: bool,
+    pub code_base: Option<usize>,
+    pub data_base: Option<usize>,
+    pub bin_base: Option<usize>,
+    pub pad_byte: u8,
+    pub image_size: Option<usize>,
+    pub split_bytes: bool,
+    pub endian: Endian,
+    pub provenance_path: Option<PathBuf>,
+    pub listing_path: Option<PathBuf>,
+    pub map_path: Option<PathBuf>,
+    pub source_map_path: Option<PathBuf>,
+    pub c_header_path: Option<PathBuf>,
+    pub rust_source_path: Option<PathBuf>,
+    pub symbols_json_path: Option<PathBuf>,
+    pub sym_path: Option<PathBuf>,
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<(String, i64)>,
+    pub aliases: Vec<(String, String)>,
+    pub scratch: String,
+    pub pseudos: bool,
+    pub march: String
+}
+
+
+/**
+ * The options the linker was invoked with.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkOptions {
+    pub input_paths: Vec<PathBuf>,
+    pub output_path: PathBuf,
+    pub layout_path: Option<PathBuf>,
+    pub code_base: Option<usize>,
+    pub data_base: Option<usize>
+}
+
+
+/**
+ * The options the archiver was invoked with.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveOptions {
+    pub archive_path: PathBuf,
+    pub member_paths: Vec<PathBuf>
+}
+
+
+/**
+ * Returns the value following an option which takes an argument, or a usage error if there is none.
+ */
+fn get_option_value<'a>(option:&str, args:&mut impl Iterator<Item = &'a String>) -> Result<&'a String, AssemblerError> {
+    args.next().ok_or_else(|| AssemblerError::usage(MessageId::OptionExpectsValue, &[option]))
+}
+
+
+/**
+ * Parses the value of a `--format` option, which is a format name optionally followed by `:` and the path to
+ * write that format to.
+ */
+fn parse_format_option(value:&str) -> Result<(OutputFormat, Option<PathBuf>), AssemblerError> {
+    let (name, path) = match value.split_once(':') {
+        Some((name, path)) => (name, Some(PathBuf::from(path))),
+        None => (value, None)
+    };
+
+    match OutputFormat::try_from(name) {
+        Ok(format) => Ok((format, path)),
+        Err(name) => Err(AssemblerError::usage(MessageId::UnsupportedFormat, &[&name, &SUPPORTED_FORMATS.join(", ")]))
+    }
+}
+
+
+/**
+ * Parses the value of a `-D` option, which is a symbol name optionally followed by `=` and an integer value.
+ * A symbol defined without a value is given the value 1.
+ */
+fn parse_define_option(value:&str) -> Result<(String, i64), AssemblerError> {
+    let (name, number) = match value.split_once('=') {
+        Some((name, number)) => (name, parse_integer(number)),
+        None => (value, Some(1))
+    };
+
+    let valid_name = name.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match (valid_name, number) {
+        (true, Some(number)) => Ok((name.to_string(), number)),
+        _ => Err(AssemblerError::usage(MessageId::InvalidDefine, &[value]))
+    }
+}
+
+
+/**
+ * Parses the value of an `--alias NAME=MNEMONIC` option, giving another name for a mnemonic. The name is stored
+ * in lowercase and the mnemonic resolved past any built-in alias, and a name which is already a mnemonic or an
+ * alias of one is rejected so an instruction cannot silently change meaning.
+ */
+fn parse_alias_option(value:&str) -> Result<(String, String), AssemblerError> {
+    let (name, mnemonic) = value.split_once('=').unwrap_or((value, ""));
+    let valid_name = name.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid_name && !is_mnemonic(name) && is_mnemonic(mnemonic) {
+        true => Ok((name.to_lowercase(), resolve_mnemonic(mnemonic))),
+        false => Err(AssemblerError::usage(MessageId::InvalidAlias, &[value]))
+    }
+}
+
+
+/**
+ * Parses the value of a `--scratch` option, the register pseudo-instructions such as `call @function` load an
+ * address into, which must be a 16-bit register they are free to overwrite.
+ */
+fn parse_scratch_option(value:&str) -> Result<String, AssemblerError> {
+    let register = value.to_lowercase();
+    match SCRATCH_REGISTERS.contains(&register.as_str()) {
+        true => Ok(register),
+        false => Err(AssemblerError::usage(MessageId::InvalidScratchRegister, &[value, &SCRATCH_REGISTERS.join(", ")]))
+    }
+}
+
+
+/**
+ * Parses the value of a `--code-base` or `--data-base` option, which is an address in the 16-bit address space
+ * in decimal, hex or binary.
+ */
+fn parse_base_option(option:&str, value:&str) -> Result<usize, AssemblerError> {
+    match parse_integer(value) {
+        Some(address @ 0..=0xFFFF) => Ok(address as usize),
+        _ => Err(AssemblerError::usage(MessageId::InvalidBaseAddress, &[option, value]))
+    }
+}
+
+
+/**
+ * Parses the value of a `--pad-byte` or `--fill` option, the byte which fills the gaps between sections in a
+ * flat binary image and pads it to the image size.
+ */
+fn parse_pad_byte_option(option:&str, value:&str) -> Result<u8, AssemblerError> {
+    match parse_integer(value) {
+        Some(byte @ 0..=0xFF) => Ok(byte as u8),
+        _ => Err(AssemblerError::usage(MessageId::InvalidPadByte, &[option, value]))
+    }
+}
+
+
+/**
+ * Parses the value of an `--image-size` option, the exact size of a flat binary image, which may be as large
+ * as the 64 KiB address space.
+ */
+fn parse_image_size_option(value:&str) -> Result<usize, AssemblerError> {
+    match parse_integer(value) {
+        Some(size @ 1..=0x10000) => Ok(size as usize),
+        _ => Err(AssemblerError::usage(MessageId::InvalidImageSize, &[value]))
+    }
+}
+
+
+/**
+ * Parses the value of an `--endian` option, the byte order instructions and `.word` and `.long` values are
+ * written in.
+ */
+fn parse_endian_option(value:&str) -> Result<Endian, AssemblerError> {
+    Endian::try_from(value).map_err(|_| AssemblerError::usage(MessageId::InvalidEndian, &[value]))
+}
+
+
+/**
+ * Removes the `--lang <code>` option from the command line arguments, which applies to every command, and
+ * returns the language it selects, if any.
+ */
+pub fn take_language_option(args:&mut Vec<String>) -> Result<Option<Language>, AssemblerError> {
+    let index = match args.iter().position(|arg| arg == "--lang" || arg.starts_with("--lang=")) {
+        Some(index) => index,
+        None => return Ok(None)
+    };
+
+    let arg = args.remove(index);
+    let code = match arg.strip_prefix("--lang=") {
+        Some(code) => code.to_string(),
+        None if index < args.len() => args.remove(index),
+        None => return Err(AssemblerError::usage(MessageId::OptionExpectsValue, &[&arg]))
+    };
+
+    match Language::try_from(code.as_str()) {
+        Ok(language) => Ok(Some(language)),
+        Err(code) => Err(AssemblerError::usage(MessageId::UnsupportedLanguage, &[&code]))
+    }
+}
+
+
+/**
+ * Takes the command line arguments, excluding the program name, and parses them into the `Command` to run,
+ * returning a usage error if they are malformed.
+ */
+pub fn parse_args(args:&[String]) -> Result<Command, AssemblerError> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("inspect") => return Ok(Command::Inspect(parse_path_arg(&args[1..], &[".sse", ".sso"])?)),
+        Some("dump") => return Ok(Command::Dump(parse_path_arg(&args[1..], &[".sse"])?)),
+        Some("verify") => return Ok(Command::Verify(parse_path_arg(&args[1..], &[".sse"])?)),
+        Some("link") => return Ok(Command::Link(parse_link_args(&args[1..])?)),
+        Some("ar") => return Ok(Command::Archive(parse_archive_args(&args[1..])?)),
+        _ => {}
+    }
+
+    let mut positional:Vec<&String> = vec![];
+    let mut keep_going = false;
+    let mut trace_symbols = false;
+    let mut debug_info = false;
+    let mut compat_numbers = false;
+    let mut align_data = false;
+    let mut strict = false;
+    let mut strip = false;
+    let mut keep_symbols = vec![];
+    let mut march = String::from(SUPPORTED_MARCH[0]);
+    let mut formats = vec![];
+    let mut layout_path = None;
+    let mut port_map_path = None;
+    let mut check_ports = false;
+    let mut code_base = None;
+    let mut data_base = None;
+    let mut bin_base = None;
+    let mut pad_byte = 0xFF;
+    let mut image_size = None;
+    let mut split_bytes = false;
+    let mut endian = Endian::Big;
+    let mut provenance_path = None;
+    let mut listing_path = None;
+    let mut map_path = None;
+    let mut source_map_path = None;
+    let mut c_header_path = None;
+    let mut rust_source_path = None;
+    let mut symbols_json_path = None;
+    let mut sym_path = None;
+    let mut include_dirs = vec![];
+    let mut defines = vec![];
+    let mut aliases = vec![];
+    let mut scratch = String::from("dx");
+    let mut pseudos = true;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--version" | "-V" => return Ok(Command::Version),
+            "--keep-going" | "-k" => keep_going = true,
+            "--trace-symbols" => trace_symbols = true,
+            "-g" => debug_info = true,
+            "--compat-numbers" => compat_numbers = true,
+            "--align-data" => align_data = true,
+            "--strict" => strict = true,
+            "--strip" => strip = true,
+            "--keep-symbols" => keep_symbols.extend(get_option_value(arg, &mut args)?.split(',').map(|name| name.trim().to_owned())),
+            "--march" => march = get_option_value(arg, &mut args)?.to_owned(),
+            flag if flag.starts_with("--march=") => march = flag["--march=".len()..].to_owned(),
+            "--layout" => layout_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--port-map" => port_map_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--check-ports" => check_ports = true,
+            "--code-base" => code_base = Some(parse_base_option(arg, get_option_value(arg, &mut args)?)?),
+            "--data-base" => data_base = Some(parse_base_option(arg, get_option_value(arg, &mut args)?)?),
+            "--bin-base" => bin_base = Some(parse_base_option(arg, get_option_value(arg, &mut args)?)?),
+            "--split-bytes" => split_bytes = true,
+            "--endian" => endian = parse_endian_option(get_option_value(arg, &mut args)?)?,
+            "--pad-byte" | "--fill" => pad_byte = parse_pad_byte_option(arg, get_option_value(arg, &mut args)?)?,
+            "--image-size" => image_size = Some(parse_image_size_option(get_option_value(arg, &mut args)?)?),
+            "--provenance" => provenance_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--listing" => listing_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--map" => map_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--source-map" => source_map_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--emit-c-header" => c_header_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--emit-rust" => rust_source_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--emit-symbols-json" => symbols_json_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--emit-sym" => sym_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "-I" => include_dirs.push(PathBuf::from(get_option_value(arg, &mut args)?)),
+            flag if flag.starts_with("-I") => include_dirs.push(PathBuf::from(&flag["-I".len()..])),
+            "-D" => defines.push(parse_define_option(get_option_value(arg, &mut args)?)?),
+            flag if flag.starts_with("-D") => defines.push(parse_define_option(&flag["-D".len()..])?),
+            "--alias" => aliases.push(parse_alias_option(get_option_value(arg, &mut args)?)?),
+            "--scratch" => scratch = parse_scratch_option(get_option_value(arg, &mut args)?)?,
+            "--no-pseudos" => pseudos = false,
+            "--format" => formats.push(parse_format_option(get_option_value(arg, &mut args)?)?),
+            flag if flag.starts_with("--format=") => formats.push(parse_format_option(&flag["--format=".len()..])?),
+            flag if flag.starts_with('-') => return Err(AssemblerError::usage(MessageId::UnknownOption, &[flag])),
+            _ => positional.push(arg)
+        }
+    }
+
+    if !SUPPORTED_MARCH.contains(&march.as_str()) {
+        return Err(AssemblerError::usage(MessageId::UnsupportedMarch, &[&march, &SUPPORTED_MARCH.join(", ")]));
+    }
+
+    let (filename, output_name) = match positional.as_slice() {
+        [filename, output_name] => (filename, Some(output_name)),
+        [filename] if !formats.is_empty() => (filename, None),
+        _ => return Err(AssemblerError::usage(MessageId::Usage, &[]))
+    };
+
+    if !filename.ends_with(".asm") {
+        return Err(AssemblerError::usage(MessageId::InputNotAsm, &[]));
+    }
+
+    // without --format the output format comes from the extension of the output path, otherwise each format
+    // is written to its own path or to the output path with the format's extension
+    let mut outputs = vec![];
+    match (formats.is_empty(), output_name) {
+        (true, Some(output_name)) => {
+            let format = PathBuf::from(output_name).extension().and_then(|ext| ext.to_str()).and_then(|ext| OutputFormat::try_from(ext).ok());
+            match format {
+                Some(format) => outputs.push((format, PathBuf::from(output_name))),
+                None => {
+                    let extensions:Vec<String> = SUPPORTED_FORMATS.iter().map(|format| format!(".{}", format)).collect();
+                    return Err(AssemblerError::usage(MessageId::FileExtension, &[&extensions.join(", ")]));
+                }
+            }
+        },
+
+        _ => for (format, path) in formats {
+            let path = match (path, output_name) {
+                (Some(path), _) => path,
+                (None, Some(output_name)) => PathBuf::from(output_name).with_extension(format.extension()),
+                (None, None) => return Err(AssemblerError::usage(MessageId::FormatExpectsPath, &[format.extension()]))
+            };
+
+            if let Some((existing, _)) = outputs.iter().find(|(_, existing_path)| *existing_path == path) {
+                return Err(AssemblerError::usage(MessageId::DuplicateOutputPath, &[existing.extension(), format.extension(), &path.display().to_string()]));
+            }
+            outputs.push((format, path));
+        }
+    }
+
+    // the linker patches relocations big-endian, so an object file must be linked before its bytes are swapped
+    if endian == Endian::Little && outputs.iter().any(|(format, _)| *format == OutputFormat::Sso) {
+        return Err(AssemblerError::usage(MessageId::LittleEndianObject, &[]));
+    }
+
+    Ok(Command::Assemble(Box::new(AssembleOptions {
+        input_path: PathBuf::from(filename),
+        outputs: outputs,
+        keep_going: keep_going,
+        trace_symbols: trace_symbols,
+        debug_info: debug_info,
+        compat_numbers: compat_numbers,
+        align_data: align_data,
+        strict: strict,
+        strip: strip,
+        keep_symbols: keep_symbols,
+        layout_path: layout_path,
+        port_map_path: port_map_path,
+        check_ports: check_ports,
+        code_base: code_base,
+        data_base: data_base,
+        bin_base: bin_base,
+        pad_byte: pad_byte,
+        image_size: image_size,
+        split_bytes: split_bytes,
+        endian: endian,
+        provenance_path: provenance_path,
+        listing_path: listing_path,
+        map_path: map_path,
+        source_map_path: source_map_path,
+        c_header_path: c_header_path,
+        rust_source_path: rust_source_path,
+        symbols_json_path: symbols_json_path,
+        sym_path: sym_path,
+        include_dirs: include_dirs,
+        defines: defines,
+        aliases: aliases,
+        scratch: scratch,
+        pseudos: pseudos,
+        march: march
+    })))
+}
+
+
+
+/**
+ * Parses the arguments of the `link` subcommand: one or more .sso object files or .ssa archives and
+ * `-o <output>.sse`.
+ */
+fn parse_link_args(args:&[String]) -> Result<LinkOptions, AssemblerError> {
+    let mut input_paths = vec![];
+    let mut output_path = None;
+    let mut layout_path = None;
+    let mut code_base = None;
+    let mut data_base = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output_path = Some(get_option_value(arg, &mut args)?),
+            "--layout" => layout_path = Some(PathBuf::from(get_option_value(arg, &mut args)?)),
+            "--code-base" => code_base = Some(parse_base_option(arg, get_option_value(arg, &mut args)?)?),
+            "--data-base" => data_base = Some(parse_base_option(arg, get_option_value(arg, &mut args)?)?),
+            flag if flag.starts_with('-') => return Err(AssemblerError::usage(MessageId::UnknownOption, &[flag])),
+            path if path.ends_with(".sso") || path.ends_with(".ssa") => input_paths.push(PathBuf::from(path)),
+            _ => return Err(AssemblerError::usage(MessageId::FileExtension, &[".sso, .ssa"]))
+        }
+    }
+
+    let output_path = match output_path {
+        Some(path) if path.ends_with(".sse") => PathBuf::from(path),
+        Some(_) => return Err(AssemblerError::usage(MessageId::FileExtension, &[".sse"])),
+        None => return Err(AssemblerError::usage(MessageId::Usage, &[]))
+    };
+
+    if input_paths.is_empty() {
+        return Err(AssemblerError::usage(MessageId::Usage, &[]));
+    }
+
+    Ok(LinkOptions {
+        input_paths: input_paths,
+        output_path: output_path,
+        layout_path: layout_path,
+        code_base: code_base,
+        data_base: data_base
+    })
+}
+
+
+/**
+ * Parses the arguments of the `ar` subcommand: the .ssa archive to create followed by the .sso object files
+ * to put in it.
+ */
+fn parse_archive_args(args:&[String]) -> Result<ArchiveOptions, AssemblerError> {
+    let (archive_path, member_paths) = match args.split_first() {
+        Some((archive_path, member_paths)) if !member_paths.is_empty() => (archive_path, member_paths),
+        _ => return Err(AssemblerError::usage(MessageId::Usage, &[]))
+    };
+
+    if !archive_path.ends_with(".ssa") {
+        return Err(AssemblerError::usage(MessageId::FileExtension, &[".ssa"]));
+    }
+
+    if !member_paths.iter().all(|path| path.ends_with(".sso")) {
+        return Err(AssemblerError::usage(MessageId::FileExtension, &[".sso"]));
+    }
+
+    Ok(ArchiveOptions {
+        archive_path: PathBuf::from(archive_path),
+        member_paths: member_paths.iter().map(PathBuf::from).collect()
+    })
+}
+
+
+/**
+ * Parses the arguments of a subcommand which takes the path of a single file with one of the given extensions.
+ */
+fn parse_path_arg(args:&[String], extensions:&[&str]) -> Result<PathBuf, AssemblerError> {
+    match args {
+        [path] if extensions.iter().any(|ext| path.ends_with(ext)) => Ok(PathBuf::from(path)),
+        [_] => Err(AssemblerError::usage(MessageId::FileExtension, &[&extensions.join(", ")])),
+        _ => Err(AssemblerError::usage(MessageId::Usage, &[]))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::emit::{Endian, OutputFormat};
+    use crate::messages::Language;
+    use super::{parse_args, take_language_option, ArchiveOptions, AssembleOptions, Command, LinkOptions};
+
+
+    fn args(args:&[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+
+    fn assemble_options(cmd_args:&[&str]) -> AssembleOptions {
+        match parse_args(&args(cmd_args)).unwrap() {
+            Command::Assemble(options) => *options,
+            command => panic!("Expected assemble command, found {:?}", command)
+        }
+    }
+
+
+    #[test]
+    fn test_parse_args() {
+        let options = assemble_options(&["prog.asm", "prog.sse"]);
+        assert_eq!(options.input_path, PathBuf::from("prog.asm"));
+        assert_eq!(options.outputs, vec![(OutputFormat::Sse, PathBuf::from("prog.sse"))]);
+        assert_eq!(options.march, "sim6");
+        assert!(!options.keep_going);
+        assert!(!options.trace_symbols);
+        assert!(!options.debug_info);
+        assert!(!options.strip);
+        assert!(!options.compat_numbers);
+        assert!(!options.align_data);
+        assert!(!options.strict);
+        assert!(options.keep_symbols.is_empty());
+
+        let options = assemble_options(&["--keep-going", "prog.asm", "prog.sse", "--march", "sim6", "--trace-symbols", "--layout", "rom.ld"]);
+        assert!(options.keep_going);
+        assert!(options.trace_symbols);
+        assert!(assemble_options(&["prog.asm", "prog.sse", "-g"]).debug_info);
+        assert!(assemble_options(&["prog.asm", "prog.sse", "--strip"]).strip);
+        assert!(assemble_options(&["prog.asm", "prog.sse", "--compat-numbers"]).compat_numbers);
+        assert!(assemble_options(&["prog.asm", "prog.sse", "--align-data"]).align_data);
+        assert!(assemble_options(&["prog.asm", "prog.sse", "--strict"]).strict);
+        assert_eq!(assemble_options(&["prog.asm", "prog.sse", "--keep-symbols", "start,table", "--keep-symbols", "loop"]).keep_symbols,
+                   vec![String::from("start"), String::from("table"), String::from("loop")]);
+        assert_eq!(options.layout_path, Some(PathBuf::from("rom.ld")));
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--port-map", "board.ports", "--check-ports"]);
+        assert_eq!((options.port_map_path, options.check_ports), (Some(PathBuf::from("board.ports")), true));
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--code-base", "0x1000", "--data-base", "0b1000000000000000"]);
+        assert_eq!((options.code_base, options.data_base), (Some(0x1000), Some(0x8000)));
+
+        let options = assemble_options(&["prog.asm", "prog.bin", "--bin-base", "0x5000", "--pad-byte", "0"]);
+        assert_eq!(options.outputs, vec![(OutputFormat::Bin, PathBuf::from("prog.bin"))]);
+        assert_eq!((options.bin_base, options.pad_byte), (Some(0x5000), 0x00));
+        assert_eq!(assemble_options(&["prog.asm", "prog.bin"]).pad_byte, 0xFF);
+        assert!(assemble_options(&["prog.asm", "prog.bin", "--split-bytes"]).split_bytes);
+        let options = assemble_options(&["prog.asm", "prog.bin", "--image-size", "0x8000", "--fill", "0x00"]);
+        assert_eq!((options.image_size, options.pad_byte), (Some(0x8000), 0x00));
+        assert_eq!(assemble_options(&["prog.asm", "prog.bin"]).endian, Endian::Big);
+        assert_eq!(assemble_options(&["prog.asm", "prog.bin", "--endian", "little"]).endian, Endian::Little);
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--provenance", "prog.json", "--listing", "prog.lst"]);
+        assert_eq!(options.provenance_path, Some(PathBuf::from("prog.json")));
+        assert_eq!(options.listing_path, Some(PathBuf::from("prog.lst")));
+        assert_eq!(assemble_options(&["prog.asm", "prog.sse", "--map", "prog.map"]).map_path, Some(PathBuf::from("prog.map")));
+        assert_eq!(assemble_options(&["prog.asm", "prog.sse", "--source-map", "prog.json"]).source_map_path, Some(PathBuf::from("prog.json")));
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--emit-c-header", "syms.h", "--emit-rust", "syms.rs"]);
+        assert_eq!(options.c_header_path, Some(PathBuf::from("syms.h")));
+        assert_eq!(options.rust_source_path, Some(PathBuf::from("syms.rs")));
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--emit-symbols-json", "syms.json", "--emit-sym", "prog.sym"]);
+        assert_eq!(options.symbols_json_path, Some(PathBuf::from("syms.json")));
+        assert_eq!(options.sym_path, Some(PathBuf::from("prog.sym")));
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "-I", "lib", "-Iinclude"]);
+        assert_eq!(options.include_dirs, vec![PathBuf::from("lib"), PathBuf::from("include")]);
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "-D", "DEBUG", "-DBOARD=0x2"]);
+        assert_eq!(options.defines, vec![(String::from("DEBUG"), 1), (String::from("BOARD"), 2)]);
+
+        let options = assemble_options(&["prog.asm", "prog.sse", "--alias", "BRA=jump", "--alias", "ldr=mov"]);
+        assert_eq!(options.aliases, vec![(String::from("bra"), String::from("jump")), (String::from("ldr"), String::from("move"))]);
+        assert_eq!(options.scratch, "dx");
+        assert_eq!(assemble_options(&["prog.asm", "prog.sse", "--scratch", "BP"]).scratch, "bp");
+        assert!(options.pseudos);
+        assert!(!assemble_options(&["prog.asm", "prog.sse", "--no-pseudos"]).pseudos);
+
+        let options = assemble_options(&["prog.asm", "prog.sso"]);
+        assert_eq!(options.outputs, vec![(OutputFormat::Sso, PathBuf::from("prog.sso"))]);
+
+        assert_eq!(parse_args(&args(&["--version"])).unwrap(), Command::Version);
+        assert_eq!(parse_args(&args(&["inspect", "prog.sse"])).unwrap(), Command::Inspect(PathBuf::from("prog.sse")));
+        assert_eq!(parse_args(&args(&["inspect", "prog.sso"])).unwrap(), Command::Inspect(PathBuf::from("prog.sso")));
+        assert_eq!(parse_args(&args(&["dump", "prog.sse"])).unwrap(), Command::Dump(PathBuf::from("prog.sse")));
+        assert_eq!(parse_args(&args(&["verify", "prog.sse"])).unwrap(), Command::Verify(PathBuf::from("prog.sse")));
+    }
+
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(parse_args(&args(&["prog.asm"])).is_err());
+        assert!(parse_args(&args(&["prog.txt", "prog.sse"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.hex"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--bad"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--march=sim9"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--march"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "-D", "BOARD=two"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "-D9LIVES"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--alias", "bra"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--scratch", "al"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--alias", "bra=branch"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--alias", "jmp=call"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--code-base", "0x10000"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--data-base"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.bin", "--pad-byte", "0x100"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.bin", "--image-size", "0x10001"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.bin", "--image-size", "0"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.bin", "--endian", "middle"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sso", "--endian", "little"])).is_err());
+        assert!(parse_args(&args(&["inspect", "prog.asm"])).is_err());
+        assert!(parse_args(&args(&["inspect"])).is_err());
+        assert!(parse_args(&args(&["dump", "prog.sso"])).is_err());
+        assert!(parse_args(&args(&["verify", "prog.sso"])).is_err());
+    }
+
+
+    #[test]
+    fn test_format_options() {
+        let options = assemble_options(&["prog.asm", "build/prog.sse", "--format=sse", "--format", "memh", "--format=srec:rom.srec"]);
+        assert_eq!(options.outputs, vec![
+            (OutputFormat::Sse, PathBuf::from("build/prog.sse")),
+            (OutputFormat::Memh, PathBuf::from("build/prog.memh")),
+            (OutputFormat::Srec, PathBuf::from("rom.srec"))
+        ]);
+
+        let options = assemble_options(&["prog.asm", "--format=memh:prog.hex"]);
+        assert_eq!(options.outputs, vec![(OutputFormat::Memh, PathBuf::from("prog.hex"))]);
+
+        assert!(parse_args(&args(&["prog.asm", "--format=memh"])).is_err());
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--format=hex"])).is_err());
+
+        let err = parse_args(&args(&["prog.asm", "--format=memh:rom.hex", "--format=ihex:rom.hex"])).unwrap_err();
+        assert_eq!(err.to_string(), "--format=memh and --format=ihex are both written to 'rom.hex', so one would overwrite the other");
+        assert!(parse_args(&args(&["prog.asm", "prog.sse", "--format=sse", "--format=sse"])).is_err());
+    }
+
+
+    #[test]
+    fn test_link_args() {
+        assert_eq!(parse_args(&args(&["link", "a.sso", "b.sso", "-o", "prog.sse"])).unwrap(), Command::Link(LinkOptions {
+            input_paths: vec![PathBuf::from("a.sso"), PathBuf::from("b.sso")],
+            output_path: PathBuf::from("prog.sse"),
+            layout_path: None,
+            code_base: None,
+            data_base: None
+        }));
+
+        match parse_args(&args(&["link", "a.sso", "-o", "prog.sse", "--code-base", "0x0000", "--data-base", "49152"])).unwrap() {
+            Command::Link(options) => assert_eq!((options.code_base, options.data_base), (Some(0), Some(0xC000))),
+            command => panic!("Expected link command, found {:?}", command)
+        }
+
+        assert!(parse_args(&args(&["link", "a.sso"])).is_err());
+        assert!(parse_args(&args(&["link", "-o", "prog.sse"])).is_err());
+        assert!(parse_args(&args(&["link", "a.asm", "-o", "prog.sse"])).is_err());
+        assert!(parse_args(&args(&["link", "a.sso", "-o", "prog.sso"])).is_err());
+        assert!(parse_args(&args(&["link", "a.sso", "libmath.ssa", "-o", "prog.sse"])).is_ok());
+    }
+
+
+    #[test]
+    fn test_archive_args() {
+        assert_eq!(parse_args(&args(&["ar", "libmath.ssa", "add.sso", "mul.sso"])).unwrap(), Command::Archive(ArchiveOptions {
+            archive_path: PathBuf::from("libmath.ssa"),
+            member_paths: vec![PathBuf::from("add.sso"), PathBuf::from("mul.sso")]
+        }));
+
+        assert!(parse_args(&args(&["ar", "libmath.ssa"])).is_err());
+        assert!(parse_args(&args(&["ar", "libmath.a", "add.sso"])).is_err());
+        assert!(parse_args(&args(&["ar", "libmath.ssa", "add.asm"])).is_err());
+    }
+
+
+    #[test]
+    fn test_language_option() {
+        let mut cmd_args = args(&["prog.asm", "--lang", "es", "prog.sse"]);
+        assert_eq!(take_language_option(&mut cmd_args).unwrap(), Some(Language::Spanish));
+        assert_eq!(cmd_args, args(&["prog.asm", "prog.sse"]));
+
+        let mut cmd_args = args(&["--lang=en", "dump", "prog.sse"]);
+        assert_eq!(take_language_option(&mut cmd_args).unwrap(), Some(Language::English));
+        assert_eq!(cmd_args, args(&["dump", "prog.sse"]));
+
+        assert_eq!(take_language_option(&mut args(&["prog.asm", "prog.sse"])).unwrap(), None);
+        assert!(take_language_option(&mut args(&["prog.asm", "--lang", "fr"])).is_err());
+        assert!(take_language_option(&mut args(&["prog.asm", "--lang"])).is_err());
+    }
+}
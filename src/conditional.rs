@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::constants::ConstantTable;
+use crate::defines::DefineTable;
+use crate::error::AssemblerError;
+use crate::expr::evaluate;
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionalError {
+    UnexpectedDirectiveError(String),
+    DirectiveAfterElseError(String),
+    UnterminatedConditionalError,
+    MissingNameError(String)
+}
+
+impl Error for ConditionalError {}
+
+impl fmt::Display for ConditionalError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConditionalError::UnexpectedDirectiveError(directive) => write!(f, "{}", message(MessageId::UnexpectedConditional, &[directive])),
+            ConditionalError::DirectiveAfterElseError(directive) => write!(f, "{}", message(MessageId::ConditionalAfterElse, &[directive])),
+            ConditionalError::UnterminatedConditionalError => write!(f, "{}", message(MessageId::UnterminatedConditional, &[])),
+            ConditionalError::MissingNameError(directive) => write!(f, "{}", message(MessageId::ConditionalExpectsName, &[directive]))
+        }
+    }
+}
+
+
+/**
+ * The state of an `.if` block which has been opened but not yet closed by its `.endif`.
+ */
+struct Conditional {
+    line: SourceLine,
+    enclosing_active: bool,
+    taken: bool,
+    active: bool,
+    seen_else: bool
+}
+
+
+/**
+ * Evaluates the condition of an `.if` or `.elif` line, where any non-zero value is true, or of an `.ifdef` or
+ * `.ifndef` line, which tests whether the name it gives is a constant or has been given a `.define`.
+ */
+fn evaluate_condition(line:&SourceLine, directive:&str, constants:&ConstantTable, defines:&DefineTable) -> Result<bool, AssemblerError> {
+    let condition = &line.text.trim()[directive.len()..];
+    if directive == ".ifdef" || directive == ".ifndef" {
+        return match condition.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [name] => Ok((constants.values().contains_key(*name) || defines.contains(name)) == (directive == ".ifdef")),
+            _ => Err(line.error(Box::new(ConditionalError::MissingNameError(directive.to_string()))))
+        };
+    }
+
+    match evaluate(&defines.substitute(condition), constants.values()) {
+        Ok(value) => Ok(value != 0),
+        Err(err) => Err(line.error(Box::new(err)))
+    }
+}
+
+
+/**
+ * The `.if` blocks which enclose the line being read, from the outermost in. Lines in a branch which is not
+ * taken are dropped, and conditions are only evaluated for branches which could be taken.
+ */
+#[derive(Default)]
+pub struct ConditionalStack {
+    conditionals: Vec<Conditional>
+}
+
+impl ConditionalStack {
+    /**
+     * Whether every enclosing block is in a branch which is taken, so the line being read is kept.
+     */
+    pub fn active(&self) -> bool {
+        self.conditionals.last().map(|conditional| conditional.active).unwrap_or(true)
+    }
+
+
+    /**
+     * Applies the line if it is an `.if`, `.ifdef`, `.ifndef`, `.elif`, `.else` or `.endif` directive, and
+     * returns whether it was one.
+     */
+    pub fn apply(&mut self, line:&SourceLine, constants:&ConstantTable, defines:&DefineTable) -> Result<bool, AssemblerError> {
+        let active = self.active();
+        let directive = line.text.split_whitespace().next().unwrap_or("").to_string();
+        let unexpected = || line.error(Box::new(ConditionalError::UnexpectedDirectiveError(directive.to_owned())));
+
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let condition = active && evaluate_condition(line, &directive, constants, defines)?;
+                self.conditionals.push(Conditional { line: line.to_owned(), enclosing_active: active, taken: condition, active: condition, seen_else: false });
+            },
+
+            ".elif" | ".else" => {
+                let conditional = match self.conditionals.last_mut() {
+                    Some(conditional) => conditional,
+                    None => return Err(unexpected())
+                };
+
+                if conditional.seen_else {
+                    return Err(line.error(Box::new(ConditionalError::DirectiveAfterElseError(directive))));
+                }
+
+                let condition = match directive.as_str() {
+                    ".elif" => conditional.enclosing_active && !conditional.taken && evaluate_condition(line, &directive, constants, defines)?,
+                    _ => {
+                        conditional.seen_else = true;
+                        conditional.enclosing_active && !conditional.taken
+                    }
+                };
+
+                conditional.active = condition;
+                conditional.taken |= condition;
+            },
+
+            ".endif" => if self.conditionals.pop().is_none() {
+                return Err(unexpected());
+            },
+
+            _ => return Ok(false)
+        }
+
+        Ok(true)
+    }
+
+
+    /**
+     * Checks that every block has been closed by its `.endif` once the last line has been read.
+     */
+    pub fn finish(mut self) -> Result<(), AssemblerError> {
+        match self.conditionals.pop() {
+            Some(conditional) => Err(conditional.line.error(Box::new(ConditionalError::UnterminatedConditionalError))),
+            None => Ok(())
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::preprocess::preprocess;
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    fn texts(source:&str, defines:&[(String, i64)]) -> Vec<String> {
+        preprocess(to_source_lines(source), Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::from_defines(defines)).unwrap().iter().map(|line| line.text.trim().to_string()).collect()
+    }
+
+
+    #[test]
+    fn test_apply_conditionals() {
+        let symbols = [(String::from("STACK_SIZE"), 512), (String::from("BOARD"), 2)];
+        let source = ".if STACK_SIZE > 256\n  big\n.elif STACK_SIZE > 128\n  medium\n.else\n  small\n.endif\nalways";
+        assert_eq!(texts(source, &symbols), vec!["big", "always"]);
+
+        let source = ".if BOARD == 1\n  one\n.elif BOARD == 2\n  two\n  .if 0\n    never\n  .else\n    nested\n  .endif\n.else\n  other\n.endif";
+        assert_eq!(texts(source, &symbols), vec!["two", "nested"]);
+
+        // conditions in branches which cannot be taken are never evaluated
+        assert_eq!(texts(".if 0\n.if UNDEFINED\nx\n.endif\n.elif 1\ny\n.elif UNDEFINED\n.endif", &[]), vec!["y"]);
+
+        // constants are only defined in branches which are taken, and can be tested by later conditions
+        let source = ".if 0\nSIZE: .equ 1\n.else\nSIZE: .equ 300\n.endif\n.if SIZE > 256\n  movi ax SIZE\n.endif";
+        assert_eq!(texts(source, &[]), vec!["movi ax 300"]);
+
+        let source = ".define REG dx\n.define LIMIT 0x100\nSIZE: .equ LIMIT * 2\n.ifdef REG\n.if SIZE > LIMIT\n  movi REG SIZE\n.endif\n.endif";
+        assert_eq!(texts(source, &[]), vec!["movi dx 512"]);
+
+        let source = ".ifdef BOARD\n  board\n.endif\n.ifndef DEBUG\n  release\n.else\n  debug\n.endif";
+        assert_eq!(texts(source, &symbols), vec!["board", "release"]);
+    }
+
+
+    #[test]
+    fn test_invalid_conditionals() {
+        let error = |source:&str| preprocess(to_source_lines(source), Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::default()).unwrap_err().to_string();
+
+        assert_eq!(error("nop\n.if 1\nnop"), "line 2: '.if' has no matching '.endif'");
+        assert_eq!(error(".endif"), "line 1: '.endif' without a matching '.if'");
+        assert_eq!(error(".else"), "line 1: '.else' without a matching '.if'");
+        assert_eq!(error(".if 1\n.else\n.elif 1\n.endif"), "line 3: '.elif' cannot follow '.else'");
+        assert_eq!(error(".if STACK_SIZE > 256\n.endif"), "line 1: Symbol 'STACK_SIZE' is not defined");
+        assert_eq!(error(".if\n.endif"), "line 1: '' is not a valid expression");
+        assert_eq!(error(".ifdef\n.endif"), "line 1: '.ifdef' expects a symbol name");
+        assert_eq!(error(".ifndef STACK_SIZE\n  .error \"STACK_SIZE must be defined\"\n.endif"), "line 2: STACK_SIZE must be defined");
+    }
+}
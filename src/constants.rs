@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::evaluate;
+use crate::messages::{message, MessageId};
+use crate::source::{replace_names, SourceLine};
+use crate::validation::validate_label;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstantError {
+    RedefinedConstantError(String),
+    LabelClashError(String)
+}
+
+impl Error for ConstantError {}
+
+impl fmt::Display for ConstantError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstantError::RedefinedConstantError(name) => write!(f, "{}", message(MessageId::RedefinedConstant, &[name])),
+            ConstantError::LabelClashError(name) => write!(f, "{}", message(MessageId::ConstantLabelClash, &[name]))
+        }
+    }
+}
+
+
+/**
+ * Returns the name, directive and value expression of a constant definition such as `SCREEN_PORT: .equ 0x10`,
+ * or `None` if the line is not one.
+ */
+fn parse_constant_definition(line:&str) -> Option<(&str, &str, &str)> {
+    let (name, rest) = line.split_once(':')?;
+    let rest = rest.trim_start();
+    let directive = rest.split_whitespace().next()?;
+
+    match directive {
+        ".equ" | ".set" => Some((name.trim(), directive, &rest[directive.len()..])),
+        _ => None
+    }
+}
+
+
+pub fn is_constant_definition(line:&str) -> bool {
+    parse_constant_definition(line).is_some()
+}
+
+
+/**
+ * The assembler constants, which live in their own namespace beside the label table. A constant defined with
+ * `.set` may be given a new value by a later `.set`, but one defined with `.equ` or with `-D` on the command
+ * line keeps its value. Constants are defined in the order their lines are assembled, so a use of a constant
+ * sees the value it had at that point in the source.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConstantTable {
+    values: HashMap<String, i64>,
+    definitions: HashMap<String, (bool, Option<SourceLine>)>
+}
+
+impl ConstantTable {
+    /**
+     * Creates a table holding the symbols defined on the command line.
+     */
+    pub fn from_defines(defines:&[(String, i64)]) -> ConstantTable {
+        ConstantTable {
+            values: defines.iter().cloned().collect(),
+            definitions: defines.iter().map(|(name, _)| (name.to_owned(), (false, None))).collect()
+        }
+    }
+
+
+    pub fn values(&self) -> &HashMap<String, i64> {
+        &self.values
+    }
+
+
+    /**
+     * Defines the constant given by a `.equ` or `.set` line, evaluating its value against the constants
+     * defined so far.
+     */
+    pub fn define(&mut self, line:&SourceLine) -> Result<(), AssemblerError> {
+        let (name, directive, expression) = match parse_constant_definition(line.text.trim()) {
+            Some(definition) => definition,
+            None => return Ok(())
+        };
+
+        validate_label(name).map_err(|err| line.error(err))?;
+        let redefinable = directive == ".set";
+        if let Some((existing_redefinable, _)) = self.definitions.get(name) {
+            if !(redefinable && *existing_redefinable) {
+                return Err(line.error(Box::new(ConstantError::RedefinedConstantError(name.to_string()))));
+            }
+        }
+
+        let value = evaluate(expression, &self.values).map_err(|err| line.error(Box::new(err)))?;
+        self.values.insert(name.to_string(), value);
+        self.definitions.insert(name.to_string(), (redefinable, Some(line.to_owned())));
+        Ok(())
+    }
+
+
+    /**
+     * Replaces every use of a constant in the text with its value. Label definitions, label references
+     * starting with `@`, directives, and the contents of strings are left as they are.
+     */
+    pub fn substitute(&self, text:&str) -> String {
+        if self.values.is_empty() {
+            return text.to_string();
+        }
+
+        replace_names(text, |name, previous, rest| match previous == '@' || rest.starts_with(':') {
+            true => None,
+            false => self.values.get(name).map(|value| value.to_string())
+        })
+    }
+
+
+    /**
+     * Checks that no constant defined in the source shares its name with a label.
+     */
+    pub fn check_labels(&self, label_table:&HashMap<String, usize>) -> Result<(), AssemblerError> {
+        for (name, (_, line)) in &self.definitions {
+            if let (true, Some(line)) = (label_table.contains_key(name), line) {
+                return Err(line.error(Box::new(ConstantError::LabelClashError(name.to_owned()))));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::SourceLine;
+    use super::*;
+
+
+    #[test]
+    fn test_define_constants() {
+        let mut constants = ConstantTable::from_defines(&[(String::from("BOARD"), 2)]);
+        constants.define(&SourceLine::new("SCREEN_PORT: .equ 0x10", 1)).unwrap();
+        constants.define(&SourceLine::new("  OFFSET: .set SCREEN_PORT + BOARD", 2)).unwrap();
+        constants.define(&SourceLine::new("OFFSET: .set OFFSET * 2", 3)).unwrap();
+
+        assert_eq!(constants.values()["SCREEN_PORT"], 16);
+        assert_eq!(constants.values()["OFFSET"], 36);
+        assert!(is_constant_definition("X: .equ 1"));
+        assert!(!is_constant_definition("x: .byte 1"));
+    }
+
+
+    #[test]
+    fn test_redefine_constants() {
+        let mut constants = ConstantTable::from_defines(&[(String::from("BOARD"), 2)]);
+        constants.define(&SourceLine::new("PORT: .equ 1", 1)).unwrap();
+        constants.define(&SourceLine::new("COUNT: .set 1", 2)).unwrap();
+
+        let error = |constants:&mut ConstantTable, text:&str| constants.define(&SourceLine::new(text, 4)).unwrap_err().to_string();
+        assert_eq!(error(&mut constants, "PORT: .equ 2"), "line 4: Constant 'PORT' is already defined, and only a constant defined with .set can be redefined with .set");
+        assert!(constants.define(&SourceLine::new("PORT: .set 2", 4)).is_err());
+        assert!(constants.define(&SourceLine::new("COUNT: .equ 2", 4)).is_err());
+        assert!(constants.define(&SourceLine::new("BOARD: .set 3", 4)).is_err());
+        assert!(constants.define(&SourceLine::new("ax: .equ 3", 4)).is_err());
+
+        let label_table = HashMap::from([(String::from("COUNT"), 0x5800)]);
+        assert_eq!(constants.check_labels(&label_table).unwrap_err().to_string(), "line 2: 'COUNT' is defined as both a constant and a label");
+    }
+
+
+    #[test]
+    fn test_substitute_constants() {
+        let mut constants = ConstantTable::default();
+        constants.define(&SourceLine::new("PORT: .equ 0x10", 1)).unwrap();
+        constants.define(&SourceLine::new("data: .equ 7", 2)).unwrap();
+
+        assert_eq!(constants.substitute("out ax, PORT"), "out ax, 16");
+        assert_eq!(constants.substitute("loop: movi bx @PORT"), "loop: movi bx @PORT");
+        assert_eq!(constants.substitute("PORT: .byte PORT"), "PORT: .byte 16");
+        assert_eq!(constants.substitute(".data:"), ".data:");
+        assert_eq!(constants.substitute("msg: .asciiz `PORT data` PORTS"), "msg: .asciiz `PORT data` PORTS");
+        assert_eq!(constants.substitute(".print \"PORT is\", PORT"), ".print \"PORT is\", 16");
+        assert_eq!(constants.substitute("movi al, 'data' + data"), "movi al, 'data' + 7");
+        assert_eq!(constants.substitute("msg: .asciiz `say \\`data\\` PORT`"), "msg: .asciiz `say \\`data\\` PORT`");
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::messages::{message, MessageId};
+use crate::source::{replace_names, SourceLine};
+use crate::validation::validate_label;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefineError {
+    MissingNameError,
+    RedefinedError(String)
+}
+
+impl Error for DefineError {}
+
+impl fmt::Display for DefineError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefineError::MissingNameError => write!(f, "{}", message(MessageId::DefineExpectsName, &[])),
+            DefineError::RedefinedError(name) => write!(f, "{}", message(MessageId::RedefinedDefine, &[name]))
+        }
+    }
+}
+
+
+pub fn is_define_directive(line:&str) -> bool {
+    line.split_whitespace().next() == Some(".define")
+}
+
+
+/**
+ * The names defined with `.define NAME replacement`, each of which is replaced by its replacement text
+ * wherever it appears as a whole name on later lines, before the line is parsed. Unlike a constant, the
+ * replacement can be any text, such as a register name or part of an operand.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DefineTable {
+    replacements: HashMap<String, String>
+}
+
+impl DefineTable {
+    pub fn contains(&self, name:&str) -> bool {
+        self.replacements.contains_key(name)
+    }
+
+
+    /**
+     * Defines the name given by a `.define` line. Names in the replacement which were defined earlier are
+     * replaced straight away, so a replacement never needs expanding more than once.
+     */
+    pub fn define(&mut self, line:&SourceLine) -> Result<(), AssemblerError> {
+        let text = line.text.trim();
+        let rest = text[".define".len()..].trim_start();
+        let name = match rest.split_whitespace().next() {
+            Some(name) => name,
+            None => return Err(line.error(Box::new(DefineError::MissingNameError)))
+        };
+
+        validate_label(name).map_err(|err| line.error(err))?;
+        if self.replacements.contains_key(name) {
+            return Err(line.error(Box::new(DefineError::RedefinedError(name.to_string()))));
+        }
+
+        let replacement = self.substitute(rest[name.len()..].trim());
+        self.replacements.insert(name.to_string(), replacement);
+        Ok(())
+    }
+
+
+    /**
+     * Replaces every defined name in the text with its replacement, leaving directives and the contents of
+     * strings as they are.
+     */
+    pub fn substitute(&self, text:&str) -> String {
+        if self.replacements.is_empty() {
+            return text.to_string();
+        }
+
+        replace_names(text, |name, _, _| self.replacements.get(name).cloned())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_define_substitution() {
+        let mut defines = DefineTable::default();
+        defines.define(&SourceLine::new(".define LED_MASK 0b00011000", 1)).unwrap();
+        defines.define(&SourceLine::new(".define COUNTER cx", 2)).unwrap();
+        defines.define(&SourceLine::new(".define CLEAR_COUNTER sub COUNTER COUNTER", 3)).unwrap();
+        defines.define(&SourceLine::new(".define DEBUG", 4)).unwrap();
+
+        assert_eq!(defines.substitute("movi COUNTER LED_MASK"), "movi cx 0b00011000");
+        assert_eq!(defines.substitute("  start: CLEAR_COUNTER"), "  start: sub cx cx");
+        assert_eq!(defines.substitute("msg: .asciiz `COUNTER` COUNTERS"), "msg: .asciiz `COUNTER` COUNTERS");
+        assert!(defines.contains("DEBUG"));
+        assert!(is_define_directive(".define X 1"));
+    }
+
+
+    #[test]
+    fn test_invalid_defines() {
+        let mut defines = DefineTable::default();
+        defines.define(&SourceLine::new(".define X 1", 1)).unwrap();
+
+        assert_eq!(defines.define(&SourceLine::new(".define X 2", 2)).unwrap_err().to_string(), "line 2: 'X' is already defined with .define");
+        assert_eq!(defines.define(&SourceLine::new(".define", 3)).unwrap_err().to_string(), "line 3: '.define' expects a name");
+        assert!(defines.define(&SourceLine::new(".define ax bx", 4)).is_err());
+    }
+}
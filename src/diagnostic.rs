@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::evaluate;
+use crate::label_table::get_line_placements;
+use crate::layout::MemoryLayout;
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+
+
+/**
+ * A diagnostic written in the source with `.error` or `.warning`, holding the directive and its text.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserDiagnostic {
+    pub directive: String,
+    pub text: String
+}
+
+impl Error for UserDiagnostic {}
+
+impl fmt::Display for UserDiagnostic {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self.text.is_empty() {
+            true => write!(f, "{}", message(MessageId::EmptyUserDiagnostic, &[&self.directive])),
+            false => write!(f, "{}", self.text)
+        }
+    }
+}
+
+
+/**
+ * Returns true if the line is an `.error` or `.warning` directive.
+ */
+pub fn is_diagnostic_directive(line:&str) -> bool {
+    match line.split_whitespace().next() {
+        Some(".error") | Some(".warning") => true,
+        _ => false
+    }
+}
+
+
+/**
+ * Returns true if the line is a `.print` directive.
+ */
+pub fn is_print_directive(line:&str) -> bool {
+    line.split_whitespace().next() == Some(".print")
+}
+
+
+/**
+ * Returns the text of a string wrapped in double quotes or backticks, or `None` if the text is not one.
+ */
+fn unquote(text:&str) -> Option<&str> {
+    match (text.chars().next(), text.chars().last()) {
+        (Some('"'), Some('"')) | (Some('`'), Some('`')) if text.len() >= 2 => Some(&text[1..text.len() - 1]),
+        _ => None
+    }
+}
+
+
+/**
+ * Splits the operands of a `.print` directive on the commas which are not inside a string.
+ */
+fn split_print_items(text:&str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quote:Option<char> = None;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '`') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, ',') => {
+                items.push(text[start..index].trim());
+                start = index + 1;
+            },
+            _ => {}
+        }
+    }
+
+    items.push(text[start..].trim());
+    items.into_iter().filter(|item| !item.is_empty()).collect()
+}
+
+
+/**
+ * Takes the lines of a source file and returns the message of every `.print` directive in it, such as
+ * `.print "data ends at", $`. Each operand is either a string, which is printed as it is, or an expression
+ * over the labels, in which `$` is the address the directive is placed at, and which is printed in hex.
+ */
+pub fn get_print_messages(source_lines:&[SourceLine], label_table:&HashMap<String, usize>, layout:&MemoryLayout) -> Result<Vec<String>, AssemblerError> {
+    let mut symbols:HashMap<String, i64> = label_table.iter().map(|(label, address)| (label.to_owned(), *address as i64)).collect();
+    let mut messages = vec![];
+
+    for (source_line, _, address) in get_line_placements(source_lines, layout) {
+        let text = source_line.text.trim();
+        if !is_print_directive(text) {
+            continue;
+        }
+
+        symbols.insert(String::from("$"), address as i64);
+        let mut parts = vec![];
+        for item in split_print_items(&text[".print".len()..]) {
+            match unquote(item) {
+                Some(string) => parts.push(string.to_string()),
+                None => {
+                    let value = evaluate(item, &symbols).map_err(|err| source_line.error(Box::new(err)))?;
+                    parts.push(format!("0x{:04X}", value));
+                }
+            }
+        }
+
+        messages.push(parts.join(" "));
+    }
+
+    Ok(messages)
+}
+
+
+/**
+ * Reports the `.error` or `.warning` directive on the line. An error stops the assembly like any other error
+ * in the source, while a warning is printed and assembly carries on. The text may be wrapped in double quotes
+ * or backticks.
+ */
+pub fn report_diagnostic(line:&SourceLine) -> Result<(), AssemblerError> {
+    let text = line.text.trim();
+    let directive = text.split_whitespace().next().unwrap_or("");
+    let value = text[directive.len()..].trim();
+    let value = unquote(value).unwrap_or(value);
+
+    let diagnostic = UserDiagnostic { directive: directive.to_string(), text: value.to_string() };
+    match directive {
+        ".error" => Err(line.error(Box::new(diagnostic))),
+        _ => {
+            eprintln!("warning: {}", line.error(Box::new(diagnostic)));
+            Ok(())
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::error::EXIT_DATA_ERROR;
+    use crate::label_table::{get_label_definitions, get_label_table};
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    #[test]
+    fn test_report_diagnostic() {
+        let err = report_diagnostic(&SourceLine::new("  .error \"STACK_SIZE must be defined\"", 7)).unwrap_err();
+        assert_eq!(err.to_string(), "line 7: STACK_SIZE must be defined");
+        assert_eq!(err.exit_code(), EXIT_DATA_ERROR);
+
+        assert_eq!(report_diagnostic(&SourceLine::new(".error", 2)).unwrap_err().to_string(), "line 2: '.error' directive reached");
+        assert!(report_diagnostic(&SourceLine::new(".warning `slow path enabled`", 3)).is_ok());
+        assert!(is_diagnostic_directive(".warning x"));
+        assert!(!is_diagnostic_directive(".errors"));
+    }
+
+
+    #[test]
+    fn test_print_messages() {
+        let layout = MemoryLayout::default();
+        let source_lines = to_source_lines(".data:\nx: .word 1\n.print \"data ends at\", $\n.code:\nstart: nop\n.print `start, size:`, start, $ - start");
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+
+        assert_eq!(get_print_messages(&source_lines, &label_table, &layout).unwrap(), vec!["data ends at 0x9002", "start, size: 0x5800 0x0002"]);
+        assert_eq!(get_print_messages(&to_source_lines(".print missing"), &label_table, &layout).unwrap_err().to_string(), "line 1: Symbol 'missing' is not defined");
+    }
+}
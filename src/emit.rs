@@ -0,0 +1,499 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::layout::{LayoutError, MemoryLayout};
+
+
+/**
+ * The kinds of file the assembler can write.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Sse,
+    Sso,
+    Memh,
+    Srec,
+    Bin,
+    Ihex,
+    Readmemh,
+    Logisim,
+    Coe,
+    Mif
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = String;
+
+    /**
+     * Takes a format name, which is also the file extension used for it, and returns the format.
+     */
+    fn try_from(name:&str) -> Result<OutputFormat, String> {
+        match name.to_lowercase().as_str() {
+            "sse" => Ok(OutputFormat::Sse),
+            "sso" => Ok(OutputFormat::Sso),
+            "memh" => Ok(OutputFormat::Memh),
+            "srec" => Ok(OutputFormat::Srec),
+            "bin" => Ok(OutputFormat::Bin),
+            "ihex" => Ok(OutputFormat::Ihex),
+            "readmemh" => Ok(OutputFormat::Readmemh),
+            "logisim" => Ok(OutputFormat::Logisim),
+            "coe" => Ok(OutputFormat::Coe),
+            "mif" => Ok(OutputFormat::Mif),
+            _ => Err(name.to_string())
+        }
+    }
+}
+
+impl OutputFormat {
+    /**
+     * Returns the file extension, without a leading dot, used for the format.
+     */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Sse => "sse",
+            OutputFormat::Sso => "sso",
+            OutputFormat::Memh => "memh",
+            OutputFormat::Srec => "srec",
+            OutputFormat::Bin => "bin",
+            OutputFormat::Ihex => "ihex",
+            OutputFormat::Readmemh => "readmemh",
+            OutputFormat::Logisim => "logisim",
+            OutputFormat::Coe => "coe",
+            OutputFormat::Mif => "mif"
+        }
+    }
+}
+
+
+/**
+ * The byte order words are written in. The assembler encodes instructions and `.word` and `.long` values
+ * big-endian unless asked for little-endian output.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little
+}
+
+impl TryFrom<&str> for Endian {
+    type Error = String;
+
+    /**
+     * Takes the name of a byte order, `big` or `little`, and returns it.
+     */
+    fn try_from(name:&str) -> Result<Endian, String> {
+        match name.to_lowercase().as_str() {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            _ => Err(name.to_string())
+        }
+    }
+}
+
+
+/**
+ * Pairs each section's name with its load address in the layout and its bytes: the code section, the data
+ * section, and then the named sections in the order the layout places them.
+ */
+fn placed_sections<'a>(data:&'a [u8], code:&'a [u8], custom:&'a [Vec<u8>], layout:&'a MemoryLayout) -> Vec<(&'a str, usize, &'a [u8])> {
+    let mut sections = vec![("code", layout.code.origin, code), ("data", layout.data.origin, data)];
+    sections.extend(layout.sections.iter().zip(custom).map(|((name, region), bytes)| (name.as_str(), region.origin, bytes.as_slice())));
+    sections
+}
+
+
+/**
+ * Writes the sections as a hex memory image which can be loaded with Verilog's `$readmemh` into a memory
+ * which is a byte wide. Each section starts with an `@address` line giving its load address in the layout,
+ * followed by its bytes 16 to a line.
+ */
+pub fn to_memh(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> String {
+    let mut text = String::new();
+
+    for (_, base_address, section) in placed_sections(data, code, custom, layout) {
+        if section.is_empty() {
+            continue;
+        }
+
+        writeln!(text, "@{:04X}", base_address).unwrap();
+        for row in section.chunks(16) {
+            let bytes:Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+            writeln!(text, "{}", bytes.join(" ")).unwrap();
+        }
+    }
+
+    text
+}
+
+
+/**
+ * Writes the sections as 16-bit words for Verilog's `$readmemh`, for memories which are a word wide. Each
+ * section starts with an `@address` line giving the word address it is loaded at, followed by one big-endian
+ * word per line. A section which starts or ends part way through a word is padded with zero bytes.
+ */
+pub fn to_readmemh(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> String {
+    let mut text = String::new();
+
+    for (_, base_address, section) in placed_sections(data, code, custom, layout) {
+        if section.is_empty() {
+            continue;
+        }
+
+        let mut bytes = vec![0x00; base_address % 2];
+        bytes.extend_from_slice(section);
+        if bytes.len() % 2 == 1 {
+            bytes.push(0x00);
+        }
+
+        writeln!(text, "@{:04X}", base_address / 2).unwrap();
+        for word in bytes.chunks(2) {
+            writeln!(text, "{:02X}{:02X}", word[0], word[1]).unwrap();
+        }
+    }
+
+    text
+}
+
+
+/**
+ * Formats a single S-record of the given type, adding the byte count and the checksum, which is the ones'
+ * complement of the low byte of the sum of the count, address, and data bytes.
+ */
+fn srec_record(record_type:u8, address:u16, data:&[u8]) -> String {
+    let count = (data.len() + 3) as u8;
+    let mut bytes = vec![count];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.extend_from_slice(data);
+
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    let hex:String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("S{}{}{:02X}\n", record_type, hex, !sum)
+}
+
+
+/**
+ * Writes the sections as Motorola S-records with 16-bit addresses: an S0 header holding `name`, an S1 record
+ * for every 16 bytes of each section at its load address in the layout, an S5 record count, and an S9 record
+ * giving the entry point, which is the start of the code section unless `entry` is given. Returns an error if
+ * a record or the entry point lies past the 16-bit address space.
+ */
+pub fn to_srec(data:&[u8], code:&[u8], custom:&[Vec<u8>], name:&str, entry:Option<usize>, layout:&MemoryLayout) -> Result<String, LayoutError> {
+    let srec_address = |address:usize| u16::try_from(address).map_err(|_| LayoutError::AddressOverflowError(String::from("srec"), address));
+    let mut text = srec_record(0, 0, name.as_bytes());
+    let mut record_count:u16 = 0;
+
+    for (_, base_address, section) in placed_sections(data, code, custom, layout) {
+        for (index, row) in section.chunks(16).enumerate() {
+            text.push_str(&srec_record(1, srec_address(base_address + index * 16)?, row));
+            record_count += 1;
+        }
+    }
+
+    text.push_str(&srec_record(5, record_count, &[]));
+    text.push_str(&srec_record(9, srec_address(entry.unwrap_or(layout.code.origin))?, &[]));
+    Ok(text)
+}
+
+
+/**
+ * Formats a single Intel HEX record, adding the byte count and the checksum, which is the two's complement of
+ * the low byte of the sum of the count, address, type, and data bytes.
+ */
+fn ihex_record(record_type:u8, address:u16, data:&[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    let hex:String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}{:02X}\n", hex, sum.wrapping_neg())
+}
+
+
+/**
+ * Writes the sections as Intel HEX: a data record for every 16 bytes of each section at its load address in
+ * the layout, a start segment address record giving the entry point, which is the start of the code section
+ * unless `entry` is given, and an end of file record.
+ */
+pub fn to_ihex(data:&[u8], code:&[u8], custom:&[Vec<u8>], entry:Option<usize>, layout:&MemoryLayout) -> String {
+    let mut text = String::new();
+    for (_, base_address, section) in placed_sections(data, code, custom, layout) {
+        for (index, row) in section.chunks(16).enumerate() {
+            text.push_str(&ihex_record(0x00, (base_address + index * 16) as u16, row));
+        }
+    }
+
+    // the start segment address is CS:IP, and the entry point fits in IP alone
+    let entry = entry.unwrap_or(layout.code.origin) as u16;
+    let mut start_address = vec![0x00, 0x00];
+    start_address.extend_from_slice(&entry.to_be_bytes());
+    text.push_str(&ihex_record(0x03, 0, &start_address));
+    text.push_str(&ihex_record(0x01, 0, &[]));
+    text
+}
+
+
+/**
+ * Writes the sections as a flat memory image, such as to burn into an EEPROM. The image starts at
+ * `base_address`, or at the lowest address of any section if it is not given, and ends at the last byte of
+ * the highest section, or is padded to `image_size` bytes if it is given, with the gaps between sections filled
+ * with `pad_byte`. Returns an error if a section lies below the base address or past the end of the image.
+ */
+pub fn to_bin(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout, base_address:Option<usize>, image_size:Option<usize>,
+              pad_byte:u8) -> Result<Vec<u8>, LayoutError> {
+    let sections:Vec<(&str, usize, &[u8])> = placed_sections(data, code, custom, layout).into_iter().filter(|(_, _, section)| !section.is_empty()).collect();
+    let base_address = match base_address {
+        Some(base_address) => base_address,
+        None => sections.iter().map(|(_, address, _)| *address).min().unwrap_or(0)
+    };
+
+    let mut image = vec![];
+    for (name, address, section) in sections {
+        if address < base_address {
+            return Err(LayoutError::SectionBelowImageBaseError(name.to_string(), address, base_address));
+        }
+
+        let offset = address - base_address;
+        if image.len() < offset + section.len() {
+            image.resize(offset + section.len(), pad_byte);
+        }
+        image[offset..offset + section.len()].copy_from_slice(section);
+    }
+
+    if let Some(image_size) = image_size {
+        if image.len() > image_size {
+            return Err(LayoutError::ImageTooLargeError(image.len(), image_size));
+        }
+        image.resize(image_size, pad_byte);
+    }
+
+    Ok(image)
+}
+
+
+/**
+ * Writes the sections as a flat memory image split into two, for a 16-bit memory built from a pair of 8-bit
+ * ROM chips: the bytes at even addresses, which hold the high byte of each word, and the bytes at odd
+ * addresses. The image is made as by `to_bin` but starts at an even address, rounding the base address down if
+ * needed, and is padded to a whole number of words so both chips hold the same number of bytes.
+ */
+pub fn to_split_bin(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout, base_address:Option<usize>, image_size:Option<usize>,
+                    pad_byte:u8) -> Result<(Vec<u8>, Vec<u8>), LayoutError> {
+    let base_address = match base_address {
+        Some(base_address) => base_address,
+        None => placed_sections(data, code, custom, layout).iter().filter(|(_, _, section)| !section.is_empty())
+                                                                  .map(|(_, address, _)| *address).min().unwrap_or(0)
+    };
+
+    let mut image = to_bin(data, code, custom, layout, Some(base_address & !1), image_size, pad_byte)?;
+    if image.len() % 2 == 1 {
+        image.push(pad_byte);
+    }
+
+    let even = image.iter().step_by(2).copied().collect();
+    let odd = image.iter().skip(1).step_by(2).copied().collect();
+    Ok((even, odd))
+}
+
+
+/**
+ * Returns the paths the even and odd halves of a split image are written to, which are the output path with
+ * `.even` or `.odd` added before its extension, so `rom.bin` is split into `rom.even.bin` and `rom.odd.bin`.
+ */
+pub fn split_output_paths(path:&Path) -> (PathBuf, PathBuf) {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    (path.with_file_name(format!("{}.even{}", stem, extension)), path.with_file_name(format!("{}.odd{}", stem, extension)))
+}
+
+
+
+/**
+ * Writes the sections as a Logisim `v2.0 raw` memory image for a RAM or ROM which is a byte wide, so that
+ * addresses in the image are the addresses of the Sim6 address space. The image starts at address 0 with the
+ * gaps between sections zeroed, and runs of 4 or more equal bytes are written as `count*value`.
+ */
+pub fn to_logisim(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> String {
+    let image = to_bin(data, code, custom, layout, Some(0), None, 0x00).expect("no section lies below address 0");
+
+    let mut values = vec![];
+    let mut index = 0;
+    while index < image.len() {
+        let run = image[index..].iter().take_while(|byte| **byte == image[index]).count();
+        match run >= 4 {
+            true => values.push(format!("{}*{:x}", run, image[index])),
+            false => values.extend(std::iter::repeat_n(format!("{:x}", image[index]), run))
+        }
+        index += run;
+    }
+
+    let mut text = String::from("v2.0 raw\n");
+    for row in values.chunks(16) {
+        writeln!(text, "{}", row.join(" ")).unwrap();
+    }
+
+    text
+}
+
+
+/**
+ * Returns the sections as a flat image of big-endian 16-bit words starting at address 0, with the gaps between
+ * sections zeroed, for initialising a memory which is a word wide. The image holds at least one word, as
+ * block RAM initialisation files cannot be empty.
+ */
+fn word_image(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> Vec<u16> {
+    let mut image = to_bin(data, code, custom, layout, Some(0), None, 0x00).expect("no section lies below address 0");
+    image.resize(image.len().max(2).div_ceil(2) * 2, 0x00);
+    image.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect()
+}
+
+
+/**
+ * Writes the sections as a Xilinx COE file for initialising a block RAM which is a word wide.
+ */
+pub fn to_coe(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> String {
+    let words:Vec<String> = word_image(data, code, custom, layout).iter().map(|word| format!("{:04X}", word)).collect();
+    format!("memory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n", words.join(",\n"))
+}
+
+
+/**
+ * Writes the sections as a Quartus MIF file for initialising a memory which is a word wide, with runs of
+ * equal words written as a single address range.
+ */
+pub fn to_mif(data:&[u8], code:&[u8], custom:&[Vec<u8>], layout:&MemoryLayout) -> String {
+    let words = word_image(data, code, custom, layout);
+    let mut text = String::new();
+    writeln!(text, "WIDTH=16;\nDEPTH={};\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN", words.len()).unwrap();
+
+    let mut address = 0;
+    while address < words.len() {
+        let run = words[address..].iter().take_while(|word| **word == words[address]).count();
+        match run {
+            1 => writeln!(text, "    {:04X} : {:04X};", address, words[address]).unwrap(),
+            _ => writeln!(text, "    [{:04X}..{:04X}] : {:04X};", address, address + run - 1, words[address]).unwrap()
+        }
+        address += run;
+    }
+
+    text.push_str("END;\n");
+    text
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::layout::{LayoutError, MemoryLayout};
+    use super::{split_output_paths, to_bin, Endian, to_coe, to_ihex, to_logisim, to_memh, to_mif, to_readmemh, to_split_bin, to_srec, OutputFormat};
+
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!(OutputFormat::try_from("SREC"), Ok(OutputFormat::Srec));
+        assert_eq!(OutputFormat::Memh.extension(), "memh");
+        assert_eq!(OutputFormat::try_from("bin"), Ok(OutputFormat::Bin));
+        assert_eq!(OutputFormat::try_from("ihex"), Ok(OutputFormat::Ihex));
+        assert!(OutputFormat::try_from("hex").is_err());
+        assert_eq!(Endian::try_from("Little"), Ok(Endian::Little));
+        assert!(Endian::try_from("middle").is_err());
+    }
+
+
+    #[test]
+    fn test_memh() {
+        assert_eq!(to_memh(&[0x55], &[0x07, 0xC1], &[], &MemoryLayout::default()), "@5800\n07 C1\n@9000\n55\n");
+        assert_eq!(to_memh(&[], &[0x07, 0xC1], &[], &MemoryLayout::default()), "@5800\n07 C1\n");
+
+        let layout = MemoryLayout::parse("region ROM 0x5800 0x3800\nregion RAM 0x9000 0x7000\nregion FAST 0x0100 0x100\n\
+                                          place code ROM\nplace data RAM\nplace fast_ram FAST").unwrap();
+        assert_eq!(to_memh(&[0x55], &[], &[vec![0xAA, 0xBB]], &layout), "@9000\n55\n@0100\nAA BB\n");
+    }
+
+
+    #[test]
+    fn test_readmemh() {
+        assert_eq!(to_readmemh(&[0x55], &[0x07, 0xC1, 0xFC, 0x00], &[], &MemoryLayout::default()), "@2C00\n07C1\nFC00\n@4800\n5500\n");
+
+        let layout = MemoryLayout::default().rebase(None, Some(0x9001)).unwrap();
+        assert_eq!(to_readmemh(&[0x55, 0x66], &[], &[], &layout), "@4800\n0055\n6600\n");
+    }
+
+
+    #[test]
+    fn test_srec() {
+        let srec = to_srec(&[0x55], &[0x07, 0xC1], &[], "prog", None, &MemoryLayout::default()).unwrap();
+        assert_eq!(srec, "S007000070726F6740\nS105580007C1DA\nS10490005516\nS5030002FA\nS9035800A4\n");
+        assert!(to_srec(&[], &[0x07, 0xC1, 0x07, 0xC1], &[], "prog", Some(0x5802), &MemoryLayout::default()).unwrap().ends_with("S9035802A2\n"));
+        assert_eq!(to_srec(&[], &[], &[], "prog", Some(0x10000), &MemoryLayout::default()),
+                   Err(LayoutError::AddressOverflowError(String::from("srec"), 0x10000)));
+    }
+
+
+    #[test]
+    fn test_ihex() {
+        let ihex = to_ihex(&[0x55], &[0x07, 0xC1], &[], None, &MemoryLayout::default());
+        assert_eq!(ihex, ":0258000007C1DE\n:01900000551A\n:0400000300005800A1\n:00000001FF\n");
+        assert!(to_ihex(&[], &[], &[], Some(0x5802), &MemoryLayout::default()).starts_with(":04000003000058029F\n"));
+    }
+
+
+    #[test]
+    fn test_bin() {
+        let layout = MemoryLayout::parse("region ROM 0x0000 0x0008\nregion RAM 0x0008 0x0008\nplace code ROM\nplace data RAM").unwrap();
+        assert_eq!(to_bin(&[0x55], &[0x07, 0xC1], &[], &layout, None, None, 0xFF), Ok(vec![0x07, 0xC1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x55]));
+        assert_eq!(to_bin(&[], &[0x07, 0xC1], &[], &layout, None, None, 0x00), Ok(vec![0x07, 0xC1]));
+        assert_eq!(to_bin(&[0x55], &[], &[], &layout, Some(0x0006), None, 0x00), Ok(vec![0x00, 0x00, 0x55]));
+        assert_eq!(to_bin(&[0x55], &[0x07, 0xC1], &[], &layout, Some(0x0001), None, 0x00),
+                   Err(LayoutError::SectionBelowImageBaseError(String::from("code"), 0x0000, 0x0001)));
+        assert_eq!(to_bin(&[], &[], &[], &layout, None, None, 0xFF), Ok(vec![]));
+        assert_eq!(to_bin(&[], &[0x07, 0xC1], &[], &layout, None, Some(4), 0xFF), Ok(vec![0x07, 0xC1, 0xFF, 0xFF]));
+        assert_eq!(to_bin(&[0x55], &[0x07, 0xC1], &[], &layout, None, Some(8), 0xFF), Err(LayoutError::ImageTooLargeError(9, 8)));
+    }
+
+
+    #[test]
+    fn test_split_bin() {
+        let layout = MemoryLayout::parse("region ROM 0x0000 0x0008\nregion RAM 0x0008 0x0008\nplace code ROM\nplace data RAM").unwrap();
+        assert_eq!(to_split_bin(&[0x55], &[0x07, 0xC1, 0x5B, 0x00], &[], &layout, None, None, 0xFF),
+                   Ok((vec![0x07, 0x5B, 0xFF, 0xFF, 0x55], vec![0xC1, 0x00, 0xFF, 0xFF, 0xFF])));
+        assert_eq!(to_split_bin(&[0x55, 0x66, 0x77], &[], &[], &layout, Some(0x0007), None, 0x00), Ok((vec![0x00, 0x55, 0x77], vec![0x00, 0x66, 0x00])));
+        assert_eq!(to_split_bin(&[], &[], &[], &layout, None, None, 0xFF), Ok((vec![], vec![])));
+        assert_eq!(to_split_bin(&[], &[0x07, 0xC1], &[], &layout, None, Some(4), 0xFF), Ok((vec![0x07, 0xFF], vec![0xC1, 0xFF])));
+
+        assert_eq!(split_output_paths(Path::new("out/rom.bin")), (PathBuf::from("out/rom.even.bin"), PathBuf::from("out/rom.odd.bin")));
+        assert_eq!(split_output_paths(Path::new("rom")), (PathBuf::from("rom.even"), PathBuf::from("rom.odd")));
+    }
+
+
+    #[test]
+    fn test_logisim() {
+        let layout = MemoryLayout::parse("region ROM 0x0000 0x0008\nregion RAM 0x0008 0x0008\nplace code ROM\nplace data RAM").unwrap();
+        assert_eq!(to_logisim(&[0x55, 0x55], &[0x07, 0xC1], &[], &layout), "v2.0 raw\n7 c1 6*0 55 55\n");
+        assert_eq!(to_logisim(&[], &[], &[], &layout), "v2.0 raw\n");
+        assert_eq!(to_logisim(&[], &[0x01; 17], &[], &layout), "v2.0 raw\n17*1\n");
+
+        let text = to_logisim(&[], &(0..20).collect::<Vec<u8>>(), &[], &layout);
+        assert_eq!(text, "v2.0 raw\n0 1 2 3 4 5 6 7 8 9 a b c d e f\n10 11 12 13\n");
+    }
+
+
+    #[test]
+    fn test_coe() {
+        let layout = MemoryLayout::parse("region ROM 0x0000 0x0004\nregion RAM 0x0004 0x0004\nplace code ROM\nplace data RAM").unwrap();
+        assert_eq!(to_coe(&[0x55], &[0x07, 0xC1], &[], &layout), "memory_initialization_radix=16;\nmemory_initialization_vector=\n07C1,\n0000,\n5500;\n");
+        assert_eq!(to_coe(&[], &[], &[], &layout), "memory_initialization_radix=16;\nmemory_initialization_vector=\n0000;\n");
+    }
+
+
+    #[test]
+    fn test_mif() {
+        let layout = MemoryLayout::parse("region ROM 0x0000 0x0008\nregion RAM 0x0008 0x0008\nplace code ROM\nplace data RAM").unwrap();
+        let mif = to_mif(&[0x55], &[0x07, 0xC1], &[], &layout);
+        assert_eq!(mif, "WIDTH=16;\nDEPTH=5;\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n    0000 : 07C1;\n    [0001..0003] : 0000;\n    0004 : 5500;\nEND;\n");
+    }
+}
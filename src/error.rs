@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::layout::LayoutError;
+use crate::messages::{message, MessageId};
+
+
+/**
+ * Exit codes returned by the assembler, following the conventions of BSD `sysexits.h` so that build
+ * tools can tell a bad invocation apart from a missing file or a failed write.
+ */
+pub const EXIT_USAGE:i32 = 64;
+pub const EXIT_DATA_ERROR:i32 = 65;
+pub const EXIT_NO_INPUT:i32 = 66;
+pub const EXIT_CANT_CREATE:i32 = 73;
+pub const EXIT_IO_ERROR:i32 = 74;
+
+
+/**
+ * The file operation that was being attempted when an I/O error occurred.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOperation {
+    ReadInput,
+    WriteOutput
+}
+
+
+/**
+ * An error which stops the assembler, reported to the user through the message catalog.
+ */
+#[derive(Debug)]
+pub enum AssemblerError {
+    UsageError(MessageId, Vec<String>),
+    IoError(IoOperation, PathBuf, io::Error),
+    SourceError(usize, Box<dyn Error>),
+    IncludedSourceError(PathBuf, usize, Box<dyn Error>),
+    FormatError(PathBuf, Box<dyn Error>),
+    AssemblyFailedError(usize),
+    LinkFailedError(usize),
+    LayoutError(LayoutError)
+}
+
+impl Error for AssemblerError {}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::UsageError(id, params) => {
+                let params:Vec<&dyn fmt::Display> = params.iter().map(|param| param as &dyn fmt::Display).collect();
+                write!(f, "{}", message(*id, &params))
+            },
+            AssemblerError::IoError(IoOperation::ReadInput, path, err) => write!(f, "{}", message(MessageId::CannotReadInput, &[&path.display(), &describe_io_error(err)])),
+            AssemblerError::IoError(IoOperation::WriteOutput, path, err) => write!(f, "{}", message(MessageId::CannotWriteOutput, &[&path.display(), &describe_io_error(err)])),
+            AssemblerError::SourceError(line_num, err) => write!(f, "{}", message(MessageId::SourceLine, &[line_num, err])),
+            AssemblerError::IncludedSourceError(path, line_num, err) => write!(f, "{}", message(MessageId::IncludedSourceLine, &[&path.display(), line_num, err])),
+            AssemblerError::FormatError(path, err) => write!(f, "{}", message(MessageId::InvalidFile, &[&path.display(), err])),
+            AssemblerError::AssemblyFailedError(count) => write!(f, "{}", message(MessageId::AssemblyFailed, &[count])),
+            AssemblerError::LinkFailedError(count) => write!(f, "{}", message(MessageId::LinkFailed, &[count])),
+            AssemblerError::LayoutError(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl AssemblerError {
+    /**
+     * Creates an `AssemblerError::IoError` for the given operation and path, for use with `map_err`.
+     */
+    pub fn io(operation:IoOperation, path:&Path) -> impl FnOnce(io::Error) -> AssemblerError {
+        let path = path.to_path_buf();
+        move |err| AssemblerError::IoError(operation, path, err)
+    }
+
+
+    /**
+     * Creates an `AssemblerError::UsageError` from a message id and its parameters.
+     */
+    pub fn usage(id:MessageId, params:&[&str]) -> AssemblerError {
+        AssemblerError::UsageError(id, params.iter().map(|param| param.to_string()).collect())
+    }
+
+
+    /**
+     * Returns the process exit code that should be reported for this error.
+     */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AssemblerError::UsageError(_, _) => EXIT_USAGE,
+            AssemblerError::SourceError(_, _) | AssemblerError::IncludedSourceError(_, _, _) | AssemblerError::FormatError(_, _)
+             | AssemblerError::AssemblyFailedError(_) | AssemblerError::LinkFailedError(_)
+             | AssemblerError::LayoutError(_) => EXIT_DATA_ERROR,
+            AssemblerError::IoError(IoOperation::ReadInput, _, err) => match err.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => EXIT_NO_INPUT,
+                _ => EXIT_IO_ERROR
+            },
+            AssemblerError::IoError(IoOperation::WriteOutput, _, err) => match err.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => EXIT_CANT_CREATE,
+                _ => EXIT_IO_ERROR
+            }
+        }
+    }
+}
+
+
+/**
+ * Gives a short lowercase description of an I/O error such as "permission denied", falling back on the
+ * OS message where the error kind carries no useful description of its own.
+ */
+fn describe_io_error(err:&io::Error) -> String {
+    match err.kind() {
+        io::ErrorKind::NotFound => message(MessageId::NoSuchFile, &[]),
+        io::ErrorKind::PermissionDenied => message(MessageId::PermissionDenied, &[]),
+        io::ErrorKind::StorageFull => message(MessageId::StorageFull, &[]),
+        io::ErrorKind::IsADirectory => message(MessageId::IsADirectory, &[]),
+        _ => err.to_string()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::Path;
+
+    use super::*;
+
+
+    #[test]
+    fn test_io_error_message() {
+        let err = AssemblerError::io(IoOperation::WriteOutput, Path::new("build/prog.sse"))(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(err.to_string(), "cannot write output 'build/prog.sse': permission denied");
+        assert_eq!(err.exit_code(), EXIT_CANT_CREATE);
+
+        let err = AssemblerError::io(IoOperation::ReadInput, Path::new("missing.asm"))(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(err.to_string(), "cannot read input 'missing.asm': no such file or directory");
+        assert_eq!(err.exit_code(), EXIT_NO_INPUT);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::assembler::AssembledSections;
+use crate::label_table::LabelDefinition;
+use crate::layout::MemoryLayout;
+use crate::provenance::escape_json;
+use crate::source::SourceLine;
+
+
+/**
+ * Returns the labels declared with `.global` along with their addresses, sorted by address then name so the
+ * output is the same on every run.
+ */
+fn exported_symbols<'a>(definitions:&'a HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>) -> Vec<(&'a str, usize)> {
+    let mut symbols:Vec<(&str, usize)> = definitions.iter().filter(|(name, _)| globals.contains_key(*name))
+                                                    .map(|(name, definition)| (name.as_str(), definition.address))
+                                                    .collect();
+    symbols.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+    symbols
+}
+
+
+/**
+ * Converts a name to an upper case identifier, replacing any character which cannot appear in one with `_`.
+ */
+fn to_identifier(name:&str) -> String {
+    name.chars().map(|c| match c.is_ascii_alphanumeric() {
+        true => c.to_ascii_uppercase(),
+        false => '_'
+    }).collect()
+}
+
+
+/**
+ * Produces a C header defining the address of every label declared with `.global` as `SIM6_<LABEL>`, so host
+ * tools can refer to Sim6 symbols without hard-coding addresses. The include guard is named after the header's
+ * file name.
+ */
+pub fn to_c_header(definitions:&HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>, source_path:&Path, header_path:&Path) -> String {
+    let guard = to_identifier(&header_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default());
+    let mut text = format!("/* Symbol addresses for {}, generated by the Sim6 assembler. */\n", source_path.display());
+    writeln!(text, "#ifndef {}\n#define {}\n", guard, guard).unwrap();
+
+    for (name, address) in exported_symbols(definitions, globals) {
+        writeln!(text, "#define SIM6_{} 0x{:04X}", to_identifier(name), address).unwrap();
+    }
+
+    writeln!(text, "\n#endif /* {} */", guard).unwrap();
+    text
+}
+
+
+/**
+ * Produces Rust source defining the address of every label declared with `.global`, and the start and end of
+ * every section, as `pub const` items which an emulator or test harness can `include!`. The end of a section is
+ * the address just past its last byte.
+ */
+pub fn to_rust_source(definitions:&HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>, sections:&AssembledSections,
+                      layout:&MemoryLayout, source_path:&Path) -> String {
+    let mut text = format!("// Symbol addresses for {}, generated by the Sim6 assembler.\n\n", source_path.display());
+    for (section, size) in sections.sizes() {
+        let name = to_identifier(layout.section_name(section));
+        let address = sections.address(section, layout);
+        writeln!(text, "pub const {}_SECTION_START: usize = 0x{:04X};", name, address).unwrap();
+        writeln!(text, "pub const {}_SECTION_END: usize = 0x{:04X};", name, address + size).unwrap();
+    }
+
+    text.push('\n');
+    for (name, address) in exported_symbols(definitions, globals) {
+        writeln!(text, "pub const {}: usize = 0x{:04X};", to_identifier(name), address).unwrap();
+    }
+
+    text
+}
+
+
+/**
+ * Produces a JSON document listing every label with its section, address, size in bytes, and whether it is
+ * declared with `.global`, sorted by address then name, so debuggers and visualisers can map addresses back to
+ * names.
+ */
+pub fn to_symbols_json(definitions:&HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>, label_sizes:&HashMap<String, usize>,
+                       layout:&MemoryLayout, source_path:&Path) -> String {
+    let mut labels:Vec<(&String, &LabelDefinition)> = definitions.iter().collect();
+    labels.sort_by(|a, b| (a.1.address, a.0).cmp(&(b.1.address, b.0)));
+
+    let mut json = format!("{{\n  \"source\": \"{}\",\n  \"symbols\": [", escape_json(&source_path.display().to_string()));
+    for (index, (name, definition)) in labels.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+
+        let visibility = match globals.contains_key(name) {
+            true => "global",
+            false => "local"
+        };
+
+        write!(json, "\n    {{\"name\": \"{}\", \"section\": \"{}\", \"address\": {}, \"size\": {}, \"visibility\": \"{}\"}}",
+               escape_json(name), escape_json(layout.section_name(definition.section)), definition.address,
+               label_sizes.get(name).copied().unwrap_or(0), visibility).unwrap();
+    }
+
+    json.push_str("\n  ]\n}\n");
+    json
+}
+
+
+/**
+ * Produces a plain text symbol file with a line for every label giving its address in hex and its name, then
+ * a line for every port named with `.port` or in the port map giving its number in hex, its name, and `port`. The lines are
+ * sorted by address or number then name rather than following the order of the label table, so the file only
+ * changes when the symbols do and can be kept under version control.
+ */
+pub fn to_sym_file(definitions:&HashMap<String, LabelDefinition>, ports:&HashMap<String, usize>) -> String {
+    let mut labels:Vec<(usize, &String)> = definitions.iter().map(|(name, definition)| (definition.address, name)).collect();
+    labels.sort();
+    let mut ports:Vec<(usize, &String)> = ports.iter().map(|(name, number)| (*number, name)).collect();
+    ports.sort();
+
+    let mut text = String::new();
+    for (address, name) in labels {
+        writeln!(text, "{:04X} {}", address, name).unwrap();
+    }
+
+    for (number, name) in ports {
+        writeln!(text, "{:04X} {} port", number, name).unwrap();
+    }
+
+    text
+}
+
+
+/**
+ * Produces a map of the program like a linker's: the start, end, and size of every section along with the
+ * region of the layout it is placed in, then every label with its address, the size of the item it labels, its
+ * section, and whether it is declared with `.global`, sorted by address then name.
+ */
+pub fn to_map_file(definitions:&HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>, label_sizes:&HashMap<String, usize>,
+                   sections:&AssembledSections, layout:&MemoryLayout, source_path:&Path) -> String {
+    let mut text = format!("Memory map of {}\n\nSections:\n", source_path.display());
+    writeln!(text, "  {:<12}{:<8}{:<8}{:>6}  region", "section", "start", "end", "size").unwrap();
+    for (section, size) in sections.sizes() {
+        let address = sections.address(section, layout);
+        let end = match size {
+            0 => String::from("-"),
+            _ => format!("0x{:04X}", address + size - 1)
+        };
+        writeln!(text, "  {:<12}0x{:04X}  {:<8}{:>6}  {}", layout.section_name(section), address, end, size, layout.region(section).name).unwrap();
+    }
+
+    let mut labels:Vec<(&String, &LabelDefinition)> = definitions.iter().collect();
+    labels.sort_by(|a, b| (a.1.address, a.0).cmp(&(b.1.address, b.0)));
+
+    text.push_str("\nSymbols:\n");
+    writeln!(text, "  {:<8}{:>6}  {:<12}{:<8}name", "address", "size", "section", "binding").unwrap();
+    for (name, definition) in labels {
+        let binding = match globals.contains_key(name) {
+            true => "global",
+            false => "local"
+        };
+        writeln!(text, "  0x{:04X}  {:>6}  {:<12}{:<8}{}", definition.address, label_sizes.get(name).copied().unwrap_or(0),
+                 layout.section_name(definition.section), binding, name).unwrap();
+    }
+
+    text
+}
+
+
+/**
+ * Produces a source map which emulators and trace viewers can load alongside the program: the file and line
+ * which produced the bytes at each address, as in the debug line table, and the range of addresses each label
+ * covers, where the end of a range is the address just past it.
+ */
+pub fn to_source_map_json(definitions:&HashMap<String, LabelDefinition>, label_sizes:&HashMap<String, usize>, sections:&AssembledSections,
+                          layout:&MemoryLayout, source_path:&Path) -> String {
+    let mut json = format!("{{\n  \"source\": \"{}\",\n  \"lines\": [", escape_json(&source_path.display().to_string()));
+    for (index, entry) in sections.line_table(layout, source_path).iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        write!(json, "\n    {{\"address\": {}, \"file\": \"{}\", \"line\": {}}}", entry.address, escape_json(&entry.file), entry.line).unwrap();
+    }
+
+    let mut labels:Vec<(&String, &LabelDefinition)> = definitions.iter().collect();
+    labels.sort_by(|a, b| (a.1.address, a.0).cmp(&(b.1.address, b.0)));
+
+    json.push_str("\n  ],\n  \"labels\": [");
+    for (index, (name, definition)) in labels.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let end = definition.address + label_sizes.get(name).copied().unwrap_or(0);
+        write!(json, "\n    {{\"name\": \"{}\", \"start\": {}, \"end\": {}}}", escape_json(name), definition.address, end).unwrap();
+    }
+
+    json.push_str("\n  ]\n}\n");
+    json
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::assembler::{AssembledSections, ByteOrigin};
+    use crate::label_table::LabelDefinition;
+    use crate::layout::MemoryLayout;
+    use crate::object::SectionKind;
+    use crate::source::SourceLine;
+    use super::{to_c_header, to_map_file, to_rust_source, to_source_map_json, to_sym_file, to_symbols_json};
+
+
+    fn symbols() -> (HashMap<String, LabelDefinition>, HashMap<String, SourceLine>) {
+        let definitions = HashMap::from([
+            (String::from("main"), LabelDefinition { address: 0x5802, section: SectionKind::Code, line_num: 4 }),
+            (String::from("start"), LabelDefinition { address: 0x5800, section: SectionKind::Code, line_num: 3 }),
+            (String::from("buffer"), LabelDefinition { address: 0x9000, section: SectionKind::Data, line_num: 1 })
+        ]);
+        let globals = HashMap::from([
+            (String::from("main"), SourceLine::new(".global main", 5)),
+            (String::from("buffer"), SourceLine::new(".global buffer", 6))
+        ]);
+
+        (definitions, globals)
+    }
+
+
+    #[test]
+    fn test_c_header() {
+        let (definitions, globals) = symbols();
+        let header = to_c_header(&definitions, &globals, Path::new("prog.asm"), Path::new("build/syms.h"));
+        assert_eq!(header, "/* Symbol addresses for prog.asm, generated by the Sim6 assembler. */\n#ifndef SYMS_H\n#define SYMS_H\n\n\
+                            #define SIM6_MAIN 0x5802\n#define SIM6_BUFFER 0x9000\n\n#endif /* SYMS_H */\n");
+    }
+
+
+    #[test]
+    fn test_rust_source() {
+        let (definitions, globals) = symbols();
+        let sections = AssembledSections { data: vec![0x00; 4], code: vec![0x00; 6], bss_size: 0x10, ..AssembledSections::default() };
+
+        let source = to_rust_source(&definitions, &globals, &sections, &MemoryLayout::default(), Path::new("prog.asm"));
+        assert_eq!(source, "// Symbol addresses for prog.asm, generated by the Sim6 assembler.\n\n\
+                            pub const DATA_SECTION_START: usize = 0x9000;\npub const DATA_SECTION_END: usize = 0x9004;\n\
+                            pub const BSS_SECTION_START: usize = 0x9004;\npub const BSS_SECTION_END: usize = 0x9014;\n\
+                            pub const CODE_SECTION_START: usize = 0x5800;\npub const CODE_SECTION_END: usize = 0x5806;\n\n\
+                            pub const MAIN: usize = 0x5802;\npub const BUFFER: usize = 0x9000;\n");
+    }
+
+
+    #[test]
+    fn test_symbols_json() {
+        let (definitions, globals) = symbols();
+        let label_sizes = HashMap::from([(String::from("start"), 2), (String::from("main"), 6), (String::from("buffer"), 16)]);
+
+        assert_eq!(to_symbols_json(&definitions, &globals, &label_sizes, &MemoryLayout::default(), Path::new("prog.asm")), "{
+  \"source\": \"prog.asm\",
+  \"symbols\": [
+    {\"name\": \"start\", \"section\": \"code\", \"address\": 22528, \"size\": 2, \"visibility\": \"local\"},
+    {\"name\": \"main\", \"section\": \"code\", \"address\": 22530, \"size\": 6, \"visibility\": \"global\"},
+    {\"name\": \"buffer\", \"section\": \"data\", \"address\": 36864, \"size\": 16, \"visibility\": \"global\"}
+  ]
+}
+");
+    }
+
+
+    #[test]
+    fn test_sym_file() {
+        let (mut definitions, _) = symbols();
+        definitions.insert(String::from("entry"), LabelDefinition { address: 0x5802, section: SectionKind::Code, line_num: 4 });
+        assert_eq!(to_sym_file(&definitions, &HashMap::new()), "5800 start\n5802 entry\n5802 main\n9000 buffer\n");
+
+        let ports = HashMap::from([(String::from("UART_TX"), 0x05), (String::from("LEDS"), 0x01)]);
+        assert_eq!(to_sym_file(&definitions, &ports), "5800 start\n5802 entry\n5802 main\n9000 buffer\n0001 LEDS port\n0005 UART_TX port\n");
+    }
+
+
+    #[test]
+    fn test_map_file() {
+        let (definitions, globals) = symbols();
+        let label_sizes = HashMap::from([(String::from("start"), 2), (String::from("main"), 4), (String::from("buffer"), 4)]);
+        let sections = AssembledSections { data: vec![0x00; 4], code: vec![0x00; 6], ..AssembledSections::default() };
+
+        let map = to_map_file(&definitions, &globals, &label_sizes, &sections, &MemoryLayout::default(), Path::new("prog.asm"));
+        assert_eq!(map, "Memory map of prog.asm
+
+Sections:
+  section     start   end       size  region
+  data        0x9000  0x9003       4  RAM
+  bss         0x9004  -            0  RAM
+  code        0x5800  0x5805       6  ROM
+
+Symbols:
+  address   size  section     binding name
+  0x5800       2  code        local   start
+  0x5802       4  code        global  main
+  0x9000       4  data        global  buffer
+");
+    }
+
+
+    #[test]
+    fn test_source_map_json() {
+        let (definitions, _) = symbols();
+        let label_sizes = HashMap::from([(String::from("start"), 2), (String::from("main"), 4), (String::from("buffer"), 4)]);
+        let sections = AssembledSections {
+            data: vec![0x00; 4],
+            code: vec![0x00; 6],
+            origins: vec![
+                ByteOrigin { section: SectionKind::Data, offset: 0, length: 4, source: SourceLine::new("buffer: .space 4", 1) },
+                ByteOrigin { section: SectionKind::Code, offset: 2, length: 4, source: SourceLine { file: Some("lib.asm".into()), ..SourceLine::new("movi ax 1", 2) } }
+            ],
+            ..AssembledSections::default()
+        };
+
+        assert_eq!(to_source_map_json(&definitions, &label_sizes, &sections, &MemoryLayout::default(), Path::new("prog.asm")), "{
+  \"source\": \"prog.asm\",
+  \"lines\": [
+    {\"address\": 22530, \"file\": \"lib.asm\", \"line\": 2},
+    {\"address\": 36864, \"file\": \"prog.asm\", \"line\": 1}
+  ],
+  \"labels\": [
+    {\"name\": \"start\", \"start\": 22528, \"end\": 22530},
+    {\"name\": \"main\", \"start\": 22530, \"end\": 22534},
+    {\"name\": \"buffer\", \"start\": 36864, \"end\": 36868}
+  ]
+}
+");
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::messages::{message, MessageId};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprError {
+    InvalidExpressionError(String),
+    UndefinedSymbolError(String),
+    DivisionByZeroError(String),
+    InvalidRadixError(String)
+}
+
+impl Error for ExprError {}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::InvalidExpressionError(expr) => write!(f, "{}", message(MessageId::InvalidExpression, &[expr])),
+            ExprError::UndefinedSymbolError(name) => write!(f, "{}", message(MessageId::UndefinedConstant, &[name])),
+            ExprError::DivisionByZeroError(expr) => write!(f, "{}", message(MessageId::DivisionByZero, &[expr])),
+            ExprError::InvalidRadixError(radix) => write!(f, "{}", message(MessageId::InvalidRadix, &[radix]))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    Open,
+    Close
+}
+
+
+/**
+ * The binary operators from lowest to highest precedence, as in C.
+ */
+const BINARY_OPERATORS:&[&[&str]] = &[
+    &["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<", "<=", ">", ">="], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]
+];
+
+/**
+ * Every operator, with those which start with another operator first so they are matched in full.
+ */
+const OPERATORS:&[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~"
+];
+
+
+/**
+ * Parses an integer literal in decimal, or in hexadecimal, octal or binary with a `0x`, `0o` or `0b` prefix.
+ * The digits may be grouped with underscores, as in `0xFF_00` or `65_535`, which are ignored.
+ */
+pub fn parse_integer(text:&str) -> Option<i64> {
+    let lowercase = text.to_lowercase().replace('_', "");
+    let (digits, radix) = match lowercase.get(..2) {
+        Some("0x") => (&lowercase[2..], 16),
+        Some("0o") => (&lowercase[2..], 8),
+        Some("0b") => (&lowercase[2..], 2),
+        _ => (lowercase.as_str(), 10)
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+}
+
+
+/**
+ * Parses a fixed point format such as `Q8.8` or `Q4.12` for a 16-bit value, returning the number of fraction
+ * bits it has, or `None` if the text is not a format whose integer and fraction bits add up to 16.
+ */
+pub fn parse_q_format(text:&str) -> Option<u32> {
+    let (integer, fraction) = text.strip_prefix(['Q', 'q'])?.split_once('.')?;
+    let (integer, fraction) = (integer.parse::<u32>().ok()?, fraction.parse::<u32>().ok()?);
+    match integer + fraction == 16 {
+        true => Some(fraction),
+        false => None
+    }
+}
+
+
+/**
+ * Replaces the escape sequences in the text of a string or character literal with the bytes they stand for:
+ * `\n`, `\r`, `\t`, `\0`, `\xHH`, and a backslash or quote character after a backslash. Returns `None` if
+ * the text holds any other escape sequence.
+ */
+pub fn unescape(text:&str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        let byte = match chars.next()? {
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            'x' => {
+                let hex:String = chars.by_ref().take(2).collect();
+                match hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    true => u8::from_str_radix(&hex, 16).ok()?,
+                    false => return None
+                }
+            },
+            c @ ('\\' | '\'' | '"' | '`') => c as u8,
+            _ => return None
+        };
+        bytes.push(byte);
+    }
+
+    Some(bytes)
+}
+
+
+/**
+ * Finds the string literal in the text of a string directive such as `.asciiz`, which is written between
+ * backticks or double quotes, returning the text between its opening delimiter and the first matching delimiter
+ * after it which is not escaped. Adjacent literals separated only by whitespace are joined into one, so
+ * `"Hello, " "world"` is the same as `"Hello, world"`. Returns `None` if a string is not closed or anything else
+ * follows it.
+ */
+pub fn string_literal(text:&str) -> Option<String> {
+    let mut literal = String::new();
+    let mut rest = &text[text.find(['`', '"'])?..];
+
+    while let Some(quote) = rest.chars().next() {
+        if quote != '`' && quote != '"' {
+            return None;
+        }
+
+        let mut escaped = false;
+        let end = rest[1..].char_indices().find(|(_, c)| {
+            let closes = !escaped && *c == quote;
+            escaped = !escaped && *c == '\\';
+            closes
+        })?.0 + 1;
+
+        literal.push_str(&rest[1..end]);
+        rest = rest[end + 1..].trim_start();
+    }
+
+    Some(literal)
+}
+
+
+/**
+ * Splits the operands of a directive such as `.word` into the values it holds, which are separated by spaces.
+ * Spaces inside parentheses or a character literal, or next to a binary operator, are part of an expression
+ * rather than separating values, so `.word 1 2 + 3` holds two values and `.word 1 -2` holds 1 and -2.
+ */
+pub fn split_values(text:&str) -> Vec<String> {
+    let mut values:Vec<String> = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut chars = text.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if !quoted && c.is_whitespace() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let rest:String = chars.clone().take(2).collect();
+            let joins_previous = current.ends_with(|c:char| "|^&=<>+-*/%!~(".contains(c));
+            let joins_next = rest.starts_with(|c:char| "|^&=<>+*/%)".contains(c)) || rest.starts_with("!=") || rest == "-"
+                || rest.starts_with("- ");
+
+            match depth > 0 || joins_previous || joins_next {
+                true => current.push(' '),
+                false => values.push(std::mem::take(&mut current))
+            }
+            continue;
+        }
+
+        match c {
+            '\'' if !escaped => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            _ => {}
+        }
+        escaped = quoted && !escaped && c == '\\';
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        values.push(current);
+    }
+
+    values
+}
+
+
+/**
+ * Parses a character literal such as `'A'` or `'\n'` at the start of the text, returning the byte it stands
+ * for and the length of the literal, or `None` if it is not a single byte between single quotes.
+ */
+fn parse_char_literal(text:&str) -> Option<(i64, usize)> {
+    let mut escaped = false;
+    let end = text.char_indices().skip(1).find(|(_, c)| {
+        let closes = !escaped && *c == '\'';
+        escaped = !escaped && *c == '\\';
+        closes
+    })?.0;
+
+    match unescape(&text[1..end])?.as_slice() {
+        [byte] => Some((*byte as i64, end + 1)),
+        _ => None
+    }
+}
+
+
+fn tokenise(text:&str) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '(' => { tokens.push(Token::Open); 1 },
+            ')' => { tokens.push(Token::Close); 1 },
+            '$' => { tokens.push(Token::Name(String::from("$"))); 1 },
+            '\'' => {
+                let (value, len) = parse_char_literal(rest)?;
+                tokens.push(Token::Number(value));
+                len
+            },
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let len = rest.find(|c:char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                match c.is_ascii_digit() {
+                    true => tokens.push(Token::Number(parse_integer(&rest[..len])?)),
+                    false => tokens.push(Token::Name(rest[..len].to_string()))
+                }
+                len
+            },
+            _ => {
+                let operator = OPERATORS.iter().find(|operator| rest.starts_with(**operator))?;
+                tokens.push(Token::Operator(operator));
+                operator.len()
+            }
+        };
+
+        rest = rest[len..].trim_start();
+    }
+
+    Some(tokens)
+}
+
+
+/**
+ * A recursive descent parser which evaluates the expression as it goes.
+ */
+struct Evaluator<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a HashMap<String, i64>
+}
+
+impl<'a> Evaluator<'a> {
+    fn invalid(&self) -> ExprError {
+        ExprError::InvalidExpressionError(self.text.trim().to_string())
+    }
+
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+
+    fn binary(&mut self, level:usize) -> Result<i64, ExprError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut value = self.binary(level + 1)?;
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position).cloned() {
+            if !BINARY_OPERATORS[level].contains(&operator) {
+                break;
+            }
+
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match operator {
+                "||" => (value != 0 || rhs != 0) as i64,
+                "&&" => (value != 0 && rhs != 0) as i64,
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "==" => (value == rhs) as i64,
+                "!=" => (value != rhs) as i64,
+                "<" => (value < rhs) as i64,
+                "<=" => (value <= rhs) as i64,
+                ">" => (value > rhs) as i64,
+                ">=" => (value >= rhs) as i64,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err(ExprError::DivisionByZeroError(self.text.trim().to_string())),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs)
+            };
+        }
+
+        Ok(value)
+    }
+
+
+    fn unary(&mut self) -> Result<i64, ExprError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) if (name == "lo" || name == "hi") && self.tokens.get(self.position) == Some(&Token::Open) => {
+                let value = self.unary()?;
+                match name.as_str() {
+                    "lo" => Ok(value & 0xFF),
+                    _ => Ok((value >> 8) & 0xFF)
+                }
+            },
+            Some(Token::Name(name)) => match self.symbols.get(&name) {
+                Some(value) => Ok(*value),
+                None => Err(ExprError::UndefinedSymbolError(name))
+            },
+            Some(Token::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            Some(Token::Open) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(self.invalid())
+                }
+            },
+            _ => Err(self.invalid())
+        }
+    }
+}
+
+
+/**
+ * Evaluates a constant integer expression, such as `STACK_SIZE > 256`, in which names are looked up in the
+ * given symbols. Expressions use the operators and precedence of C, and comparisons and logical operators
+ * give 1 for true and 0 for false. `lo(x)` and `hi(x)` give the low and high bytes of a 16-bit value, and a
+ * character literal such as `'A'` gives the value of its byte.
+ */
+pub fn evaluate(text:&str, symbols:&HashMap<String, i64>) -> Result<i64, ExprError> {
+    let invalid = || ExprError::InvalidExpressionError(text.trim().to_string());
+    let mut evaluator = Evaluator {
+        text: text,
+        tokens: tokenise(text).ok_or_else(invalid)?,
+        position: 0,
+        symbols: symbols
+    };
+
+    let value = evaluator.binary(0)?;
+    match evaluator.position == evaluator.tokens.len() {
+        true => Ok(value),
+        false => Err(invalid())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_evaluate() {
+        let symbols = HashMap::from([(String::from("STACK_SIZE"), 512), (String::from("BOARD"), 2)]);
+
+        assert_eq!(evaluate("STACK_SIZE > 256", &symbols), Ok(1));
+        assert_eq!(evaluate("1 + 2 * 3", &symbols), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3", &symbols), Ok(9));
+        assert_eq!(evaluate("0x10 | 0b11 << 1", &symbols), Ok(0x16));
+        assert_eq!(evaluate("BOARD == 2 && !(STACK_SIZE < 0)", &symbols), Ok(1));
+        assert_eq!(evaluate("-7 / 2 + ~0", &symbols), Ok(-4));
+        assert_eq!(evaluate("hi(0x9004) + lo(0x9004)", &symbols), Ok(0x94));
+        assert_eq!(evaluate("lo(STACK_SIZE - 1) | hi(-1)", &symbols), Ok(0xFF));
+        assert_eq!(evaluate("'A' + 1", &symbols), Ok(0x42));
+        assert_eq!(evaluate("0b1010_1010 + 0xFF_00 + 65_535", &symbols), Ok(0xAA + 0xFF00 + 65535));
+        assert_eq!(evaluate("0o755 & 0O7", &symbols), Ok(5));
+        assert_eq!(evaluate("'\\n' | '\\'' << 8", &symbols), Ok(0x270A));
+        assert_eq!(evaluate("'\\x7F' - ' '", &symbols), Ok(0x5F));
+    }
+
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a\\tb\\0"), Some(vec![b'a', b'\t', b'b', 0]));
+        assert_eq!(unescape("\\`quoted\\` \\\\ \\x41"), Some(b"`quoted` \\ A".to_vec()));
+        assert_eq!(unescape("\\q"), None);
+        assert_eq!(unescape("\\x4"), None);
+        assert_eq!(unescape("trailing\\"), None);
+    }
+
+
+    #[test]
+    fn test_q_format() {
+        assert_eq!(parse_q_format("Q8.8"), Some(8));
+        assert_eq!(parse_q_format("q4.12"), Some(12));
+        assert_eq!(parse_q_format("Q16.0"), Some(0));
+        assert_eq!(parse_q_format("Q8.4"), None);
+        assert_eq!(parse_q_format("3.25"), None);
+    }
+
+
+    #[test]
+    fn test_split_values() {
+        assert_eq!(split_values("1 2 3 400"), vec!["1", "2", "3", "400"]);
+        assert_eq!(split_values("1<<12 | 0x0F"), vec!["1<<12 | 0x0F"]);
+        assert_eq!(split_values("1 2 + 3  (4 * 5) -6"), vec!["1", "2 + 3", "(4 * 5)", "-6"]);
+        assert_eq!(split_values("1 - 2 ~3 ' ' 'a'"), vec!["1 - 2", "~3", "' '", "'a'"]);
+        assert_eq!(split_values("A != 1 hi( 300 )"), vec!["A != 1", "hi( 300 )"]);
+        assert_eq!(split_values(""), Vec::<String>::new());
+    }
+
+
+    #[test]
+    fn test_string_literal() {
+        assert_eq!(string_literal("msg: .asciiz `hello`").as_deref(), Some("hello"));
+        assert_eq!(string_literal(".asciiz `say \\`hi\\``  ").as_deref(), Some("say \\`hi\\`"));
+        assert_eq!(string_literal(".asciiz `a\\\\`").as_deref(), Some("a\\\\"));
+        assert_eq!(string_literal(".asciiz `open").as_deref(), None);
+        assert_eq!(string_literal(".asciiz `a` b").as_deref(), None);
+        assert_eq!(string_literal(".asciiz \"it's `here`\"").as_deref(), Some("it's `here`"));
+        assert_eq!(string_literal(".asciiz `say \"hi\"`").as_deref(), Some("say \"hi\""));
+        assert_eq!(string_literal(".asciiz \"a\\\"b\"").as_deref(), Some("a\\\"b"));
+        assert_eq!(string_literal(".asciiz \"open`").as_deref(), None);
+        assert_eq!(string_literal(".asciiz \"Hello, \"   `\"world\"`\"!\"").as_deref(), Some("Hello, \"world\"!"));
+        assert_eq!(string_literal(".asciiz \"a\" \"b").as_deref(), None);
+    }
+
+
+    #[test]
+    fn test_invalid_expressions() {
+        let symbols = HashMap::new();
+
+        assert_eq!(evaluate("MISSING > 1", &symbols), Err(ExprError::UndefinedSymbolError(String::from("MISSING"))));
+        assert_eq!(evaluate("1 +", &symbols), Err(ExprError::InvalidExpressionError(String::from("1 +"))));
+        assert_eq!(evaluate("(1", &symbols), Err(ExprError::InvalidExpressionError(String::from("(1"))));
+        assert_eq!(evaluate("1 2", &symbols), Err(ExprError::InvalidExpressionError(String::from("1 2"))));
+        assert_eq!(evaluate("0xZZ", &symbols), Err(ExprError::InvalidExpressionError(String::from("0xZZ"))));
+        assert_eq!(evaluate("0x_", &symbols), Err(ExprError::InvalidExpressionError(String::from("0x_"))));
+        assert_eq!(evaluate("0o8", &symbols), Err(ExprError::InvalidExpressionError(String::from("0o8"))));
+        assert_eq!(evaluate("4 % 0", &symbols), Err(ExprError::DivisionByZeroError(String::from("4 % 0"))));
+        assert_eq!(evaluate("'AB'", &symbols), Err(ExprError::InvalidExpressionError(String::from("'AB'"))));
+        assert_eq!(evaluate("'A", &symbols), Err(ExprError::InvalidExpressionError(String::from("'A"))));
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::read_source;
+use crate::error::{AssemblerError, IoOperation};
+use crate::expr::{evaluate, ExprError};
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IncludeError {
+    MissingPathError(String),
+    NotFoundError(String),
+    CircularIncludeError(String, String),
+    BinaryRangeError(String, usize)
+}
+
+impl Error for IncludeError {}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeError::MissingPathError(directive) => write!(f, "{}", message(MessageId::IncludeExpectsPath, &[directive])),
+            IncludeError::NotFoundError(name) => write!(f, "{}", message(MessageId::IncludeNotFound, &[name])),
+            IncludeError::CircularIncludeError(name, chain) => write!(f, "{}", message(MessageId::CircularInclude, &[name, chain])),
+            IncludeError::BinaryRangeError(name, size) => write!(f, "{}", message(MessageId::BinaryIncludeRange, &[name, size]))
+        }
+    }
+}
+
+
+/**
+ * Returns the path named by an `.include` directive, which must be wrapped in double quotes or backticks.
+ */
+fn parse_include_path(operand:&str) -> Option<&str> {
+    let operand = operand.trim();
+    match (operand.chars().next(), operand.chars().last()) {
+        (Some('"'), Some('"')) | (Some('`'), Some('`')) if operand.len() > 2 => Some(&operand[1..operand.len() - 1]),
+        _ => None
+    }
+}
+
+
+/**
+ * Finds an included file, looking first in the directory of the file including it and then in each of the
+ * include directories in the order they were given.
+ */
+fn resolve_include(name:&str, including_path:&Path, include_dirs:&[PathBuf]) -> Option<PathBuf> {
+    let local_dir = including_path.parent().unwrap_or(Path::new(""));
+    std::iter::once(local_dir).chain(include_dirs.iter().map(|dir| dir.as_path()))
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+
+/**
+ * The files currently being read, from the input file down to the innermost `.include`, so a file which
+ * includes itself, directly or through other files, is reported rather than included forever. A file may be
+ * read again while it is already being read, as an include guard such as `.ifndef FILE_INC` skips it the
+ * second time, but a third time means nothing stopped it. Each file is kept as its canonical path, used to
+ * compare them, along with the path it was found at, used in diagnostics.
+ */
+pub struct IncludeStack {
+    files: Vec<(PathBuf, PathBuf)>
+}
+
+impl IncludeStack {
+    pub fn new(source_path:&Path) -> IncludeStack {
+        let canonical = fs::canonicalize(source_path).unwrap_or(source_path.to_path_buf());
+        IncludeStack { files: vec![(canonical, source_path.to_path_buf())] }
+    }
+
+
+    /**
+     * Reads the file named by an `.include "file"` line and adds it to the stack, returning its lines, which
+     * keep the path of the file and their line number in it so diagnostics point at the original file. The
+     * file is searched for starting from the directory of the file being read. Once its lines have been read,
+     * it must be removed from the stack with `pop`.
+     */
+    pub fn push(&mut self, line:&SourceLine, include_dirs:&[PathBuf]) -> Result<Vec<SourceLine>, AssemblerError> {
+        let text = line.text.trim();
+        let missing_path = || line.error(Box::new(IncludeError::MissingPathError(String::from(".include"))));
+        let name = parse_include_path(&text[".include".len()..]).ok_or_else(missing_path)?;
+
+        let (_, including_path) = self.files.last().unwrap();
+        let included = match resolve_include(name, including_path, include_dirs) {
+            Some(included) => included,
+            None => return Err(line.error(Box::new(IncludeError::NotFoundError(name.to_string()))))
+        };
+
+        let canonical = fs::canonicalize(&included).unwrap_or(included.to_owned());
+        if self.files.iter().filter(|(existing, _)| *existing == canonical).count() > 1 {
+            let chain:Vec<String> = self.files.iter().map(|(_, shown)| shown.display().to_string()).chain([included.display().to_string()]).collect();
+            return Err(line.error(Box::new(IncludeError::CircularIncludeError(name.to_string(), chain.join(" -> ")))));
+        }
+
+        let mut included_lines = read_source(&included)?;
+        for included_line in included_lines.iter_mut() {
+            included_line.file = Some(included.to_owned());
+        }
+
+        self.files.push((canonical, included));
+        Ok(included_lines)
+    }
+
+
+    pub fn pop(&mut self) {
+        self.files.pop();
+    }
+}
+
+
+/**
+ * Evaluates the offset or length given to an `.incbin` directive, which must not be negative.
+ */
+fn evaluate_incbin_operand(operand:&str) -> Result<usize, ExprError> {
+    let value = evaluate(operand, &HashMap::new())?;
+    usize::try_from(value).map_err(|_| ExprError::InvalidExpressionError(operand.trim().to_string()))
+}
+
+
+/**
+ * Takes the lines of the input file at the given path and replaces every `.incbin "file"` directive with an
+ * `.array` of the bytes of that file, so assets such as fonts and lookup tables can be embedded in the data
+ * section and the label pass sees their size like any other data. An offset into the file and a length may
+ * follow the path, as in `.incbin "font.bin", 16, 64`. The file is searched for in the same way as an
+ * `.include`, starting from the file the directive is in.
+ */
+pub fn expand_binary_includes(source_lines:Vec<SourceLine>, source_path:&Path, include_dirs:&[PathBuf]) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut output = vec![];
+
+    for line in source_lines {
+        let (label, contents) = match line.text.split_once(':') {
+            Some((label, contents)) if !label.trim().contains(|c:char| c.is_whitespace() || c == '"' || c == '`') => {
+                (format!("{}: ", label.trim()), contents.trim())
+            },
+            _ => (String::new(), line.text.trim())
+        };
+
+        if contents.split_whitespace().next() != Some(".incbin") {
+            output.push(line);
+            continue;
+        }
+
+        let operands = contents[".incbin".len()..].trim();
+        let missing_path = || line.error(Box::new(IncludeError::MissingPathError(String::from(".incbin"))));
+        let end = match operands.chars().next() {
+            Some(quote) if quote == '"' || quote == '`' => operands[1..].find(quote).ok_or_else(missing_path)? + 2,
+            _ => return Err(missing_path())
+        };
+        let name = parse_include_path(&operands[..end]).ok_or_else(missing_path)?;
+
+        let including_path = line.file.to_owned().unwrap_or(source_path.to_path_buf());
+        let included = match resolve_include(name, &including_path, include_dirs) {
+            Some(included) => included,
+            None => return Err(line.error(Box::new(IncludeError::NotFoundError(name.to_string()))))
+        };
+        let bytes = fs::read(&included).map_err(AssemblerError::io(IoOperation::ReadInput, &included))?;
+
+        let range:Vec<&str> = operands[end..].split(',').skip(1).collect();
+        let offset = match range.first() {
+            Some(offset) => evaluate_incbin_operand(offset).map_err(|err| line.error(Box::new(err)))?,
+            None => 0
+        };
+        let length = match range.get(1) {
+            Some(length) => evaluate_incbin_operand(length).map_err(|err| line.error(Box::new(err)))?,
+            None => bytes.len().saturating_sub(offset)
+        };
+
+        let included_bytes = match bytes.get(offset..offset.saturating_add(length)) {
+            Some(included_bytes) => included_bytes,
+            None => return Err(line.error(Box::new(IncludeError::BinaryRangeError(name.to_string(), bytes.len()))))
+        };
+
+        let array:Vec<String> = included_bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        output.push(SourceLine { text: format!("{}.array {}", label, array.join(" ")), ..line });
+    }
+
+    Ok(output)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::assembler::read_source;
+    use crate::constants::ConstantTable;
+    use crate::preprocess::preprocess;
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    fn include(path:&str, include_dirs:&[PathBuf]) -> Result<Vec<SourceLine>, AssemblerError> {
+        expand_includes(read_source(Path::new(path)).unwrap(), Path::new(path), include_dirs)
+    }
+
+
+    fn expand_includes(source_lines:Vec<SourceLine>, source_path:&Path, include_dirs:&[PathBuf]) -> Result<Vec<SourceLine>, AssemblerError> {
+        preprocess(source_lines, source_path, include_dirs, &[], false, &mut ConstantTable::default())
+    }
+
+
+    #[test]
+    fn test_expand_includes() {
+        let lines = include("test_files/test_include.asm", &[PathBuf::from("test_files/include")]).unwrap();
+        let texts:Vec<&str> = lines.iter().map(|line| line.text.trim()).collect();
+        assert_eq!(texts, vec![".data:", "counter: .byte 0", "", ".code:", "sub ax ax", "push bx", "start: add ax bx"]);
+
+        assert_eq!(lines[0].file, Some(PathBuf::from("test_files/include/data.inc")));
+        assert_eq!((lines[4].file.to_owned(), lines[4].line_num), (Some(PathBuf::from("test_files/include/clear.inc")), 1));
+        assert_eq!((lines[6].file.to_owned(), lines[6].line_num), (None, 5));
+    }
+
+
+    #[test]
+    fn test_invalid_includes() {
+        assert_eq!(include("test_files/test_include.asm", &[]).unwrap_err().to_string(), "line 1: Cannot find included file 'data.inc'");
+        assert_eq!(include("test_files/test_include_cycle.asm", &[]).unwrap_err().to_string(),
+                   "test_files/include/cycle_b.inc, line 2: Circular include of 'cycle_a.inc': test_files/test_include_cycle.asm -> test_files/include/cycle_a.inc -> test_files/include/cycle_b.inc -> test_files/include/cycle_a.inc -> test_files/include/cycle_b.inc -> test_files/include/cycle_a.inc");
+
+        let err = expand_includes(to_source_lines(".include defs.inc"), Path::new("prog.asm"), &[]).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '.include' expects a file path in double quotes or backticks");
+    }
+
+
+    #[test]
+    fn test_conditional_includes() {
+        let include_dirs = [PathBuf::from("test_files/include")];
+        let expand = |source:&str| expand_includes(to_source_lines(source), Path::new("prog.asm"), &include_dirs);
+
+        // a file guarded by `.ifndef` is only read once, even when it includes itself
+        let lines = expand(".include \"guarded.inc\"\n.include \"guarded.inc\"\nhalt").unwrap();
+        let texts:Vec<&str> = lines.iter().map(|line| line.text.trim()).collect();
+        assert_eq!(texts, vec!["push ax", "halt"]);
+
+        // an include in a branch which is not taken is never looked for
+        let lines = expand(".ifdef DEBUG\n.include \"missing.inc\"\n.endif\nhalt").unwrap();
+        assert_eq!(lines.iter().map(|line| line.text.as_str()).collect::<Vec<&str>>(), vec!["halt"]);
+    }
+
+
+    #[test]
+    fn test_expand_binary_includes() {
+        let include_dirs = [PathBuf::from("test_files/include")];
+        let expand = |source:&str| expand_binary_includes(to_source_lines(source), Path::new("prog.asm"), &include_dirs);
+
+        let lines = expand(".data:\nfont: .incbin \"font.bin\"\n.incbin `font.bin`, 2, 3\n.incbin \"font.bin\", 8").unwrap();
+        let texts:Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec![".data:", "font: .array 0x00 0x18 0x24 0x42 0x7E 0x42 0x42 0x00", ".array 0x24 0x42 0x7E", ".array "]);
+
+        assert_eq!(expand(".incbin \"font.bin\", 4, 5").unwrap_err().to_string(), "line 1: The offset and length given for 'font.bin' go past its end, as it is 8 bytes long");
+        assert_eq!(expand(".incbin font.bin").unwrap_err().to_string(), "line 1: '.incbin' expects a file path in double quotes or backticks");
+        assert_eq!(expand(".incbin \"missing.bin\"").unwrap_err().to_string(), "line 1: Cannot find included file 'missing.bin'");
+        assert!(expand(".incbin \"font.bin\", -1").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt::Write;
+
+use crate::emit::Endian;
+use crate::metadata::PROGRAM_METADATA_KEYS;
+use crate::object::ObjectFile;
+use crate::repr::instruction::{DecodeError, Instruction};
+use crate::sse::{SseFile, SseSectionKind};
+
+
+/**
+ * An instruction decoded from a code section, or the error from attempting to decode it, along with its
+ * address and the bytes it was decoded from.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstr {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub instr: Result<Instruction, DecodeError>
+}
+
+
+/**
+ * Reads the 16-bit word at an offset into a code section in the given byte order.
+ */
+fn read_word(code:&[u8], offset:usize, endian:Endian) -> u16 {
+    match endian {
+        Endian::Big => u16::from_be_bytes([code[offset], code[offset + 1]]),
+        Endian::Little => u16::from_le_bytes([code[offset], code[offset + 1]])
+    }
+}
+
+
+/**
+ * Takes the bytes of a code section and decodes them into instructions, using `base_address` as the
+ * address of the first byte and reading each word in the given byte order. Words which cannot be decoded are
+ * skipped over 2 bytes at a time.
+ */
+pub fn disassemble(code:&[u8], base_address:usize, endian:Endian) -> Vec<DisassembledInstr> {
+    let mut instrs = vec![];
+    let mut offset = 0;
+
+    while offset < code.len() {
+        if offset + 2 > code.len() {
+            instrs.push(DisassembledInstr {
+                address: base_address + offset,
+                bytes: code[offset..].to_vec(),
+                instr: Err(DecodeError::TruncatedInstructionError)
+            });
+            break;
+        }
+
+        let word = read_word(code, offset, endian);
+        let (length, instr) = match Instruction::try_from(word) {
+            Err(DecodeError::LongInstructionError(_)) if offset + 4 <= code.len() => {
+                let long = (word as u32) << 16 | read_word(code, offset + 2, endian) as u32;
+                (4, Instruction::try_from(long))
+            },
+
+            Err(DecodeError::LongInstructionError(_)) => (2, Err(DecodeError::TruncatedInstructionError)),
+            result => (2, result)
+        };
+
+        instrs.push(DisassembledInstr {
+            address: base_address + offset,
+            bytes: code[offset..offset + length].to_vec(),
+            instr: instr
+        });
+        offset += length;
+    }
+
+    instrs
+}
+
+
+/**
+ * Formats a section's address range and size for the section table, or notes that it is empty.
+ */
+fn format_section(name:&str, base_address:usize, length:usize) -> String {
+    match length {
+        0 => format!("  {:<5} {:>8}   (empty)\n", name, format!("0x{:04X}", base_address)),
+        _ => format!("  {:<5} 0x{:04X} - 0x{:04X}  {} bytes\n", name, base_address, base_address + length - 1, length)
+    }
+}
+
+
+/**
+ * Produces an objdump-style description of a .sse file: its section layout, metadata, debug line table and
+ * symbol table if it has them, and a disassembly of its code section with addresses.
+ */
+pub fn inspect(file:&SseFile) -> String {
+    let mut text = String::from("Sections:\n");
+    for section in &file.sections {
+        text.push_str(&format_section(section.kind.name(), section.address, section.size));
+    }
+
+    writeln!(text, "\nEntry point: 0x{:04X}", file.entry).unwrap();
+
+    if !file.metadata.is_empty() {
+        text.push_str("\nMetadata:\n");
+        for (key, value) in &file.metadata {
+            writeln!(text, "  {:<14}{}", key, value).unwrap();
+        }
+    }
+
+    if !file.lines.is_empty() {
+        text.push_str("\nDebug lines:\n");
+        for entry in &file.lines {
+            writeln!(text, "  {:04X}  {}:{}", entry.address, entry.file, entry.line).unwrap();
+        }
+    }
+
+    if !file.symbols.is_empty() {
+        text.push_str("\nSymbols:\n");
+        for (name, address) in &file.symbols {
+            writeln!(text, "  {:04X}  {}", address, name).unwrap();
+        }
+    }
+
+    // a file assembled with `--endian little` records it in its metadata
+    let endian = match file.metadata.iter().any(|(key, value)| key == "endian" && value == "little") {
+        true => Endian::Little,
+        false => Endian::Big
+    };
+
+    let code_address = file.section(&SseSectionKind::Code).map(|section| section.address).unwrap_or(0);
+    text.push_str("\nDisassembly of code:\n");
+    for line in disassemble(file.code(), code_address, endian) {
+        let bytes:Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        match line.instr {
+            Ok(instr) => writeln!(text, "  {:04X}:  {:<12}  {}", line.address, bytes.join(" "), instr).unwrap(),
+            Err(err) => writeln!(text, "  {:04X}:  {:<12}  <invalid: {}>", line.address, bytes.join(" "), err).unwrap()
+        }
+    }
+
+    text
+}
+
+
+
+/**
+ * Produces a description of a relocatable .sso object file: its section sizes, symbol table, relocations,
+ * and a disassembly of its code section with offsets from the start of the section.
+ */
+pub fn inspect_object(object:&ObjectFile) -> String {
+    let mut text = String::from("Sections:\n");
+    writeln!(text, "  data  {} bytes", object.data.len()).unwrap();
+    writeln!(text, "  code  {} bytes", object.code.len()).unwrap();
+
+    text.push_str("\nSymbols:\n");
+    for symbol in &object.symbols {
+        let binding = match symbol.global {
+            true => "global",
+            false => "local"
+        };
+        writeln!(text, "  {:<6}+0x{:04X}  {:<7} {}", format!("{:?}", symbol.section).to_lowercase(), symbol.offset, binding, symbol.name).unwrap();
+    }
+
+    text.push_str("\nRelocations:\n");
+    for relocation in &object.relocations {
+        writeln!(text, "  {:<6}+0x{:04X}  {:<12}{}", format!("{:?}", relocation.section).to_lowercase(), relocation.offset,
+                 format!("{:?}", relocation.kind), relocation.symbol).unwrap();
+    }
+
+    text.push_str("\nDisassembly of code:\n");
+    for line in disassemble(&object.code, 0, Endian::Big) {
+        let bytes:Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        match line.instr {
+            Ok(instr) => writeln!(text, "  +{:04X}:  {:<12}  {}", line.address, bytes.join(" "), instr).unwrap(),
+            Err(err) => writeln!(text, "  +{:04X}:  {:<12}  <invalid: {}>", line.address, bytes.join(" "), err).unwrap()
+        }
+    }
+
+    text
+}
+
+
+/**
+ * Formats the bytes of a section as rows of 16 bytes in hex and ASCII, each annotated with the memory
+ * address of its first byte.
+ */
+fn hex_dump(bytes:&[u8], base_address:usize) -> String {
+    let mut text = String::new();
+    for (row_num, row) in bytes.chunks(16).enumerate() {
+        let hex:Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let ascii:String = row.iter().map(|byte| match byte.is_ascii_graphic() || *byte == b' ' {
+            true => *byte as char,
+            false => '.'
+        }).collect();
+
+        writeln!(text, "  {:04X}  {:<23}  {:<23}  |{}|", base_address + row_num * 16, left.join(" "), right.join(" "), ascii).unwrap();
+    }
+
+    text
+}
+
+
+/**
+ * Produces a hex and ASCII dump of each section of a .sse file, addressed as the sections are laid out in
+ * memory rather than by file offset, except for the bss section, headed by the program's name, version, and author if it has them.
+ */
+pub fn dump(file:&SseFile) -> String {
+    let mut text = String::new();
+    for (key, value) in file.metadata.iter().filter(|(key, _)| PROGRAM_METADATA_KEYS.contains(&key.as_str())) {
+        writeln!(text, "{}: {}", key, value).unwrap();
+    }
+
+    // the bss section has no contents to dump
+    for section in file.sections.iter().filter(|section| section.kind != SseSectionKind::Bss) {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+
+        writeln!(text, "{} ({} bytes at 0x{:04X}):", section.kind.name(), section.contents.len(), section.address).unwrap();
+        text.push_str(&hex_dump(&section.contents, section.address));
+    }
+
+    text
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emit::Endian;
+    use crate::object::{ObjectFile, Relocation, RelocationKind, SectionKind, Symbol};
+    use crate::repr::instruction::{DecodeError, Instruction};
+    use crate::sse::{LineEntry, SseFile, SseSection, SseSectionKind};
+    use super::{disassemble, dump, inspect, inspect_object};
+
+
+    #[test]
+    fn test_disassemble() {
+        let instrs = disassemble(&[0x07, 0xC1, 0x5B, 0x38, 0x02, 0xBC, 0xE8, 0x00, 0xFC], 0x5800, Endian::Big);
+
+        assert_eq!(instrs.len(), 4);
+        assert_eq!((instrs[0].address, instrs[0].instr.clone()), (0x5800, Ok(Instruction::try_from("add ax, bx").unwrap())));
+        assert_eq!((instrs[1].address, instrs[1].instr.clone()), (0x5802, Ok(Instruction::try_from("movi sp, 700").unwrap())));
+        assert_eq!((instrs[2].address, instrs[2].instr.clone()), (0x5806, Err(DecodeError::InvalidOpcodeError(58))));
+        assert_eq!((instrs[3].address, instrs[3].instr.clone()), (0x5808, Err(DecodeError::TruncatedInstructionError)));
+
+        let instrs = disassemble(&[0xC1, 0x07, 0x38, 0x5B, 0xBC, 0x02], 0x5800, Endian::Little);
+        assert_eq!(instrs[0].instr, Ok(Instruction::try_from("add ax, bx").unwrap()));
+        assert_eq!((instrs[1].bytes.clone(), instrs[1].instr.clone()), (vec![0x38, 0x5B, 0xBC, 0x02], Ok(Instruction::try_from("movi sp, 700").unwrap())));
+    }
+
+
+    #[test]
+    fn test_inspect() {
+        let file = SseFile {
+            entry: 0x5802,
+            sections: vec![
+                SseSection::new(SseSectionKind::Data, 0x9000, vec![0x55, 0x66]),
+                SseSection::new(SseSectionKind::Code, 0x5800, vec![0x07, 0xC1, 0xFC, 0x00]),
+                SseSection { kind: SseSectionKind::Bss, address: 0x9002, size: 0x10, contents: vec![] },
+                SseSection::new(SseSectionKind::Named(String::from("tables")), 0x0100, vec![0x01, 0x02, 0x03])
+            ],
+            metadata: vec![(String::from("march"), String::from("sim6"))],
+            lines: vec![LineEntry { address: 0x5800, file: String::from("prog.asm"), line: 3 }],
+            symbols: vec![(String::from("start"), 0x5800)]
+        };
+
+        let text = inspect(&file);
+        assert!(text.contains("\nDebug lines:\n  5800  prog.asm:3\n"));
+        assert!(text.contains("\nSymbols:\n  5800  start\n"));
+        assert!(text.contains("  data  0x9000 - 0x9001  2 bytes\n"));
+        assert!(text.contains("  code  0x5800 - 0x5803  4 bytes\n"));
+        assert!(text.contains("  tables 0x0100 - 0x0102  3 bytes\n"));
+        assert!(text.contains("  bss   0x9002 - 0x9011  16 bytes\n"));
+        assert!(text.contains("\nEntry point: 0x5802\n"));
+        assert!(text.contains("  march         sim6\n"));
+        assert!(text.contains("  5800:  07 C1         add ax, bx\n"));
+        assert!(text.contains("  5802:  FC 00         halt\n"));
+    }
+
+
+    #[test]
+    fn test_inspect_object() {
+        let object = ObjectFile {
+            data: vec![],
+            code: vec![0x5B, 0x00, 0x00, 0x00],
+            symbols: vec![Symbol { name: String::from("start"), section: SectionKind::Code, offset: 0, global: true }],
+            relocations: vec![Relocation { section: SectionKind::Code, offset: 2, symbol: String::from("start"), kind: RelocationKind::Absolute16 }]
+        };
+
+        let text = inspect_object(&object);
+        assert!(text.contains("  code  4 bytes\n"));
+        assert!(text.contains("Symbols:\n  code  +0x0000  global  start\n"));
+        assert!(text.contains("Relocations:\n  code  +0x0002  Absolute16  start\n"));
+        assert!(text.contains("  +0000:  5B 00 00 00   movi ax, 0x0000\n"));
+    }
+
+
+    #[test]
+    fn test_dump() {
+        let mut data = b"Hello world!".to_vec();
+        data.extend_from_slice(&[0x00, 0x01, 0x02, 0x03, 0x7F, 0x41]);
+        let file = SseFile {
+            entry: 0x5800,
+            sections: vec![
+                SseSection::new(SseSectionKind::Data, 0x9000, data),
+                SseSection::new(SseSectionKind::Code, 0x5800, vec![0x07, 0xC1]),
+                SseSection { kind: SseSectionKind::Bss, address: 0x9012, size: 0x10, contents: vec![] },
+                SseSection::new(SseSectionKind::Named(String::from("fast")), 0x0100, vec![0xAA])
+            ],
+            metadata: vec![],
+            lines: vec![],
+            symbols: vec![]
+        };
+
+        let text = dump(&file);
+        assert!(text.starts_with("data (18 bytes at 0x9000):\n"));
+        assert!(text.contains("  9000  48 65 6C 6C 6F 20 77 6F  72 6C 64 21 00 01 02 03  |Hello world!....|\n"));
+        assert!(text.contains("  9010  7F 41                                             |.A|\n"));
+        assert!(text.contains("code (2 bytes at 0x5800):\n  5800  07 C1                                             |..|\n"));
+        assert!(text.contains("\n\nfast (1 bytes at 0x0100):\n  0100  AA"));
+        assert!(!text.contains("bss"));
+    }
+
+
+    #[test]
+    fn test_dump_program_metadata() {
+        let file = SseFile {
+            entry: 0x5800,
+            sections: vec![SseSection::new(SseSectionKind::Data, 0x9000, vec![])],
+            metadata: vec![(String::from("name"), String::from("Pong")), (String::from("git_hash"), String::from("abc1234"))],
+            lines: vec![],
+            symbols: vec![]
+        };
+
+        assert!(dump(&file).starts_with("name: Pong\n\ndata (0 bytes at 0x9000):\n"));
+    }
+}
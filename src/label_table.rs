@@ -1,102 +1,953 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::fs::File;
+use std::error::Error;
+use std::fmt;
 
+use crate::diagnostic::is_print_directive;
+use crate::error::AssemblerError;
+use crate::expr::{evaluate, parse_q_format, split_values, string_literal, unescape};
+use crate::layout::MemoryLayout;
+use crate::messages::{message, MessageId};
+use crate::metadata::is_metadata_directive;
+use crate::object::{RelocationError, SectionKind};
+use crate::repr::instruction::unbracket_memory_operands;
+use crate::repr::opcode::{is_mnemonic, resolve_mnemonic, split_mnemonic_suffix, Opcode};
+use crate::source::{find_label_end, replace_names, SourceLine};
 use crate::validation::validate_label;
 
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolError {
+    UndefinedLabelError(String, Vec<String>),
+    MissingSymbolNameError(String),
+    UndefinedGlobalError(String),
+    DefinedExternError(String),
+    ExternInExecutableError(String),
+    LocalLabelScopeError(String),
+    MissingSectionNameError,
+    UnplacedSectionError(String),
+    InitialisedBssError,
+    EntryExpectsLabelError,
+    DuplicateEntryError(String),
+    EntryInObjectError(String),
+    EntryOutsideCodeError(String),
+    MisalignedDataError(String, usize),
+    InvalidPortError(String),
+    RedefinedPortError(String),
+    JumpOutsideCodeError(String, String),
+    CodeAccessError(String, String)
+}
+
+impl Error for SymbolError {}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::UndefinedLabelError(label, similar) => match similar.is_empty() {
+                true => write!(f, "{}", message(MessageId::UndefinedLabel, &[label])),
+                false => {
+                    let similar:Vec<String> = similar.iter().map(|name| format!("'{}'", name)).collect();
+                    write!(f, "{}", message(MessageId::UndefinedLabelSuggestions, &[label, &similar.join(", ")]))
+                }
+            },
+            SymbolError::MissingSymbolNameError(directive) => write!(f, "{}", message(MessageId::MissingSymbolName, &[directive])),
+            SymbolError::UndefinedGlobalError(label) => write!(f, "{}", message(MessageId::UndefinedGlobal, &[label])),
+            SymbolError::DefinedExternError(label) => write!(f, "{}", message(MessageId::DefinedExtern, &[label])),
+            SymbolError::ExternInExecutableError(label) => write!(f, "{}", message(MessageId::ExternInExecutable, &[label])),
+            SymbolError::LocalLabelScopeError(label) => write!(f, "{}", message(MessageId::LocalLabelScope, &[label])),
+            SymbolError::MissingSectionNameError => write!(f, "{}", message(MessageId::SectionExpectsName, &[])),
+            SymbolError::UnplacedSectionError(name) => write!(f, "{}", message(MessageId::UnplacedSection, &[name])),
+            SymbolError::InitialisedBssError => write!(f, "{}", message(MessageId::InitialisedBss, &[])),
+            SymbolError::EntryExpectsLabelError => write!(f, "{}", message(MessageId::EntryExpectsLabel, &[])),
+            SymbolError::DuplicateEntryError(label) => write!(f, "{}", message(MessageId::DuplicateEntry, &[label])),
+            SymbolError::EntryInObjectError(label) => write!(f, "{}", message(MessageId::EntryInObject, &[label])),
+            SymbolError::EntryOutsideCodeError(label) => write!(f, "{}", message(MessageId::EntryOutsideCode, &[label])),
+            SymbolError::MisalignedDataError(directive, address) => {
+                write!(f, "{}", message(MessageId::MisalignedData, &[directive, &format!("0x{:04X}", address)]))
+            },
+            SymbolError::InvalidPortError(operands) => write!(f, "{}", message(MessageId::InvalidPort, &[operands])),
+            SymbolError::RedefinedPortError(name) => write!(f, "{}", message(MessageId::RedefinedPort, &[name])),
+            SymbolError::JumpOutsideCodeError(mnemonic, label) => write!(f, "{}", message(MessageId::JumpOutsideCode, &[mnemonic, label])),
+            SymbolError::CodeAccessError(mnemonic, label) => write!(f, "{}", message(MessageId::CodeAccess, &[mnemonic, label]))
+        }
+    }
+}
+
+
 /**
- * Takes a filename as input and generates the label table for that file where the label is the key and the 
- * address of the label is the value.
+ * Supplies the value substituted for a `@label` reference when a line is processed.
  */
-pub fn get_label_table(input_file:&File) -> HashMap<String, usize> {
-    let mut lable_table:HashMap<String, usize> = HashMap::new();
+pub trait LabelResolver {
+    fn resolve(&self, label:&str) -> Result<usize, Box<dyn Error>>;
 
-    let mut data_mode = true;
-    let mut code_line_num:usize = 0x5800;
-    let mut data_line_num:usize = 0x9000;
+    /**
+     * Returns the label resolved since the last call if it must be recorded as a relocation, rather than
+     * having been resolved to its final address.
+     */
+    fn take_relocation(&self) -> Option<String> {
+        None
+    }
 
-    // filter out all empty lines and trim away whitespace
-    let input_lines:Vec<String> = BufReader::new(input_file).lines().filter_map(|line| match line.unwrap().trim() {
-        "" => None, 
-        l => Some(l.to_string())
-    }).collect();
+    /**
+     * Returns true if resolved labels are recorded as relocations, in which case a reference can only be
+     * patched if it is a whole operand rather than part of an expression.
+     */
+    fn relocates(&self) -> bool {
+        false
+    }
+}
 
-    for line in input_lines {
-        println!("{}", line);
-        // if the data section has ended, move into code mode
-        if line.contains(".code:") {
-            data_mode = false;
-            continue
+impl LabelResolver for HashMap<String, usize> {
+    fn resolve(&self, label:&str) -> Result<usize, Box<dyn Error>> {
+        match self.get(label) {
+            Some(address) => Ok(*address),
+            None => Err(Box::new(undefined_label_error(label, self.keys())))
         }
+    }
+}
 
-        // if the line is just a label
-        if line.ends_with(":") { 
-            let label = line[..line.len() - 1].to_string();
 
-            validate_label(&label).unwrap();
-            match data_mode {
-                true => lable_table.insert(label, data_line_num),
-                false => lable_table.insert(label, code_line_num)
-            };
-            
+/**
+ * Returns the number of single character insertions, deletions and substitutions needed to turn one name
+ * into the other, ignoring case.
+ */
+fn edit_distance(a:&str, b:&str) -> usize {
+    let a:Vec<char> = a.to_lowercase().chars().collect();
+    let b:Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous:Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + (a_char != b_char) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+
+/**
+ * Builds the error for a reference to an undefined label, suggesting the known labels which are within two
+ * edits of it, closest first, so a misspelling is easy to spot.
+ */
+pub fn undefined_label_error<'a>(label:&str, known_labels:impl Iterator<Item = &'a String>) -> SymbolError {
+    let mut similar:Vec<(usize, &String)> = known_labels.map(|known| (edit_distance(label, known), known))
+                                                        .filter(|(distance, _)| *distance <= 2)
+                                                        .collect();
+    similar.sort();
+
+    SymbolError::UndefinedLabelError(label.to_string(), similar.into_iter().take(3).map(|(_, known)| known.to_owned()).collect())
+}
+
+/**
+ * Where a label was defined: the address it refers to, the section it is in, and the source line it appears on.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelDefinition {
+    pub address: usize,
+    pub section: SectionKind,
+    pub line_num: usize
+}
+
+
+/**
+ * Wraps another `LabelResolver` and remembers every label it resolved along with the value it gave, so
+ * the assembler can report how each reference was resolved.
+ */
+pub struct RecordingResolver<'a, R:LabelResolver> {
+    resolver: &'a R,
+    resolved: RefCell<Vec<(String, usize)>>
+}
+
+impl<'a, R:LabelResolver> RecordingResolver<'a, R> {
+    pub fn new(resolver:&'a R) -> RecordingResolver<'a, R> {
+        RecordingResolver { resolver: resolver, resolved: RefCell::new(vec![]) }
+    }
+
+    pub fn take_resolved(&self) -> Vec<(String, usize)> {
+        std::mem::take(&mut *self.resolved.borrow_mut())
+    }
+}
+
+impl<'a, R:LabelResolver> LabelResolver for RecordingResolver<'a, R> {
+    fn resolve(&self, label:&str) -> Result<usize, Box<dyn Error>> {
+        let value = self.resolver.resolve(label)?;
+        self.resolved.borrow_mut().push((label.to_string(), value));
+        Ok(value)
+    }
+
+    fn take_relocation(&self) -> Option<String> {
+        self.resolver.take_relocation()
+    }
+
+    fn relocates(&self) -> bool {
+        self.resolver.relocates()
+    }
+}
+
+
+/**
+ * The symbols named by `.global`, `.extern` and `.entry` directives in a source file, each with the line of the
+ * directive which named it, along with the I/O ports named by `.port` directives and their numbers.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SymbolDirectives {
+    pub globals: HashMap<String, SourceLine>,
+    pub externs: HashMap<String, SourceLine>,
+    pub entry: Option<(String, SourceLine)>,
+    pub ports: HashMap<String, (usize, SourceLine)>
+}
+
+
+/**
+ * Returns true if the line is a `.global`, `.extern`, `.entry` or `.port` directive, which takes up no space in
+ * any section.
+ */
+pub fn is_symbol_directive(line:&str) -> bool {
+    match line.split_whitespace().next() {
+        Some(".global") | Some(".extern") | Some(".entry") | Some(".port") => true,
+        _ => false
+    }
+}
+
+
+/**
+ * Evaluates the operands of a `.port` directive, such as `UART_TX, 0x05`, giving the name of the port and its
+ * number, which must fit in the 5 bits `in` and `out` give it.
+ */
+fn parse_port(operands:&str) -> Result<(String, usize), Box<dyn Error>> {
+    let invalid = || SymbolError::InvalidPortError(operands.trim().to_string());
+    let operands = operands.trim();
+    let name_end = operands.find(|c:char| c.is_whitespace() || c == ',').ok_or_else(invalid)?;
+    let name = &operands[..name_end];
+    validate_label(name)?;
+
+    let value = operands[name_end..].trim_start().strip_prefix(',').unwrap_or(&operands[name_end..]);
+    match evaluate(value, &HashMap::new()).map_err(|_| invalid())? {
+        number @ 0..=0x1F => Ok((name.to_string(), number as usize)),
+        _ => Err(Box::new(invalid()))
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and collects the symbols exported with `.global` and declared as defined
+ * elsewhere with `.extern`, the label execution starts at given by `.entry`, and the ports named with `.port`.
+ * Each `.global` and `.extern` names one or more symbols separated by whitespace or commas, while a file has at
+ * most one `.entry`, which names a single label, and a port can only be named once.
+ */
+pub fn get_symbol_directives(source_lines:&[SourceLine]) -> Result<SymbolDirectives, AssemblerError> {
+    let mut directives = SymbolDirectives::default();
+
+    for source_line in source_lines {
+        let line = source_line.text.trim();
+        if !is_symbol_directive(line) {
+            continue;
+        }
+
+        if line.split_whitespace().next() == Some(".port") {
+            let (name, number) = parse_port(&line[".port".len()..]).map_err(|err| source_line.error(err))?;
+            if directives.ports.contains_key(&name) {
+                return Err(source_line.error(Box::new(SymbolError::RedefinedPortError(name))));
+            }
+
+            directives.ports.insert(name, (number, source_line.to_owned()));
             continue;
-        } 
-        
-        // if the line is a label and an instruction or data
-        else if let Some(index) = line.find(":") { 
-            let label = line[..index].to_string();
-            validate_label(&label).unwrap();
-
-            line[..line.len() - 1].to_string();
-            match data_mode {
-                true => lable_table.insert(label, data_line_num),
-                false => lable_table.insert(label, code_line_num)
+        }
+
+        let mut tokens = line.split(|c:char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty());
+        let directive = tokens.next().unwrap();
+        let names:Vec<&str> = tokens.collect();
+        if names.is_empty() {
+            let err = SymbolError::MissingSymbolNameError(directive.to_string());
+            return Err(source_line.error(Box::new(err)));
+        }
+
+        if directive == ".entry" {
+            let err = match (names.as_slice(), &directives.entry) {
+                ([_], Some((existing, _))) => SymbolError::DuplicateEntryError(existing.to_owned()),
+                ([name], None) => {
+                    validate_label(name).map_err(|err| source_line.error(err))?;
+                    directives.entry = Some((name.to_string(), source_line.to_owned()));
+                    continue;
+                },
+                _ => SymbolError::EntryExpectsLabelError
             };
+
+            return Err(source_line.error(Box::new(err)));
         }
 
-        if data_mode == true {
-            let data = match line.find(":") {
-                Some(index) => &line[index + 1..],
-                None => &line
+        for name in names {
+            validate_label(name).map_err(|err| source_line.error(err))?;
+            match directive {
+                ".global" => directives.globals.insert(name.to_string(), source_line.to_owned()),
+                _ => directives.externs.insert(name.to_string(), source_line.to_owned())
             };
+        }
+    }
+
+    Ok(directives)
+}
+
+
+/**
+ * Returns the symbols named by `.global` or `.extern` directives in the order of the lines which named them, so
+ * when more than one is wrong the first in the source is always the one reported.
+ */
+pub fn in_source_order(symbols:&HashMap<String, SourceLine>) -> Vec<(&String, &SourceLine)> {
+    let mut symbols:Vec<(&String, &SourceLine)> = symbols.iter().collect();
+    symbols.sort_by_key(|(name, source_line)| (source_line.file.to_owned(), source_line.line_num, name.to_string()));
+    symbols
+}
+
+
+/**
+ * Checks the `.global`, `.extern` and `.entry` directives against the labels defined in the file: every global
+ * and the entry point must be defined in the file and no extern may be, and the entry point must be in the code
+ * section, as execution cannot start in data.
+ */
+pub fn check_symbol_directives(directives:&SymbolDirectives, definitions:&HashMap<String, LabelDefinition>) -> Result<(), AssemblerError> {
+    for (name, source_line) in in_source_order(&directives.globals) {
+        if !definitions.contains_key(name) {
+            return Err(source_line.error(Box::new(SymbolError::UndefinedGlobalError(name.to_owned()))));
+        }
+    }
+
+    for (name, source_line) in in_source_order(&directives.externs) {
+        if definitions.contains_key(name) {
+            return Err(source_line.error(Box::new(SymbolError::DefinedExternError(name.to_owned()))));
+        }
+    }
 
-            let tokens:Vec<&str> = data.split_whitespace().collect();
-            match *tokens.get(0).unwrap() {
-                ".byte" => data_line_num += 1,
-                ".word" => data_line_num += 2,
-                ".long" => data_line_num += 4,
-                ".array" => data_line_num += tokens.len() - 1,
-                ".asciiz" => data_line_num += line[line.find("`").unwrap()..line.len() - 1].len() + 1,
-                invalid => panic!("{} is not a valid datatype", invalid)
+    if let Some((name, source_line)) = &directives.entry {
+        match definitions.get(name) {
+            Some(definition) if definition.section != SectionKind::Code => {
+                return Err(source_line.error(Box::new(SymbolError::EntryOutsideCodeError(name.to_owned()))));
+            },
+            Some(_) => {},
+            None => return Err(source_line.error(Box::new(undefined_label_error(name, definitions.keys()))))
+        }
+    }
+
+    Ok(())
+}
+
+
+/**
+ * Takes the lines of a source file and replaces each port name used in an `in` or `out` instruction, as in
+ * `out al, UART_TX`, with the number of the port.
+ */
+pub fn substitute_port_names(source_lines:Vec<SourceLine>, ports:&HashMap<String, usize>) -> Vec<SourceLine> {
+    if ports.is_empty() {
+        return source_lines;
+    }
+
+    source_lines.into_iter().map(|source_line| {
+        let contents = find_label_end(&source_line.text).map(|index| &source_line.text[index + 1..]).unwrap_or(&source_line.text);
+        let mnemonic = contents.split(|c:char| c.is_whitespace() || c == ',').find(|token| !token.is_empty()).unwrap_or("");
+        match resolve_mnemonic(mnemonic).as_str() {
+            "in" | "out" => {
+                let text = replace_names(&source_line.text, |name, previous, rest| match previous == '@' || rest.starts_with(':') {
+                    true => None,
+                    false => ports.get(name).map(|number| number.to_string())
+                });
+                SourceLine { text: text, ..source_line }
+            },
+            _ => source_line
+        }
+    }).collect()
+}
+
+
+/**
+ * Returns the name of the section started by a `.data:`, `.code:` or `.bss:` marker or a `.section <name>`
+ * directive, or `None` if the line is not one. A file may switch between the sections as often as it likes,
+ * and the lines of each section are placed one after another in the order they appear.
+ */
+pub fn get_section_marker(line:&str) -> Option<&str> {
+    match line.trim() {
+        ".data:" => Some("data"),
+        ".code:" => Some("code"),
+        ".bss:" => Some("bss"),
+        line if line.split_whitespace().next() == Some(".section") => Some(line[".section".len()..].trim()),
+        _ => None
+    }
+}
+
+
+/**
+ * Checks that every section started in the source is placed in the memory layout. The bss and named sections
+ * hold data like the data section, but are only supported in executables, so they are rejected if the file is
+ * assembled as a relocatable object.
+ */
+pub fn check_section_markers(source_lines:&[SourceLine], layout:&MemoryLayout, relocatable:bool) -> Result<(), AssemblerError> {
+    for source_line in source_lines {
+        let name = match get_section_marker(&source_line.text) {
+            Some(name) => name,
+            None => continue
+        };
+
+        let err:Box<dyn Error> = match layout.section_kind(name) {
+            _ if name.is_empty() => Box::new(SymbolError::MissingSectionNameError),
+            None => Box::new(SymbolError::UnplacedSectionError(name.to_string())),
+            Some(SectionKind::Bss) | Some(SectionKind::Custom(_)) if relocatable => Box::new(RelocationError::SectionInObjectError(name.to_string())),
+            Some(_) => continue
+        };
+
+        return Err(source_line.error(err));
+    }
+
+    Ok(())
+}
+
+
+/**
+ * Checks that every label defined in the source is a valid name, so that a label which is malformed or is the
+ * name of a register or mnemonic is reported against the line it is defined on. Section markers such as
+ * `.bss:` are not labels.
+ */
+pub fn check_label_definitions(source_lines:&[SourceLine]) -> Result<(), AssemblerError> {
+    for source_line in source_lines {
+        if get_section_marker(&source_line.text).is_some() {
+            continue;
+        }
+
+        if let Some(label) = get_defined_label(&source_line.text) {
+            validate_label(label).map_err(|err| source_line.error(err))?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/**
+ * Returns true if the line is a `.space` directive, which reserves a number of zero bytes, or a `.fill`
+ * directive, which repeats a byte value a number of times.
+ */
+pub fn is_fill_directive(line:&str) -> bool {
+    match line.split_whitespace().next() {
+        Some(".space") | Some(".fill") => true,
+        _ => false
+    }
+}
+
+
+/**
+ * Splits the contents of a `.space` or `.fill` line into its count and, for `.fill`, the value to repeat.
+ */
+fn split_fill_operands(contents:&str) -> (&str, Option<&str>) {
+    let directive = contents.split_whitespace().next().unwrap_or("");
+    let operands = &contents[directive.len()..];
+    match operands.split_once(',') {
+        Some((count, value)) => (count, Some(value)),
+        None => (operands, None)
+    }
+}
+
+
+/**
+ * Returns the label defined on a line, if any. The text of a `.print` may hold a ':' without defining a label.
+ */
+pub fn get_defined_label(line:&str) -> Option<&str> {
+    let line = line.trim();
+    match is_print_directive(line) {
+        true => None,
+        false => find_label_end(line).map(|index| &line[..index])
+    }
+}
+
+
+/**
+ * Returns the number of bytes taken up by the contents of a `.space` or `.fill` line placed at the given
+ * address. The count may be an expression using the address as `$`, such as `.space 0x6000 - $` to pad up to
+ * 0x6000.
+ */
+fn get_fill_size(contents:&str, address:usize) -> usize {
+    let symbols = HashMap::from([(String::from("$"), address as i64)]);
+    let (count, _) = split_fill_operands(contents);
+    evaluate(count, &symbols).ok().and_then(|size| usize::try_from(size).ok()).unwrap_or(0)
+}
+
+
+/**
+ * Returns the number of bytes a line placed at the given address takes up in the data section or a named
+ * section if `data_mode` is set, or in the code section otherwise. A line holding only a label takes up no space,
+ * and neither does an invalid data line, which is reported when it is assembled.
+ */
+fn get_line_size(line:&str, data_mode:bool, address:usize) -> usize {
+    let contents = match find_label_end(line) {
+        Some(index) => line[index + 1..].trim(),
+        None => line
+    };
+
+    if contents.is_empty() {
+        return 0;
+    }
+
+    if is_fill_directive(contents) {
+        return get_fill_size(contents, address);
+    }
+
+    if data_mode {
+        let tokens:Vec<&str> = contents.split_whitespace().collect();
+        match tokens[0] {
+            ".byte" => 1,
+            ".word" => 2 * split_values(&tokens[1..].join(" ")).len(),
+            ".long" => 4 * split_values(&tokens[1..].join(" ")).len(),
+            ".array" => split_values(&tokens[1..].join(" ")).len(),
+            ".bcd" => tokens[1..].join("").replace('_', "").len().div_ceil(2),
+            ".fixed" => 2 * tokens[1..].iter().filter(|token| parse_q_format(token).is_none()).count(),
+            ".ascii" | ".asciiz" | ".asciip" => {
+                let text = string_literal(contents).unwrap_or(contents.to_string());
+                let extra = match tokens[0] {
+                    ".ascii" => 0,
+                    _ => 1
+                };
+                unescape(&text).map(|bytes| bytes.len()).unwrap_or(text.len()) + extra
+            },
+            _ => 0
+        }
+    }
+
+    // 2 bytes for a 16 bit instr and 4 for a 32 bit instr, as decided by the operand format of its opcode
+    else {
+        let mnemonic = resolve_mnemonic(contents.split(|c:char| c.is_whitespace() || c == ',').next().unwrap_or(""));
+        let mnemonic = split_mnemonic_suffix(&mnemonic).0;
+        match is_mnemonic(mnemonic) {
+            true => Opcode::from(&mnemonic.to_string()).format().size(),
+            false => 2
+        }
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and works out where each one is placed in the given memory layout,
+ * returning the section, address and size of each line, or `None` for section markers and directives which are
+ * not placed.
+ */
+fn get_line_addresses(source_lines:&[SourceLine], layout:&MemoryLayout) -> Vec<Option<(SectionKind, usize, usize)>> {
+    let mut addresses = vec![];
+
+    let mut section = SectionKind::Data;
+    let mut next_addresses:HashMap<SectionKind, usize> = HashMap::new();
+    let mut bss_lines = vec![];
+
+    for source_line in source_lines {
+        // skip empty lines and trim away whitespace
+        let line = match source_line.text.trim() {
+            "" => {
+                addresses.push(None);
+                continue
+            },
+            l => l
+        };
+
+        // the section markers and symbol directives are not placed, and the data section starts the file
+        if let Some(name) = get_section_marker(line) {
+            section = layout.section_kind(name).unwrap_or_else(|| panic!("{}", SymbolError::UnplacedSectionError(name.to_string())));
+            addresses.push(None);
+            continue
+        }
+
+        if is_symbol_directive(line) || is_metadata_directive(line) {
+            addresses.push(None);
+            continue
+        }
+
+        // the bss section follows the data section, so it is placed once the size of the data is known
+        if section == SectionKind::Bss {
+            bss_lines.push((addresses.len(), line));
+            addresses.push(None);
+            continue
+        }
+
+        let address = next_addresses.entry(section).or_insert(layout.region(section).origin);
+        let size = match is_print_directive(line) {
+            true => 0,
+            false => get_line_size(line, section != SectionKind::Code, *address)
+        };
+
+        addresses.push(Some((section, *address, size)));
+        *address += size;
+    }
+
+    let mut address = next_addresses.get(&SectionKind::Data).copied().unwrap_or(layout.data.origin);
+    for (index, line) in bss_lines {
+        let size = match is_print_directive(line) {
+            true => 0,
+            false => get_line_size(line, true, address)
+        };
+
+        addresses[index] = Some((SectionKind::Bss, address, size));
+        address += size;
+    }
+
+    addresses
+}
+
+
+/**
+ * Finds the lines holding a `.word`, `.long` or `.fixed` outside of the code section which are placed at an
+ * odd address, returning the index of each along with its directive and address.
+ */
+fn get_misaligned_lines(source_lines:&[SourceLine], layout:&MemoryLayout) -> Vec<(usize, String, usize)> {
+    source_lines.iter().zip(get_line_addresses(source_lines, layout)).enumerate().filter_map(|(index, (source_line, placement))| {
+        let (section, address, _) = placement?;
+        let contents = find_label_end(&source_line.text).map(|end| &source_line.text[end + 1..]).unwrap_or(&source_line.text);
+        match contents.split_whitespace().next() {
+            Some(directive @ (".word" | ".long" | ".fixed")) if section != SectionKind::Code && address % 2 == 1 => {
+                Some((index, directive.to_string(), address))
+            },
+            _ => None
+        }
+    }).collect()
+}
+
+
+/**
+ * Checks that every `.word`, `.long` and `.fixed` outside of the code section is placed at an even address,
+ * as the Sim6 memory system reads words from even addresses only, returning a warning for each which is not.
+ * If `align` is set, a `.space 1` is placed before each of them instead, along with any lines holding only a
+ * label just before it so the label keeps the address of the value.
+ */
+pub fn align_data_words(source_lines:Vec<SourceLine>, layout:&MemoryLayout, align:bool) -> (Vec<SourceLine>, Vec<AssemblerError>) {
+    if !align {
+        let warnings = get_misaligned_lines(&source_lines, layout).into_iter().map(|(index, directive, address)| {
+            source_lines[index].error(Box::new(SymbolError::MisalignedDataError(directive, address)))
+        }).collect();
+        return (source_lines, warnings);
+    }
+
+    // padding one line moves every line after it, so the addresses are worked out again after each
+    let mut source_lines = source_lines;
+    while let Some((index, _, _)) = get_misaligned_lines(&source_lines, layout).first() {
+        let mut position = *index;
+        while position > 0 && find_label_end(&source_lines[position - 1].text).is_some_and(|end| source_lines[position - 1].text[end + 1..].trim().is_empty()) {
+            position -= 1;
+        }
+
+        let padding = SourceLine { text: String::from(".space 1"), ..source_lines[*index].to_owned() };
+        source_lines.insert(position, padding);
+    }
+
+    (source_lines, vec![])
+}
+
+
+/**
+ * Takes the lines of a source file and works out where each one is placed in the given memory layout,
+ * returning the section and address of every line along with the line itself. Section markers and directives
+ * which take up no space are left out.
+ */
+pub fn get_line_placements<'a>(source_lines:&'a [SourceLine], layout:&MemoryLayout) -> Vec<(&'a SourceLine, SectionKind, usize)> {
+    source_lines.iter()
+                .zip(get_line_addresses(source_lines, layout))
+                .filter_map(|(source_line, placement)| placement.map(|(section, address, _)| (source_line, section, address)))
+                .collect()
+}
+
+
+/**
+ * Replaces a PC-relative reference `@rel(label)` in a line with the expression `(@label - $)`, the signed
+ * offset from the start of the line to the label, returning the label as well if there was one.
+ */
+fn expand_relative_reference(text:&str) -> (String, Option<String>) {
+    let start = match text.find("@rel(") {
+        Some(start) => start,
+        None => return (text.to_string(), None)
+    };
+
+    let rest = &text[start + "@rel(".len()..];
+    match rest.find(')') {
+        Some(end) => {
+            let label = rest[..end].trim();
+            (format!("{}(@{} - $){}", &text[..start], label, &rest[end + 1..]), Some(label.to_string()))
+        },
+        None => (text.to_string(), None)
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and replaces the current address symbol `$` in each line with the address
+ * the line is placed at in the given memory layout, so the label pass and the encoder agree on its value.
+ * A PC-relative reference `@rel(label)` is expanded to the offset `(@label - $)` first.
+ *
+ * The address of a line is not known until the object is linked, so if the lines are assembled into a
+ * `relocatable` object `$` may only be used in the count of a `.space` or `.fill`, or in a `.print`.
+ */
+pub fn substitute_current_address(source_lines:Vec<SourceLine>, layout:&MemoryLayout, relocatable:bool) -> Result<Vec<SourceLine>, AssemblerError> {
+    let addresses = get_line_addresses(&source_lines, layout);
+    let mut output = vec![];
+
+    for (source_line, placement) in source_lines.into_iter().zip(addresses) {
+        let (text, relative_label) = expand_relative_reference(&source_line.text);
+        let source_line = SourceLine { text: text, ..source_line };
+        let address = match (placement, source_line.text.contains('$')) {
+            (Some((_, address, _)), true) => address,
+            _ => {
+                output.push(source_line);
+                continue;
             }
+        };
+
+        let text = replace_names(&source_line.text, |name, _, _| match name {
+            "$" => Some(address.to_string()),
+            _ => None
+        });
+
+        let contents = find_label_end(&source_line.text).map(|index| &source_line.text[index + 1..]).unwrap_or(&source_line.text).trim();
+        let only_in_count = is_fill_directive(contents) && !split_fill_operands(contents).1.unwrap_or("").contains('$');
+        if relocatable && text != source_line.text && !only_in_count && !is_print_directive(&source_line.text) {
+            let err = RelocationError::UnrelocatableReferenceError(relative_label.unwrap_or(String::from("$")));
+            return Err(source_line.error(Box::new(err)));
         }
 
-        // add 2 lines for a 16 bit instr and 4 for a 32 bit instr
-        else {
-            match line.to_lowercase().contains("movi") {
-                true => code_line_num += 4,
-                false => code_line_num += 2
+        output.push(SourceLine { text: text, ..source_line });
+    }
+
+    Ok(output)
+}
+
+
+/**
+ * Takes the lines of a source file as input and finds every label defined in it, along with its address in
+ * the given memory layout and the number of the source line it is defined on.
+ */
+pub fn get_label_definitions(source_lines:&[SourceLine], layout:&MemoryLayout) -> HashMap<String, LabelDefinition> {
+    let mut definitions:HashMap<String, LabelDefinition> = HashMap::new();
+
+    for (source_line, section, address) in get_line_placements(source_lines, layout) {
+        if let Some(label) = get_defined_label(&source_line.text) {
+            definitions.insert(label.to_string(), LabelDefinition { address: address, section: section, line_num: source_line.line_num });
+        }
+    }
+
+    definitions
+}
+
+
+/**
+ * Replaces each local label in a line, defined at the start of the line or referenced after an `@`, with its
+ * name within the given scope. Returns the name of the local label as an error if there is no scope.
+ */
+fn localise_line(text:&str, scope:Option<&str>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find(".L") {
+        let before = &rest[..index];
+        let name_len = rest[index + 2..].find(|c:char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len() - index - 2);
+        let local = &rest[index..index + 2 + name_len];
+        let is_local = name_len > 0 && (before.ends_with('@') || before.ends_with("@rel(") || (result.is_empty() && before.trim().is_empty()));
+
+        result.push_str(before);
+        match (is_local, scope) {
+            (true, Some(scope)) => result.push_str(&format!("{}__L{}", scope, &local[2..])),
+            (true, None) => return Err(local.to_string()),
+            (false, _) => result.push_str(local)
+        }
+        rest = &rest[index + local.len()..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+
+/**
+ * Takes the lines of a source file and gives every local label, such as `.Lloop`, a name of its own within
+ * the scope of the most recent non-local label, so every function can have its own `.Lloop`. A local label is
+ * referred to as `@.Lloop` from anywhere in its scope, including before it is defined.
+ */
+pub fn localise_labels(source_lines:Vec<SourceLine>) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut scope:Option<String> = None;
+    let mut output = vec![];
+
+    for source_line in source_lines {
+        if let Some(label) = get_defined_label(&source_line.text) {
+            // section markers such as `.code:` and local labels do not start a new scope
+            if !label.starts_with('.') {
+                scope = Some(label.to_string());
             }
         }
+
+        match localise_line(&source_line.text, scope.as_deref()) {
+            Ok(text) => output.push(SourceLine { text: text, ..source_line }),
+            Err(local) => return Err(source_line.error(Box::new(SymbolError::LocalLabelScopeError(local))))
+        }
+    }
+
+    Ok(output)
+}
+
+
+/**
+ * Takes the lines of a source file and returns the size of the item at each label, which is the number of
+ * bytes from the label to the next label in the same section, or to the end of the section.
+ */
+pub fn get_label_sizes(source_lines:&[SourceLine], layout:&MemoryLayout) -> HashMap<String, usize> {
+    let mut sizes:HashMap<String, usize> = HashMap::new();
+    let mut current_labels:HashMap<SectionKind, &str> = HashMap::new();
+
+    for (source_line, placement) in source_lines.iter().zip(get_line_addresses(source_lines, layout)) {
+        let (section, _, size) = match placement {
+            Some(placement) => placement,
+            None => continue
+        };
+
+        if let Some(label) = get_defined_label(&source_line.text) {
+            current_labels.insert(section, label);
+        }
+
+        if let Some(label) = current_labels.get(&section) {
+            *sizes.entry(label.to_string()).or_insert(0) += size;
+        }
+    }
+
+    sizes
+}
+
+
+/**
+ * Takes the lines of a source file and replaces each use of `<label>_len` with the size of the item at the
+ * label, so loops over data don't need a hand-counted length. A name which is itself a label is left as it is.
+ */
+pub fn substitute_label_sizes(source_lines:Vec<SourceLine>, sizes:&HashMap<String, usize>) -> Vec<SourceLine> {
+    source_lines.into_iter().map(|source_line| {
+        let text = replace_names(&source_line.text, |name, previous, rest| {
+            match (name.strip_suffix("_len"), previous == '@' || rest.starts_with(':') || sizes.contains_key(name)) {
+                (Some(label), false) => sizes.get(label).map(|size| size.to_string()),
+                _ => None
+            }
+        });
+
+        SourceLine { text: text, ..source_line }
+    }).collect()
+}
+
+
+/**
+ * Takes the label definitions of a source file and generates the label table for that file where the label is
+ * the key and the address of the label is the value.
+ */
+pub fn get_label_table(definitions:&HashMap<String, LabelDefinition>) -> HashMap<String, usize> {
+    definitions.iter().map(|(label, definition)| (label.to_owned(), definition.address)).collect()
+}
+
+
+/**
+ * Lists the labels to store in the symbol table of a .sse file, sorted by address then name. Every label is
+ * kept unless `strip` is set, in which case none are, but if `keep` names any labels only those are kept
+ * whether or not the file is stripped. Naming a label which is not defined is a usage error.
+ */
+pub fn get_kept_symbols(label_table:&HashMap<String, usize>, keep:&[String], strip:bool) -> Result<Vec<(String, usize)>, AssemblerError> {
+    if let Some(name) = keep.iter().find(|name| !label_table.contains_key(*name)) {
+        return Err(AssemblerError::usage(MessageId::UnknownKeptSymbol, &[name]));
+    }
+
+    let mut symbols:Vec<(String, usize)> = match (keep.is_empty(), strip) {
+        (true, true) => vec![],
+        (true, false) => label_table.iter().map(|(name, address)| (name.to_owned(), *address)).collect(),
+        (false, _) => keep.iter().map(|name| (name.to_owned(), label_table[name])).collect()
+    };
+
+    symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    symbols.dedup();
+    Ok(symbols)
+}
+
+
+/**
+ * Splits an instruction into its mnemonic, without any suffix, and its operands, reading the bracketed address
+ * of a `load` or `store` as its 2nd operand. Returns `None` if the line holds no instruction.
+ */
+fn split_instruction(text:&str) -> Option<(String, Vec<String>)> {
+    let contents = find_label_end(text).map(|index| &text[index + 1..]).unwrap_or(text).trim();
+    let (name, operands) = contents.split_once(char::is_whitespace).unwrap_or((contents, ""));
+    if !is_mnemonic(name) {
+        return None;
     }
 
-    lable_table
+    let mnemonic = split_mnemonic_suffix(&resolve_mnemonic(name)).0.to_string();
+    let operands = match mnemonic.as_str() {
+        "load" | "store" => unbracket_memory_operands(operands).ok().flatten().unwrap_or(operands.to_string()),
+        _ => operands.to_string()
+    };
+    let operands = operands.split(|c:char| c.is_whitespace() || c == ',').filter(|operand| !operand.is_empty()).map(|operand| operand.to_string()).collect();
+
+    Some((mnemonic, operands))
 }
 
 
+/**
+ * Takes the lines of a source file, once pseudo-instructions are expanded, and checks the section of each label
+ * loaded into a register with `movi` which the next instruction uses as its address, as in `movi dx, @print`
+ * followed by `call dx`. A jump or call must land in the code section, so one to any other section is an error,
+ * while a `load` or `store` from the code section is likely a mistake, so a warning is returned for each. Labels
+ * which are not defined, such as externs, are left alone.
+ */
+pub fn check_label_targets(source_lines:&[SourceLine], definitions:&HashMap<String, LabelDefinition>) -> Result<Vec<AssemblerError>, AssemblerError> {
+    let instructions:Vec<(&SourceLine, (String, Vec<String>))> = source_lines.iter()
+                                                                             .filter_map(|line| split_instruction(&line.text).map(|instr| (line, instr)))
+                                                                             .collect();
+    let mut warnings = vec![];
+
+    for pair in instructions.windows(2) {
+        let ((_, (mnemonic, operands)), (line, (next_mnemonic, next_operands))) = (&pair[0], &pair[1]);
+        let (register, label) = match (mnemonic.as_str(), operands.as_slice()) {
+            ("movi", [register, label]) if label.starts_with('@') => (register, &label[1..]),
+            _ => continue
+        };
+
+        let section = match definitions.get(label) {
+            Some(definition) => definition.section,
+            None => continue
+        };
+
+        let opcode = Opcode::from(next_mnemonic);
+        let address = match opcode {
+            Opcode::Load | Opcode::Store => next_operands.get(1),
+            _ if opcode.is_jump() => next_operands.first(),
+            _ => None
+        };
+        if !address.is_some_and(|address| address.eq_ignore_ascii_case(register)) {
+            continue;
+        }
+
+        match (opcode, section == SectionKind::Code) {
+            (Opcode::Load | Opcode::Store, true) => warnings.push(line.error(Box::new(SymbolError::CodeAccessError(next_mnemonic.to_owned(), label.to_string())))),
+            (Opcode::Load | Opcode::Store, false) | (_, true) => {},
+            (_, false) => return Err(line.error(Box::new(SymbolError::JumpOutsideCodeError(next_mnemonic.to_owned(), label.to_string()))))
+        }
+    }
+
+    Ok(warnings)
+}
+
 
 #[cfg(test)]
 mod tests {
-    use std::fs::OpenOptions;
+    use std::collections::HashSet;
+    use std::path::Path;
 
-    use super::get_label_table;
+    use crate::layout::MemoryLayout;
+    use crate::object::SectionKind;
+
+    use crate::assembler::read_source;
+    use crate::source::to_source_lines;
+    use super::*;
 
 
     #[test]
     fn test_label_table_generation() {
-        let input_file = OpenOptions::new().read(true).open("test_files/test_label_table_gen.asm").unwrap();
-        let label_table = get_label_table(&input_file);
+        let source_lines = read_source(Path::new("test_files/test_label_table_gen.asm")).unwrap();
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &MemoryLayout::default()));
 
         assert_eq!(label_table["my_byte"], 0x9000);
         assert_eq!(label_table["my_word"], 0x9001);
@@ -112,9 +963,251 @@ mod tests {
 
 
     #[test]
-    #[should_panic]
+    fn test_label_definition_lines() {
+        let source_lines = read_source(Path::new("test_files/test_label_table_gen.asm")).unwrap();
+        let definitions = get_label_definitions(&source_lines, &MemoryLayout::default());
+
+        assert_eq!(definitions["my_byte"], LabelDefinition { address: 0x9000, section: SectionKind::Data, line_num: 2 });
+        assert_eq!(definitions["start"].address, 0x5800);
+        assert_eq!(source_lines[definitions["label_4"].line_num - 1].text.trim_start().split(':').next(), Some("label_4"));
+    }
+
+
+    #[test]
+    fn test_symbol_directives() {
+        let source_lines = to_source_lines(".global start, helper\n.code:\nstart:\n.extern print\nmovi ax @print");
+        let directives = get_symbol_directives(&source_lines).unwrap();
+
+        assert_eq!(directives.globals.keys().collect::<HashSet<_>>(), HashSet::from([&String::from("start"), &String::from("helper")]));
+        assert_eq!(directives.externs["print"].line_num, 4);
+
+        let definitions = get_label_definitions(&source_lines, &MemoryLayout::default());
+        assert_eq!(definitions.keys().collect::<Vec<_>>(), vec!["start"]);
+        assert_eq!(check_symbol_directives(&directives, &definitions).unwrap_err().to_string(), "line 1: '.global helper' names a label which is not defined in this file");
+
+        assert!(get_symbol_directives(&to_source_lines(".extern")).is_err());
+        assert!(get_symbol_directives(&to_source_lines(".global 9lives")).is_err());
+
+        // of several undefined globals, the first in the source is reported
+        let source_lines = to_source_lines(".global zeta\n.global alpha, beta\n.extern gamma, delta\n.code:\ngamma:\ndelta:\nhalt");
+        let directives = get_symbol_directives(&source_lines).unwrap();
+        let definitions = get_label_definitions(&source_lines, &MemoryLayout::default());
+        assert_eq!(check_symbol_directives(&directives, &definitions).unwrap_err().to_string(), "line 1: '.global zeta' names a label which is not defined in this file");
+
+        let names:Vec<&str> = in_source_order(&directives.globals).into_iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["zeta", "alpha", "beta"]);
+    }
+
+
+    #[test]
+    fn test_label_targets() {
+        let layout = MemoryLayout::default();
+        let source = ".data:\ntable: .word 1\n.code:\nstart: movi dx, @print\ncall dx\nmovi cx @table\nload ax, [cx]\n\
+                      movi bx, @print\nstore al, BX\nprint: movi dx, @table\nmovi ax, 5\njump dx\nret";
+        let source_lines = to_source_lines(source);
+        let warnings = check_label_targets(&source_lines, &get_label_definitions(&source_lines, &layout)).unwrap();
+        let warnings:Vec<String> = warnings.into_iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, vec!["line 9: 'store' accesses 'print', which is in the code section rather than the data"]);
+
+        let source_lines = to_source_lines(".data:\ntable: .word 1\n.code:\nmovi dx, @table\njeq dx\nmovi dx, @undefined\njump dx");
+        let err = check_label_targets(&source_lines, &get_label_definitions(&source_lines, &layout)).unwrap_err();
+        assert_eq!(err.to_string(), "line 5: 'jeq' jumps to 'table', which is not in the code section");
+    }
+
+
+    #[test]
+    fn test_port_directives() {
+        let source_lines = to_source_lines(".port UART_TX, 0x05\n.port LEDS 1 + 2\n.code:\nout al, UART_TX\nsend: in bl LEDS\nmovi ax, UART_TX");
+        let directives = get_symbol_directives(&source_lines).unwrap();
+        assert_eq!(directives.ports["UART_TX"].0, 5);
+        assert_eq!(directives.ports["LEDS"].0, 3);
+
+        let ports = directives.ports.iter().map(|(name, (number, _))| (name.to_owned(), *number)).collect();
+        let texts:Vec<String> = substitute_port_names(source_lines, &ports).into_iter().map(|line| line.text).collect();
+        assert_eq!(&texts[3..], &["out al, 5", "send: in bl 3", "movi ax, UART_TX"]);
+
+        let error = |source:&str| get_symbol_directives(&to_source_lines(source)).unwrap_err().to_string();
+        assert_eq!(error(".port UART_TX, 32"), "line 1: '.port' expects a name and a port number from 0 to 31, as in '.port UART_TX, 0x05', found 'UART_TX, 32'");
+        assert_eq!(error(".port UART_TX"), "line 1: '.port' expects a name and a port number from 0 to 31, as in '.port UART_TX, 0x05', found 'UART_TX'");
+        assert_eq!(error(".port LEDS, 1\n.port LEDS, 2"), "line 2: Port 'LEDS' is already defined");
+    }
+
+
+    #[test]
+    fn test_entry_directive() {
+        let source_lines = to_source_lines(".code:\nsetup: nop\nmain: nop\n.entry main");
+        let directives = get_symbol_directives(&source_lines).unwrap();
+        assert_eq!(directives.entry.as_ref().map(|(name, line)| (name.as_str(), line.line_num)), Some(("main", 4)));
+
+        let definitions = get_label_definitions(&source_lines, &MemoryLayout::default());
+        assert_eq!(get_label_table(&definitions)["main"], 0x5802);
+        assert!(check_symbol_directives(&directives, &definitions).is_ok());
+
+        let directives = get_symbol_directives(&to_source_lines(".entry mian")).unwrap();
+        assert_eq!(check_symbol_directives(&directives, &definitions).unwrap_err().to_string(), "line 1: Label 'mian' is not defined, did you mean 'main'?");
+
+        let source_lines = to_source_lines(".data:
+table: .word 1
+.code:
+main: nop
+.entry table");
+        let directives = get_symbol_directives(&source_lines).unwrap();
+        let definitions = get_label_definitions(&source_lines, &MemoryLayout::default());
+        assert_eq!(check_symbol_directives(&directives, &definitions).unwrap_err().to_string(),
+                   "line 5: '.entry table' names a label outside the code section, and execution can only start in code");
+
+        let error = |source:&str| get_symbol_directives(&to_source_lines(source)).unwrap_err().to_string();
+        assert_eq!(error(".entry main, setup"), "line 1: '.entry' expects exactly one label");
+        assert_eq!(error(".entry main\n.entry setup"), "line 2: The entry point is already set by '.entry main'");
+    }
+
+
+    #[test]
+    fn test_current_address() {
+        let layout = MemoryLayout::default();
+        let source = ".data:\nhere: .word $\nmsg: .asciiz `costs $5`\n.code:\nnop\npadding: .space 0x5810 - $\nend: movi ax $ + 4";
+        let source_lines = substitute_current_address(to_source_lines(source), &layout, false).unwrap();
+        let texts:Vec<&str> = source_lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec![".data:", "here: .word 36864", "msg: .asciiz `costs $5`", ".code:", "nop", "padding: .space 0x5810 - 22530", "end: movi ax 22544 + 4"]);
+
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &layout));
+        assert_eq!(label_table["end"], 0x5810);
+
+        let source_lines = to_source_lines(".code:\npadding: .space 0x5810 - $\n.fill 0x5820 - $, 0xFF\n.print $\nmovi ax $");
+        let err = substitute_current_address(source_lines, &layout, true).unwrap_err();
+        assert_eq!(err.to_string(), "line 5: Label '$' cannot be relocated in this position");
+        assert!(substitute_current_address(to_source_lines(".code:\n.fill 2, lo($)"), &layout, true).is_err());
+
+        let source_lines = to_source_lines(".code:\nback: nop\nmovi ax @rel(back)\nmovi bx @rel( ahead ) + 4\nahead:");
+        let source_lines = substitute_current_address(source_lines, &layout, false).unwrap();
+        assert_eq!(source_lines[2].text, "movi ax (@back - 22530)");
+        assert_eq!(source_lines[3].text, "movi bx (@ahead - 22534) + 4");
+
+        let err = substitute_current_address(to_source_lines(".code:\nmovi ax @rel(back)"), &layout, true).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: Label 'back' cannot be relocated in this position");
+    }
+
+
+    #[test]
+    fn test_label_sizes() {
+        let layout = MemoryLayout::default();
+        let source = ".data:\nmsg: .asciiz `hello`\ntable:\n.byte 1\n.word 2 -3\n.code:\nstart: movi cx msg_len\nmovi dx table_len\nloop: nop";
+        let source_lines = to_source_lines(source);
+        let sizes = get_label_sizes(&source_lines, &layout);
+
+        assert_eq!(sizes, HashMap::from([
+            (String::from("msg"), 6), (String::from("table"), 5), (String::from("start"), 8), (String::from("loop"), 2)
+        ]));
+
+        let source_lines = substitute_label_sizes(source_lines, &sizes);
+        assert_eq!(source_lines[6].text, "start: movi cx 6");
+        assert_eq!(source_lines[7].text, "movi dx 5");
+
+        let source = ".data:\nmsg: .asciiz `time: \\`\\x41\\`\\n`\n.asciiz `a:b`\nname: .ascii \"ab\\tc\"\ncount: .asciip `abc`\nchars: .array 'a' ' ' 'c'\nend:";
+        let sizes = get_label_sizes(&to_source_lines(source), &layout);
+        assert_eq!(sizes, HashMap::from([
+            (String::from("msg"), 15), (String::from("name"), 4), (String::from("count"), 4), (String::from("chars"), 3),
+            (String::from("end"), 0)
+        ]));
+
+        let sizes = get_label_sizes(&to_source_lines(".code:\nmovie: nop\nload: MOVI.W ax, 5\nend:"), &layout);
+        assert_eq!(sizes, HashMap::from([(String::from("movie"), 2), (String::from("load"), 4), (String::from("end"), 0)]));
+    }
+
+
+    #[test]
+    fn test_align_data_words() {
+        let layout = MemoryLayout::default();
+        let source = ".data:\nflag: .byte 1\ntable:\n.word 2\n.long 3\n.byte 4\nfrac: .fixed 1.5\n.code:\nnop";
+
+        let (_, warnings) = align_data_words(to_source_lines(source), &layout, false);
+        let warnings:Vec<String> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "line 4: '.word' is placed at the odd address 0x9001, but Sim6 reads words from even addresses only; '--align-data' pads it to the next even address",
+            "line 5: '.long' is placed at the odd address 0x9003, but Sim6 reads words from even addresses only; '--align-data' pads it to the next even address"
+        ]);
+
+        let (source_lines, warnings) = align_data_words(to_source_lines(source), &layout, true);
+        let texts:Vec<&str> = source_lines.iter().map(|line| line.text.as_str()).collect();
+        assert!(warnings.is_empty());
+        assert_eq!(texts, vec![".data:", "flag: .byte 1", ".space 1", "table:", ".word 2", ".long 3", ".byte 4", ".space 1", "frac: .fixed 1.5", ".code:", "nop"]);
+        assert_eq!(get_label_table(&get_label_definitions(&source_lines, &layout))["frac"], 0x900A);
+    }
+
+
+    #[test]
+    fn test_check_section_markers() {
+        let layout = MemoryLayout::parse("region ROM 0x5800 0x3800\nregion RAM 0x9000 0x7000\nregion FAST 0x0100 0x100\n\
+                                          place code ROM\nplace data RAM\nplace fast_ram FAST").unwrap();
+        let source_lines = to_source_lines(".data:\n.section fast_ram\n.code:\n.section code");
+        assert!(check_section_markers(&source_lines, &layout, false).is_ok());
+
+        let error = |source:&str, relocatable:bool| check_section_markers(&to_source_lines(source), &layout, relocatable).unwrap_err().to_string();
+        assert_eq!(error(".code:\n.section", false), "line 2: '.section' expects a section name");
+        assert_eq!(error(".section rom_tables", false), "line 1: Section 'rom_tables' is not placed in the memory layout");
+        assert_eq!(error(".section fast_ram", true), "line 1: Section 'fast_ram' cannot be used in an object file, which only holds code and data");
+    }
+
+
+    #[test]
+    fn test_local_labels() {
+        let source = ".code:\nfirst: nop\n.Lloop: movi ax @.Lend\n.Lend:\nsecond:\n  .Lloop: movi bx @.Lloop\n.print `.Lloop`";
+        let source_lines = localise_labels(to_source_lines(source)).unwrap();
+        let texts:Vec<&str> = source_lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec![".code:", "first: nop", "first__Lloop: movi ax @first__Lend", "first__Lend:", "second:", "  second__Lloop: movi bx @second__Lloop", ".print `.Lloop`"]);
+
+        let label_table = get_label_table(&get_label_definitions(&source_lines, &MemoryLayout::default()));
+        assert_eq!(label_table["first__Lend"], 0x5806);
+        assert_eq!(label_table["second__Lloop"], 0x5806);
+
+        let err = localise_labels(to_source_lines(".code:\n.Lloop: nop")).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: Local label '.Lloop' must follow a non-local label");
+    }
+
+
+    #[test]
+    fn test_undefined_label() {
+        let label_table = HashMap::from([
+            (String::from("counter"), 0x9000), (String::from("Counter2"), 0x9002), (String::from("start"), 0x5800)
+        ]);
+
+        assert_eq!(label_table.resolve("counter").unwrap(), 0x9000);
+        assert_eq!(label_table.resolve("countr").unwrap_err().to_string(), "Label 'countr' is not defined, did you mean 'counter', 'Counter2'?");
+        assert_eq!(label_table.resolve("finish").unwrap_err().to_string(), "Label 'finish' is not defined");
+        assert_eq!(edit_distance("loop", "LOOP"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+
+    #[test]
+    fn test_kept_symbols() {
+        let label_table = HashMap::from([
+            (String::from("start"), 0x5800), (String::from("loop"), 0x5804), (String::from("table"), 0x9000), (String::from("begin"), 0x5800)
+        ]);
+        let keep = vec![String::from("table"), String::from("start")];
+
+        assert_eq!(get_kept_symbols(&label_table, &[], false).unwrap(), vec![
+            (String::from("begin"), 0x5800), (String::from("start"), 0x5800), (String::from("loop"), 0x5804), (String::from("table"), 0x9000)
+        ]);
+        assert_eq!(get_kept_symbols(&label_table, &[], true).unwrap(), vec![]);
+        assert_eq!(get_kept_symbols(&label_table, &keep, false).unwrap(), vec![(String::from("start"), 0x5800), (String::from("table"), 0x9000)]);
+        assert_eq!(get_kept_symbols(&label_table, &keep, true).unwrap(), vec![(String::from("start"), 0x5800), (String::from("table"), 0x9000)]);
+        assert_eq!(get_kept_symbols(&label_table, &[String::from("end")], true).unwrap_err().to_string(),
+                   "'--keep-symbols' names 'end', which is not a label defined in the file");
+    }
+
+
+    #[test]
     fn test_invalid_label() {
-        let input_file = OpenOptions::new().read(true).open("test_files/test_invalid_label.asm").unwrap();
-        let _ = get_label_table(&input_file);
+        let source_lines = read_source(Path::new("test_files/test_invalid_label.asm")).unwrap();
+        assert!(check_label_definitions(&source_lines).is_err());
+
+        for text in ["ax: halt", "1abc: halt", "my-label: halt"] {
+            let source_lines = vec![SourceLine::new(text, 1)];
+            assert!(check_label_definitions(&source_lines).unwrap_err().to_string().starts_with("line 1: "));
+        }
+
+        let source_lines = to_source_lines(".data:\nstart: .byte 1\n.bss:\nbuffer: .space 16\n.code:\nhalt");
+        assert!(check_label_definitions(&source_lines).is_ok());
     }
 }
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::messages::{message, MessageId};
+use crate::object::SectionKind;
+
+
+/**
+ * The size of the Sim6 address space, which every memory region must fit inside.
+ */
+const ADDRESS_SPACE_SIZE:usize = 0x10000;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+    InvalidLineError(usize),
+    InvalidNumberError(usize, String),
+    UnknownRegionError(usize, String),
+    UnknownSectionError(usize, String),
+    RegionOutOfRangeError(String),
+    OverlappingRegionsError(String, String),
+    MissingPlacementError(String),
+    SharedRegionError(String),
+    SectionOverflowError(String, usize, String, usize),
+    SectionBelowImageBaseError(String, usize, usize),
+    ImageTooLargeError(usize, usize),
+    AddressOverflowError(String, usize)
+}
+
+impl Error for LayoutError {}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::InvalidLineError(line_num) => write!(f, "{}", message(MessageId::InvalidLayoutLine, &[line_num])),
+            LayoutError::InvalidNumberError(line_num, text) => write!(f, "{}", message(MessageId::InvalidLayoutNumber, &[line_num, text])),
+            LayoutError::UnknownRegionError(line_num, name) => write!(f, "{}", message(MessageId::UnknownRegion, &[line_num, name])),
+            LayoutError::UnknownSectionError(line_num, name) => write!(f, "{}", message(MessageId::UnknownSection, &[line_num, name])),
+            LayoutError::RegionOutOfRangeError(name) => write!(f, "{}", message(MessageId::RegionOutOfRange, &[name])),
+            LayoutError::OverlappingRegionsError(first, second) => write!(f, "{}", message(MessageId::OverlappingRegions, &[first, second])),
+            LayoutError::MissingPlacementError(section) => write!(f, "{}", message(MessageId::MissingPlacement, &[section])),
+            LayoutError::SharedRegionError(name) => write!(f, "{}", message(MessageId::SharedRegion, &[name])),
+            LayoutError::SectionOverflowError(section, size, region, length) => {
+                write!(f, "{}", message(MessageId::SectionOverflow, &[section, size, region, &format!("0x{:04X}", length)]))
+            },
+            LayoutError::SectionBelowImageBaseError(section, address, base) => {
+                write!(f, "{}", message(MessageId::SectionBelowImageBase, &[section, &format!("0x{:04X}", address), &format!("0x{:04X}", base)]))
+            },
+            LayoutError::ImageTooLargeError(needed, size) => write!(f, "{}", message(MessageId::ImageTooLarge, &[needed, size])),
+            LayoutError::AddressOverflowError(format, address) => {
+                write!(f, "{}", message(MessageId::AddressOverflow, &[&format!("0x{:04X}", address), format]))
+            }
+        }
+    }
+}
+
+
+/**
+ * A named range of the address space which a section can be placed in.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: usize,
+    pub length: usize
+}
+
+
+/**
+ * Where the code and data sections are loaded into memory, and how much room each has, along with the named
+ * sections started with `.section`, in the order they are placed.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub code: MemoryRegion,
+    pub data: MemoryRegion,
+    pub sections: Vec<(String, MemoryRegion)>
+}
+
+impl Default for MemoryLayout {
+    /**
+     * The layout the Sim6 emulator loads programs with: code in ROM from 0x5800 and data in RAM from 0x9000.
+     */
+    fn default() -> MemoryLayout {
+        MemoryLayout {
+            code: MemoryRegion { name: String::from("ROM"), origin: 0x5800, length: 0x3800 },
+            data: MemoryRegion { name: String::from("RAM"), origin: 0x9000, length: 0x7000 },
+            sections: vec![]
+        }
+    }
+}
+
+
+/**
+ * Parses a hexadecimal number starting with "0x" or a decimal number.
+ */
+fn parse_number(text:&str, line_num:usize) -> Result<usize, LayoutError> {
+    let result = match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse()
+    };
+
+    result.map_err(|_| LayoutError::InvalidNumberError(line_num, text.to_string()))
+}
+
+
+/**
+ * Returns true if the name can be given to a section with `.section`, which like a label is a letter or
+ * underscore followed by letters, digits and underscores.
+ */
+fn is_section_name(name:&str) -> bool {
+    name.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+
+/**
+ * Checks that every region fits in the address space and that no two regions overlap.
+ */
+fn check_regions(regions:&[MemoryRegion]) -> Result<(), LayoutError> {
+    for (index, region) in regions.iter().enumerate() {
+        if region.origin + region.length > ADDRESS_SPACE_SIZE {
+            return Err(LayoutError::RegionOutOfRangeError(region.name.to_owned()));
+        }
+
+        for other in &regions[index + 1..] {
+            if region.origin < other.origin + other.length && other.origin < region.origin + region.length {
+                return Err(LayoutError::OverlappingRegionsError(region.name.to_owned(), other.name.to_owned()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+impl MemoryLayout {
+    /**
+     * Parses a layout file. Each line either declares a region as `region <name> <origin> <length>` or places
+     * a section in a region as `place <section> <region>`, and everything after a `#` is a comment. The section
+     * is `code`, `data`, or the name of a section started with `.section`. Regions must fit in the address space
+     * without overlapping, and every section must be placed in a different region.
+     */
+    pub fn parse(text:&str) -> Result<MemoryLayout, LayoutError> {
+        let mut regions:Vec<MemoryRegion> = vec![];
+        let mut code = None;
+        let mut data = None;
+        let mut sections:Vec<(String, MemoryRegion)> = vec![];
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line_num = line_num + 1;
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line
+            };
+
+            let tokens:Vec<&str> = line.split_whitespace().collect();
+            match tokens.as_slice() {
+                [] => {},
+                ["region", name, origin, length] => regions.push(MemoryRegion {
+                    name: name.to_string(),
+                    origin: parse_number(origin, line_num)?,
+                    length: parse_number(length, line_num)?
+                }),
+
+                ["place", section, name] => {
+                    let region = match regions.iter().find(|region| region.name == *name) {
+                        Some(region) => region.to_owned(),
+                        None => return Err(LayoutError::UnknownRegionError(line_num, name.to_string()))
+                    };
+
+                    match *section {
+                        "code" => code = Some(region),
+                        "data" => data = Some(region),
+                        _ if is_section_name(section) && *section != "bss" => {
+                            sections.retain(|(name, _)| name != section);
+                            sections.push((section.to_string(), region));
+                        },
+                        _ => return Err(LayoutError::UnknownSectionError(line_num, section.to_string()))
+                    }
+                },
+
+                _ => return Err(LayoutError::InvalidLineError(line_num))
+            }
+        }
+
+        check_regions(&regions)?;
+        let code = code.ok_or(LayoutError::MissingPlacementError(String::from("code")))?;
+        let data = data.ok_or(LayoutError::MissingPlacementError(String::from("data")))?;
+        let layout = MemoryLayout {
+            code: code,
+            data: data,
+            sections: sections
+        };
+
+        let placed = layout.regions();
+        for (index, region) in placed.iter().enumerate() {
+            if placed[index + 1..].iter().any(|other| other.name == region.name) {
+                return Err(LayoutError::SharedRegionError(region.name.to_owned()));
+            }
+        }
+
+        Ok(layout)
+    }
+
+
+    /**
+     * Returns the region of every section in the layout, starting with the code and data sections.
+     */
+    fn regions(&self) -> Vec<MemoryRegion> {
+        let mut regions = vec![self.code.to_owned(), self.data.to_owned()];
+        regions.extend(self.sections.iter().map(|(_, region)| region.to_owned()));
+        regions
+    }
+
+
+    /**
+     * Returns the section a `.data:`, `.code:`, `.bss:` or `.section` marker with the given name starts, or
+     * `None` if the layout does not place a section of that name.
+     */
+    pub fn section_kind(&self, name:&str) -> Option<SectionKind> {
+        match name {
+            "data" => Some(SectionKind::Data),
+            "code" => Some(SectionKind::Code),
+            "bss" => Some(SectionKind::Bss),
+            _ => self.sections.iter().position(|(section, _)| section == name).map(SectionKind::Custom)
+        }
+    }
+
+
+    pub fn section_name(&self, section:SectionKind) -> &str {
+        match section {
+            SectionKind::Data => "data",
+            SectionKind::Code => "code",
+            SectionKind::Bss => "bss",
+            SectionKind::Custom(index) => &self.sections[index].0
+        }
+    }
+
+
+    /**
+     * Returns the region the section is placed in, where the bss section shares the region of the data section.
+     */
+    pub fn region(&self, section:SectionKind) -> &MemoryRegion {
+        match section {
+            SectionKind::Data | SectionKind::Bss => &self.data,
+            SectionKind::Code => &self.code,
+            SectionKind::Custom(index) => &self.sections[index].1
+        }
+    }
+
+
+    /**
+     * Returns the layout with the code and data regions moved to the given base addresses, as set on the
+     * command line for boards with a different memory map. A moved region keeps its length unless that would
+     * take it past the start of the other region or the end of the address space, and the regions must still
+     * not overlap.
+     */
+    pub fn rebase(&self, code_base:Option<usize>, data_base:Option<usize>) -> Result<MemoryLayout, LayoutError> {
+        let mut layout = self.to_owned();
+        layout.code.origin = code_base.unwrap_or(layout.code.origin);
+        layout.data.origin = data_base.unwrap_or(layout.data.origin);
+
+        let (code_origin, data_origin) = (layout.code.origin, layout.data.origin);
+        for (region, base, other_origin) in [(&mut layout.code, code_base, data_origin), (&mut layout.data, data_base, code_origin)] {
+            let end = match other_origin > region.origin {
+                true => other_origin,
+                false => ADDRESS_SPACE_SIZE
+            };
+
+            if base.is_some() {
+                region.length = region.length.min(end - region.origin);
+            }
+        }
+
+        check_regions(&layout.regions())?;
+        Ok(layout)
+    }
+
+
+    /**
+     * Checks that sections of the given sizes fit in the regions they are placed in, along with any sections
+     * before them in the same region.
+     */
+    pub fn check_fits(&self, sizes:&[(SectionKind, usize)]) -> Result<(), LayoutError> {
+        let mut used:HashMap<&str, usize> = HashMap::new();
+        for (section, size) in sizes {
+            let region = self.region(*section);
+            let used = used.entry(region.name.as_str()).or_insert(0);
+            *used += size;
+            if *used > region.length {
+                return Err(LayoutError::SectionOverflowError(self.section_name(*section).to_string(), *size, region.name.to_owned(), region.length));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_parse_layout() {
+        let layout = MemoryLayout::parse("# the default layout\nregion ROM 0x5800 0x3800\nregion RAM 36864 0x7000  # data\n\nplace code ROM\nplace data RAM\n").unwrap();
+        assert_eq!(layout, MemoryLayout::default());
+
+        let layout = MemoryLayout::parse("region LOW 0x0000 0x1000\nregion HIGH 0x8000 0x100\nplace data LOW\nplace code HIGH").unwrap();
+        assert_eq!(layout.code, MemoryRegion { name: String::from("HIGH"), origin: 0x8000, length: 0x100 });
+        assert_eq!(layout.data.origin, 0);
+
+        let layout = MemoryLayout::parse("region ROM 0x5800 0x3800\nregion RAM 0x9000 0x7000\nregion FAST 0x0100 0x100\n\
+                                          place code ROM\nplace data RAM\nplace fast_ram FAST").unwrap();
+        assert_eq!(layout.sections, vec![(String::from("fast_ram"), MemoryRegion { name: String::from("FAST"), origin: 0x0100, length: 0x100 })]);
+        assert_eq!((layout.section_kind("fast_ram"), layout.section_kind("code"), layout.section_kind("slow")), (Some(SectionKind::Custom(0)), Some(SectionKind::Code), None));
+        assert_eq!(layout.region(SectionKind::Custom(0)).origin, 0x0100);
+    }
+
+
+    #[test]
+    fn test_invalid_layout() {
+        assert_eq!(MemoryLayout::parse("region ROM 0x5800"), Err(LayoutError::InvalidLineError(1)));
+        assert_eq!(MemoryLayout::parse("region ROM 0x58G0 0x10"), Err(LayoutError::InvalidNumberError(1, String::from("0x58G0"))));
+        assert_eq!(MemoryLayout::parse("place code ROM"), Err(LayoutError::UnknownRegionError(1, String::from("ROM"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nplace 2fast A"), Err(LayoutError::UnknownSectionError(2, String::from("2fast"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nplace bss A"), Err(LayoutError::UnknownSectionError(2, String::from("bss"))));
+        assert_eq!(MemoryLayout::parse("region A 0xFF00 0x200\nplace code A"), Err(LayoutError::RegionOutOfRangeError(String::from("A"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nregion B 0x80 0x100"), Err(LayoutError::OverlappingRegionsError(String::from("A"), String::from("B"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nplace code A"), Err(LayoutError::MissingPlacementError(String::from("data"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nplace code A\nplace data A"), Err(LayoutError::SharedRegionError(String::from("A"))));
+        assert_eq!(MemoryLayout::parse("region A 0 0x100\nregion B 0x100 0x100\nplace code A\nplace data B\nplace fast_ram B"), Err(LayoutError::SharedRegionError(String::from("B"))));
+    }
+
+
+    #[test]
+    fn test_rebase_layout() {
+        let layout = MemoryLayout::default().rebase(Some(0x0000), Some(0xC000)).unwrap();
+        assert_eq!(layout.code, MemoryRegion { name: String::from("ROM"), origin: 0x0000, length: 0x3800 });
+        assert_eq!(layout.data, MemoryRegion { name: String::from("RAM"), origin: 0xC000, length: 0x4000 });
+        assert_eq!(MemoryLayout::default().rebase(None, None).unwrap(), MemoryLayout::default());
+
+        let layout = MemoryLayout::default().rebase(Some(0x1000), Some(0x2000)).unwrap();
+        assert_eq!((layout.code.length, layout.data.length), (0x1000, 0x7000));
+
+        let err = MemoryLayout::default().rebase(Some(0xA000), None).unwrap_err();
+        assert_eq!(err, LayoutError::OverlappingRegionsError(String::from("ROM"), String::from("RAM")));
+    }
+
+
+    #[test]
+    fn test_check_fits() {
+        let layout = MemoryLayout::default();
+        assert!(layout.check_fits(&[(SectionKind::Data, 0x7000), (SectionKind::Code, 0x3800)]).is_ok());
+        assert_eq!(layout.check_fits(&[(SectionKind::Data, 0), (SectionKind::Code, 0x3802)]).unwrap_err().to_string(), "code section is 14338 bytes but region 'ROM' only holds 0x3800");
+        assert!(layout.check_fits(&[(SectionKind::Data, 0x6000), (SectionKind::Bss, 0x1000)]).is_ok());
+        assert_eq!(layout.check_fits(&[(SectionKind::Data, 0x6000), (SectionKind::Bss, 0x1001)]).unwrap_err().to_string(), "bss section is 4097 bytes but region 'RAM' only holds 0x7000");
+    }
+
+}
@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::archive::Archive;
+use crate::layout::MemoryLayout;
+use crate::messages::{message, MessageId};
+use crate::object::{ObjectFile, RelocationKind, SectionKind};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    UndefinedSymbolError(String, PathBuf),
+    DuplicateSymbolError(String, PathBuf, PathBuf),
+    RelocationOverflowError(String, usize, RelocationKind, PathBuf),
+    RelocationOutOfBoundsError(String, PathBuf)
+}
+
+impl Error for LinkError {}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbolError(symbol, path) => write!(f, "{}", message(MessageId::UndefinedSymbol, &[symbol, &path.display()])),
+            LinkError::DuplicateSymbolError(symbol, first, second) => {
+                write!(f, "{}", message(MessageId::DuplicateSymbol, &[symbol, &first.display(), &second.display()]))
+            },
+            LinkError::RelocationOverflowError(symbol, address, kind, path) => {
+                write!(f, "{}", message(MessageId::RelocationOverflow, &[&format!("0x{:04X}", address), symbol, &format!("{:?}", kind), &path.display()]))
+            },
+            LinkError::RelocationOutOfBoundsError(symbol, path) => write!(f, "{}", message(MessageId::RelocationOutOfBounds, &[symbol, &path.display()]))
+        }
+    }
+}
+
+
+/**
+ * The data and code sections produced by linking, ready to be loaded at the origins of their regions.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkedSections {
+    pub data: Vec<u8>,
+    pub code: Vec<u8>
+}
+
+
+/**
+ * Returns the global symbols referenced by the given objects which none of them define.
+ */
+fn get_undefined_symbols(objects:&[(PathBuf, ObjectFile)]) -> HashSet<String> {
+    let globals:HashSet<&str> = objects.iter()
+        .flat_map(|(_, object)| object.symbols.iter().filter(|symbol| symbol.global))
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+
+    let mut undefined = HashSet::new();
+    for (_, object) in objects {
+        for relocation in &object.relocations {
+            let symbol = relocation.symbol.as_str();
+            if !globals.contains(symbol) && !object.symbols.iter().any(|defined| defined.name == symbol) {
+                undefined.insert(symbol.to_string());
+            }
+        }
+    }
+
+    undefined
+}
+
+
+/**
+ * Takes the object files given to the linker and appends the archive members needed to define their undefined
+ * symbols. A member is only pulled in if it defines a global symbol which is still undefined, and the members
+ * it pulls in may need further members in turn, from any archive. Each member is labelled with the path of
+ * its archive followed by its name in brackets.
+ */
+pub fn add_archive_members(objects:&mut Vec<(PathBuf, ObjectFile)>, archives:&[(PathBuf, Archive)]) {
+    let mut added:HashSet<(usize, usize)> = HashSet::new();
+
+    loop {
+        let undefined = get_undefined_symbols(objects);
+        let needed = archives.iter().enumerate().flat_map(|(archive_index, (path, archive))| {
+            archive.members.iter().enumerate().map(move |(member_index, member)| (archive_index, member_index, path, member))
+        }).find(|(archive_index, member_index, _, member)| {
+            !added.contains(&(*archive_index, *member_index))
+             && member.object.symbols.iter().any(|symbol| symbol.global && undefined.contains(&symbol.name))
+        });
+
+        match needed {
+            Some((archive_index, member_index, path, member)) => {
+                added.insert((archive_index, member_index));
+                objects.push((PathBuf::from(format!("{}({})", path.display(), member.name)), member.object.to_owned()));
+            },
+            None => break
+        }
+    }
+}
+
+
+/**
+ * Takes a list of object files along with the paths they were read from and links them into a single
+ * program. Each object's sections are appended in the order given, every symbol is given its final address in
+ * the memory layout,
+ * and every relocation is patched with the address of its symbol, looking first among the symbols of its own
+ * object and then among the global symbols of every object. All undefined and duplicate symbols are reported
+ * rather than just the first.
+ */
+pub fn link(objects:&[(PathBuf, ObjectFile)], layout:&MemoryLayout) -> Result<LinkedSections, Vec<LinkError>> {
+    let mut sections = LinkedSections::default();
+    let mut globals:HashMap<&str, (usize, &PathBuf)> = HashMap::new();
+    let mut locals:Vec<HashMap<&str, usize>> = vec![];
+    let mut section_offsets = vec![];
+    let mut errors = vec![];
+
+    // lay out the sections and give every symbol its final address
+    for (path, object) in objects {
+        let offsets = (sections.data.len(), sections.code.len());
+        sections.data.extend_from_slice(&object.data);
+        sections.code.extend_from_slice(&object.code);
+        section_offsets.push(offsets);
+
+        let mut object_symbols = HashMap::new();
+        for symbol in &object.symbols {
+            let address = match symbol.section {
+                SectionKind::Data => layout.data.origin + offsets.0 + symbol.offset,
+                SectionKind::Code => layout.code.origin + offsets.1 + symbol.offset,
+                SectionKind::Bss | SectionKind::Custom(_) => unreachable!("object files only hold code and data")
+            };
+
+            object_symbols.insert(symbol.name.as_str(), address);
+            if !symbol.global {
+                continue;
+            }
+
+            match globals.get(symbol.name.as_str()) {
+                Some((_, first_path)) => errors.push(LinkError::DuplicateSymbolError(symbol.name.to_owned(), first_path.to_path_buf(), path.to_path_buf())),
+                None => { globals.insert(&symbol.name, (address, path)); }
+            }
+        }
+        locals.push(object_symbols);
+    }
+
+    // patch every reference with the address of its symbol
+    for (((path, object), (data_offset, code_offset)), object_symbols) in objects.iter().zip(section_offsets).zip(&locals) {
+        for relocation in &object.relocations {
+            let symbol = relocation.symbol.as_str();
+            let address = match object_symbols.get(symbol).or(globals.get(symbol).map(|(address, _)| address)) {
+                Some(address) => *address,
+                None => {
+                    errors.push(LinkError::UndefinedSymbolError(relocation.symbol.to_owned(), path.to_path_buf()));
+                    continue;
+                }
+            };
+
+            let width = relocation.kind.width();
+            if width < 8 && address >> (width * 8) != 0 {
+                errors.push(LinkError::RelocationOverflowError(relocation.symbol.to_owned(), address, relocation.kind, path.to_path_buf()));
+                continue;
+            }
+
+            let (section, offset) = match relocation.section {
+                SectionKind::Data => (&mut sections.data, data_offset + relocation.offset),
+                SectionKind::Code => (&mut sections.code, code_offset + relocation.offset),
+                SectionKind::Bss | SectionKind::Custom(_) => unreachable!("object files only hold code and data")
+            };
+
+            match section.get_mut(offset..offset + width) {
+                Some(field) => field.copy_from_slice(&(address as u64).to_be_bytes()[8 - width..]),
+                None => errors.push(LinkError::RelocationOutOfBoundsError(relocation.symbol.to_owned(), path.to_path_buf()))
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(sections),
+        false => Err(errors)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::archive::{Archive, ArchiveMember};
+    use crate::object::{ObjectFile, Relocation, RelocationKind, SectionKind, Symbol};
+    use crate::layout::MemoryLayout;
+    use super::{add_archive_members, link, LinkError};
+
+
+    fn symbol(name:&str, section:SectionKind, offset:usize, global:bool) -> Symbol {
+        Symbol { name: String::from(name), section: section, offset: offset, global: global }
+    }
+
+
+    fn relocation(symbol:&str, section:SectionKind, offset:usize, kind:RelocationKind) -> Relocation {
+        Relocation { section: section, offset: offset, symbol: String::from(symbol), kind: kind }
+    }
+
+
+    #[test]
+    fn test_link() {
+        let main = ObjectFile {
+            data: vec![0x00, 0x00],
+            code: vec![0x00, 0x00, 0x5B, 0x00, 0x00, 0x00],
+            symbols: vec![symbol("start", SectionKind::Code, 0, true), symbol("ptr", SectionKind::Data, 0, false)],
+            relocations: vec![
+                relocation("func", SectionKind::Code, 4, RelocationKind::Absolute16),
+                relocation("value", SectionKind::Data, 0, RelocationKind::Absolute16)
+            ]
+        };
+
+        let lib = ObjectFile {
+            data: vec![0x2A],
+            code: vec![0x00, 0x00],
+            symbols: vec![symbol("func", SectionKind::Code, 0, true), symbol("value", SectionKind::Data, 0, true)],
+            relocations: vec![]
+        };
+
+        let linked = link(&[(PathBuf::from("main.sso"), main), (PathBuf::from("lib.sso"), lib)], &MemoryLayout::default()).unwrap();
+        assert_eq!(linked.code, vec![0x00, 0x00, 0x5B, 0x00, 0x58, 0x06, 0x00, 0x00]);
+        assert_eq!(linked.data, vec![0x90, 0x02, 0x2A]);
+    }
+
+
+    #[test]
+    fn test_link_errors() {
+        let a = ObjectFile {
+            data: vec![0x00],
+            code: vec![],
+            symbols: vec![symbol("start", SectionKind::Code, 0, true), symbol("helper", SectionKind::Code, 0, false)],
+            relocations: vec![
+                relocation("missing", SectionKind::Data, 0, RelocationKind::Absolute8),
+                relocation("start", SectionKind::Data, 0, RelocationKind::Absolute8)
+            ]
+        };
+
+        let b = ObjectFile {
+            data: vec![0x00],
+            symbols: vec![symbol("start", SectionKind::Code, 0, true)],
+            relocations: vec![relocation("helper", SectionKind::Data, 0, RelocationKind::Absolute8)],
+            ..ObjectFile::default()
+        };
+
+        let errors = link(&[(PathBuf::from("a.sso"), a), (PathBuf::from("b.sso"), b)], &MemoryLayout::default()).unwrap_err();
+        assert_eq!(errors, vec![
+            LinkError::DuplicateSymbolError(String::from("start"), PathBuf::from("a.sso"), PathBuf::from("b.sso")),
+            LinkError::UndefinedSymbolError(String::from("missing"), PathBuf::from("a.sso")),
+            LinkError::RelocationOverflowError(String::from("start"), 0x5800, RelocationKind::Absolute8, PathBuf::from("a.sso")),
+            LinkError::UndefinedSymbolError(String::from("helper"), PathBuf::from("b.sso"))
+        ]);
+        assert_eq!(errors[0].to_string(), "symbol 'start' is defined in both a.sso and b.sso");
+    }
+
+
+    #[test]
+    fn test_add_archive_members() {
+        let member = |name:&str, defines:&str, needs:Option<&str>| ArchiveMember {
+            name: String::from(name),
+            object: ObjectFile {
+                code: vec![0x5B, 0x00, 0x00, 0x00],
+                symbols: vec![symbol(defines, SectionKind::Code, 0, true)],
+                relocations: needs.map(|needs| relocation(needs, SectionKind::Code, 2, RelocationKind::Absolute16)).into_iter().collect(),
+                ..ObjectFile::default()
+            }
+        };
+
+        let archive = Archive {
+            members: vec![member("mul.sso", "mul", Some("add")), member("div.sso", "div", None), member("add.sso", "add", None)]
+        };
+
+        let main = ObjectFile {
+            code: vec![0x5B, 0x00, 0x00, 0x00],
+            relocations: vec![relocation("mul", SectionKind::Code, 2, RelocationKind::Absolute16)],
+            ..ObjectFile::default()
+        };
+
+        let mut objects = vec![(PathBuf::from("main.sso"), main)];
+        add_archive_members(&mut objects, &[(PathBuf::from("libmath.ssa"), archive)]);
+
+        let names:Vec<String> = objects.iter().map(|(path, _)| path.display().to_string()).collect();
+        assert_eq!(names, vec!["main.sso", "libmath.ssa(mul.sso)", "libmath.ssa(add.sso)"]);
+        assert_eq!(link(&objects, &MemoryLayout::default()).unwrap().code[2..4], [0x58, 0x04]);
+    }
+}
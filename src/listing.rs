@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{AssembledSections, ByteOrigin};
+use crate::layout::MemoryLayout;
+use crate::object::SectionKind;
+use crate::source::SourceLine;
+
+
+/**
+ * How many bytes are shown on each row of the listing, with the rest of a line's bytes on continuation rows.
+ */
+const BYTES_PER_ROW:usize = 4;
+
+
+/**
+ * Reads the lines of every file the source lines came from, so the listing can show each line as it was
+ * written rather than after macros and constants were substituted. Files which cannot be read are left out.
+ */
+pub fn read_original_lines(source_lines:&[SourceLine], source_path:&Path) -> HashMap<PathBuf, Vec<String>> {
+    let mut originals = HashMap::new();
+    for source_line in source_lines {
+        let path = source_line.file.as_deref().unwrap_or(source_path);
+        if originals.contains_key(path) {
+            continue;
+        }
+
+        if let Ok(text) = fs::read_to_string(path) {
+            originals.insert(path.to_path_buf(), text.lines().map(|line| line.to_string()).collect());
+        }
+    }
+
+    originals
+}
+
+
+/**
+ * Formats the rows of the listing for a line: the first row gives the line number, the address, up to
+ * `BYTES_PER_ROW` of the bytes it produced and its text, and the rest of the bytes follow on rows of their own.
+ * A bss line shows only its address, as it reserves space rather than producing bytes.
+ */
+fn format_rows(line_num:Option<usize>, marker:char, origin:Option<&ByteOrigin>, sections:&AssembledSections, layout:&MemoryLayout, text:&str) -> String {
+    let line_num = line_num.map(|line_num| line_num.to_string()).unwrap_or_default();
+    let (address, bytes) = match origin {
+        Some(origin) if origin.section == SectionKind::Bss => (Some(sections.address(origin.section, layout) + origin.offset), &[] as &[u8]),
+        Some(origin) => {
+            let bytes = &sections.section(origin.section)[origin.offset..origin.offset + origin.length];
+            (Some(sections.address(origin.section, layout) + origin.offset), bytes)
+        },
+        None => (None, &[] as &[u8])
+    };
+
+    let mut rows = String::new();
+    let mut chunks = bytes.chunks(BYTES_PER_ROW);
+    let first:Vec<String> = chunks.next().unwrap_or(&[]).iter().map(|byte| format!("{:02X}", byte)).collect();
+    let address_text = address.map(|address| format!("{:04X}", address)).unwrap_or_default();
+    let row = format!("{:>5}{} {:4}  {:11}  {}", line_num, marker, address_text, first.join(" "), text);
+    writeln!(rows, "{}", row.trim_end()).unwrap();
+
+    for (index, chunk) in chunks.enumerate() {
+        let hex:Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(rows, "{:>5}  {:04X}  {}", "", address.unwrap_or(0) + (index + 1) * BYTES_PER_ROW, hex.join(" ")).unwrap();
+    }
+
+    rows
+}
+
+
+/**
+ * Produces an assembler listing giving the line number, address, bytes produced, and text of every line that
+ * was assembled. Each line is shown as it was written in its file, and the lines a macro invocation expanded
+ * to follow it marked with a `+`. The name of the file is given before its first line and whenever the listing
+ * moves between the input file and an included file.
+ */
+pub fn to_listing(source_lines:&[SourceLine], sections:&AssembledSections, layout:&MemoryLayout, source_path:&Path,
+                  originals:&HashMap<PathBuf, Vec<String>>) -> String {
+    let mut text = String::new();
+    let mut origins = sections.origins.iter().peekable();
+    let mut last_line:Option<(&Path, usize)> = None;
+
+    for source_line in source_lines {
+        let origin = origins.next_if(|origin| origin.source == *source_line);
+        let path = source_line.file.as_deref().unwrap_or(source_path);
+        let original = originals.get(path).and_then(|lines| lines.get(source_line.line_num - 1)).map(|line| line.trim_end());
+
+        if last_line.map(|(last_path, _)| last_path) != Some(path) {
+            writeln!(text, "{}:", path.display()).unwrap();
+        }
+
+        let new_line = last_line != Some((path, source_line.line_num));
+        last_line = Some((path, source_line.line_num));
+
+        match (source_line.expansion.is_empty(), new_line, original) {
+            (true, true, Some(original)) => text.push_str(&format_rows(Some(source_line.line_num), ' ', origin, sections, layout, original)),
+            (true, _, _) => text.push_str(&format_rows(Some(source_line.line_num), ' ', origin, sections, layout, source_line.text.trim())),
+            (false, new_line, original) => {
+                if let (true, Some(original)) = (new_line, original) {
+                    text.push_str(&format_rows(Some(source_line.line_num), ' ', None, sections, layout, original));
+                }
+                text.push_str(&format_rows(None, '+', origin, sections, layout, source_line.text.trim()));
+            }
+        }
+    }
+
+    text
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::assembler::{AssembledSections, ByteOrigin};
+    use crate::layout::MemoryLayout;
+    use crate::object::SectionKind;
+    use crate::source::SourceLine;
+    use super::to_listing;
+
+
+    #[test]
+    fn test_listing() {
+        let push = SourceLine { expansion: vec![String::from("push2")], ..SourceLine::new("push ax", 4) };
+        let source_lines = vec![
+            SourceLine::new(".data:", 1),
+            SourceLine::new("msg: .asciiz `hello`", 2),
+            SourceLine::new(".code:", 3),
+            push.to_owned(),
+            SourceLine { text: String::from("push bx"), ..push.to_owned() },
+            SourceLine::new("halt", 5)
+        ];
+
+        let sections = AssembledSections {
+            data: b"hello\0".to_vec(),
+            code: vec![0x00, 0x10, 0x00, 0x11, 0xFC, 0x00],
+            origins: vec![
+                ByteOrigin { section: SectionKind::Data, offset: 0, length: 6, source: source_lines[1].to_owned() },
+                ByteOrigin { section: SectionKind::Code, offset: 0, length: 2, source: source_lines[3].to_owned() },
+                ByteOrigin { section: SectionKind::Code, offset: 2, length: 2, source: source_lines[4].to_owned() },
+                ByteOrigin { section: SectionKind::Code, offset: 4, length: 2, source: source_lines[5].to_owned() }
+            ],
+            ..AssembledSections::default()
+        };
+
+        let originals = HashMap::from([(PathBuf::from("prog.asm"), vec![
+            String::from(".data:"), String::from("msg: .asciiz `hello`"), String::from(".code:"), String::from("    push2 ax, bx"), String::from("halt")
+        ])]);
+
+        assert_eq!(to_listing(&source_lines, &sections, &MemoryLayout::default(), Path::new("prog.asm"), &originals), "prog.asm:
+    1                     .data:
+    2  9000  68 65 6C 6C  msg: .asciiz `hello`
+       9004  6F 00
+    3                     .code:
+    4                         push2 ax, bx
+     + 5800  00 10        push ax
+     + 5802  00 11        push bx
+    5  5804  FC 00        halt
+");
+    }
+}
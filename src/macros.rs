@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::evaluate;
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+use crate::validation::validate_label;
+
+
+/**
+ * How deeply macro invocations may nest before expansion gives up, which stops a macro that invokes itself
+ * from expanding forever.
+ */
+const MAX_EXPANSION_DEPTH:usize = 32;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroError {
+    MissingNameError,
+    DuplicateMacroError(String),
+    NestedDefinitionError(String),
+    UnterminatedMacroError(String),
+    UnexpectedEndmError,
+    ArgumentCountError(String, usize, usize),
+    RecursionLimitError(String),
+    InvalidRepeatCountError(String),
+    UnterminatedRepeatError,
+    UnexpectedEndrError
+}
+
+impl Error for MacroError {}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroError::MissingNameError => write!(f, "{}", message(MessageId::MacroMissingName, &[])),
+            MacroError::DuplicateMacroError(name) => write!(f, "{}", message(MessageId::DuplicateMacro, &[name])),
+            MacroError::NestedDefinitionError(name) => write!(f, "{}", message(MessageId::NestedMacro, &[name])),
+            MacroError::UnterminatedMacroError(name) => write!(f, "{}", message(MessageId::UnterminatedMacro, &[name])),
+            MacroError::UnexpectedEndmError => write!(f, "{}", message(MessageId::UnexpectedEndm, &[])),
+            MacroError::ArgumentCountError(name, expected, given) => write!(f, "{}", message(MessageId::MacroArgumentCount, &[name, expected, given])),
+            MacroError::RecursionLimitError(name) => write!(f, "{}", message(MessageId::MacroRecursion, &[name, &MAX_EXPANSION_DEPTH])),
+            MacroError::InvalidRepeatCountError(count) => write!(f, "{}", message(MessageId::InvalidRepeatCount, &[count])),
+            MacroError::UnterminatedRepeatError => write!(f, "{}", message(MessageId::UnterminatedRepeat, &[])),
+            MacroError::UnexpectedEndrError => write!(f, "{}", message(MessageId::UnexpectedEndr, &[]))
+        }
+    }
+}
+
+
+/**
+ * A macro defined with `.macro NAME param, ...` and ended with `.endm`. Parameters are referred to in the body
+ * as `\param`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<SourceLine>
+}
+
+
+/**
+ * Splits the operands of a `.macro` line or a macro invocation on commas and whitespace.
+ */
+fn split_operands(text:&str) -> Vec<String> {
+    text.split(|c:char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()).map(|token| token.to_string()).collect()
+}
+
+
+/**
+ * Returns the part of a line before its mnemonic or directive, including the label and its colon if it has
+ * one, along with the rest of the line.
+ */
+fn split_label(line:&str) -> (&str, &str) {
+    match line.find(':') {
+        Some(index) => (&line[..index + 1], line[index + 1..].trim()),
+        None => ("", line)
+    }
+}
+
+
+/**
+ * Replaces every `\param` in the text with the argument given for that parameter. A backslash followed by
+ * anything other than a parameter name is left as it is.
+ */
+fn substitute(text:&str, args:&HashMap<&str, &str>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find('\\') {
+        result.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        let name_len = after.find(|c:char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+
+        match args.get(&after[..name_len]) {
+            Some(arg) => result.push_str(arg),
+            None => result.push_str(&rest[index..index + 1 + name_len])
+        }
+
+        rest = &after[name_len..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+
+/**
+ * Renames every macro-local label in the text, written as `@@name`, to a name unique to the expansion with the
+ * given number, so a macro defining a label can be invoked more than once. A reference to a local label is
+ * written with the usual `@` in front, as in `@@@loop`.
+ */
+fn localise_labels(text:&str, expansion_id:usize) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find("@@") {
+        result.push_str(&rest[..index]);
+        let after = &rest[index + 2..];
+        let name_len = after.find(|c:char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+
+        // the first `@` of `@@@name` is a reference to the local label which follows it
+        if name_len == 0 {
+            result.push('@');
+            rest = &rest[index + 1..];
+            continue;
+        }
+
+        result.push_str(&format!("__{}_{}", &after[..name_len], expansion_id));
+        rest = &after[name_len..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+
+/**
+ * The macros defined so far by name, along with the number to give the next expansion to make its local labels
+ * unique.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MacroTable {
+    macros: HashMap<String, Macro>,
+    next_id: usize
+}
+
+impl MacroTable {
+    /**
+     * Defines the macro opened by a `.macro NAME param, ...` line, taking its body from the lines which follow
+     * it up to its `.endm`. The body is kept as it was written and is only read when the macro is invoked.
+     */
+    pub fn define(&mut self, line:&SourceLine, lines:&mut impl Iterator<Item = SourceLine>) -> Result<(), AssemblerError> {
+        let text = line.text.trim();
+        let source_error = |err:MacroError| line.error(Box::new(err));
+
+        let mut operands = split_operands(&text[".macro".len()..]).into_iter();
+        let name = operands.next().ok_or_else(|| source_error(MacroError::MissingNameError))?;
+        validate_label(&name).map_err(|err| line.error(err))?;
+        if self.macros.contains_key(&name) {
+            return Err(source_error(MacroError::DuplicateMacroError(name)));
+        }
+
+        let mut body = vec![];
+        for body_line in lines {
+            let body_text = body_line.text.trim();
+            match body_text.split_whitespace().next() {
+                Some(".endm") => {
+                    self.macros.insert(name, Macro { params: operands.collect(), body: body });
+                    return Ok(());
+                },
+
+                Some(".macro") => {
+                    let nested = split_operands(&body_text[".macro".len()..]).first().cloned().unwrap_or_default();
+                    return Err(body_line.error(Box::new(MacroError::NestedDefinitionError(nested))));
+                },
+
+                _ => body.push(body_line)
+            }
+        }
+
+        Err(source_error(MacroError::UnterminatedMacroError(name)))
+    }
+
+
+    /**
+     * Returns the lines produced by the line if it invokes a macro, or `None` if it does not. A macro is
+     * invoked by using its name in place of a mnemonic, optionally after a label, followed by one argument for
+     * each of its parameters. Lines produced by a macro keep the line number of the invocation in the source
+     * file, and each expansion is given the next number from the counter to make its local labels unique.
+     */
+    pub fn expand(&mut self, line:&SourceLine) -> Result<Option<Vec<SourceLine>>, AssemblerError> {
+        let text = line.text.trim();
+        let (label, rest) = split_label(text);
+        let name = rest.split_whitespace().next().unwrap_or("");
+
+        let definition = match self.macros.get(name) {
+            Some(definition) => definition,
+            None => return Ok(None)
+        };
+
+        if line.expansion.len() >= MAX_EXPANSION_DEPTH {
+            return Err(line.error(Box::new(MacroError::RecursionLimitError(name.to_string()))));
+        }
+
+        let args = split_operands(&rest[name.len()..]);
+        if args.len() != definition.params.len() {
+            let err = MacroError::ArgumentCountError(name.to_string(), definition.params.len(), args.len());
+            return Err(line.error(Box::new(err)));
+        }
+
+        let args:HashMap<&str, &str> = definition.params.iter().map(|param| param.as_str()).zip(args.iter().map(|arg| arg.as_str())).collect();
+        let mut expansion = line.expansion.to_owned();
+        expansion.push(name.to_string());
+
+        let expansion_id = self.next_id;
+        self.next_id += 1;
+
+        // a label before the invocation names the first line of the expansion
+        let mut expanded = vec![];
+        if !label.is_empty() {
+            expanded.push(SourceLine { text: label.to_string(), ..line.to_owned() });
+        }
+
+        expanded.extend(definition.body.iter().map(|body_line| SourceLine {
+            text: localise_labels(&substitute(&body_line.text, &args), expansion_id),
+            expansion: expansion.to_owned(),
+            ..line.to_owned()
+        }));
+
+        Ok(Some(expanded))
+    }
+}
+
+
+/**
+ * Parses the operands of a `.rept` line, which are the number of times to repeat the block as a constant
+ * expression, such as `.rept COUNT * 2`, optionally followed by a comma and the name of a counter.
+ */
+fn parse_repeat(line:&SourceLine, constants:&HashMap<String, i64>) -> Result<(usize, Option<String>), AssemblerError> {
+    let text = line.text.trim();
+    let (count_text, counter) = match text[".rept".len()..].split_once(',') {
+        Some((count_text, counter)) => (count_text, Some(counter.trim())),
+        None => (&text[".rept".len()..], None)
+    };
+
+    let count = evaluate(count_text, constants).ok().and_then(|count| usize::try_from(count).ok());
+    match (count, counter) {
+        (Some(count), None) => Ok((count, None)),
+        (Some(count), Some(counter)) if validate_label(counter).is_ok() => Ok((count, Some(counter.to_string()))),
+        _ => Err(line.error(Box::new(MacroError::InvalidRepeatCountError(text.to_string()))))
+    }
+}
+
+
+/**
+ * Takes the block opened by a `.rept` line from the lines which follow it up to its matching `.endr`, and
+ * returns its repetitions. A block from `.rept N` to its `.endr` is repeated N times, where N may use the
+ * constants defined so far, and if the `.rept` names a counter, as in `.rept 8, i`, then `\i` in the block is
+ * replaced with the number of the repetition from 0. Blocks nested in the block are left for the caller to
+ * expand along with the rest of each repetition.
+ */
+pub fn read_repeat(line:&SourceLine, lines:&mut impl Iterator<Item = SourceLine>, constants:&HashMap<String, i64>) -> Result<Vec<SourceLine>, AssemblerError> {
+    let (count, counter) = parse_repeat(line, constants)?;
+    let mut depth = 1;
+    let mut body = vec![];
+    for body_line in lines {
+        match body_line.text.split_whitespace().next() {
+            Some(".rept") => depth += 1,
+            Some(".endr") => depth -= 1,
+            _ => {}
+        }
+
+        if depth == 0 {
+            break;
+        }
+
+        body.push(body_line);
+    }
+
+    if depth > 0 {
+        return Err(line.error(Box::new(MacroError::UnterminatedRepeatError)));
+    }
+
+    let mut repetitions = vec![];
+    for index in 0..count {
+        let index = index.to_string();
+        let args:HashMap<&str, &str> = counter.iter().map(|counter| (counter.as_str(), index.as_str())).collect();
+        repetitions.extend(body.iter().map(|body_line| SourceLine {
+            text: substitute(&body_line.text, &args),
+            ..body_line.to_owned()
+        }));
+    }
+
+    Ok(repetitions)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::constants::ConstantTable;
+    use crate::preprocess::preprocess;
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    fn expand_macros(source_lines:Vec<SourceLine>) -> Result<Vec<SourceLine>, AssemblerError> {
+        preprocess(source_lines, Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::default())
+    }
+
+
+    fn texts(lines:&[SourceLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.trim()).collect()
+    }
+
+
+    #[test]
+    fn test_expand_macros() {
+        let source_lines = to_source_lines(".macro SAVE_REGS\n  push ax\n  push bx\n.endm\n.macro ADD3 dst, a, b\n  add \\dst, \\a\n  add \\dst, \\b\n.endm\n.code:\nstart: SAVE_REGS\nADD3 ax, bx, cx");
+        let expanded = expand_macros(source_lines).unwrap();
+
+        assert_eq!(texts(&expanded), vec![".code:", "start:", "push ax", "push bx", "add ax, bx", "add ax, cx"]);
+        assert_eq!(expanded[2], SourceLine { expansion: vec![String::from("SAVE_REGS")], ..SourceLine::new("  push ax", 10) });
+        assert_eq!(expanded[5].line_num, 11);
+    }
+
+
+    #[test]
+    fn test_nested_expansion() {
+        let source_lines = to_source_lines(".macro INNER r\n  inc \\r\n.endm\n.macro OUTER r\n  INNER \\r\n  INNER \\r\n.endm\nOUTER dx");
+        let expanded = expand_macros(source_lines).unwrap();
+
+        assert_eq!(texts(&expanded), vec!["inc dx", "inc dx"]);
+        assert_eq!(expanded[0].expansion, vec![String::from("OUTER"), String::from("INNER")]);
+    }
+
+
+    #[test]
+    fn test_local_labels() {
+        let source_lines = to_source_lines(".macro WAIT r\n@@loop: dec \\r\n  movi pc @@@loop\n.endm\nWAIT ax\nWAIT bx");
+        let expanded = expand_macros(source_lines).unwrap();
+
+        assert_eq!(texts(&expanded), vec!["__loop_0: dec ax", "movi pc @__loop_0", "__loop_1: dec bx", "movi pc @__loop_1"]);
+    }
+
+
+    #[test]
+    fn test_repeat_blocks() {
+        let source_lines = to_source_lines(".data:\ntable:\n.rept 3, i\n  .byte \\i\n.endr\n.code:\n.rept 2\n.rept 0x2\n  nop\n.endr\n.endr");
+        let expanded = expand_macros(source_lines).unwrap();
+
+        assert_eq!(texts(&expanded), vec![".data:", "table:", ".byte 0", ".byte 1", ".byte 2", ".code:", "nop", "nop", "nop", "nop"]);
+        assert_eq!(expanded[3].line_num, 4);
+
+        let source_lines = to_source_lines(".macro FILL n, value\n.rept \\n\n  .byte \\value\n.endr\n.endm\n.rept 2\nFILL 2, 7\n.endr");
+        assert_eq!(texts(&expand_macros(source_lines).unwrap()), vec![".byte 7", ".byte 7", ".byte 7", ".byte 7"]);
+
+        let source_lines = to_source_lines("SIZE: .equ 2\n.define TIMES SIZE + 1\n.rept (TIMES) * SIZE, i\n  .byte \\i\n.endr");
+        assert_eq!(texts(&expand_macros(source_lines).unwrap()), vec![".byte 0", ".byte 1", ".byte 2", ".byte 3", ".byte 4", ".byte 5"]);
+    }
+
+
+    #[test]
+    fn test_invalid_macros() {
+        let error = |source:&str| expand_macros(to_source_lines(source)).unwrap_err().to_string();
+
+        assert_eq!(error(".macro ADD3 dst, a, b\n.endm\nADD3 ax, bx"), "line 3: Macro 'ADD3' takes 3 arguments but was given 2");
+        assert_eq!(error(".macro LOOP\n  LOOP\n.endm\nLOOP"), "line 4: Macro 'LOOP' expands more than 32 levels deep");
+        assert_eq!(error("nop\n.macro OPEN\nnop"), "line 2: Macro 'OPEN' has no matching '.endm'");
+        assert_eq!(error(".endm"), "line 1: '.endm' without a matching '.macro'");
+        assert_eq!(error(".macro A\n.endm\n.macro A\n.endm"), "line 3: Macro 'A' is already defined");
+        assert_eq!(error(".macro A\n.macro B\n.endm\n.endm"), "line 2: Macro 'B' cannot be defined inside another macro");
+        assert!(expand_macros(to_source_lines(".macro push\n.endm")).is_err());
+        assert!(expand_macros(to_source_lines(".macro")).is_err());
+
+        assert_eq!(error("nop\n.rept 4\nnop"), "line 2: '.rept' has no matching '.endr'");
+        assert_eq!(error(".endr"), "line 1: '.endr' without a matching '.rept'");
+        assert_eq!(error(".rept many\n.endr"), "line 1: '.rept many' does not give a valid repeat count");
+        assert_eq!(error(".rept -1\n.endr"), "line 1: '.rept -1' does not give a valid repeat count");
+    }
+}
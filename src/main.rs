@@ -1,74 +1,505 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::from_over_into, clippy::match_like_matches_macro,
+         clippy::single_match, clippy::enum_variant_names)]
+
 use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, BufWriter, Write, Seek};
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::env;
 
+mod archive;
 mod assembler;
+mod charmap;
+mod cli;
+mod conditional;
+mod constants;
+mod defines;
+mod diagnostic;
+mod emit;
+mod error;
+mod export;
+mod expr;
+mod include;
+mod inspect;
+mod output;
+mod portmap;
+mod preprocess;
+mod provenance;
+mod pseudo;
+mod regalias;
 mod repr;
+mod source;
+mod sse;
 mod validation;
 mod label_table;
+mod layout;
+mod linker;
+mod listing;
+mod macros;
+mod messages;
+mod metadata;
+mod object;
+mod trace;
+mod version;
 
-use assembler::process_line;
-use label_table::get_label_table;
-use repr::instruction::{InstrType, InstructionOrData};
+use archive::{Archive, ArchiveMember};
+use assembler::{assemble_lines, read_source};
+use charmap::apply_charmaps;
+use cli::{parse_args, take_language_option, ArchiveOptions, AssembleOptions, Command, LinkOptions};
+use constants::ConstantTable;
+use diagnostic::get_print_messages;
+use emit::{split_output_paths, to_bin, to_coe, to_ihex, to_logisim, to_memh, to_mif, to_readmemh, to_split_bin, to_srec, Endian, OutputFormat};
+use error::{AssemblerError, IoOperation};
+use export::{to_c_header, to_map_file, to_rust_source, to_source_map_json, to_sym_file, to_symbols_json};
+use include::expand_binary_includes;
+use inspect::{dump, inspect, inspect_object};
+use label_table::{align_data_words, check_label_definitions, check_label_targets, check_section_markers, check_symbol_directives, get_kept_symbols, get_label_definitions, get_label_table, get_label_sizes, get_symbol_directives,
+                  in_source_order, localise_labels, substitute_current_address, substitute_label_sizes, substitute_port_names, SymbolError};
+use layout::MemoryLayout;
+use linker::{add_archive_members, link};
+use listing::{read_original_lines, to_listing};
+use messages::{message, set_language, Language, MessageId};
+use metadata::get_program_metadata;
+use output::write_atomically;
+use portmap::{check_ports, get_port_numbers, PortMap};
+use preprocess::preprocess;
+use provenance::to_provenance_json;
+use pseudo::expand_pseudo_instructions;
+use regalias::apply_register_aliases;
+use object::{get_object_symbols, ObjectFile, RelocationRecorder, SectionKind};
+use sse::{verify, SseFile, SseSection, SseSectionKind};
+use trace::SymbolTrace;
+use validation::check_operand_separators;
+use version::{toolchain_metadata, version_text, SUPPORTED_MARCH};
 
 
 
-#[allow(unused_variables)]
 fn main() {
-    let cmd_args:Vec<String> = env::args().collect();
-    let filename:&str = cmd_args.get(1).expect("Expected <input file path>.asm <output file path>.sse");
-    let output_name:&str = cmd_args.get(2).expect("Expected <input file path>.asm <output file path>.sse");
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+
+/**
+ * Runs the command given on the command line.
+ */
+fn run() -> Result<(), AssemblerError> {
+    let mut cmd_args:Vec<String> = env::args().skip(1).collect();
+
+    // the language comes from --lang, falling back on the locale in the environment
+    if let Ok(language) = env::var("LANG").as_deref().map(Language::try_from) {
+        set_language(language.unwrap_or(Language::English));
+    }
+
+    if let Some(language) = take_language_option(&mut cmd_args)? {
+        set_language(language);
+    }
+
+    match parse_args(&cmd_args)? {
+        Command::Assemble(options) => assemble(&options),
+        Command::Inspect(path) => {
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("sso") => print!("{}", inspect_object(&read_object_file(&path)?)),
+                _ => print!("{}", inspect(&read_sse_file(&path)?))
+            }
+            Ok(())
+        },
+        Command::Dump(path) => {
+            print!("{}", dump(&read_sse_file(&path)?));
+            Ok(())
+        },
+        Command::Verify(path) => {
+            let bytes = fs::read(&path).map_err(AssemblerError::io(IoOperation::ReadInput, &path))?;
+            let checksum = verify(&bytes).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))?;
+            println!("{}", message(MessageId::SseChecksumVerified, &[&path.display(), &format!("0x{:08X}", checksum)]));
+            Ok(())
+        },
+        Command::Link(options) => link_objects(&options),
+        Command::Archive(options) => create_archive(&options),
+        Command::Version => {
+            println!("{}", version_text());
+            Ok(())
+        }
+    }
+}
+
+
+/**
+ * Reads and parses the .sse file at the given path.
+ */
+fn read_sse_file(path:&Path) -> Result<SseFile, AssemblerError> {
+    let bytes = fs::read(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    SseFile::parse(&bytes).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))
+}
+
+
+/**
+ * Reads and parses the .sso object file at the given path.
+ */
+fn read_object_file(path:&Path) -> Result<ObjectFile, AssemblerError> {
+    let bytes = fs::read(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    ObjectFile::parse(&bytes).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))
+}
+
+
+/**
+ * Reads the memory layout file at the given path, or returns the default layout if there is none.
+ */
+fn read_layout_file(path:Option<&Path>) -> Result<MemoryLayout, AssemblerError> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(MemoryLayout::default())
+    };
+
+    let text = fs::read_to_string(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    MemoryLayout::parse(&text).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))
+}
+
+
+/**
+ * Reads the port map file at the given path, if there is one.
+ */
+fn read_port_map_file(path:Option<&Path>) -> Result<Option<PortMap>, AssemblerError> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(None)
+    };
 
-    if !filename.ends_with(".asm") {
-        panic!("Input filename must end in .asm");
+    let text = fs::read_to_string(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    PortMap::parse(&text).map(Some).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))
+}
+
+
+/**
+ * Assembles the input file and writes every requested output. The source is assembled once for all the
+ * executable formats and once more if a relocatable object file is requested. No output is written if any
+ * line fails to assemble.
+ */
+fn assemble(options:&AssembleOptions) -> Result<(), AssemblerError> {
+    let layout = read_layout_file(options.layout_path.as_deref())?.rebase(options.code_base, options.data_base)
+                                                                   .map_err(AssemblerError::LayoutError)?;
+    let source_lines = read_source(&options.input_path)?;
+    let mut constants = ConstantTable::from_defines(&options.defines);
+    let source_lines = preprocess(source_lines, &options.input_path, &options.include_dirs, &options.aliases, options.compat_numbers, &mut constants)?;
+    let source_lines = apply_charmaps(source_lines)?;
+    let source_lines = apply_register_aliases(expand_binary_includes(source_lines, &options.input_path, &options.include_dirs)?)?;
+    let source_lines = localise_labels(source_lines)?;
+    let source_lines = match options.pseudos {
+        true => expand_pseudo_instructions(source_lines, &options.scratch)?,
+        false => source_lines
+    };
+
+    let wants_object = options.outputs.iter().any(|(format, _)| *format == OutputFormat::Sso);
+    let wants_executable = options.outputs.iter().any(|(format, _)| *format != OutputFormat::Sso);
+
+    check_section_markers(&source_lines, &layout, wants_object)?;
+    if options.strict {
+        check_operand_separators(&source_lines)?;
+    }
+    let (source_lines, alignment_warnings) = align_data_words(source_lines, &layout, options.align_data);
+    for warning in alignment_warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let source_lines = substitute_current_address(source_lines, &layout, wants_object)?;
+    let label_sizes = get_label_sizes(&source_lines, &layout);
+    let source_lines = substitute_label_sizes(source_lines, &label_sizes);
+    check_label_definitions(&source_lines)?;
+    let definitions = get_label_definitions(&source_lines, &layout);
+    for warning in check_label_targets(&source_lines, &definitions)? {
+        eprintln!("warning: {}", warning);
+    }
+    let label_table:HashMap<String, usize> = get_label_table(&definitions);
+    constants.check_labels(&label_table)?;
+    let directives = get_symbol_directives(&source_lines)?;
+    check_symbol_directives(&directives, &definitions)?;
+    let port_map = read_port_map_file(options.port_map_path.as_deref())?;
+    let ports = get_port_numbers(&directives.ports, port_map.as_ref())?;
+    let source_lines = substitute_port_names(source_lines, &ports);
+    if options.check_ports || port_map.is_some() {
+        check_ports(&source_lines, &ports)?;
+    }
+    let symbols = get_kept_symbols(&label_table, &options.keep_symbols, options.strip)?;
+
+    for print_message in get_print_messages(&source_lines, &label_table, &layout)? {
+        println!("{}", print_message);
+    }
+
+    let mut metadata = get_program_metadata(&source_lines)?;
+    metadata.extend(toolchain_metadata(&options.march));
+    if options.endian == Endian::Little {
+        metadata.push((String::from("endian"), String::from("little")));
     }
 
-    if !output_name.ends_with(".sse") {
-        panic!("Output filename must end in .sse");
+    // execution starts at the start of the code section unless the source gives an entry point
+    let entry = directives.entry.as_ref().map(|(name, _)| label_table[name]);
+
+    let trace = match options.trace_symbols {
+        true => Some(SymbolTrace::new(definitions.to_owned())),
+        false => None
+    };
+
+    if let Some(trace) = &trace {
+        trace.log_definitions();
     }
 
-    let mut input_file = OpenOptions::new().read(true).open(filename).unwrap();
+    let object = match wants_object {
+        true => {
+            if let Some((name, source_line)) = &directives.entry {
+                return Err(source_line.error(Box::new(SymbolError::EntryInObjectError(name.to_owned()))));
+            }
 
-    let label_table:HashMap<String, usize> = get_label_table(&input_file);
-    input_file.rewind().unwrap();
+            let labels = RelocationRecorder::new(&label_table, &directives.externs);
+            Some(assemble_lines(&source_lines, &labels, &layout, options.keep_going, trace.as_ref())?)
+        },
+        false => None
+    };
 
-    let mut data_mode = true;
-    let input_lines = BufReader::new(&input_file).lines().filter_map(|line| match line.unwrap().trim() {
-        "" => None, 
-        l => process_line(l, &label_table, &mut data_mode)
-    });
+    let executable = match wants_executable {
+        true => {
+            if let Some((name, source_line)) = in_source_order(&directives.externs).first() {
+                return Err(source_line.error(Box::new(SymbolError::ExternInExecutableError(name.to_string()))));
+            }
 
-    let output_file = OpenOptions::new().create(true)
-                                        .truncate(true)
-                                        .write(true)
-                                        .open(output_name)
-                                        .unwrap();
-    let mut writer = BufWriter::new(output_file);
+            let mut sections = assemble_lines(&source_lines, &label_table, &layout, options.keep_going, trace.as_ref())?;
+            if options.endian == Endian::Little {
+                sections.swap_to_little_endian();
+            }
+            Some(sections)
+        },
+        false => None
+    };
+
+    for sections in object.iter().chain(executable.iter()) {
+        layout.check_fits(&sections.sizes()).map_err(AssemblerError::LayoutError)?;
+    }
 
-    let mut bytes:Vec<u8> = vec![0x2E, 0x64, 0x61, 0x74, 0x61, 0x3A]; // ".data:" in ASCII
-    let mut data_mode = true;
-    for line in input_lines {
-        match line {
-            InstructionOrData::Data(data) => {
-                bytes.append(&mut data.bytes.clone().as_mut_slice().to_vec());
-            } 
+    let mut files = vec![];
+    for (format, path) in &options.outputs {
+        let bytes = match (format, &object, &executable) {
+            (OutputFormat::Sso, Some(sections), _) => ObjectFile {
+                data: sections.data.to_owned(),
+                code: sections.code.to_owned(),
+                symbols: get_object_symbols(&definitions, &directives.globals, &layout),
+                relocations: sections.relocations.to_owned()
+            }.to_bytes().map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))?,
 
-            InstructionOrData::Instruction(instr) => {
-                if data_mode {
-                    data_mode = false;
-                    bytes.append(&mut ".code:".as_bytes().to_vec()); // ".code:" in ASCII 
+            (OutputFormat::Sse, _, Some(sections)) => {
+                let mut file = sections.to_sse_file(&layout, entry, metadata.to_owned());
+                file.symbols = symbols.to_owned();
+                if options.debug_info && !options.strip {
+                    file.lines = sections.line_table(&layout, &options.input_path);
                 }
+                file.to_bytes().map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))?
+            },
 
-                let instr_type:InstrType = instr.into();
+            (OutputFormat::Memh, _, Some(sections)) => to_memh(&sections.data, &sections.code, &sections.custom, &layout).into_bytes(),
+            (OutputFormat::Srec, _, Some(sections)) => {
+                let name = options.input_path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+                to_srec(&sections.data, &sections.code, &sections.custom, &name, entry, &layout).map_err(AssemblerError::LayoutError)?.into_bytes()
+            },
+            (OutputFormat::Coe, _, Some(sections)) => to_coe(&sections.data, &sections.code, &sections.custom, &layout).into_bytes(),
+            (OutputFormat::Mif, _, Some(sections)) => to_mif(&sections.data, &sections.code, &sections.custom, &layout).into_bytes(),
+            (OutputFormat::Logisim, _, Some(sections)) => to_logisim(&sections.data, &sections.code, &sections.custom, &layout).into_bytes(),
+            (OutputFormat::Readmemh, _, Some(sections)) => to_readmemh(&sections.data, &sections.code, &sections.custom, &layout).into_bytes(),
+            (OutputFormat::Ihex, _, Some(sections)) => to_ihex(&sections.data, &sections.code, &sections.custom, entry, &layout).into_bytes(),
+            (OutputFormat::Bin, _, Some(sections)) if options.split_bytes => {
+                let (even, odd) = to_split_bin(&sections.data, &sections.code, &sections.custom, &layout, options.bin_base, options.image_size, options.pad_byte)
+                                      .map_err(AssemblerError::LayoutError)?;
+                let (even_path, odd_path) = split_output_paths(path);
+                files.push((even_path, even));
+                files.push((odd_path, odd));
+                continue;
+            },
+            (OutputFormat::Bin, _, Some(sections)) => {
+                to_bin(&sections.data, &sections.code, &sections.custom, &layout, options.bin_base, options.image_size, options.pad_byte)
+                    .map_err(AssemblerError::LayoutError)?
+            },
 
-                match instr_type {
-                    InstrType::Regular(reg) => bytes.append(&mut reg.to_be_bytes().to_vec()),
-                    InstrType::Long(long) => bytes.append(&mut long.to_be_bytes().to_vec())
-                } 
+            _ => unreachable!("sections are assembled for every requested format")
+        };
+
+        files.push((path.to_owned(), bytes));
+    }
+
+    // the provenance gives addresses as the program is loaded, which for a lone object file assumes it is linked first
+    if let (Some(path), Some(sections)) = (&options.provenance_path, executable.as_ref().or(object.as_ref())) {
+        files.push((path.to_owned(), to_provenance_json(sections, &options.input_path, &layout).into_bytes()));
+    }
+
+    if let (Some(path), Some(sections)) = (&options.listing_path, executable.as_ref().or(object.as_ref())) {
+        let originals = read_original_lines(&source_lines, &options.input_path);
+        files.push((path.to_owned(), to_listing(&source_lines, sections, &layout, &options.input_path, &originals).into_bytes()));
+    }
+
+    if let (Some(path), Some(sections)) = (&options.map_path, executable.as_ref().or(object.as_ref())) {
+        let map = to_map_file(&definitions, &directives.globals, &label_sizes, sections, &layout, &options.input_path);
+        files.push((path.to_owned(), map.into_bytes()));
+    }
+
+    if let (Some(path), Some(sections)) = (&options.source_map_path, executable.as_ref().or(object.as_ref())) {
+        let source_map = to_source_map_json(&definitions, &label_sizes, sections, &layout, &options.input_path);
+        files.push((path.to_owned(), source_map.into_bytes()));
+    }
+
+    if let Some(path) = &options.c_header_path {
+        files.push((path.to_owned(), to_c_header(&definitions, &directives.globals, &options.input_path, path).into_bytes()));
+    }
+
+    if let Some(path) = &options.sym_path {
+        files.push((path.to_owned(), to_sym_file(&definitions, &ports).into_bytes()));
+    }
+
+    if let Some(path) = &options.symbols_json_path {
+        files.push((path.to_owned(), to_symbols_json(&definitions, &directives.globals, &label_sizes, &layout, &options.input_path).into_bytes()));
+    }
+
+    if let (Some(path), Some(sections)) = (&options.rust_source_path, executable.as_ref().or(object.as_ref())) {
+        files.push((path.to_owned(), to_rust_source(&definitions, &directives.globals, sections, &layout, &options.input_path).into_bytes()));
+    }
+
+    for (path, bytes) in files {
+        write_atomically(&path, &bytes)?;
+    }
+
+    Ok(())
+}
+
+
+/**
+ * Reads and parses the .ssa archive at the given path.
+ */
+fn read_archive_file(path:&Path) -> Result<Archive, AssemblerError> {
+    let bytes = fs::read(path).map_err(AssemblerError::io(IoOperation::ReadInput, path))?;
+    Archive::parse(&bytes).map_err(|err| AssemblerError::FormatError(path.to_path_buf(), Box::new(err)))
+}
+
+
+/**
+ * Bundles the object files into a .ssa archive, each member named after the file it was read from.
+ */
+fn create_archive(options:&ArchiveOptions) -> Result<(), AssemblerError> {
+    let mut archive = Archive::default();
+    for path in &options.member_paths {
+        archive.members.push(ArchiveMember {
+            name: path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+            object: read_object_file(path)?
+        });
+    }
+
+    let bytes = archive.to_bytes().map_err(|err| AssemblerError::FormatError(options.archive_path.to_path_buf(), Box::new(err)))?;
+    write_atomically(&options.archive_path, &bytes)
+}
+
+
+/**
+ * Links the input object files, and any members of the input archives they need, into a single .sse file,
+ * printing every link error found before failing.
+ */
+fn link_objects(options:&LinkOptions) -> Result<(), AssemblerError> {
+    let layout = read_layout_file(options.layout_path.as_deref())?.rebase(options.code_base, options.data_base)
+                                                                   .map_err(AssemblerError::LayoutError)?;
+    let mut objects = vec![];
+    let mut archives = vec![];
+    for path in &options.input_paths {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ssa") => archives.push((path.to_path_buf(), read_archive_file(path)?)),
+            _ => objects.push((path.to_path_buf(), read_object_file(path)?))
+        }
+    }
+
+    add_archive_members(&mut objects, &archives);
+    let sections = match link(&objects, &layout) {
+        Ok(sections) => sections,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("error: {}", err);
             }
+            return Err(AssemblerError::LinkFailedError(errors.len()));
+        }
+    };
+
+    layout.check_fits(&[(SectionKind::Data, sections.data.len()), (SectionKind::Code, sections.code.len())]).map_err(AssemblerError::LayoutError)?;
+
+    let bytes = SseFile {
+        entry: layout.code.origin,
+        sections: vec![
+            SseSection::new(SseSectionKind::Data, layout.data.origin, sections.data),
+            SseSection::new(SseSectionKind::Code, layout.code.origin, sections.code)
+        ],
+        metadata: toolchain_metadata(SUPPORTED_MARCH[0]),
+        lines: vec![],
+        symbols: vec![]
+    }.to_bytes().map_err(|err| AssemblerError::FormatError(options.output_path.to_path_buf(), Box::new(err)))?;
+
+    write_atomically(&options.output_path, &bytes)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{assemble, parse_args, read_sse_file, AssemblerError, Command};
+    use crate::sse::SseSectionKind;
+
+
+    /**
+     * Writes the source to a file named after the test and assembles it to a .sse file, returning the path of
+     * the output along with the result.
+     */
+    fn assemble_text(name:&str, source:&str) -> (PathBuf, Result<(), AssemblerError>) {
+        let input_path = env::temp_dir().join(format!("s16_{}_{}.asm", name, std::process::id()));
+        let output_path = input_path.with_extension("sse");
+        fs::write(&input_path, source).unwrap();
+
+        let args = vec![input_path.display().to_string(), output_path.display().to_string()];
+        let options = match parse_args(&args).unwrap() {
+            Command::Assemble(options) => options,
+            _ => panic!("expected an assemble command")
+        };
+
+        let result = assemble(&options);
+        fs::remove_file(&input_path).unwrap();
+        (output_path, result)
+    }
+
+
+    #[test]
+    fn test_assemble_reserved_label() {
+        let (output_path, result) = assemble_text("reserved_label", ".code:\nax: halt\n");
+        let err = result.unwrap_err();
+        assert_eq!(err.exit_code(), 65);
+        assert!(err.to_string().starts_with("line 2: "));
+        assert!(!output_path.exists());
+    }
+
+
+    #[test]
+    fn test_assemble_unknown_names() {
+        let lines = [("foo ax", "line 2: 'foo' is not an instruction mnemonic"), ("inc foo", "line 2: 'foo' is not a register"),
+                     ("push ax, foo", "line 2: 'foo' is not a register")];
+        for (line, expected) in lines {
+            let (_, result) = assemble_text("unknown_names", &format!(".code:\n{}\n", line));
+            let err = result.unwrap_err();
+            assert_eq!((err.exit_code(), err.to_string()), (65, expected.to_string()));
         }
     }
 
-    writer.write_all(&bytes).unwrap();
+
+    #[test]
+    fn test_assemble_bss_section() {
+        let (output_path, result) = assemble_text("bss_section", ".data:\ncount: .word 0\n.bss:\nbuffer: .space 16\n.code:\nhalt\n");
+        result.unwrap();
+
+        let file = read_sse_file(&output_path).unwrap();
+        let bss = file.section(&SseSectionKind::Bss).unwrap();
+        assert_eq!((bss.address, bss.size), (0x9002, 16));
+        fs::remove_file(&output_path).unwrap();
+    }
 }
@@ -0,0 +1,703 @@
+use std::cell::Cell;
+use std::fmt::Display;
+
+
+/**
+ * The languages diagnostics can be reported in.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish
+}
+
+impl TryFrom<&str> for Language {
+    type Error = String;
+
+    /**
+     * Takes a language code such as "en" or "es_ES.UTF-8" and returns the language it refers to.
+     */
+    fn try_from(code:&str) -> Result<Language, String> {
+        match code.to_lowercase().get(..2) {
+            Some("en") => Ok(Language::English),
+            Some("es") => Ok(Language::Spanish),
+            _ => Err(code.to_string())
+        }
+    }
+}
+
+
+// this is a single-threaded assembler, so the language is held per thread which also keeps tests independent
+thread_local! {
+    static LANGUAGE:Cell<Language> = const { Cell::new(Language::English) };
+}
+
+pub fn set_language(language:Language) {
+    LANGUAGE.with(|current| current.set(language));
+}
+
+pub fn get_language() -> Language {
+    LANGUAGE.with(|current| current.get())
+}
+
+
+/**
+ * Identifies each diagnostic message the assembler can produce. The text for each id lives in the catalog
+ * below, with `{0}`, `{1}`, ... marking where its parameters are substituted.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageId {
+    // command line and file handling
+    Usage,
+    UnknownOption,
+    OptionExpectsValue,
+    InvalidDefine,
+    InvalidAlias,
+    InvalidScratchRegister,
+    ScratchOperand,
+    ScratchHalf,
+    InvalidRegisterPair,
+    RegisterPairOverlap,
+    MissingTestedRegister,
+    InvalidBaseAddress,
+    InvalidPadByte,
+    InvalidImageSize,
+    UnknownKeptSymbol,
+    InvalidEndian,
+    LittleEndianObject,
+    UnsupportedMarch,
+    UnsupportedLanguage,
+    InputNotAsm,
+    UnsupportedFormat,
+    FormatExpectsPath,
+    DuplicateOutputPath,
+    FileExtension,
+    CannotReadInput,
+    CannotWriteOutput,
+    NoSuchFile,
+    PermissionDenied,
+    StorageFull,
+    IsADirectory,
+    SourceLine,
+    IncludedSourceLine,
+    InvalidFile,
+    AssemblyFailed,
+    LinkFailed,
+
+    // validation
+    RegisterNotNone,
+    MixedRegisterTypes,
+    RegisterIsNone,
+    OperandNotRegister,
+    UncodedRegister,
+    MulDivSource,
+    SelfDivision,
+    OperandNotShortImmediate,
+    OperandNotLongImmediate,
+    ImmediateTooLarge,
+    ImmediateOverlapsRegister,
+    ImmediateOutOfRange,
+    LabelInvalidFormat,
+    LabelTooLong,
+    ReservedLabel,
+    OperandSeparators,
+    TooManyOperands,
+    FlagSuffix,
+    SizeSuffix,
+    ByteSizeMismatch,
+    WordSizeMismatch,
+    UnknownSuffix,
+    UnknownMnemonic,
+    UnknownRegister,
+    MemoryOperand,
+
+    // symbols
+    UndefinedLabel,
+    UndefinedLabelSuggestions,
+    MissingSymbolName,
+    UndefinedGlobal,
+    DefinedExtern,
+    ExternInExecutable,
+    LocalLabelScope,
+    SectionExpectsName,
+    UnplacedSection,
+    InitialisedBss,
+    EntryExpectsLabel,
+    DuplicateEntry,
+    EntryInObject,
+    EntryOutsideCode,
+    MisalignedData,
+    InvalidPort,
+    RedefinedPort,
+    JumpOutsideCode,
+    CodeAccess,
+    InvalidPortMapLine,
+    InvalidPortMapNumber,
+    DuplicatePortMapPort,
+    PortNotInMap,
+    PortMismatch,
+    UndeclaredPort,
+    MetadataExpectsValue,
+    DuplicateMetadata,
+
+    // macros
+    MacroMissingName,
+    DuplicateMacro,
+    NestedMacro,
+    UnterminatedMacro,
+    UnexpectedEndm,
+    MacroArgumentCount,
+    MacroRecursion,
+    InvalidRepeatCount,
+    UnterminatedRepeat,
+    UnexpectedEndr,
+    IncludeExpectsPath,
+    IncludeNotFound,
+    CircularInclude,
+    BinaryIncludeRange,
+    InvalidExpression,
+    UndefinedConstant,
+    DivisionByZero,
+    InvalidRadix,
+    InvalidCharmap,
+    UnknownDataDirective,
+    DataExpectsValue,
+    DataValueOutOfRange,
+    InvalidDataCount,
+    FillExpectsOperands,
+    InvalidFixed,
+    FixedOutOfRange,
+    InvalidBcd,
+    InvalidString,
+    InvalidEscape,
+    PascalStringTooLong,
+    TrailingDataTokens,
+    UnexpectedConditional,
+    ConditionalAfterElse,
+    UnterminatedConditional,
+    ConditionalExpectsName,
+    RedefinedConstant,
+    ConstantLabelClash,
+    DefineExpectsName,
+    RedefinedDefine,
+    InvalidRegisterAlias,
+    RedefinedRegisterAlias,
+    EmptyUserDiagnostic,
+
+    // decoding and output files
+    InvalidOpcode,
+    InvalidRegisterCode,
+    UnusedOperandBits,
+    LongInstruction,
+    NotLongInstruction,
+    TruncatedInstruction,
+    MissingSseMagic,
+    UnsupportedSseVersion,
+    TruncatedSse,
+    SseChecksumMismatch,
+    SseChecksumVerified,
+    UnknownLineFile,
+    InvalidMetadata,
+
+    // object files
+    MissingObjectMagic,
+    UnsupportedObjectVersion,
+    TruncatedObject,
+    InvalidObjectSection,
+    InvalidRelocationKind,
+    UnrelocatableReference,
+    SectionInObject,
+    NameTooLong,
+    TooManySymbols,
+    TooManyRelocations,
+
+    // archives
+    MissingArchiveMagic,
+    UnsupportedArchiveVersion,
+    TruncatedArchive,
+    InvalidArchiveMember,
+
+    // memory layout
+    InvalidLayoutLine,
+    InvalidLayoutNumber,
+    UnknownRegion,
+    UnknownSection,
+    RegionOutOfRange,
+    OverlappingRegions,
+    MissingPlacement,
+    SharedRegion,
+    SectionOverflow,
+    SectionBelowImageBase,
+    ImageTooLarge,
+    AddressOverflow,
+
+    // linking
+    UndefinedSymbol,
+    DuplicateSymbol,
+    RelocationOverflow,
+    RelocationOutOfBounds,
+
+    // symbol tracing
+    TraceDefinition,
+    TraceResolved,
+    TraceRelocated
+}
+
+
+fn english(id:MessageId) -> &'static str {
+    match id {
+        MessageId::Usage => "Expected <input file path>.asm <output file path>.sse|.sso|.memh|.srec|.bin|.ihex|.readmemh|.logisim|.coe|.mif [--format <format>[:<path>]]... [--bin-base <address>] [--pad-byte|--fill <byte>] [--image-size <bytes>] [--split-bytes] [--endian <big|little>] [--keep-going] [--trace-symbols] [-g] [--strip] [--keep-symbols <label>[,<label>]...] [--compat-numbers] [--align-data] [--strict] [--layout <file>] [--port-map <file>] [--check-ports] [--code-base <address>] [--data-base <address>] [--provenance <file>.json] [--listing <file>.lst] [--map <file>.map] [--source-map <file>.json] [--emit-c-header <file>.h] [--emit-rust <file>.rs] [--emit-symbols-json <file>.json] [--emit-sym <file>.sym] [-I <dir>]... [-D <name>[=<value>]]... [--alias <name>=<mnemonic>]... [--scratch <register>] [--no-pseudos] [--march <level>] [--lang <en|es>]
+    or inspect <file path>.sse|.sso
+    or dump <file path>.sse
+    or verify <file path>.sse
+    or link <object path>.sso|.ssa... -o <output path>.sse [--layout <file>] [--code-base <address>] [--data-base <address>]
+    or ar <archive path>.ssa <object path>.sso...",
+        MessageId::UnknownOption => "Unknown option '{0}'",
+        MessageId::OptionExpectsValue => "Option '{0}' expects a value",
+        MessageId::InvalidDefine => "Invalid define '{0}', expected NAME or NAME=<integer>",
+        MessageId::InvalidAlias => "Invalid alias '{0}', expected NAME=<mnemonic> where NAME is not already a mnemonic",
+        MessageId::InvalidScratchRegister => "Invalid scratch register '{0}', expected one of {1}",
+        MessageId::ScratchOperand => "'{0}' uses the scratch register {1} as an operand, which its expansion overwrites, so another must be given with '--scratch'",
+        MessageId::ScratchHalf => "'{0}' is a byte register, but the scratch register {1} has no byte halves to load a constant for it into",
+        MessageId::InvalidRegisterPair => "'{0}' is not a pair of two different 16-bit registers, such as 'dx:ax'",
+        MessageId::RegisterPairOverlap => "'{0}' writes the low word of its result to {1} before reading it as the high word of its source",
+        MessageId::MissingTestedRegister => "'{0}' to a label expects the register it tests after the label, as in '{0} @done, cx'",
+        MessageId::InvalidBaseAddress => "'{0}' expects an address from 0x0000 to 0xFFFF, found '{1}'",
+        MessageId::InvalidPadByte => "'{0}' expects a byte from 0x00 to 0xFF, found '{1}'",
+        MessageId::InvalidImageSize => "'--image-size' expects a size from 1 to 0x10000 bytes, found '{0}'",
+        MessageId::UnknownKeptSymbol => "'--keep-symbols' names '{0}', which is not a label defined in the file",
+        MessageId::InvalidEndian => "'--endian' expects 'big' or 'little', found '{0}'",
+        MessageId::LittleEndianObject => "'--endian little' cannot be used with .sso output, as object files are linked big-endian",
+        MessageId::UnsupportedMarch => "Unsupported --march level '{0}', expected one of: {1}",
+        MessageId::UnsupportedLanguage => "Unsupported language '{0}', expected one of: en, es",
+        MessageId::InputNotAsm => "Input filename must end in .asm",
+        MessageId::UnsupportedFormat => "Unsupported --format '{0}', expected one of: {1}",
+        MessageId::FormatExpectsPath => "--format={0} needs a path, either as --format={0}:<path> or as the output file path",
+        MessageId::DuplicateOutputPath => "--format={0} and --format={1} are both written to '{2}', so one would overwrite the other",
+        MessageId::FileExtension => "Filename must end in one of: {0}",
+        MessageId::CannotReadInput => "cannot read input '{0}': {1}",
+        MessageId::CannotWriteOutput => "cannot write output '{0}': {1}",
+        MessageId::NoSuchFile => "no such file or directory",
+        MessageId::PermissionDenied => "permission denied",
+        MessageId::StorageFull => "no space left on device",
+        MessageId::IsADirectory => "is a directory",
+        MessageId::SourceLine => "line {0}: {1}",
+        MessageId::IncludedSourceLine => "{0}, line {1}: {2}",
+        MessageId::InvalidFile => "'{0}' is not valid: {1}",
+        MessageId::AssemblyFailed => "assembly failed with {0} error(s), output not written",
+        MessageId::LinkFailed => "linking failed with {0} error(s), output not written",
+
+        MessageId::RegisterNotNone => "Register {0} should be None",
+        MessageId::MixedRegisterTypes => "Register {0} and {1} are either of different sizes or mixed high/low",
+        MessageId::RegisterIsNone => "Register {0} must not be None",
+        MessageId::OperandNotRegister => "Operand {0} should be a register",
+        MessageId::UncodedRegister => "Register {0} has no register code, so it cannot be an operand",
+        MessageId::MulDivSource => "'{0}' operates on the high and low bytes of its source, but {1} has none, so it must be ax, bx, cx or dx",
+        MessageId::SelfDivision => "'{0}' divides {1} by itself, which is always 1 or undefined",
+        MessageId::OperandNotShortImmediate => "Operand {0} should be a short immediate",
+        MessageId::OperandNotLongImmediate => "Operand {0} should be a long immediate",
+        MessageId::ImmediateTooLarge => "Immediate {0} is too large",
+        MessageId::ImmediateOverlapsRegister => "Immediate {0} overlaps the register code, which takes bits 3 and 4 of the operand, so it must be from 0 to 7",
+        MessageId::ImmediateOutOfRange => "Immediate {0} does not fit in {1} bits, which hold {2} to {3}",
+        MessageId::LabelInvalidFormat => "Label '{0}' is in an invalid format",
+        MessageId::LabelTooLong => "Label '{0}' is longer than {1} bytes",
+        MessageId::ReservedLabel => "Label '{0}' is reserved as a register name or mnemonic",
+        MessageId::OperandSeparators => "Operands must be separated by a single comma in strict mode, found '{0}'",
+        MessageId::TooManyOperands => "'{0}' takes at most {1} operand(s), but {2} were given",
+        MessageId::FlagSuffix => "'{0}' cannot take the '.{1}' suffix, as whether it updates the flags is fixed",
+        MessageId::SizeSuffix => "'{0}' has no register operands, so it cannot take the '.{1}' suffix",
+        MessageId::ByteSizeMismatch => "'{0}' is a byte operation, but {1} is a 16-bit register",
+        MessageId::WordSizeMismatch => "'{0}' is a word operation, but {1} is a byte register",
+        MessageId::UnknownSuffix => "'{0}' has an unknown suffix, expected '.f' or '.nf', and '.b' or '.w'",
+        MessageId::UnknownMnemonic => "'{0}' is not an instruction mnemonic",
+        MessageId::UnknownRegister => "'{0}' is not a register",
+        MessageId::MemoryOperand => "expected a register and an address in brackets, as in 'load ax, [bx]', found '{0}'",
+
+        MessageId::UndefinedLabel => "Label '{0}' is not defined",
+        MessageId::UndefinedLabelSuggestions => "Label '{0}' is not defined, did you mean {1}?",
+        MessageId::MissingSymbolName => "'{0}' expects at least one symbol name",
+        MessageId::UndefinedGlobal => "'.global {0}' names a label which is not defined in this file",
+        MessageId::DefinedExtern => "'.extern {0}' names a label which is defined in this file",
+        MessageId::ExternInExecutable => "'.extern {0}' cannot be resolved when assembling to .sse, assemble to .sso and link instead",
+        MessageId::LocalLabelScope => "Local label '{0}' must follow a non-local label",
+        MessageId::SectionExpectsName => "'.section' expects a section name",
+        MessageId::UnplacedSection => "Section '{0}' is not placed in the memory layout",
+        MessageId::InitialisedBss => "The bss section only reserves space, so every value in it must be zero",
+        MessageId::EntryExpectsLabel => "'.entry' expects exactly one label",
+        MessageId::DuplicateEntry => "The entry point is already set by '.entry {0}'",
+        MessageId::EntryInObject => "'.entry {0}' cannot be used when assembling to .sso, as only an executable has an entry point",
+        MessageId::EntryOutsideCode => "'.entry {0}' names a label outside the code section, and execution can only start in code",
+        MessageId::MisalignedData => "'{0}' is placed at the odd address {1}, but Sim6 reads words from even addresses only; '--align-data' pads it to the next even address",
+        MessageId::InvalidPort => "'.port' expects a name and a port number from 0 to 31, as in '.port UART_TX, 0x05', found '{0}'",
+        MessageId::RedefinedPort => "Port '{0}' is already defined",
+        MessageId::JumpOutsideCode => "'{0}' jumps to '{1}', which is not in the code section",
+        MessageId::CodeAccess => "'{0}' accesses '{1}', which is in the code section rather than the data",
+        MessageId::InvalidPortMapLine => "line {0}: expected 'port <name> <number>'",
+        MessageId::InvalidPortMapNumber => "line {0}: '{1}' is not a port number from 0 to 31",
+        MessageId::DuplicatePortMapPort => "line {0}: port '{1}' is already declared",
+        MessageId::PortNotInMap => "'.port {0}' gives port {1}, which is not in the port map",
+        MessageId::PortMismatch => "'.port {0}' gives port {1}, but the port map gives it as port {2}",
+        MessageId::UndeclaredPort => "Port {0} is not declared with '.port' or in the port map",
+        MessageId::MetadataExpectsValue => "'{0}' expects a value",
+        MessageId::DuplicateMetadata => "'{0}' is given more than once",
+
+        MessageId::MacroMissingName => "'.macro' expects a name",
+        MessageId::DuplicateMacro => "Macro '{0}' is already defined",
+        MessageId::NestedMacro => "Macro '{0}' cannot be defined inside another macro",
+        MessageId::UnterminatedMacro => "Macro '{0}' has no matching '.endm'",
+        MessageId::UnexpectedEndm => "'.endm' without a matching '.macro'",
+        MessageId::MacroArgumentCount => "Macro '{0}' takes {1} arguments but was given {2}",
+        MessageId::MacroRecursion => "Macro '{0}' expands more than {1} levels deep",
+        MessageId::InvalidRepeatCount => "'{0}' does not give a valid repeat count",
+        MessageId::UnterminatedRepeat => "'.rept' has no matching '.endr'",
+        MessageId::UnexpectedEndr => "'.endr' without a matching '.rept'",
+        MessageId::IncludeExpectsPath => "'{0}' expects a file path in double quotes or backticks",
+        MessageId::IncludeNotFound => "Cannot find included file '{0}'",
+        MessageId::CircularInclude => "Circular include of '{0}': {1}",
+        MessageId::BinaryIncludeRange => "The offset and length given for '{0}' go past its end, as it is {1} bytes long",
+        MessageId::InvalidExpression => "'{0}' is not a valid expression",
+        MessageId::UndefinedConstant => "Symbol '{0}' is not defined",
+        MessageId::DivisionByZero => "'{0}' divides by zero",
+        MessageId::InvalidRadix => "'.radix' expects 2, 8, 10 or 16, found '{0}'",
+        MessageId::InvalidCharmap => "'.charmap' expects a character and the byte it is encoded as, as in '.charmap 'A', 0x0A', found '{0}'",
+        MessageId::UnknownDataDirective => "'{0}' is not a data directive",
+        MessageId::DataExpectsValue => "'{0}' expects a value",
+        MessageId::DataValueOutOfRange => "'{0}' is {1}, which does not fit in {2} bits, as they hold {3} to {4}",
+        MessageId::InvalidDataCount => "'{0}' is not a valid number of bytes",
+        MessageId::FillExpectsOperands => "'.fill' expects a count and a value, as in '.fill 16, 0xFF', found '{0}'",
+        MessageId::InvalidFixed => "'{0}' is not a decimal number",
+        MessageId::FixedOutOfRange => "'{0}' does not fit in Q{1}.{2}, which holds {3} to {4}",
+        MessageId::InvalidBcd => "'.bcd' expects a decimal number, found '{0}'",
+        MessageId::InvalidString => "Expected a string between backticks or double quotes, found '{0}'",
+        MessageId::InvalidEscape => "'{0}' holds an invalid escape sequence",
+        MessageId::PascalStringTooLong => "An '.asciip' string can be at most 255 bytes long, but this one is {0}",
+        MessageId::TrailingDataTokens => "'{0}' takes a single value, but found '{1}' after it",
+        MessageId::UnexpectedConditional => "'{0}' without a matching '.if'",
+        MessageId::ConditionalAfterElse => "'{0}' cannot follow '.else'",
+        MessageId::UnterminatedConditional => "'.if' has no matching '.endif'",
+        MessageId::ConditionalExpectsName => "'{0}' expects a symbol name",
+        MessageId::RedefinedConstant => "Constant '{0}' is already defined, and only a constant defined with .set can be redefined with .set",
+        MessageId::ConstantLabelClash => "'{0}' is defined as both a constant and a label",
+        MessageId::DefineExpectsName => "'.define' expects a name",
+        MessageId::RedefinedDefine => "'{0}' is already defined with .define",
+        MessageId::InvalidRegisterAlias => "'.reg' expects a name and the register it stands for, as in '.reg counter, cx', found '{0}'",
+        MessageId::RedefinedRegisterAlias => "'{0}' is already a register alias in this scope",
+        MessageId::EmptyUserDiagnostic => "'{0}' directive reached",
+
+        MessageId::InvalidOpcode => "{0} is not a valid opcode",
+        MessageId::InvalidRegisterCode => "Register code {0} with high bit {1} and low bit {2} is not a valid register",
+        MessageId::UnusedOperandBits => "Opcode {0} does not take the operand bits {1}",
+        MessageId::LongInstruction => "Opcode {0} is a 32 bit instruction and cannot be decoded from 16 bits",
+        MessageId::NotLongInstruction => "Opcode {0} is a 16 bit instruction and cannot be decoded from 32 bits",
+        MessageId::TruncatedInstruction => "Instruction is truncated",
+        MessageId::MissingSseMagic => "file does not start with the .sse magic number",
+        MessageId::UnsupportedSseVersion => "executable format version {0} is not supported",
+        MessageId::TruncatedSse => "executable file is truncated",
+        MessageId::SseChecksumMismatch => "stored checksum {0} does not match the computed checksum {1}",
+        MessageId::SseChecksumVerified => "{0}: checksum {1} OK",
+        MessageId::UnknownLineFile => "debug line table refers to source file {0}, which it does not name",
+        MessageId::InvalidMetadata => "metadata entry '{0}' is not in the form key=value",
+
+        MessageId::MissingObjectMagic => "file does not start with the .sso magic number",
+        MessageId::UnsupportedObjectVersion => "object format version {0} is not supported",
+        MessageId::TruncatedObject => "object file is truncated",
+        MessageId::InvalidObjectSection => "{0} is not a valid section type",
+        MessageId::InvalidRelocationKind => "{0} is not a valid relocation type",
+        MessageId::UnrelocatableReference => "Label '{0}' cannot be relocated in this position",
+        MessageId::SectionInObject => "Section '{0}' cannot be used in an object file, which only holds code and data",
+        MessageId::NameTooLong => "the name '{0}' is longer than {1} bytes",
+        MessageId::TooManySymbols => "{0} symbols do not fit in an object file, which holds at most {1}",
+        MessageId::TooManyRelocations => "{0} relocations do not fit in an object file, which holds at most {1}",
+
+        MessageId::MissingArchiveMagic => "file does not start with the .ssa magic number",
+        MessageId::UnsupportedArchiveVersion => "archive format version {0} is not supported",
+        MessageId::TruncatedArchive => "archive is truncated",
+        MessageId::InvalidArchiveMember => "archive member '{0}' is not a valid object file: {1}",
+
+        MessageId::InvalidLayoutLine => "line {0}: expected 'region <name> <origin> <length>' or 'place <code|data> <region>'",
+        MessageId::InvalidLayoutNumber => "line {0}: '{1}' is not a valid number",
+        MessageId::UnknownRegion => "line {0}: region '{1}' has not been declared",
+        MessageId::UnknownSection => "line {0}: '{1}' is not a valid section name",
+        MessageId::RegionOutOfRange => "region '{0}' does not fit in the 16 bit address space",
+        MessageId::OverlappingRegions => "regions '{0}' and '{1}' overlap",
+        MessageId::MissingPlacement => "the {0} section is not placed in any region",
+        MessageId::SharedRegion => "only one section can be placed in region '{0}'",
+        MessageId::SectionOverflow => "{0} section is {1} bytes but region '{2}' only holds {3}",
+        MessageId::SectionBelowImageBase => "{0} section at {1} lies below the image base address {2}",
+        MessageId::ImageTooLarge => "the image needs {0} bytes but '--image-size' only allows {1}",
+        MessageId::AddressOverflow => "address {0} does not fit in the 16-bit addresses of the {1} format",
+
+        MessageId::UndefinedSymbol => "undefined symbol '{0}' referenced in {1}",
+        MessageId::DuplicateSymbol => "symbol '{0}' is defined in both {1} and {2}",
+        MessageId::RelocationOverflow => "address {0} of symbol '{1}' does not fit in a {2} relocation in {3}",
+        MessageId::RelocationOutOfBounds => "relocation for symbol '{0}' in {1} lies outside its section",
+
+        MessageId::TraceDefinition => "pass 1: line {0}: label '{1}' defined at {2} in the {3} section",
+        MessageId::TraceResolved => "pass 2: line {0}: '@{1}' (defined on line {2}) resolved to {3} and substituted",
+        MessageId::TraceRelocated => "pass 2: line {0}: '@{1}' (defined on line {2}) left as 0, {3} relocation recorded at {4}+{5} for the linker"
+    }
+}
+
+
+/**
+ * Returns the Spanish text for a message.
+ */
+fn spanish(id:MessageId) -> &'static str {
+    match id {
+        MessageId::Usage => "Se esperaba <ruta de entrada>.asm <ruta de salida>.sse|.sso|.memh|.srec|.bin|.ihex|.readmemh|.logisim|.coe|.mif [--format <formato>[:<ruta>]]... [--bin-base <dirección>] [--pad-byte|--fill <byte>] [--image-size <bytes>] [--split-bytes] [--endian <big|little>] [--keep-going] [--trace-symbols] [-g] [--strip] [--keep-symbols <etiqueta>[,<etiqueta>]...] [--compat-numbers] [--align-data] [--strict] [--layout <archivo>] [--port-map <archivo>] [--check-ports] [--code-base <dirección>] [--data-base <dirección>] [--provenance <archivo>.json] [--listing <archivo>.lst] [--map <archivo>.map] [--source-map <archivo>.json] [--emit-c-header <archivo>.h] [--emit-rust <archivo>.rs] [--emit-symbols-json <archivo>.json] [--emit-sym <archivo>.sym] [-I <directorio>]... [-D <nombre>[=<valor>]]... [--alias <nombre>=<mnemónico>]... [--scratch <registro>] [--no-pseudos] [--march <nivel>] [--lang <en|es>]
+    o inspect <ruta del archivo>.sse|.sso
+    o dump <ruta del archivo>.sse
+    o verify <ruta del archivo>.sse
+    o link <ruta del objeto>.sso|.ssa... -o <ruta de salida>.sse [--layout <archivo>] [--code-base <dirección>] [--data-base <dirección>]
+    o ar <ruta de la biblioteca>.ssa <ruta del objeto>.sso...",
+        MessageId::UnknownOption => "Opción desconocida '{0}'",
+        MessageId::OptionExpectsValue => "La opción '{0}' necesita un valor",
+        MessageId::InvalidDefine => "Definición '{0}' no válida, se esperaba NOMBRE o NOMBRE=<entero>",
+        MessageId::InvalidAlias => "Alias '{0}' no válido, se esperaba NOMBRE=<mnemónico> donde NOMBRE no sea ya un mnemónico",
+        MessageId::InvalidScratchRegister => "Registro auxiliar '{0}' no válido, se esperaba uno de {1}",
+        MessageId::ScratchOperand => "'{0}' usa como operando el registro auxiliar {1}, que su expansión sobrescribe, así que debe indicarse otro con '--scratch'",
+        MessageId::ScratchHalf => "'{0}' es un registro de un byte, pero el registro auxiliar {1} no tiene mitades de un byte en las que cargar una constante para él",
+        MessageId::InvalidRegisterPair => "'{0}' no es un par de dos registros de 16 bits distintos, como 'dx:ax'",
+        MessageId::RegisterPairOverlap => "'{0}' escribe la palabra baja de su resultado en {1} antes de leerlo como la palabra alta de su origen",
+        MessageId::MissingTestedRegister => "'{0}' a una etiqueta espera tras la etiqueta el registro que comprueba, como en '{0} @done, cx'",
+        MessageId::InvalidBaseAddress => "'{0}' necesita una dirección de 0x0000 a 0xFFFF, se encontró '{1}'",
+        MessageId::InvalidPadByte => "'{0}' necesita un byte de 0x00 a 0xFF, se encontró '{1}'",
+        MessageId::InvalidImageSize => "'--image-size' necesita un tamaño de 1 a 0x10000 bytes, se encontró '{0}'",
+        MessageId::UnknownKeptSymbol => "'--keep-symbols' nombra '{0}', que no es una etiqueta definida en el archivo",
+        MessageId::InvalidEndian => "'--endian' necesita 'big' o 'little', se encontró '{0}'",
+        MessageId::LittleEndianObject => "'--endian little' no se puede usar con una salida .sso, ya que los archivos objeto se enlazan en big-endian",
+        MessageId::UnsupportedMarch => "Nivel de --march '{0}' no soportado, se esperaba uno de: {1}",
+        MessageId::UnsupportedLanguage => "Idioma '{0}' no soportado, se esperaba uno de: en, es",
+        MessageId::InputNotAsm => "El archivo de entrada debe terminar en .asm",
+        MessageId::UnsupportedFormat => "Formato --format '{0}' no soportado, se esperaba uno de: {1}",
+        MessageId::FormatExpectsPath => "--format={0} necesita una ruta, como --format={0}:<ruta> o como la ruta de salida",
+        MessageId::DuplicateOutputPath => "--format={0} y --format={1} se escriben ambos en '{2}', así que uno sobrescribiría al otro",
+        MessageId::FileExtension => "El archivo debe terminar en uno de: {0}",
+        MessageId::CannotReadInput => "no se puede leer la entrada '{0}': {1}",
+        MessageId::CannotWriteOutput => "no se puede escribir la salida '{0}': {1}",
+        MessageId::NoSuchFile => "no existe el archivo o directorio",
+        MessageId::PermissionDenied => "permiso denegado",
+        MessageId::StorageFull => "no queda espacio en el dispositivo",
+        MessageId::IsADirectory => "es un directorio",
+        MessageId::SourceLine => "línea {0}: {1}",
+        MessageId::IncludedSourceLine => "{0}, línea {1}: {2}",
+        MessageId::InvalidFile => "'{0}' no es válido: {1}",
+        MessageId::AssemblyFailed => "el ensamblado falló con {0} error(es), no se escribió la salida",
+        MessageId::LinkFailed => "el enlazado falló con {0} error(es), no se escribió la salida",
+
+        MessageId::RegisterNotNone => "El registro {0} debería ser None",
+        MessageId::MixedRegisterTypes => "Los registros {0} y {1} son de distinto tamaño o mezclan parte alta y baja",
+        MessageId::RegisterIsNone => "El registro {0} no debe ser None",
+        MessageId::OperandNotRegister => "El operando {0} debería ser un registro",
+        MessageId::UncodedRegister => "El registro {0} no tiene código de registro, así que no puede ser un operando",
+        MessageId::MulDivSource => "'{0}' opera con los bytes alto y bajo de su origen, pero {1} no los tiene, así que debe ser ax, bx, cx o dx",
+        MessageId::SelfDivision => "'{0}' divide {1} entre sí mismo, lo que siempre da 1 o no está definido",
+        MessageId::OperandNotShortImmediate => "El operando {0} debería ser un inmediato corto",
+        MessageId::OperandNotLongImmediate => "El operando {0} debería ser un inmediato largo",
+        MessageId::ImmediateTooLarge => "El inmediato {0} es demasiado grande",
+        MessageId::ImmediateOverlapsRegister => "El inmediato {0} se solapa con el código de registro, que ocupa los bits 3 y 4 del operando, así que debe estar entre 0 y 7",
+        MessageId::ImmediateOutOfRange => "El inmediato {0} no cabe en {1} bits, que admiten de {2} a {3}",
+        MessageId::LabelInvalidFormat => "La etiqueta '{0}' tiene un formato no válido",
+        MessageId::LabelTooLong => "La etiqueta '{0}' ocupa más de {1} bytes",
+        MessageId::ReservedLabel => "La etiqueta '{0}' está reservada como nombre de registro o mnemónico",
+        MessageId::OperandSeparators => "En modo estricto los operandos deben separarse con una sola coma, se encontró '{0}'",
+        MessageId::TooManyOperands => "'{0}' admite como mucho {1} operando(s), pero se dieron {2}",
+        MessageId::FlagSuffix => "'{0}' no admite el sufijo '.{1}', ya que si actualiza los indicadores es fijo",
+        MessageId::SizeSuffix => "'{0}' no tiene operandos de registro, así que no admite el sufijo '.{1}'",
+        MessageId::ByteSizeMismatch => "'{0}' es una operación de bytes, pero {1} es un registro de 16 bits",
+        MessageId::WordSizeMismatch => "'{0}' es una operación de palabras, pero {1} es un registro de un byte",
+        MessageId::UnknownSuffix => "'{0}' tiene un sufijo desconocido, se esperaba '.f' o '.nf', y '.b' o '.w'",
+        MessageId::UnknownMnemonic => "'{0}' no es un mnemónico de instrucción",
+        MessageId::UnknownRegister => "'{0}' no es un registro",
+        MessageId::MemoryOperand => "se esperaba un registro y una dirección entre corchetes, como en 'load ax, [bx]', se encontró '{0}'",
+
+        MessageId::UndefinedLabel => "La etiqueta '{0}' no está definida",
+        MessageId::UndefinedLabelSuggestions => "La etiqueta '{0}' no está definida, ¿quería decir {1}?",
+        MessageId::MissingSymbolName => "'{0}' necesita al menos un nombre de símbolo",
+        MessageId::UndefinedGlobal => "'.global {0}' nombra una etiqueta que no está definida en este archivo",
+        MessageId::DefinedExtern => "'.extern {0}' nombra una etiqueta que está definida en este archivo",
+        MessageId::ExternInExecutable => "'.extern {0}' no se puede resolver al ensamblar a .sse, ensamble a .sso y enlace",
+        MessageId::LocalLabelScope => "La etiqueta local '{0}' debe ir después de una etiqueta no local",
+        MessageId::SectionExpectsName => "'.section' necesita un nombre de sección",
+        MessageId::UnplacedSection => "La sección '{0}' no está colocada en el mapa de memoria",
+        MessageId::InitialisedBss => "La sección bss solo reserva espacio, así que todos sus valores deben ser cero",
+        MessageId::EntryExpectsLabel => "'.entry' necesita exactamente una etiqueta",
+        MessageId::DuplicateEntry => "El punto de entrada ya está fijado por '.entry {0}'",
+        MessageId::EntryInObject => "'.entry {0}' no se puede usar al ensamblar a .sso, ya que solo un ejecutable tiene punto de entrada",
+        MessageId::EntryOutsideCode => "'.entry {0}' nombra una etiqueta fuera de la sección de código, y la ejecución solo puede empezar en código",
+        MessageId::MisalignedData => "'{0}' está en la dirección impar {1}, pero Sim6 solo lee palabras de direcciones pares; '--align-data' lo lleva a la siguiente dirección par",
+        MessageId::InvalidPort => "'.port' necesita un nombre y un número de puerto de 0 a 31, como en '.port UART_TX, 0x05', se encontró '{0}'",
+        MessageId::RedefinedPort => "El puerto '{0}' ya está definido",
+        MessageId::JumpOutsideCode => "'{0}' salta a '{1}', que no está en la sección de código",
+        MessageId::CodeAccess => "'{0}' accede a '{1}', que está en la sección de código en lugar de la de datos",
+        MessageId::InvalidPortMapLine => "línea {0}: se esperaba 'port <nombre> <número>'",
+        MessageId::InvalidPortMapNumber => "línea {0}: '{1}' no es un número de puerto de 0 a 31",
+        MessageId::DuplicatePortMapPort => "línea {0}: el puerto '{1}' ya está declarado",
+        MessageId::PortNotInMap => "'.port {0}' da el puerto {1}, que no está en el mapa de puertos",
+        MessageId::PortMismatch => "'.port {0}' da el puerto {1}, pero el mapa de puertos lo da como puerto {2}",
+        MessageId::UndeclaredPort => "El puerto {0} no está declarado con '.port' ni en el mapa de puertos",
+        MessageId::MetadataExpectsValue => "'{0}' necesita un valor",
+        MessageId::DuplicateMetadata => "'{0}' aparece más de una vez",
+
+        MessageId::MacroMissingName => "'.macro' necesita un nombre",
+        MessageId::DuplicateMacro => "La macro '{0}' ya está definida",
+        MessageId::NestedMacro => "La macro '{0}' no se puede definir dentro de otra macro",
+        MessageId::UnterminatedMacro => "La macro '{0}' no tiene un '.endm' correspondiente",
+        MessageId::UnexpectedEndm => "'.endm' sin un '.macro' correspondiente",
+        MessageId::MacroArgumentCount => "La macro '{0}' recibe {1} argumentos pero se le dieron {2}",
+        MessageId::MacroRecursion => "La macro '{0}' se expande a más de {1} niveles de profundidad",
+        MessageId::InvalidRepeatCount => "'{0}' no indica un número de repeticiones válido",
+        MessageId::UnterminatedRepeat => "'.rept' no tiene un '.endr' correspondiente",
+        MessageId::UnexpectedEndr => "'.endr' sin un '.rept' correspondiente",
+        MessageId::IncludeExpectsPath => "'{0}' necesita la ruta de un archivo entre comillas dobles o acentos graves",
+        MessageId::IncludeNotFound => "No se encuentra el archivo incluido '{0}'",
+        MessageId::CircularInclude => "Inclusión circular de '{0}': {1}",
+        MessageId::BinaryIncludeRange => "La posición y longitud dadas para '{0}' pasan de su final, ya que tiene {1} bytes",
+        MessageId::InvalidExpression => "'{0}' no es una expresión válida",
+        MessageId::UndefinedConstant => "El símbolo '{0}' no está definido",
+        MessageId::DivisionByZero => "'{0}' divide entre cero",
+        MessageId::InvalidRadix => "'.radix' necesita 2, 8, 10 o 16, se encontró '{0}'",
+        MessageId::InvalidCharmap => "'.charmap' necesita un carácter y el byte con el que se codifica, como en '.charmap 'A', 0x0A', se encontró '{0}'",
+        MessageId::UnknownDataDirective => "'{0}' no es una directiva de datos",
+        MessageId::DataExpectsValue => "'{0}' necesita un valor",
+        MessageId::DataValueOutOfRange => "'{0}' vale {1}, que no cabe en {2} bits, ya que guardan de {3} a {4}",
+        MessageId::InvalidDataCount => "'{0}' no es un número de bytes válido",
+        MessageId::FillExpectsOperands => "'.fill' necesita un número de bytes y un valor, como en '.fill 16, 0xFF', se encontró '{0}'",
+        MessageId::InvalidFixed => "'{0}' no es un número decimal",
+        MessageId::FixedOutOfRange => "'{0}' no cabe en Q{1}.{2}, que guarda de {3} a {4}",
+        MessageId::InvalidBcd => "'.bcd' necesita un número decimal, se encontró '{0}'",
+        MessageId::InvalidString => "Se esperaba una cadena entre acentos graves o comillas dobles, se encontró '{0}'",
+        MessageId::InvalidEscape => "'{0}' contiene una secuencia de escape no válida",
+        MessageId::PascalStringTooLong => "Una cadena '.asciip' puede tener como mucho 255 bytes, pero esta tiene {0}",
+        MessageId::TrailingDataTokens => "'{0}' admite un solo valor, pero se encontró '{1}' después",
+        MessageId::UnexpectedConditional => "'{0}' sin un '.if' correspondiente",
+        MessageId::ConditionalAfterElse => "'{0}' no puede ir después de '.else'",
+        MessageId::UnterminatedConditional => "'.if' no tiene un '.endif' correspondiente",
+        MessageId::ConditionalExpectsName => "'{0}' necesita el nombre de un símbolo",
+        MessageId::RedefinedConstant => "La constante '{0}' ya está definida, y solo una constante definida con .set se puede redefinir con .set",
+        MessageId::ConstantLabelClash => "'{0}' está definido como constante y como etiqueta",
+        MessageId::DefineExpectsName => "'.define' necesita un nombre",
+        MessageId::RedefinedDefine => "'{0}' ya está definido con .define",
+        MessageId::InvalidRegisterAlias => "'.reg' necesita un nombre y el registro al que representa, como en '.reg counter, cx', se encontró '{0}'",
+        MessageId::RedefinedRegisterAlias => "'{0}' ya es un alias de registro en este ámbito",
+        MessageId::EmptyUserDiagnostic => "se alcanzó la directiva '{0}'",
+
+        MessageId::InvalidOpcode => "{0} no es un código de operación válido",
+        MessageId::InvalidRegisterCode => "El código de registro {0} con bit alto {1} y bit bajo {2} no es un registro válido",
+        MessageId::UnusedOperandBits => "El código de operación {0} no admite los bits de operando {1}",
+        MessageId::LongInstruction => "El código de operación {0} es una instrucción de 32 bits y no se puede decodificar desde 16 bits",
+        MessageId::NotLongInstruction => "El código de operación {0} es una instrucción de 16 bits y no se puede decodificar desde 32 bits",
+        MessageId::TruncatedInstruction => "La instrucción está truncada",
+        MessageId::MissingSseMagic => "el archivo no empieza con el número mágico de .sse",
+        MessageId::UnsupportedSseVersion => "la versión {0} del formato ejecutable no está soportada",
+        MessageId::TruncatedSse => "el archivo ejecutable está truncado",
+        MessageId::SseChecksumMismatch => "la suma de verificación almacenada {0} no coincide con la calculada {1}",
+        MessageId::SseChecksumVerified => "{0}: suma de verificación {1} correcta",
+        MessageId::UnknownLineFile => "la tabla de líneas de depuración hace referencia al archivo fuente {0}, que no nombra",
+        MessageId::InvalidMetadata => "la entrada de metadatos '{0}' no tiene la forma clave=valor",
+
+        MessageId::MissingObjectMagic => "el archivo no empieza con el número mágico de .sso",
+        MessageId::UnsupportedObjectVersion => "la versión {0} del formato de objeto no está soportada",
+        MessageId::TruncatedObject => "el archivo objeto está truncado",
+        MessageId::InvalidObjectSection => "{0} no es un tipo de sección válido",
+        MessageId::InvalidRelocationKind => "{0} no es un tipo de reubicación válido",
+        MessageId::UnrelocatableReference => "La etiqueta '{0}' no se puede reubicar en esta posición",
+        MessageId::SectionInObject => "La sección '{0}' no se puede usar en un archivo objeto, que solo contiene código y datos",
+        MessageId::NameTooLong => "el nombre '{0}' ocupa más de {1} bytes",
+        MessageId::TooManySymbols => "{0} símbolos no caben en un archivo objeto, que admite como máximo {1}",
+        MessageId::TooManyRelocations => "{0} reubicaciones no caben en un archivo objeto, que admite como máximo {1}",
+
+        MessageId::MissingArchiveMagic => "el archivo no empieza con el número mágico de .ssa",
+        MessageId::UnsupportedArchiveVersion => "la versión {0} del formato de biblioteca no está soportada",
+        MessageId::TruncatedArchive => "la biblioteca está truncada",
+        MessageId::InvalidArchiveMember => "el miembro '{0}' de la biblioteca no es un archivo objeto válido: {1}",
+
+        MessageId::InvalidLayoutLine => "línea {0}: se esperaba 'region <nombre> <origen> <longitud>' o 'place <code|data> <región>'",
+        MessageId::InvalidLayoutNumber => "línea {0}: '{1}' no es un número válido",
+        MessageId::UnknownRegion => "línea {0}: la región '{1}' no ha sido declarada",
+        MessageId::UnknownSection => "línea {0}: '{1}' no es un nombre de sección válido",
+        MessageId::RegionOutOfRange => "la región '{0}' no cabe en el espacio de direcciones de 16 bits",
+        MessageId::OverlappingRegions => "las regiones '{0}' y '{1}' se solapan",
+        MessageId::MissingPlacement => "la sección {0} no está colocada en ninguna región",
+        MessageId::SharedRegion => "solo se puede colocar una sección en la región '{0}'",
+        MessageId::SectionOverflow => "la sección {0} ocupa {1} bytes pero la región '{2}' solo admite {3}",
+        MessageId::SectionBelowImageBase => "la sección {0} en {1} está por debajo de la dirección base de la imagen {2}",
+        MessageId::ImageTooLarge => "la imagen necesita {0} bytes pero '--image-size' solo permite {1}",
+        MessageId::AddressOverflow => "la dirección {0} no cabe en las direcciones de 16 bits del formato {1}",
+
+        MessageId::UndefinedSymbol => "símbolo '{0}' no definido referenciado en {1}",
+        MessageId::DuplicateSymbol => "el símbolo '{0}' está definido tanto en {1} como en {2}",
+        MessageId::RelocationOverflow => "la dirección {0} del símbolo '{1}' no cabe en una reubicación {2} en {3}",
+        MessageId::RelocationOutOfBounds => "la reubicación del símbolo '{0}' en {1} queda fuera de su sección",
+
+        MessageId::TraceDefinition => "pasada 1: línea {0}: etiqueta '{1}' definida en {2} en la sección {3}",
+        MessageId::TraceResolved => "pasada 2: línea {0}: '@{1}' (definida en la línea {2}) resuelta a {3} y sustituida",
+        MessageId::TraceRelocated => "pasada 2: línea {0}: '@{1}' (definida en la línea {2}) dejada a 0, reubicación {3} registrada en {4}+{5} para el enlazador"
+    }
+}
+
+
+/**
+ * Looks up the message with the given id in the current language and substitutes its parameters. The template
+ * is scanned once, so a parameter which itself contains `{0}` is not substituted again.
+ */
+pub fn message(id:MessageId, params:&[&dyn Display]) -> String {
+    let template = match get_language() {
+        Language::English => english(id),
+        Language::Spanish => spanish(id)
+    };
+
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..].split_once('}').and_then(|(index, after)| Some((params.get(index.parse::<usize>().ok()?)?, after)));
+        match placeholder {
+            Some((param, after)) => {
+                text.push_str(&param.to_string());
+                rest = after;
+            },
+            None => {
+                text.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+    text
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_message_params() {
+        assert_eq!(message(MessageId::SourceLine, &[&12, &"bad"]), "line 12: bad");
+        assert_eq!(message(MessageId::TruncatedInstruction, &[]), "Instruction is truncated");
+        assert_eq!(message(MessageId::SourceLine, &[&"{1}", &"{0}"]), "line {1}: {0}");
+        assert_eq!(message(MessageId::CannotReadInput, &[&"{1}.asm", &"no such file or directory"]),
+                   "cannot read input '{1}.asm': no such file or directory");
+    }
+
+
+    #[test]
+    fn test_spanish_messages() {
+        set_language(Language::Spanish);
+        assert_eq!(message(MessageId::CannotWriteOutput, &[&"prog.sse", &message(MessageId::PermissionDenied, &[])]),
+                   "no se puede escribir la salida 'prog.sse': permiso denegado");
+        set_language(Language::English);
+    }
+
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(Language::try_from("es_ES.UTF-8"), Ok(Language::Spanish));
+        assert_eq!(Language::try_from("EN"), Ok(Language::English));
+        assert!(Language::try_from("fr").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+
+
+/**
+ * The metadata keys which can be set from the source with a directive of the same name, such as
+ * `.name "Pong"`, in the order they are written to the metadata section.
+ */
+pub const PROGRAM_METADATA_KEYS:&[&str] = &["name", "version", "author"];
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataError {
+    MissingValueError(String),
+    DuplicateDirectiveError(String)
+}
+
+impl Error for MetadataError {}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetadataError::MissingValueError(directive) => write!(f, "{}", message(MessageId::MetadataExpectsValue, &[directive])),
+            MetadataError::DuplicateDirectiveError(directive) => write!(f, "{}", message(MessageId::DuplicateMetadata, &[directive]))
+        }
+    }
+}
+
+
+/**
+ * Returns the metadata key set by the line if it is a metadata directive, which takes up no space in either
+ * section.
+ */
+fn get_metadata_key(line:&str) -> Option<&'static str> {
+    let directive = line.split_whitespace().next()?;
+    PROGRAM_METADATA_KEYS.iter().find(|key| directive.strip_prefix('.') == Some(**key)).copied()
+}
+
+
+pub fn is_metadata_directive(line:&str) -> bool {
+    get_metadata_key(line).is_some()
+}
+
+
+/**
+ * Takes the lines of a source file and collects the values of its metadata directives. A value is the rest of
+ * the line after the directive, and may be wrapped in double quotes or backticks.
+ */
+pub fn get_program_metadata(source_lines:&[SourceLine]) -> Result<Vec<(String, String)>, AssemblerError> {
+    let mut metadata:Vec<(String, String)> = vec![];
+
+    for source_line in source_lines {
+        let line = source_line.text.trim();
+        let key = match get_metadata_key(line) {
+            Some(key) => key,
+            None => continue
+        };
+
+        let directive = format!(".{}", key);
+        let value = line[directive.len()..].trim();
+        let value = match (value.chars().next(), value.chars().last()) {
+            (Some('"'), Some('"')) | (Some('`'), Some('`')) if value.len() >= 2 => &value[1..value.len() - 1],
+            _ => value
+        };
+
+        if value.is_empty() {
+            return Err(source_line.error(Box::new(MetadataError::MissingValueError(directive))));
+        }
+
+        if metadata.iter().any(|(existing, _)| existing == key) {
+            return Err(source_line.error(Box::new(MetadataError::DuplicateDirectiveError(directive))));
+        }
+
+        metadata.push((key.to_string(), value.to_string()));
+    }
+
+    metadata.sort_by_key(|(key, _)| PROGRAM_METADATA_KEYS.iter().position(|known| known == key));
+    Ok(metadata)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::{to_source_lines, SourceLine};
+    use super::{get_program_metadata, is_metadata_directive};
+
+
+    fn lines(lines:&[&str]) -> Vec<SourceLine> {
+        to_source_lines(&lines.join("\n"))
+    }
+
+
+    #[test]
+    fn test_program_metadata() {
+        let source_lines = lines(&["  .author `A. Student`", ".name \"Pong\"", ".data:", ".version 1.2"]);
+        assert_eq!(get_program_metadata(&source_lines).unwrap(), vec![
+            (String::from("name"), String::from("Pong")),
+            (String::from("version"), String::from("1.2")),
+            (String::from("author"), String::from("A. Student"))
+        ]);
+
+        assert!(is_metadata_directive(".name Pong"));
+        assert!(!is_metadata_directive(".names Pong"));
+        assert!(!is_metadata_directive("name: .byte 1"));
+    }
+
+
+    #[test]
+    fn test_invalid_metadata() {
+        assert_eq!(get_program_metadata(&lines(&[".name"])).unwrap_err().to_string(), "line 1: '.name' expects a value");
+        assert!(get_program_metadata(&lines(&[".name \"\""])).is_err());
+        assert!(get_program_metadata(&lines(&[".name a", ".name b"])).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::label_table::{undefined_label_error, LabelDefinition, LabelResolver};
+use crate::layout::MemoryLayout;
+use crate::messages::{message, MessageId};
+use crate::source::SourceLine;
+
+
+const OBJECT_MAGIC:&[u8] = b"SSO\0";
+const OBJECT_VERSION:u8 = 2;
+
+
+/**
+ * The longest name that fits behind the 8-bit length prefix used for names in object files and executables.
+ */
+pub const MAX_NAME_LENGTH:usize = u8::MAX as usize;
+
+
+/**
+ * A section of the output. Besides code and data there is the bss section of zeroed data which follows the
+ * data section, and the named sections started with `.section`, each given by its index in the memory layout.
+ * Only executables can hold the bss and named sections.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+    Data,
+    Code,
+    Bss,
+    Custom(usize)
+}
+
+impl Into<u8> for SectionKind {
+    fn into(self) -> u8 {
+        match self {
+            SectionKind::Data => 0,
+            SectionKind::Code => 1,
+            SectionKind::Bss | SectionKind::Custom(_) => unreachable!("object files only hold code and data")
+        }
+    }
+}
+
+impl TryFrom<u8> for SectionKind {
+    type Error = ObjectFormatError;
+
+    fn try_from(code:u8) -> Result<SectionKind, ObjectFormatError> {
+        match code {
+            0 => Ok(SectionKind::Data),
+            1 => Ok(SectionKind::Code),
+            _ => Err(ObjectFormatError::InvalidSectionError(code))
+        }
+    }
+}
+
+
+/**
+ * The width of the field a relocation patches with the address of its symbol.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    Absolute8,
+    Absolute16,
+    Absolute32
+}
+
+impl Into<u8> for RelocationKind {
+    fn into(self) -> u8 {
+        match self {
+            RelocationKind::Absolute8 => 0,
+            RelocationKind::Absolute16 => 1,
+            RelocationKind::Absolute32 => 2
+        }
+    }
+}
+
+impl TryFrom<u8> for RelocationKind {
+    type Error = ObjectFormatError;
+
+    fn try_from(code:u8) -> Result<RelocationKind, ObjectFormatError> {
+        match code {
+            0 => Ok(RelocationKind::Absolute8),
+            1 => Ok(RelocationKind::Absolute16),
+            2 => Ok(RelocationKind::Absolute32),
+            _ => Err(ObjectFormatError::InvalidRelocationKindError(code))
+        }
+    }
+}
+
+impl RelocationKind {
+    /**
+     * Returns the number of bytes patched by the relocation.
+     */
+    pub fn width(&self) -> usize {
+        match self {
+            RelocationKind::Absolute8 => 1,
+            RelocationKind::Absolute16 => 2,
+            RelocationKind::Absolute32 => 4
+        }
+    }
+}
+
+/**
+ * A label defined in an object file, located by its offset from the start of its section. Only global symbols,
+ * those exported with `.global`, can be referenced from other object files.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: SectionKind,
+    pub offset: usize,
+    pub global: bool
+}
+
+
+/**
+ * A reference to a symbol which must be patched with the symbol's final address once it is known.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: SectionKind,
+    pub offset: usize,
+    pub symbol: String,
+    pub kind: RelocationKind
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectFormatError {
+    MissingMagicError,
+    UnsupportedVersionError(u8),
+    TruncatedError,
+    InvalidSectionError(u8),
+    InvalidRelocationKindError(u8)
+}
+
+impl Error for ObjectFormatError {}
+
+impl fmt::Display for ObjectFormatError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectFormatError::MissingMagicError => write!(f, "{}", message(MessageId::MissingObjectMagic, &[])),
+            ObjectFormatError::UnsupportedVersionError(version) => write!(f, "{}", message(MessageId::UnsupportedObjectVersion, &[version])),
+            ObjectFormatError::TruncatedError => write!(f, "{}", message(MessageId::TruncatedObject, &[])),
+            ObjectFormatError::InvalidSectionError(code) => write!(f, "{}", message(MessageId::InvalidObjectSection, &[code])),
+            ObjectFormatError::InvalidRelocationKindError(code) => write!(f, "{}", message(MessageId::InvalidRelocationKind, &[code]))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectWriteError {
+    NameTooLongError(String),
+    TooManySymbolsError(usize),
+    TooManyRelocationsError(usize)
+}
+
+impl Error for ObjectWriteError {}
+
+impl fmt::Display for ObjectWriteError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectWriteError::NameTooLongError(name) => write!(f, "{}", message(MessageId::NameTooLong, &[name, &MAX_NAME_LENGTH])),
+            ObjectWriteError::TooManySymbolsError(count) => write!(f, "{}", message(MessageId::TooManySymbols, &[count, &u16::MAX])),
+            ObjectWriteError::TooManyRelocationsError(count) => write!(f, "{}", message(MessageId::TooManyRelocations, &[count, &u16::MAX]))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocationError {
+    UnrelocatableReferenceError(String),
+    SectionInObjectError(String)
+}
+
+impl Error for RelocationError {}
+
+impl fmt::Display for RelocationError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelocationError::UnrelocatableReferenceError(label) => write!(f, "{}", message(MessageId::UnrelocatableReference, &[label])),
+            RelocationError::SectionInObjectError(name) => write!(f, "{}", message(MessageId::SectionInObject, &[name]))
+        }
+    }
+}
+
+
+/**
+ * The contents of a relocatable .sso object file: the data and code sections assembled as though each
+ * started at address 0, the labels they define, and the relocations for every label reference.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectFile {
+    pub data: Vec<u8>,
+    pub code: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>
+}
+
+
+/**
+ * Reads big-endian fields from the bytes of an object file or archive, failing with `TruncatedError` at the end.
+ */
+pub struct ObjectReader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> ObjectReader<'a> {
+    pub fn new(bytes:&'a [u8]) -> ObjectReader<'a> {
+        ObjectReader { bytes: bytes, position: 0 }
+    }
+
+    pub fn take(&mut self, length:usize) -> Result<&'a [u8], ObjectFormatError> {
+        let bytes = self.bytes.get(self.position..self.position + length).ok_or(ObjectFormatError::TruncatedError)?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ObjectFormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ObjectFormatError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ObjectFormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_name(&mut self) -> Result<String, ObjectFormatError> {
+        let length = self.read_u8()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).to_string())
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+}
+
+
+/**
+ * Writes a name prefixed by its 8-bit length, failing rather than truncating the length if the name is too long.
+ */
+pub fn write_name(bytes:&mut Vec<u8>, name:&str) -> Result<(), ObjectWriteError> {
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(ObjectWriteError::NameTooLongError(name.to_string()));
+    }
+
+    bytes.push(name.len() as u8);
+    bytes.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+
+impl ObjectFile {
+    /**
+     * Serialises the object file. After the magic number and format version come the data and code sections,
+     * each prefixed by a 32-bit length, then the symbol table and the relocation table, each prefixed by a
+     * 16-bit count. Each symbol has a flag byte which is 1 if it is global. All integers are big-endian and
+     * names are prefixed by an 8-bit length.
+     */
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectWriteError> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.push(OBJECT_VERSION);
+
+        for section in [&self.data, &self.code] {
+            bytes.extend_from_slice(&(section.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(section);
+        }
+
+        let symbol_count = u16::try_from(self.symbols.len()).map_err(|_| ObjectWriteError::TooManySymbolsError(self.symbols.len()))?;
+        bytes.extend_from_slice(&symbol_count.to_be_bytes());
+        for symbol in &self.symbols {
+            bytes.push(symbol.section.into());
+            bytes.extend_from_slice(&(symbol.offset as u32).to_be_bytes());
+            bytes.push(symbol.global as u8);
+            write_name(&mut bytes, &symbol.name)?;
+        }
+
+        let relocation_count = u16::try_from(self.relocations.len()).map_err(|_| ObjectWriteError::TooManyRelocationsError(self.relocations.len()))?;
+        bytes.extend_from_slice(&relocation_count.to_be_bytes());
+        for relocation in &self.relocations {
+            bytes.push(relocation.section.into());
+            bytes.extend_from_slice(&(relocation.offset as u32).to_be_bytes());
+            bytes.push(relocation.kind.into());
+            write_name(&mut bytes, &relocation.symbol)?;
+        }
+
+        Ok(bytes)
+    }
+
+
+    /**
+     * Parses the bytes of a .sso object file.
+     */
+    pub fn parse(bytes:&[u8]) -> Result<ObjectFile, ObjectFormatError> {
+        let mut reader = ObjectReader::new(bytes);
+        if reader.take(OBJECT_MAGIC.len()).map_err(|_| ObjectFormatError::MissingMagicError)? != OBJECT_MAGIC {
+            return Err(ObjectFormatError::MissingMagicError);
+        }
+
+        let version = reader.read_u8()?;
+        if version != OBJECT_VERSION {
+            return Err(ObjectFormatError::UnsupportedVersionError(version));
+        }
+
+        let data_length = reader.read_u32()? as usize;
+        let data = reader.take(data_length)?.to_vec();
+        let code_length = reader.read_u32()? as usize;
+        let code = reader.take(code_length)?.to_vec();
+
+        let mut symbols = vec![];
+        for _ in 0..reader.read_u16()? {
+            symbols.push(Symbol {
+                section: SectionKind::try_from(reader.read_u8()?)?,
+                offset: reader.read_u32()? as usize,
+                global: reader.read_u8()? != 0,
+                name: reader.read_name()?
+            });
+        }
+
+        let mut relocations = vec![];
+        for _ in 0..reader.read_u16()? {
+            relocations.push(Relocation {
+                section: SectionKind::try_from(reader.read_u8()?)?,
+                offset: reader.read_u32()? as usize,
+                kind: RelocationKind::try_from(reader.read_u8()?)?,
+                symbol: reader.read_name()?
+            });
+        }
+
+        Ok(ObjectFile {
+            data: data,
+            code: code,
+            symbols: symbols,
+            relocations: relocations
+        })
+    }
+}
+
+
+/**
+ * Takes the label definitions of a file assembled with the given layout and converts them to object file
+ * symbols, each located by its offset into its section and marked global if it is one of `globals`, sorted by
+ * section then offset.
+ */
+pub fn get_object_symbols(definitions:&HashMap<String, LabelDefinition>, globals:&HashMap<String, SourceLine>, layout:&MemoryLayout) -> Vec<Symbol> {
+    let mut symbols:Vec<Symbol> = definitions.iter().map(|(name, definition)| {
+        Symbol {
+            name: name.to_owned(),
+            section: definition.section,
+            offset: definition.address - layout.region(definition.section).origin,
+            global: globals.contains_key(name)
+        }
+    }).collect();
+
+    symbols.sort_by(|a, b| (Into::<u8>::into(a.section), a.offset, &a.name).cmp(&(Into::<u8>::into(b.section), b.offset, &b.name)));
+    symbols
+}
+
+
+/**
+ * A `LabelResolver` used when assembling to an object file, which leaves every label reference as 0 and
+ * remembers the label so the assembler can record a relocation for it. Only labels defined in the file or
+ * declared with `.extern` may be referenced.
+ */
+#[derive(Debug, Default)]
+pub struct RelocationRecorder {
+    known_labels: HashSet<String>,
+    reference: RefCell<Option<String>>
+}
+
+impl RelocationRecorder {
+    pub fn new(label_table:&HashMap<String, usize>, externs:&HashMap<String, SourceLine>) -> RelocationRecorder {
+        RelocationRecorder {
+            known_labels: label_table.keys().chain(externs.keys()).cloned().collect(),
+            reference: RefCell::new(None)
+        }
+    }
+}
+
+impl LabelResolver for RelocationRecorder {
+    fn resolve(&self, label:&str) -> Result<usize, Box<dyn Error>> {
+        if !self.known_labels.contains(label) {
+            return Err(Box::new(undefined_label_error(label, self.known_labels.iter())));
+        }
+
+        *self.reference.borrow_mut() = Some(label.to_string());
+        Ok(0)
+    }
+
+    fn take_relocation(&self) -> Option<String> {
+        self.reference.borrow_mut().take()
+    }
+
+    fn relocates(&self) -> bool {
+        true
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+
+    #[test]
+    fn test_object_round_trip() {
+        let object = ObjectFile {
+            data: vec![0x55, 0x00, 0x00],
+            code: vec![0x5B, 0x00, 0x00, 0x00, 0x98, 0x00],
+            symbols: vec![
+                Symbol { name: String::from("my_byte"), section: SectionKind::Data, offset: 0, global: false },
+                Symbol { name: String::from("start"), section: SectionKind::Code, offset: 0, global: true }
+            ],
+            relocations: vec![
+                Relocation { section: SectionKind::Data, offset: 1, symbol: String::from("start"), kind: RelocationKind::Absolute16 },
+                Relocation { section: SectionKind::Code, offset: 2, symbol: String::from("my_byte"), kind: RelocationKind::Absolute16 }
+            ]
+        };
+
+        assert_eq!(ObjectFile::parse(&object.to_bytes().unwrap()).unwrap(), object);
+    }
+
+
+    #[test]
+    fn test_invalid_object() {
+        assert_eq!(ObjectFile::parse(b".data:"), Err(ObjectFormatError::MissingMagicError));
+        assert_eq!(ObjectFile::parse(b"SSO\0\x07"), Err(ObjectFormatError::UnsupportedVersionError(7)));
+        assert_eq!(ObjectFile::parse(b"SSO\0\x02\x00\x00\x00\x05\x01"), Err(ObjectFormatError::TruncatedError));
+    }
+
+
+    #[test]
+    fn test_unwritable_object() {
+        let long_name = "a".repeat(256);
+        let symbol = Symbol { name: long_name.to_owned(), section: SectionKind::Code, offset: 0, global: true };
+        let object = ObjectFile { symbols: vec![symbol], ..ObjectFile::default() };
+        assert_eq!(object.to_bytes(), Err(ObjectWriteError::NameTooLongError(long_name)));
+
+        let symbol = Symbol { name: String::from("start"), section: SectionKind::Code, offset: 0, global: true };
+        let object = ObjectFile { symbols: vec![symbol; 0x10000], ..ObjectFile::default() };
+        assert_eq!(object.to_bytes(), Err(ObjectWriteError::TooManySymbolsError(0x10000)));
+
+        let relocation = Relocation { section: SectionKind::Code, offset: 2, symbol: String::from("start"), kind: RelocationKind::Absolute16 };
+        let object = ObjectFile { relocations: vec![relocation; 0x10000], ..ObjectFile::default() };
+        assert_eq!(object.to_bytes(), Err(ObjectWriteError::TooManyRelocationsError(0x10000)));
+    }
+
+
+    #[test]
+    fn test_object_symbols() {
+        let definitions = HashMap::from([
+            (String::from("start"), LabelDefinition { address: 0x5804, section: SectionKind::Code, line_num: 3 }),
+            (String::from("msg"), LabelDefinition { address: 0x9002, section: SectionKind::Data, line_num: 1 })
+        ]);
+        let globals = HashMap::from([(String::from("start"), SourceLine::new(".global start", 1))]);
+        assert_eq!(get_object_symbols(&definitions, &globals, &MemoryLayout::default()), vec![
+            Symbol { name: String::from("msg"), section: SectionKind::Data, offset: 2, global: false },
+            Symbol { name: String::from("start"), section: SectionKind::Code, offset: 4, global: true }
+        ]);
+    }
+
+
+    #[test]
+    fn test_relocation_recorder() {
+        let label_table = HashMap::from([(String::from("start"), 0x5800)]);
+        let externs = HashMap::from([(String::from("print"), SourceLine::new(".extern print", 2))]);
+        let recorder = RelocationRecorder::new(&label_table, &externs);
+
+        assert_eq!(recorder.resolve("print").unwrap(), 0);
+        assert_eq!(recorder.take_relocation(), Some(String::from("print")));
+        assert!(recorder.resolve("start").is_ok());
+        assert_eq!(recorder.resolve("missing").unwrap_err().to_string(), "Label 'missing' is not defined");
+    }
+}
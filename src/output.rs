@@ -0,0 +1,67 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::error::{AssemblerError, IoOperation};
+
+
+/**
+ * Returns the path of the temporary file used while writing to `path`. It is kept in the same directory
+ * as the destination so the final rename never has to cross filesystems.
+ */
+fn temp_path_for(path:&Path) -> PathBuf {
+    let filename = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", filename, process::id()))
+}
+
+
+/**
+ * Writes the bytes to a temporary file next to `path` and then renames it over `path`, so the destination
+ * either keeps its old contents or holds the complete new output, never a partially written file.
+ */
+pub fn write_atomically(path:&Path, bytes:&[u8]) -> Result<(), AssemblerError> {
+    let temp_path = temp_path_for(path);
+
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result.map_err(AssemblerError::io(IoOperation::WriteOutput, path))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{temp_path_for, write_atomically};
+
+
+    #[test]
+    fn test_write_atomically() {
+        let path = env::temp_dir().join(format!("s16_write_atomically_{}.sse", std::process::id()));
+        fs::write(&path, b"old contents").unwrap();
+
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!temp_path_for(&path).exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+
+    #[test]
+    fn test_failed_write_leaves_no_temp_file() {
+        let path = env::temp_dir().join("s16_missing_dir").join("prog.sse");
+        assert!(write_atomically(&path, b"data").is_err());
+        assert!(!temp_path_for(&path).exists());
+    }
+}
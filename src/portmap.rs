@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::parse_integer;
+use crate::messages::{message, MessageId};
+use crate::repr::instruction::{Instruction, Operand};
+use crate::repr::opcode::resolve_mnemonic;
+use crate::source::{find_label_end, SourceLine};
+use crate::validation::validate_label;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortMapError {
+    InvalidLineError(usize),
+    InvalidPortNumberError(usize, String),
+    DuplicatePortError(usize, String),
+    PortNotInMapError(String, usize),
+    PortMismatchError(String, usize, usize),
+    UndeclaredPortError(usize)
+}
+
+impl Error for PortMapError {}
+
+impl fmt::Display for PortMapError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortMapError::InvalidLineError(line_num) => write!(f, "{}", message(MessageId::InvalidPortMapLine, &[line_num])),
+            PortMapError::InvalidPortNumberError(line_num, text) => write!(f, "{}", message(MessageId::InvalidPortMapNumber, &[line_num, text])),
+            PortMapError::DuplicatePortError(line_num, name) => write!(f, "{}", message(MessageId::DuplicatePortMapPort, &[line_num, name])),
+            PortMapError::PortNotInMapError(name, number) => write!(f, "{}", message(MessageId::PortNotInMap, &[name, number])),
+            PortMapError::PortMismatchError(name, number, mapped) => write!(f, "{}", message(MessageId::PortMismatch, &[name, number, mapped])),
+            PortMapError::UndeclaredPortError(number) => write!(f, "{}", message(MessageId::UndeclaredPort, &[number]))
+        }
+    }
+}
+
+
+/**
+ * The I/O ports of the machine a program is assembled for, as given by a port map file.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PortMap {
+    pub ports: HashMap<String, usize>
+}
+
+impl PortMap {
+    /**
+     * Parses a port map file. Each line declares a port as `port <name> <number>`, where the number fits in the
+     * 5 bits `in` and `out` give it, and everything after a `#` is a comment. A name can only be declared once.
+     */
+    pub fn parse(text:&str) -> Result<PortMap, PortMapError> {
+        let mut ports = HashMap::new();
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line_num = line_num + 1;
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line
+            };
+
+            let tokens:Vec<&str> = line.split_whitespace().collect();
+            match tokens.as_slice() {
+                [] => {},
+                ["port", name, number] => {
+                    if validate_label(name).is_err() {
+                        return Err(PortMapError::InvalidLineError(line_num));
+                    }
+
+                    let number = match parse_integer(number) {
+                        Some(number @ 0..=0x1F) => number as usize,
+                        _ => return Err(PortMapError::InvalidPortNumberError(line_num, number.to_string()))
+                    };
+
+                    if ports.insert(name.to_string(), number).is_some() {
+                        return Err(PortMapError::DuplicatePortError(line_num, name.to_string()));
+                    }
+                },
+                _ => return Err(PortMapError::InvalidLineError(line_num))
+            }
+        }
+
+        Ok(PortMap {
+            ports: ports
+        })
+    }
+}
+
+
+/**
+ * Returns the number of every port which can be named in an `in` or `out`: those in the port map, if there is
+ * one, and those declared with `.port`. When there is a port map, a `.port` must give a port in the map, and
+ * one naming a port of the map must give it the same number.
+ */
+pub fn get_port_numbers(declared:&HashMap<String, (usize, SourceLine)>, port_map:Option<&PortMap>) -> Result<HashMap<String, usize>, AssemblerError> {
+    let mut ports = port_map.map(|port_map| port_map.ports.to_owned()).unwrap_or_default();
+
+    let mut declared:Vec<(&String, &(usize, SourceLine))> = declared.iter().collect();
+    declared.sort_by_key(|(_, (_, line))| line.line_num);
+    for (name, (number, line)) in declared {
+        if let Some(port_map) = port_map {
+            let err = match port_map.ports.get(name) {
+                Some(mapped) if mapped != number => PortMapError::PortMismatchError(name.to_owned(), *number, *mapped),
+                None if !port_map.ports.values().any(|mapped| mapped == number) => PortMapError::PortNotInMapError(name.to_owned(), *number),
+                _ => {
+                    ports.insert(name.to_owned(), *number);
+                    continue;
+                }
+            };
+            return Err(line.error(Box::new(err)));
+        }
+
+        ports.insert(name.to_owned(), *number);
+    }
+
+    Ok(ports)
+}
+
+
+/**
+ * Checks that the port of every `in` and `out` instruction is one of the given ports. An instruction which
+ * cannot be parsed is left for the assembler to report.
+ */
+pub fn check_ports(source_lines:&[SourceLine], ports:&HashMap<String, usize>) -> Result<(), AssemblerError> {
+    for source_line in source_lines {
+        let contents = find_label_end(&source_line.text).map(|index| &source_line.text[index + 1..]).unwrap_or(&source_line.text);
+        let mnemonic = contents.split(|c:char| c.is_whitespace() || c == ',').find(|token| !token.is_empty()).unwrap_or("");
+        if !matches!(resolve_mnemonic(mnemonic).as_str(), "in" | "out") {
+            continue;
+        }
+
+        if let Ok(Instruction { operand_b: Operand::ShortImmediate(number), .. }) = Instruction::try_from(contents.trim()) {
+            if !ports.values().any(|port| *port == number as usize) {
+                return Err(source_line.error(Box::new(PortMapError::UndeclaredPortError(number as usize))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    #[test]
+    fn test_parse_port_map() {
+        let port_map = PortMap::parse("# board ports\nport UART_TX 0x05\nport LEDS 1  # the 8 leds\n\n").unwrap();
+        assert_eq!(port_map.ports, HashMap::from([(String::from("UART_TX"), 5), (String::from("LEDS"), 1)]));
+
+        assert_eq!(PortMap::parse("port UART_TX").unwrap_err().to_string(), "line 1: expected 'port <name> <number>'");
+        assert_eq!(PortMap::parse("port UART_TX 0x20").unwrap_err().to_string(), "line 1: '0x20' is not a port number from 0 to 31");
+        assert_eq!(PortMap::parse("port LEDS 1\nport LEDS 2").unwrap_err().to_string(), "line 2: port 'LEDS' is already declared");
+        assert!(PortMap::parse("port ax 1").is_err());
+    }
+
+
+    #[test]
+    fn test_check_ports() {
+        let port_map = PortMap::parse("port UART_TX 5\nport UART_RX 6").unwrap();
+        let declared = HashMap::from([(String::from("SERIAL_OUT"), (5, SourceLine::new(".port SERIAL_OUT, 5", 1)))]);
+        let ports = get_port_numbers(&declared, Some(&port_map)).unwrap();
+        assert_eq!(ports.len(), 3);
+
+        let source_lines = to_source_lines(".code:\nout al, 5\nsend: in bl, 6\nmovi ax, 9\nout al, 9");
+        assert_eq!(check_ports(&source_lines, &ports).unwrap_err().to_string(), "line 5: Port 9 is not declared with '.port' or in the port map");
+        assert!(check_ports(&source_lines[..4], &ports).is_ok());
+
+        let declared = HashMap::from([(String::from("LEDS"), (7, SourceLine::new(".port LEDS, 7", 3)))]);
+        assert_eq!(get_port_numbers(&declared, Some(&port_map)).unwrap_err().to_string(), "line 3: '.port LEDS' gives port 7, which is not in the port map");
+
+        let declared = HashMap::from([(String::from("UART_TX"), (6, SourceLine::new(".port UART_TX, 6", 2)))]);
+        assert_eq!(get_port_numbers(&declared, Some(&port_map)).unwrap_err().to_string(), "line 2: '.port UART_TX' gives port 6, but the port map gives it as port 5");
+        assert_eq!(get_port_numbers(&declared, None).unwrap(), HashMap::from([(String::from("UART_TX"), 6)]));
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::{Path, PathBuf};
+
+use crate::conditional::ConditionalStack;
+use crate::constants::{is_constant_definition, ConstantTable};
+use crate::defines::{is_define_directive, DefineTable};
+use crate::diagnostic::{is_diagnostic_directive, report_diagnostic};
+use crate::error::AssemblerError;
+use crate::include::IncludeStack;
+use crate::macros::{read_repeat, MacroError, MacroTable};
+use crate::source::{apply_mnemonic_alias, apply_radix, convert_compat_numbers, get_radix_prefix, SourceLine};
+
+
+/**
+ * The state of the preprocessor as it reads the lines of a source file in order.
+ */
+struct Preprocessor<'a> {
+    include_dirs: &'a [PathBuf],
+    aliases: &'a [(String, String)],
+    compat_numbers: bool,
+    constants: &'a mut ConstantTable,
+    defines: DefineTable,
+    macros: MacroTable,
+    conditionals: ConditionalStack,
+    includes: IncludeStack,
+    radix_prefix: &'static str,
+    output: Vec<SourceLine>
+}
+
+impl Preprocessor<'_> {
+    /**
+     * Rewrites the mnemonic aliases and the numbers of the line, which applies to every line read, including
+     * the conditions of `.if` directives and the lines produced by macros.
+     */
+    fn rewrite(&self, line:SourceLine) -> SourceLine {
+        let line = apply_mnemonic_alias(line, self.aliases);
+        let line = match self.compat_numbers {
+            true => convert_compat_numbers(line),
+            false => line
+        };
+
+        apply_radix(line, self.radix_prefix)
+    }
+
+
+    /**
+     * Reads the lines in order, adding the lines which remain to the output. The lines of an included file and
+     * those produced by a macro or a `.rept` block are read in place of the line which produced them, so any
+     * directive in them is applied as if it had been written there.
+     */
+    fn read_lines(&mut self, lines:Vec<SourceLine>) -> Result<(), AssemblerError> {
+        let mut lines = lines.into_iter();
+
+        while let Some(line) = lines.next() {
+            let line = self.rewrite(line);
+            if self.conditionals.apply(&line, self.constants, &self.defines)? || !self.conditionals.active() {
+                continue;
+            }
+
+            if let Some(prefix) = get_radix_prefix(&line)? {
+                self.radix_prefix = prefix;
+                continue;
+            }
+
+            match line.text.split_whitespace().next() {
+                Some(".include") => {
+                    let included_lines = self.includes.push(&line, self.include_dirs)?;
+                    self.read_lines(included_lines)?;
+                    self.includes.pop();
+                },
+
+                Some(".macro") => self.macros.define(&line, &mut lines)?,
+                Some(".endm") => return Err(line.error(Box::new(MacroError::UnexpectedEndmError))),
+                Some(".rept") => {
+                    let line = SourceLine { text: self.defines.substitute(&line.text), ..line };
+                    let repetitions = read_repeat(&line, &mut lines, self.constants.values())?;
+                    self.read_lines(repetitions)?;
+                },
+
+                Some(".endr") => return Err(line.error(Box::new(MacroError::UnexpectedEndrError))),
+                _ if is_define_directive(&line.text) => self.defines.define(&line)?,
+                _ if is_diagnostic_directive(&line.text) => report_diagnostic(&line)?,
+                _ => match self.macros.expand(&line)? {
+                    Some(expanded) => self.read_lines(expanded)?,
+                    None => {
+                        let line = SourceLine { text: self.defines.substitute(&line.text), ..line };
+                        match is_constant_definition(line.text.trim()) {
+                            true => self.constants.define(&line)?,
+                            false => self.output.push(SourceLine { text: self.constants.substitute(&line.text), ..line })
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and applies every directive which changes the lines the label pass sees,
+ * in the order they are written, so a directive only takes effect if the branches of the `.if` blocks around
+ * it are taken. Lines in a branch which is not taken are removed, every `.include "file"` directive is replaced
+ * with the lines of that file, and macro invocations and `.rept` blocks are replaced with the lines they
+ * produce. Constants and `.define` names are defined here too, and every use of
+ * them in the remaining lines is replaced with its value, while `.error` and `.warning` directives are
+ * reported as they are read. This runs before the label pass, so the addresses of labels account for the size
+ * of every expansion, and the label pass and the encoder see exactly the same lines.
+ */
+pub fn preprocess(source_lines:Vec<SourceLine>, source_path:&Path, include_dirs:&[PathBuf], aliases:&[(String, String)],
+                  compat_numbers:bool, constants:&mut ConstantTable) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut preprocessor = Preprocessor {
+        include_dirs: include_dirs,
+        aliases: aliases,
+        compat_numbers: compat_numbers,
+        constants: constants,
+        defines: DefineTable::default(),
+        macros: MacroTable::default(),
+        conditionals: ConditionalStack::default(),
+        includes: IncludeStack::new(source_path),
+        radix_prefix: "",
+        output: vec![]
+    };
+
+    preprocessor.read_lines(source_lines)?;
+    preprocessor.conditionals.finish()?;
+    Ok(preprocessor.output)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    fn texts(source:&str) -> Vec<String> {
+        preprocess(to_source_lines(source), Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::default()).unwrap().iter().map(|line| line.text.trim().to_string()).collect()
+    }
+
+
+    #[test]
+    fn test_disabled_blocks() {
+        // a macro or `.rept` block in a branch which is not taken is never defined or expanded
+        assert_eq!(texts(".if 0\n.macro PUSH2\npush ax\npush bx\n.endm\n.endif\n.macro PUSH2\npush cx\n.endm\nPUSH2"), vec!["push cx"]);
+        assert_eq!(texts(".ifdef DEBUG\n.rept 4\nnop\n.endr\n.endif\nhalt"), vec!["halt"]);
+        assert_eq!(texts(".if 0\n.macro OPEN\n.endif\nhalt"), vec!["halt"]);
+
+        // conditions in a macro body are evaluated for each invocation
+        let source = ".macro SAVE n\n.if \\n > 1\npush bx\n.endif\npush ax\n.endm\nSAVE 1\nSAVE 2";
+        assert_eq!(texts(source), vec!["push ax", "push bx", "push ax"]);
+
+        let source = "COUNT: .equ 2\n.rept 3, i\n.if \\i < COUNT\n.byte \\i\n.endif\n.endr";
+        assert_eq!(texts(source), vec![".byte 0", ".byte 1"]);
+    }
+}
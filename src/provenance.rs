@@ -0,0 +1,99 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::assembler::AssembledSections;
+use crate::layout::MemoryLayout;
+use crate::object::SectionKind;
+
+
+/**
+ * Escapes a string for use inside a JSON string literal.
+ */
+pub fn escape_json(text:&str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+
+/**
+ * Produces a JSON document mapping every range of output bytes to the source line which produced it. Each
+ * range gives its section, its load address in the layout, its length, and the file, line number, kind, and
+ * text of its source line, so tools can answer what produced the byte at a given address. A line produced by
+ * a macro also gives the chain of macros it was expanded from, outermost first.
+ */
+pub fn to_provenance_json(sections:&AssembledSections, source_path:&Path, layout:&MemoryLayout) -> String {
+    let mut json = format!("{{\n  \"source\": \"{}\",\n  \"ranges\": [", escape_json(&source_path.display().to_string()));
+
+    for (index, origin) in sections.origins.iter().enumerate() {
+        let (section, kind, base_address) = match origin.section {
+            SectionKind::Data => ("data", "data", layout.data.origin),
+            SectionKind::Code => ("code", "instruction", layout.code.origin),
+            SectionKind::Bss => ("bss", "data", layout.data.origin + sections.data.len()),
+            SectionKind::Custom(index) => (layout.sections[index].0.as_str(), "data", layout.sections[index].1.origin)
+        };
+
+        if index > 0 {
+            json.push(',');
+        }
+
+        // lines from included files are attributed to the included file itself rather than the input file
+        let file = escape_json(&origin.source.file.as_deref().unwrap_or(source_path).display().to_string());
+        let expansion:Vec<String> = origin.source.expansion.iter().map(|name| format!("\"{}\"", escape_json(name))).collect();
+
+        write!(json, "\n    {{\"section\": \"{}\", \"address\": {}, \"length\": {}, \"file\": \"{}\", \"line\": {}, \"kind\": \"{}\", \"text\": \"{}\", \"expansion\": [{}]}}",
+               section, base_address + origin.offset, origin.length, file, origin.source.line_num, kind, escape_json(&origin.source.text), expansion.join(", ")).unwrap();
+    }
+
+    json.push_str("\n  ]\n}\n");
+    json
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::assembler::{AssembledSections, ByteOrigin};
+    use crate::layout::MemoryLayout;
+    use crate::object::SectionKind;
+    use crate::source::SourceLine;
+    use super::to_provenance_json;
+
+
+    #[test]
+    fn test_provenance_json() {
+        let sections = AssembledSections {
+            origins: vec![
+                ByteOrigin { section: SectionKind::Data, offset: 0, length: 6, source: SourceLine::new("msg: .asciiz `say \"hi\"`", 2) },
+                ByteOrigin { section: SectionKind::Code, offset: 0, length: 2, source: SourceLine::new("add ax, bx", 5) },
+                ByteOrigin {
+                    section: SectionKind::Code, offset: 2, length: 2,
+                    source: SourceLine { expansion: vec![String::from("OUTER"), String::from("INNER")], ..SourceLine::new("inc dx", 9) }
+                }
+            ],
+            ..AssembledSections::default()
+        };
+
+        assert_eq!(to_provenance_json(&sections, Path::new("prog.asm"), &MemoryLayout::default()), "{
+  \"source\": \"prog.asm\",
+  \"ranges\": [
+    {\"section\": \"data\", \"address\": 36864, \"length\": 6, \"file\": \"prog.asm\", \"line\": 2, \"kind\": \"data\", \"text\": \"msg: .asciiz `say \\\"hi\\\"`\", \"expansion\": []},
+    {\"section\": \"code\", \"address\": 22528, \"length\": 2, \"file\": \"prog.asm\", \"line\": 5, \"kind\": \"instruction\", \"text\": \"add ax, bx\", \"expansion\": []},
+    {\"section\": \"code\", \"address\": 22530, \"length\": 2, \"file\": \"prog.asm\", \"line\": 9, \"kind\": \"instruction\", \"text\": \"inc dx\", \"expansion\": [\"OUTER\", \"INNER\"]}
+  ]
+}
+");
+    }
+}
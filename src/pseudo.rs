@@ -0,0 +1,351 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::expr::split_values;
+use crate::messages::{message, MessageId};
+use crate::repr::instruction::unbracket_memory_operands;
+use crate::repr::opcode::{is_jump_mnemonic, resolve_mnemonic, split_mnemonic_suffix, Opcode, OperandFormat};
+use crate::repr::register::REGISTER_NAMES;
+use crate::source::{find_label_end, SourceLine};
+
+
+/**
+ * The 16-bit registers a pseudo-instruction may use as its scratch register, which it overwrites.
+ */
+pub const SCRATCH_REGISTERS:&[&str] = &["ax", "bx", "cx", "dx", "rp", "fp", "bp"];
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoError {
+    ScratchOperandError(String, String),
+    ScratchHalfError(String, String),
+    InvalidRegisterPairError(String),
+    RegisterPairOverlapError(String, String),
+    MissingTestedRegisterError(String)
+}
+
+impl Error for PseudoError {}
+
+impl fmt::Display for PseudoError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PseudoError::ScratchOperandError(mnemonic, scratch) => write!(f, "{}", message(MessageId::ScratchOperand, &[mnemonic, scratch])),
+            PseudoError::ScratchHalfError(register, scratch) => write!(f, "{}", message(MessageId::ScratchHalf, &[register, scratch])),
+            PseudoError::InvalidRegisterPairError(operand) => write!(f, "{}", message(MessageId::InvalidRegisterPair, &[operand])),
+            PseudoError::RegisterPairOverlapError(mnemonic, register) => write!(f, "{}", message(MessageId::RegisterPairOverlap, &[mnemonic, register])),
+            PseudoError::MissingTestedRegisterError(mnemonic) => write!(f, "{}", message(MessageId::MissingTestedRegister, &[mnemonic]))
+        }
+    }
+}
+
+
+/**
+ * Returns the register of the same size as the given one to use as the scratch register, which is the half of
+ * the scratch register matching a byte register such as `al`, or an error if the scratch register has no
+ * halves or the given register is the one which would be used.
+ */
+fn sized_scratch(mnemonic:&str, register:&str, scratch:&str) -> Result<String, PseudoError> {
+    let register = register.to_lowercase();
+    let sized = match register.as_str() {
+        "al" | "bl" | "cl" | "dl" | "ah" | "bh" | "ch" | "dh" => match ["ax", "bx", "cx", "dx"].contains(&scratch) {
+            true => format!("{}{}", &scratch[..1], &register[1..]),
+            false => return Err(PseudoError::ScratchHalfError(register, scratch.to_string()))
+        },
+        _ => scratch.to_string()
+    };
+
+    match sized == register {
+        true => Err(PseudoError::ScratchOperandError(mnemonic.to_string(), scratch.to_string())),
+        false => Ok(sized)
+    }
+}
+
+
+/**
+ * Parses a register pair such as `dx:ax`, which holds a 32-bit value as its high and low words in two different
+ * 16-bit registers.
+ */
+fn parse_register_pair(operand:&str) -> Result<(String, String), PseudoError> {
+    let invalid = || PseudoError::InvalidRegisterPairError(operand.to_string());
+    let (high, low) = operand.split_once(':').ok_or_else(invalid)?;
+    let (high, low) = (high.to_lowercase(), low.to_lowercase());
+
+    match SCRATCH_REGISTERS.contains(&high.as_str()) && SCRATCH_REGISTERS.contains(&low.as_str()) && high != low {
+        true => Ok((high, low)),
+        false => Err(invalid())
+    }
+}
+
+
+/**
+ * Splits the operands of an instruction into its first operand and the text of the rest.
+ */
+fn split_first_operand(operands:&str) -> (&str, &str) {
+    let end = operands.find(|c:char| c.is_whitespace() || c == ',').unwrap_or(operands.len());
+    (&operands[..end], operands[end..].trim_start_matches(|c:char| c.is_whitespace() || c == ','))
+}
+
+
+/**
+ * Returns the instructions a pseudo-instruction expands to, or `None` if the line is a real instruction. A
+ * `call @function` or a jump to a label such as `jeq @loop` loads the address of the label into the scratch
+ * register and calls or jumps from there, as they only take their address in a register. The label of a `jzro`
+ * or `jnzro` is followed by the register it tests, as in `jzro @done, cx`, which cannot be the scratch register.
+ *
+ * An `li` loads a constant into any register, such as `li al, 0x41`, as a `movi`. Loading a half register sets
+ * the high or low bit of the instruction, so only that half is written and the other half keeps its value.
+ *
+ * A `push` or `pop` of several registers, such as `push ax, bx, cx`, pushes or pops each of them in the order
+ * they are given, so a matching epilogue lists them in reverse, as in `pop cx, bx, ax`.
+ *
+ * A `load` or `store` with a label as its address, as in `load ax, @buffer`, loads the address into the scratch
+ * register and reads or writes memory through it, and may be given in brackets, as in `store [@result], al`.
+ * The register a `store` writes cannot be the scratch register.
+ *
+ * An arithmetic, logic or shift instruction with a constant as its 2nd operand, such as `add ax, 5`, `and al,
+ * 0x0F` or `sll ax, 3`, loads the constant into the scratch register, or the matching half of it for a byte
+ * register, and operates on that, as the ISA only takes these operands from a register.
+ *
+ * An `add32` or `sub32` adds or subtracts the 32-bit values in two register pairs, as in `add32 dx:ax, cx:bx`,
+ * by operating on the low words and then carrying or borrowing into the high word of the destination. As the
+ * low word of the destination is written first, it cannot be the high word of the source.
+ *
+ * A suffix such as the `.nf` of `add.nf ax, 5` is kept on the instructions doing the operation. An `add32` or
+ * `sub32` keeps it only on its last instruction, as the ones before must set the carry or borrow it reads.
+ */
+fn expand_pseudo(mnemonic:&str, operands:&str, scratch:&str) -> Result<Option<Vec<String>>, PseudoError> {
+    let operands = operands.trim();
+    let (name, suffix) = split_mnemonic_suffix(mnemonic);
+    let suffix = suffix.map(|suffix| format!(".{}", suffix)).unwrap_or_default();
+    match name {
+        _ if operands.starts_with('@') && is_jump_mnemonic(name) => match Opcode::from(&name.to_string()).format() {
+            OperandFormat::TwoRegisters => {
+                let (label, tested) = split_first_operand(operands);
+                if tested.is_empty() {
+                    return Err(PseudoError::MissingTestedRegisterError(mnemonic.to_string()));
+                }
+
+                if tested.eq_ignore_ascii_case(scratch) {
+                    return Err(PseudoError::ScratchOperandError(mnemonic.to_string(), scratch.to_string()));
+                }
+
+                Ok(Some(vec![format!("movi {}, {}", scratch, label), format!("{} {}, {}", mnemonic, scratch, tested)]))
+            },
+
+            _ => Ok(Some(vec![format!("movi {}, {}", scratch, operands), format!("{} {}", mnemonic, scratch)]))
+        },
+
+        "li" => Ok(Some(vec![format!("movi{} {}", suffix, operands)])),
+
+        "load" | "store" => {
+            let operands = match unbracket_memory_operands(operands) {
+                Ok(Some(operands)) => operands,
+                _ => operands.to_string()
+            };
+            let (register, address) = split_first_operand(&operands);
+            if !address.starts_with('@') {
+                return Ok(None);
+            }
+
+            if name == "store" && register.eq_ignore_ascii_case(scratch) {
+                return Err(PseudoError::ScratchOperandError(mnemonic.to_string(), scratch.to_string()));
+            }
+
+            Ok(Some(vec![format!("movi {}, {}", scratch, address), format!("{} {}, {}", mnemonic, register, scratch)]))
+        },
+
+        "add" | "addu" | "sub" | "subu" | "cmp" | "and" | "or" | "xor" | "mul" | "mulu" | "div" | "divu" | "sra" | "srl" | "sll" => {
+            // anything but a single constant is left for the instruction itself to accept or report
+            let (register, value) = split_first_operand(operands);
+            if split_values(value).len() != 1 || REGISTER_NAMES.contains(&value.to_lowercase().as_str()) {
+                return Ok(None);
+            }
+
+            let scratch = sized_scratch(mnemonic, register, scratch)?;
+            Ok(Some(vec![format!("movi {}, {}", scratch, value), format!("{} {}, {}", mnemonic, register, scratch)]))
+        },
+
+        "add32" | "sub32" => {
+            let pairs:Vec<&str> = operands.split(|c:char| c.is_whitespace() || c == ',').filter(|pair| !pair.is_empty()).collect();
+            let (destination, source) = match pairs.as_slice() {
+                [destination, source] => (parse_register_pair(destination)?, parse_register_pair(source)?),
+                _ => return Err(PseudoError::InvalidRegisterPairError(operands.to_string()))
+            };
+
+            if destination.1 == source.0 {
+                return Err(PseudoError::RegisterPairOverlapError(mnemonic.to_string(), source.0));
+            }
+
+            let (operation, carry) = match name {
+                "add32" => ("add", "addc"),
+                _ => ("sub", "subb")
+            };
+            Ok(Some(vec![
+                format!("{} {}, {}", operation, destination.1, source.1),
+                format!("{} {}", carry, destination.0),
+                format!("{}{} {}, {}", operation, suffix, destination.0, source.0)
+            ]))
+        },
+
+        "push" | "pop" => {
+            let registers:Vec<&str> = operands.split(|c:char| c.is_whitespace() || c == ',').filter(|register| !register.is_empty()).collect();
+            match registers.len() > 1 {
+                true => Ok(Some(registers.iter().map(|register| format!("{} {}", mnemonic, register)).collect())),
+                false => Ok(None)
+            }
+        },
+
+        _ => Ok(None)
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and expands every pseudo-instruction into the real instructions it stands
+ * for, which use the given scratch register. A label on the line names the first instruction of the expansion,
+ * and the expanded lines are marked with the name of the pseudo-instruction like the lines of a macro. This runs
+ * before the label pass, so the addresses of labels account for the size of every expansion.
+ */
+pub fn expand_pseudo_instructions(source_lines:Vec<SourceLine>, scratch:&str) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut output = vec![];
+
+    for line in source_lines {
+        let (label, contents) = match find_label_end(&line.text) {
+            Some(index) => (line.text[..index + 1].trim(), line.text[index + 1..].trim()),
+            None => ("", line.text.trim())
+        };
+
+        let name = contents.split(|c:char| c.is_whitespace() || c == ',').next().unwrap_or("");
+        let mnemonic = resolve_mnemonic(name);
+        let expanded = match expand_pseudo(&mnemonic, &contents[name.len()..], scratch).map_err(|err| line.error(Box::new(err)))? {
+            Some(expanded) => expanded,
+            None => {
+                output.push(line);
+                continue;
+            }
+        };
+
+        let mut expansion = line.expansion.to_owned();
+        expansion.push(mnemonic);
+        for (index, text) in expanded.into_iter().enumerate() {
+            let text = match (index, label.is_empty()) {
+                (0, false) => format!("{} {}", label, text),
+                _ => text
+            };
+            output.push(SourceLine { text: text, expansion: expansion.to_owned(), ..line.to_owned() });
+        }
+    }
+
+    Ok(output)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    fn texts(source:&str) -> Vec<String> {
+        expand_pseudo_instructions(to_source_lines(source), "dx").unwrap().into_iter().map(|line| line.text).collect()
+    }
+
+
+    #[test]
+    fn test_call_label() {
+        assert_eq!(texts(".code:\nstart: call @print\nCALL @.Lhelper\ncall cx"), vec![
+            ".code:", "start: movi dx, @print", "call dx", "movi dx, @.Lhelper", "call dx", "call cx"
+        ]);
+
+        let expanded = expand_pseudo_instructions(to_source_lines("call @print"), "bp").unwrap();
+        assert_eq!(expanded[1], SourceLine { expansion: vec![String::from("call")], ..SourceLine::new("call bp", 1) });
+    }
+
+
+    #[test]
+    fn test_load_immediate() {
+        assert_eq!(texts("LI al, 0x41\nval: li bh -1\nli cx, 1 << 12"), vec!["movi al, 0x41", "val: movi bh -1", "movi cx, 1 << 12"]);
+    }
+
+
+    #[test]
+    fn test_push_pop_registers() {
+        assert_eq!(texts("save: push ax, bx cx\npush dx\npop cx,bx, ax"), vec![
+            "save: push ax", "push bx", "push cx", "push dx", "pop cx", "pop bx", "pop ax"
+        ]);
+    }
+
+
+    #[test]
+    fn test_immediate_operations() {
+        assert_eq!(texts("top: add ax, 5\ncmp bx 10\nand al, 0x0F\nxor ch, 1 << 2\nsub ax, bx\nadd ax"), vec![
+            "top: movi dx, 5", "add ax, dx", "movi dx, 10", "cmp bx, dx", "movi dl, 0x0F", "and al, dl", "movi dh, 1 << 2", "xor ch, dh", "sub ax, bx", "add ax"
+        ]);
+
+        let error = |source:&str, scratch:&str| expand_pseudo_instructions(to_source_lines(source), scratch).unwrap_err().to_string();
+        assert_eq!(error("add dx, 5", "dx"), "line 1: 'add' uses the scratch register dx as an operand, which its expansion overwrites, so another must be given with '--scratch'");
+        assert_eq!(error("or dl, 1", "dx"), "line 1: 'or' uses the scratch register dx as an operand, which its expansion overwrites, so another must be given with '--scratch'");
+        assert_eq!(error("add al, 5", "bp"), "line 1: 'al' is a byte register, but the scratch register bp has no byte halves to load a constant for it into");
+        assert_eq!(texts("add ax, bx, cx
+sub ax, 1, 2"), vec!["add ax, bx, cx", "sub ax, 1, 2"]);
+    }
+
+
+    #[test]
+    fn test_shift_immediate() {
+        assert_eq!(texts("sll ax, 3\nshr bl, COUNT\nsar cx, dx"), vec!["movi dx, 3", "sll ax, dx", "movi dl, COUNT", "srl bl, dl", "sar cx, dx"]);
+        assert!(expand_pseudo_instructions(to_source_lines("sra dh, 1"), "dx").is_err());
+        assert_eq!(texts("shl.nf ax, 3\nadd32.nf dx:ax, cx:bx"), vec!["movi dx, 3", "sll.nf ax, dx", "add ax, bx", "addc dx", "add.nf dx, cx"]);
+    }
+
+
+    #[test]
+    fn test_double_word_arithmetic() {
+        assert_eq!(texts("sum: add32 dx:ax, cx:bx\nSUB32 BP:FP cx:ax\nadd32 dx:ax, dx:ax"), vec![
+            "sum: add ax, bx", "addc dx", "add dx, cx", "sub fp, ax", "subb bp", "sub bp, cx", "add ax, ax", "addc dx", "add dx, dx"
+        ]);
+
+        let error = |source:&str| expand_pseudo_instructions(to_source_lines(source), "dx").unwrap_err().to_string();
+        assert_eq!(error("add32 ax:ax, cx:bx"), "line 1: 'ax:ax' is not a pair of two different 16-bit registers, such as 'dx:ax'");
+        assert_eq!(error("sub32 dx:ax, cl:bl"), "line 1: 'cl:bl' is not a pair of two different 16-bit registers, such as 'dx:ax'");
+        assert_eq!(error("add32 dx:ax"), "line 1: 'dx:ax' is not a pair of two different 16-bit registers, such as 'dx:ax'");
+        assert_eq!(error("add32 dx:ax, ax:bx"), "line 1: 'add32' writes the low word of its result to ax before reading it as the high word of its source");
+    }
+
+
+    #[test]
+    fn test_jump_label() {
+        assert_eq!(texts("top: jeq @top
+jmp @end
+jne bx
+jzro @top, cx
+end: jnzro @top bx"), vec![
+            "top: movi dx, @top", "jeq dx", "movi dx, @end", "jump dx", "jne bx", "movi dx, @top", "jzro dx, cx", "end: movi dx, @top", "jnzro dx, bx"
+        ]);
+
+        // `jz` and `jnz` test the zero flag like `jeq` and `jne`, so they take only the label
+        assert_eq!(texts("jz @top\njnz @top"), vec!["movi dx, @top", "jeq dx", "movi dx, @top", "jne dx"]);
+
+        let err = expand_pseudo_instructions(to_source_lines("nop
+jzro @top, DX"), "dx").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: 'jzro' uses the scratch register dx as an operand, which its expansion overwrites, so another must be given with '--scratch'");
+
+        let err = expand_pseudo_instructions(to_source_lines("jnzro @top"), "dx").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: 'jnzro' to a label expects the register it tests after the label, as in 'jnzro @done, cx'");
+    }
+
+
+    #[test]
+    fn test_load_store_label() {
+        assert_eq!(texts("get: load ax, @buffer\nstore al @result\nload dx, @buffer\nload ax, bx"), vec![
+            "get: movi dx, @buffer", "load ax, dx", "movi dx, @result", "store al, dx", "movi dx, @buffer", "load dx, dx", "load ax, bx"
+        ]);
+
+        assert!(expand_pseudo_instructions(to_source_lines("store DX, @result"), "dx").is_err());
+        assert_eq!(texts("load ax, [@buffer]\nstore [@result], bl\nload ax, [bx]"), vec![
+            "movi dx, @buffer", "load ax, dx", "movi dx, @result", "store bl, dx", "load ax, [bx]"
+        ]);
+    }
+}
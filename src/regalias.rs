@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::AssemblerError;
+use crate::label_table::get_defined_label;
+use crate::messages::{message, MessageId};
+use crate::repr::instruction::split_operands;
+use crate::repr::register::REGISTER_NAMES;
+use crate::source::{find_label_end, replace_names, SourceLine};
+use crate::validation::validate_label;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegisterAliasError {
+    InvalidRegisterAliasError(String),
+    RedefinedRegisterAliasError(String)
+}
+
+impl Error for RegisterAliasError {}
+
+impl fmt::Display for RegisterAliasError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterAliasError::InvalidRegisterAliasError(operands) => write!(f, "{}", message(MessageId::InvalidRegisterAlias, &[operands])),
+            RegisterAliasError::RedefinedRegisterAliasError(name) => write!(f, "{}", message(MessageId::RedefinedRegisterAlias, &[name]))
+        }
+    }
+}
+
+
+/**
+ * The register aliases in effect at a line: those defined before the first label, which last for the whole
+ * file, and those defined since the most recent label, which last until the next one and may shadow a
+ * file-wide alias of the same name.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+struct RegisterAliases {
+    global: HashMap<String, String>,
+    scoped: HashMap<String, String>,
+    in_scope: bool
+}
+
+impl RegisterAliases {
+    fn get(&self, name:&str) -> Option<&String> {
+        self.scoped.get(name).or_else(|| self.global.get(name))
+    }
+
+
+    /**
+     * Defines the alias given by the operands of a `.reg` line, such as `counter, cx`. The register may be
+     * given by another alias, in which case the new alias stands for the register that one stands for.
+     */
+    fn define(&mut self, operands:&str) -> Result<(), Box<dyn Error>> {
+        let invalid = || RegisterAliasError::InvalidRegisterAliasError(operands.trim().to_string());
+        let parts:Vec<&str> = split_operands(operands).iter().flat_map(|part| part.split_whitespace()).collect();
+        let (name, register) = match parts.as_slice() {
+            [name, register] => (*name, *register),
+            _ => return Err(Box::new(invalid()))
+        };
+
+        validate_label(name)?;
+        let register = match self.get(register) {
+            Some(aliased) => aliased.to_owned(),
+            None if REGISTER_NAMES.contains(&register.to_lowercase().as_str()) && register.to_lowercase() != "none" => register.to_lowercase(),
+            None => return Err(Box::new(invalid()))
+        };
+
+        let aliases = match self.in_scope {
+            true => &mut self.scoped,
+            false => &mut self.global
+        };
+        if aliases.contains_key(name) {
+            return Err(Box::new(RegisterAliasError::RedefinedRegisterAliasError(name.to_string())));
+        }
+
+        aliases.insert(name.to_string(), register);
+        Ok(())
+    }
+}
+
+
+/**
+ * Takes the lines of a source file and applies the `.reg counter, cx` directives in it, replacing every later
+ * use of an alias as a name, such as `inc counter`, with the register it stands for, and removing the
+ * directives. An alias defined before the first label lasts for the whole file, while one defined after a
+ * label lasts until the next label which is not local, so each function can give its registers its own names.
+ * An alias cannot be redefined in the scope it was defined in.
+ */
+pub fn apply_register_aliases(source_lines:Vec<SourceLine>) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut aliases = RegisterAliases::default();
+    let mut output = vec![];
+
+    for line in source_lines {
+        // section markers such as `.code:` and local labels do not start a new scope
+        if let Some(label) = get_defined_label(&line.text) {
+            if !label.starts_with('.') {
+                aliases.scoped.clear();
+                aliases.in_scope = true;
+            }
+        }
+
+        let (label, contents) = match find_label_end(&line.text) {
+            Some(index) => (line.text[..index + 1].trim(), line.text[index + 1..].trim()),
+            None => ("", line.text.trim())
+        };
+
+        if contents.split_whitespace().next() == Some(".reg") {
+            aliases.define(&contents[".reg".len()..]).map_err(|err| line.error(err))?;
+            if !label.is_empty() {
+                output.push(SourceLine { text: label.to_string(), ..line });
+            }
+            continue;
+        }
+
+        if aliases.global.is_empty() && aliases.scoped.is_empty() {
+            output.push(line);
+            continue;
+        }
+
+        let text = replace_names(&line.text, |name, previous, rest| match previous == '@' || rest.starts_with(':') {
+            true => None,
+            false => aliases.get(name).cloned()
+        });
+        output.push(SourceLine { text: text, ..line });
+    }
+
+    Ok(output)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::source::to_source_lines;
+    use super::*;
+
+
+    #[test]
+    fn test_register_aliases() {
+        let source = ".reg counter, cx\n.reg index bx\n.code:\nstart: inc counter\n.reg tmp, AX\n.reg counter, dx\nmove tmp, counter\n.Lloop: add tmp, index\n\
+                      next: .reg acc, counter\nmove acc, counter\n.asciiz `counter`";
+        let texts:Vec<String> = apply_register_aliases(to_source_lines(source)).unwrap().into_iter().map(|line| line.text).collect();
+        assert_eq!(texts, vec![".code:", "start: inc cx", "move ax, dx", ".Lloop: add ax, bx", "next:", "move cx, cx", ".asciiz `counter`"]);
+
+        let err = apply_register_aliases(to_source_lines("f:\n.reg tmp, ax\n.reg tmp, bx")).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: 'tmp' is already a register alias in this scope");
+
+        let err = apply_register_aliases(to_source_lines(".reg tmp, 5")).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '.reg' expects a name and the register it stands for, as in '.reg counter, cx', found 'tmp, 5'");
+
+        assert!(apply_register_aliases(to_source_lines(".reg ax, bx")).is_err());
+        assert!(apply_register_aliases(to_source_lines(".reg tmp")).is_err());
+    }
+}
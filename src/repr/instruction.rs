@@ -1,10 +1,15 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
 use std::fmt;
-use num_traits::Num;
+use std::num::TryFromIntError;
 
+use crate::expr::{evaluate, parse_q_format, split_values, string_literal, unescape};
+use crate::messages::{message, MessageId};
+use crate::source::find_label_end;
+use crate::validation::encode_immediate;
 use super::register::Register;
-use super::opcode::Opcode;
+use super::opcode::{resolve_mnemonic, split_mnemonic_suffix, Opcode, OperandFormat};
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -19,7 +24,20 @@ impl Into<u16> for Operand {
         match self {
             Operand::Register(reg) => reg.into(),
             Operand::ShortImmediate(imm) => imm.into(),
-            Operand::LargeImmediate(imm) => imm.into()
+            Operand::LargeImmediate(imm) => imm
+        }
+    }
+}
+
+impl Display for Operand {
+    /**
+     * Writes the operand as it would appear in assembly source, with large immediates in hex.
+     */
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(reg) => write!(f, "{}", Into::<String>::into(reg.clone())),
+            Operand::ShortImmediate(imm) => write!(f, "{}", imm),
+            Operand::LargeImmediate(imm) => write!(f, "0x{:04X}", imm)
         }
     }
 }
@@ -39,17 +57,56 @@ pub struct Instruction {
     pub operand_b: Operand
 }
 
+impl Display for Instruction {
+    /**
+     * Writes the instruction as canonical assembly source such as `add ax, bx`, leaving out any operands
+     * which are `Register::None`. An instruction whose flag bit differs from the default of its opcode is
+     * written with the `.f` or `.nf` suffix which sets it.
+     */
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let operands:Vec<String> = [&self.operand_a, &self.operand_b].into_iter()
+                                                                      .filter(|operand| **operand != Operand::Register(Register::None))
+                                                                      .map(|operand| operand.to_string())
+                                                                      .collect();
+
+        let mnemonic = match (self.set_flags == self.opcode.set_flags(), self.set_flags) {
+            (true, _) => self.opcode.to_string(),
+            (false, true) => format!("{}.f", self.opcode),
+            (false, false) => format!("{}.nf", self.opcode)
+        };
+
+        match operands.is_empty() {
+            true => write!(f, "{}", mnemonic),
+            false => write!(f, "{} {}", mnemonic, operands.join(", "))
+        }
+    }
+}
+
+/**
+ * The operands of an instruction as the operand format of its opcode lays them out, so a register can only be
+ * where the format has one and an immediate only where the format has one of its width.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionKind {
+    NoOperands,
+    OneRegister { rd: Register },
+    TwoRegisters { rd: Register, rt: Register },
+    RegisterShortImmediate { rd: Register, imm: u8 },
+    RegisterLargeImmediate { rd: Register, imm: u16 }
+}
+
 pub enum InstrType {
     Regular(u16),
     Long(u32)
 }
 
-impl Into<InstrType> for Instruction {
+impl Instruction {
     /**
-     * Takes a Sim6 instruction and converts it to its binary representation
+     * Takes a Sim6 instruction and converts it to its binary representation, taking its operands from the
+     * kind `validate_instruction` returned for it, so only the operands its format has a place for are encoded.
      */
-    fn into(self) -> InstrType {
-        let opcode:u16 = self.opcode.into();
+    pub fn encode(&self, kind:&InstructionKind) -> InstrType {
+        let opcode:u16 = self.opcode.clone().into();
         let opcode = opcode << 10;
 
         let high = self.high as u16;
@@ -64,43 +121,392 @@ impl Into<InstrType> for Instruction {
         let signed = self.signed as u16;
         let signed:u16 = signed << 6;
 
-        let operand_b_code:u16 = self.operand_b.clone().into();
-        let operand_a_code:u16 = self.operand_a.into();
+        let upper_instr = opcode | high | low | flag | signed;
+        let code = |reg:&Register| -> u16 { reg.clone().into() };
 
-        let upper_instr = 0 | opcode | high | low | flag | signed;
+        match kind {
+            InstructionKind::NoOperands => InstrType::Regular(upper_instr),
+            InstructionKind::OneRegister { rd } => InstrType::Regular(upper_instr | code(rd) << 3),
+            InstructionKind::TwoRegisters { rd, rt } => InstrType::Regular(upper_instr | code(rd) << 3 | code(rt)),
+            InstructionKind::RegisterShortImmediate { rd, imm } => InstrType::Regular(upper_instr | code(rd) << 3 | u16::from(*imm)),
+            InstructionKind::RegisterLargeImmediate { rd, imm } => InstrType::Long(u32::from(upper_instr) << 16 | u32::from(code(rd)) << 19 | u32::from(*imm))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    InvalidOpcodeError(u16),
+    InvalidRegisterError(u16, bool, bool),
+    UnusedOperandBitsError(Opcode, u16),
+    LongInstructionError(Opcode),
+    NotLongInstructionError(Opcode),
+    TruncatedInstructionError
+}
 
-        match self.operand_b {
-            Operand::Register(_) | Operand::ShortImmediate(_) => InstrType::Regular(upper_instr | operand_a_code << 3 | operand_b_code),
-            Operand::LargeImmediate(_) => InstrType::Long(u32::from(upper_instr) << 16 | u32::from(operand_a_code) << 19 | operand_b_code as u32)
+impl Error for DecodeError {}
+
+impl Display for DecodeError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidOpcodeError(code) => write!(f, "{}", message(MessageId::InvalidOpcode, &[code])),
+            DecodeError::InvalidRegisterError(code, high, low) => write!(f, "{}", message(MessageId::InvalidRegisterCode, &[code, &(*high as u8), &(*low as u8)])),
+            DecodeError::UnusedOperandBitsError(opcode, bits) => write!(f, "{}", message(MessageId::UnusedOperandBits, &[opcode, &format!("0b{:06b}", bits)])),
+            DecodeError::LongInstructionError(opcode) => write!(f, "{}", message(MessageId::LongInstruction, &[opcode])),
+            DecodeError::NotLongInstructionError(opcode) => write!(f, "{}", message(MessageId::NotLongInstruction, &[opcode])),
+            DecodeError::TruncatedInstructionError => write!(f, "{}", message(MessageId::TruncatedInstruction, &[]))
         }
     }
 }
 
-impl From<&str> for Instruction {
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataError {
+    UnknownDirectiveError(String),
+    MissingValueError(String),
+    ValueOutOfRangeError(String, i64, u32),
+    InvalidCountError(String),
+    FillOperandsError(String),
+    InvalidFixedError(String),
+    FixedOutOfRangeError(String, u32),
+    InvalidBcdError(String),
+    InvalidStringError(String),
+    InvalidEscapeError(String),
+    StringTooLongError(usize),
+    TrailingTokensError(String, String)
+}
+
+impl Error for DataError {}
+
+impl Display for DataError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::UnknownDirectiveError(directive) => write!(f, "{}", message(MessageId::UnknownDataDirective, &[directive])),
+            DataError::MissingValueError(directive) => write!(f, "{}", message(MessageId::DataExpectsValue, &[directive])),
+            DataError::ValueOutOfRangeError(token, value, bits) => {
+                let (min, max) = (-(1i64 << (bits - 1)), (1i64 << bits) - 1);
+                write!(f, "{}", message(MessageId::DataValueOutOfRange, &[token, value, bits, &min, &max]))
+            },
+            DataError::InvalidCountError(count) => write!(f, "{}", message(MessageId::InvalidDataCount, &[count])),
+            DataError::FillOperandsError(operands) => write!(f, "{}", message(MessageId::FillExpectsOperands, &[operands])),
+            DataError::InvalidFixedError(token) => write!(f, "{}", message(MessageId::InvalidFixed, &[token])),
+            DataError::FixedOutOfRangeError(token, fraction_bits) => {
+                let scale = (1u32 << fraction_bits) as f64;
+                let (min, max) = (i16::MIN as f64 / scale, i16::MAX as f64 / scale);
+                write!(f, "{}", message(MessageId::FixedOutOfRange, &[token, &(16 - fraction_bits), fraction_bits, &min, &max]))
+            },
+            DataError::InvalidBcdError(token) => write!(f, "{}", message(MessageId::InvalidBcd, &[token])),
+            DataError::InvalidStringError(operands) => write!(f, "{}", message(MessageId::InvalidString, &[operands])),
+            DataError::InvalidEscapeError(text) => write!(f, "{}", message(MessageId::InvalidEscape, &[text])),
+            DataError::StringTooLongError(length) => write!(f, "{}", message(MessageId::PascalStringTooLong, &[length])),
+            DataError::TrailingTokensError(directive, rest) => write!(f, "{}", message(MessageId::TrailingDataTokens, &[directive, rest]))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionError {
+    TooManyOperandsError(Opcode, usize, usize),
+    FlagSuffixError(Opcode, String),
+    SizeSuffixError(Opcode, String),
+    ByteSizeMismatchError(String, String),
+    WordSizeMismatchError(String, String),
+    UnknownSuffixError(String),
+    MemoryOperandError(String),
+    UnknownMnemonicError(String),
+    UnknownRegisterError(String)
+}
+
+impl Error for InstructionError {}
+
+impl Display for InstructionError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstructionError::TooManyOperandsError(opcode, max, found) => write!(f, "{}", message(MessageId::TooManyOperands, &[opcode, max, found])),
+            InstructionError::FlagSuffixError(opcode, suffix) => write!(f, "{}", message(MessageId::FlagSuffix, &[opcode, suffix])),
+            InstructionError::SizeSuffixError(opcode, suffix) => write!(f, "{}", message(MessageId::SizeSuffix, &[opcode, suffix])),
+            InstructionError::ByteSizeMismatchError(mnemonic, reg) => write!(f, "{}", message(MessageId::ByteSizeMismatch, &[mnemonic, reg])),
+            InstructionError::WordSizeMismatchError(mnemonic, reg) => write!(f, "{}", message(MessageId::WordSizeMismatch, &[mnemonic, reg])),
+            InstructionError::UnknownSuffixError(mnemonic) => write!(f, "{}", message(MessageId::UnknownSuffix, &[mnemonic])),
+            InstructionError::MemoryOperandError(operands) => write!(f, "{}", message(MessageId::MemoryOperand, &[operands])),
+            InstructionError::UnknownMnemonicError(mnemonic) => write!(f, "{}", message(MessageId::UnknownMnemonic, &[mnemonic])),
+            InstructionError::UnknownRegisterError(name) => write!(f, "{}", message(MessageId::UnknownRegister, &[name]))
+        }
+    }
+}
+
+
+/**
+ * Decodes the upper 16 bits of an instruction into its opcode, control bits, and 1st operand, leaving the
+ * 2nd operand as `Register::None` for the caller to fill in.
+ */
+fn decode_upper_instr(word:u16) -> Result<Instruction, DecodeError> {
+    let opcode = Opcode::try_from(word >> 10).map_err(DecodeError::InvalidOpcodeError)?;
+    let high = word & (1 << 9) != 0;
+    let low = word & (1 << 8) != 0;
+    let set_flags = word & (1 << 7) != 0;
+    let signed = word & (1 << 6) != 0;
+
+    let reg_a_code = (word >> 3) & 0x0007;
+    let operand_a = match opcode.format() {
+        OperandFormat::NoOperands => Register::None,
+        _ => Register::from_code(reg_a_code, high, low).ok_or(DecodeError::InvalidRegisterError(reg_a_code, high, low))?
+    };
+
+    Ok(Instruction {
+        opcode: opcode,
+        high: high,
+        low: low,
+        signed: signed,
+        set_flags: set_flags,
+        operand_a: Operand::Register(operand_a),
+        operand_b: Operand::Register(Register::None)
+    })
+}
+
+impl TryFrom<u16> for Instruction {
+    type Error = DecodeError;
+
     /**
-     * Takes a string representing a Sim6 instruction and converts it to an `Instruction`, will panic if it
-     * find an immediate too big for the number of bits given.
+     * Takes a 16-bit machine word and decodes it back into an `Instruction`. Returns a `DecodeError` if the
+     * word does not hold a valid instruction or holds the first half of a 32-bit instruction.
+     *
+     * Short immediates share bits 3 and 4 with the register code, so only immediates below 8 are decoded.
      */
-    fn from(line:&str) -> Instruction {        
-        let tokens:Vec<String> = line.split_whitespace().map(|token| token.replace(",", "").to_owned()).collect();
-
-        let opcode = Opcode::from(tokens.get(0).unwrap());
-        let operand_a = Operand::Register(Register::from(tokens.get(1).unwrap_or(&String::from("none"))));
-
-        // get register operand or an immediate operand if the 1st character is a base-10 digit (hex and binary immediates
-        // start with a prefix starting with 0)
-        match tokens.get(2).unwrap_or(&String::from("none")).chars().nth(0).unwrap().is_digit(10) {
-            false => { // is a register
-                let operand_b = Operand::Register(Register::from(tokens.get(2).unwrap_or(&String::from("none"))));
-                return Instruction::new(opcode, operand_a, operand_b);
+    fn try_from(word:u16) -> Result<Instruction, DecodeError> {
+        let mut instr = decode_upper_instr(word)?;
+        let operand_bits = word & 0x003F;
+
+        match instr.opcode.format() {
+            OperandFormat::NoOperands => {
+                if operand_bits != 0 || instr.high || instr.low {
+                    return Err(DecodeError::UnusedOperandBitsError(instr.opcode, operand_bits));
+                }
             },
 
-            true => {
-                let operand_b = get_immediate_from_string(&opcode, tokens.get(2).unwrap()).unwrap();
-                return Instruction::new(opcode, operand_a, operand_b)
+            OperandFormat::OneRegister => {
+                if operand_bits & 0x0007 != 0 {
+                    return Err(DecodeError::UnusedOperandBitsError(instr.opcode, operand_bits));
+                }
+            },
+
+            OperandFormat::TwoRegisters => {
+                let reg_b_code = word & 0x0007;
+                let reg_b = Register::from_code(reg_b_code, instr.high, instr.low)
+                                     .ok_or(DecodeError::InvalidRegisterError(reg_b_code, instr.high, instr.low))?;
+                instr.operand_b = Operand::Register(reg_b);
+            },
+
+            OperandFormat::RegisterShortImmediate => instr.operand_b = Operand::ShortImmediate((word & 0x0007) as u8),
+            OperandFormat::RegisterLargeImmediate => return Err(DecodeError::LongInstructionError(instr.opcode))
+        }
+
+        Ok(instr)
+    }
+}
+
+impl TryFrom<u32> for Instruction {
+    type Error = DecodeError;
+
+    /**
+     * Takes a 32-bit machine word and decodes it back into an `Instruction`, returning a `DecodeError` if the
+     * word does not hold a valid 32-bit instruction.
+     */
+    fn try_from(word:u32) -> Result<Instruction, DecodeError> {
+        let upper = (word >> 16) as u16;
+        let mut instr = decode_upper_instr(upper)?;
+
+        match instr.opcode.format() {
+            OperandFormat::RegisterLargeImmediate => {
+                if upper & 0x0007 != 0 {
+                    return Err(DecodeError::UnusedOperandBitsError(instr.opcode, upper & 0x003F));
+                }
+
+                instr.operand_b = Operand::LargeImmediate(word as u16);
+                Ok(instr)
+            },
+
+            _ => Err(DecodeError::NotLongInstructionError(instr.opcode))
+        }
+    }
+}
+
+/**
+ * Splits the suffix of a mnemonic, such as the `b.nf` of `add.b.nf`, into the flag suffix (`f` or `nf`) and the
+ * size suffix (`b` or `w`) it holds, each of which may be given at most once and in either order.
+ */
+fn parse_suffixes<'a>(mnemonic:&str, suffix:Option<&'a str>) -> Result<(Option<&'a str>, Option<&'a str>), InstructionError> {
+    let mut flags = None;
+    let mut size = None;
+
+    for part in suffix.map(|suffix| suffix.split('.').collect()).unwrap_or(vec![]) {
+        match (part, flags, size) {
+            ("f" | "nf", None, _) => flags = Some(part),
+            ("b" | "w", _, None) => size = Some(part),
+            _ => return Err(InstructionError::UnknownSuffixError(mnemonic.to_string()))
+        }
+    }
+
+    Ok((flags, size))
+}
+
+
+/**
+ * Checks that every register operand of an instruction is of the size given by its `.b` or `.w` suffix, so a
+ * byte operation written with 16-bit registers, or the other way round, is reported as such.
+ */
+fn check_operand_size(mnemonic:&str, opcode:&Opcode, size:&str, operands:&[&Operand]) -> Result<(), InstructionError> {
+    if opcode.format() == OperandFormat::NoOperands {
+        return Err(InstructionError::SizeSuffixError(opcode.clone(), size.to_string()));
+    }
+
+    for operand in operands {
+        if let Operand::Register(reg) = operand {
+            let byte = reg.is_high_reg() != reg.is_low_reg();
+            match (size, byte) {
+                ("b", false) if *reg != Register::None => return Err(InstructionError::ByteSizeMismatchError(mnemonic.to_string(), operand.to_string())),
+                ("w", true) => return Err(InstructionError::WordSizeMismatchError(mnemonic.to_string(), operand.to_string())),
+                _ => {}
             }
         }
     }
+
+    Ok(())
+}
+
+
+/**
+ * Splits the text of an instruction's operands at each comma, leaving a comma in a character literal such as
+ * `','` as part of its operand. The parts are not trimmed, and a run of commas gives empty parts between them.
+ */
+pub fn split_operands(text:&str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        if c == ',' && !quoted {
+            parts.push(&text[start..index]);
+            start = index + 1;
+        }
+
+        if c == '\'' && !escaped {
+            quoted = !quoted;
+        }
+        escaped = quoted && !escaped && c == '\\';
+    }
+
+    parts.push(&text[start..]);
+    parts
+}
+
+
+/**
+ * Rewrites the operands of a `load` or `store` whose address is given in brackets, as in `load ax, [bx]` or
+ * `store [bx], ax`, into the register form `ax, bx` with the address second. The bracketed operand is the
+ * address whichever side it is on, so `store ax, [bx]` is read the same way. Returns `None` if there are no
+ * brackets.
+ */
+pub fn unbracket_memory_operands(operands:&str) -> Result<Option<String>, InstructionError> {
+    let invalid = || InstructionError::MemoryOperandError(operands.trim().to_string());
+    let open = match operands.find('[') {
+        Some(open) => open,
+        None => return Ok(None)
+    };
+
+    let close = open + operands[open..].find(']').ok_or_else(invalid)?;
+    let address = operands[open + 1..close].trim();
+    let register = format!("{} {}", &operands[..open], &operands[close + 1..]);
+    let register = register.trim_matches(|c:char| c.is_whitespace() || c == ',');
+    if address.is_empty() || register.is_empty() || register.contains(['[', ']']) {
+        return Err(invalid());
+    }
+
+    Ok(Some(format!("{}, {}", register, address)))
+}
+
+
+/**
+ * Splits an instruction into its mnemonic and the tokens of its operands. The mnemonic and operands may be
+ * separated by any mix of commas, spaces and tabs, as in `add ax,bx` or `add\tax , bx`.
+ */
+fn tokenise_instruction(line:&str) -> Vec<String> {
+    split_operands(line).join(" ").split_whitespace().map(|token| token.to_string()).collect()
+}
+
+
+impl TryFrom<&str> for Instruction {
+    type Error = Box<dyn Error>;
+
+    /**
+     * Takes a string representing a Sim6 instruction and converts it to an `Instruction`.
+     *
+     * The mnemonic may end in `.f` or `.nf` to choose whether the instruction updates the flags, as in
+     * `add.nf ax, bx`, for the opcodes where this is optional. It may also end in `.b` or `.w` to state that it
+     * operates on bytes or words, as in `move.b ah, bh`, which its register operands must then match. The
+     * address of a `load` or `store` may be written in brackets, as in `load ax, [bx]` or `store [bx], ax`.
+     *
+     * Will return an error if it is given an unknown mnemonic, a first operand which is not a register, more
+     * operands than its opcode takes, an unknown suffix or one its opcode does not allow, registers of another size than its suffix gives, or finds an immediate which is not
+     * a valid expression or is too big for the number of bits given.
+     */
+    fn try_from(line:&str) -> Result<Instruction, Box<dyn Error>> {
+        let line = line.trim();
+        let (name, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let tokens = match split_mnemonic_suffix(&resolve_mnemonic(name)).0 {
+            "load" | "store" => match unbracket_memory_operands(operands)? {
+                Some(operands) => tokenise_instruction(&format!("{} {}", name, operands)),
+                None => tokenise_instruction(line)
+            },
+            _ => tokenise_instruction(line)
+        };
+
+        let mnemonic = tokens.first().unwrap().to_lowercase();
+        let (name, suffix) = split_mnemonic_suffix(&mnemonic);
+        let opcode = Opcode::from_mnemonic(name).ok_or_else(|| InstructionError::UnknownMnemonicError(tokens[0].to_string()))?;
+        let (flags, size) = parse_suffixes(&tokens[0], suffix)?;
+        let set_flags = match flags {
+            None => opcode.set_flags(),
+            Some(flags) => match opcode.flags_optional() {
+                true => flags == "f",
+                false => return Err(Box::new(InstructionError::FlagSuffixError(opcode, flags.to_string())))
+            }
+        };
+        let operand_a = match tokens.get(1) {
+            Some(name) => Register::from_name(name).ok_or_else(|| InstructionError::UnknownRegisterError(name.to_string()))?,
+            None => Register::None
+        };
+        let operand_a = Operand::Register(operand_a);
+
+        // the 2nd operand is a register if it names one, otherwise it is an immediate written as a constant
+        // expression, which may be split over several tokens such as `1 << 12 | 0x0F`
+        let operand_b = match tokens.len() > 2 {
+            true => tokens[2..].join(" "),
+            false => String::from("none")
+        };
+
+        let max_operands = opcode.format().max_operands();
+        let found_operands = match tokens.len() {
+            0 | 1 => 0,
+            _ => 1 + split_values(&tokens[2..].join(" ")).len()
+        };
+        if found_operands > max_operands {
+            return Err(Box::new(InstructionError::TooManyOperandsError(opcode, max_operands, found_operands)));
+        }
+
+        let operand_b = match Register::from_name(&operand_b) {
+            Some(reg) => Operand::Register(reg),
+            None => get_immediate_from_string(&opcode, &operand_b)?
+        };
+
+        if let Some(size) = size {
+            check_operand_size(&tokens[0], &opcode, size, &[&operand_a, &operand_b])?;
+        }
+
+        Ok(Instruction { set_flags: set_flags, ..Instruction::new(opcode, operand_a, operand_b) })
+    }
 }
 
 impl Instruction {
@@ -135,35 +541,112 @@ impl Instruction {
 
 
 /**
- * Takes a string representing a number in decimal, hex, or binary, removes the "0x" or "0b" prefix if
- * necessary, and returns the value as type `T`. 
- * 
- * Will return a `FromStrRadixErr` if the number is invalid.
+ * Takes a constant expression over integers in decimal, hex (with the prefix '0x'), octal (with the prefix
+ * '0o'), or binary (with the prefix '0b'), such as `(8*2)+1` or `1 << 12 | 0x0F`, and returns its value as
+ * type `T`.
+ *
+ * Will return an error if the expression is invalid or its value does not fit in `T`.
+ */
+fn evaluate_immediate<T: TryFrom<i64, Error = TryFromIntError>>(original:&str) -> Result<T, Box<dyn Error>> {
+    let value = evaluate(original, &HashMap::new())?;
+    Ok(T::try_from(value)?)
+}
+
+
+/**
+ * Takes a constant expression for a value stored in a field of the given width in bits and returns the value
+ * as stored in the field, with a negative value such as `-5` stored in two's complement.
+ *
+ * Will return an error if the expression is invalid or its value does not fit in the field.
+ */
+fn evaluate_signed_immediate(original:&str, bits:u32) -> Result<u64, Box<dyn Error>> {
+    encode_immediate(evaluate(original, &HashMap::new())?, bits)
+}
+
+
+/**
+ * Takes a constant expression for a data value stored in the given number of bits and returns the value as
+ * stored, with a negative value such as `-5` stored in two's complement.
+ *
+ * Will return an error if the expression is invalid or its value does not fit in the given number of bits.
+ */
+fn evaluate_data_value(token:&str, bits:u32) -> Result<u64, Box<dyn Error>> {
+    let value = evaluate(token, &HashMap::new())?;
+    encode_immediate(value, bits).map_err(|_| Box::new(DataError::ValueOutOfRangeError(token.trim().to_string(), value, bits)) as Box<dyn Error>)
+}
+
+
+/**
+ * Takes a constant expression for the number of bytes reserved or filled by a `.space` or `.fill` and returns
+ * its value.
+ *
+ * Will return an error if the expression is invalid or its value is negative.
+ */
+fn evaluate_data_count(token:&str) -> Result<usize, Box<dyn Error>> {
+    let count = evaluate(token, &HashMap::new())?;
+    usize::try_from(count).map_err(|_| Box::new(DataError::InvalidCountError(token.trim().to_string())) as Box<dyn Error>)
+}
+
+
+/**
+ * Takes the operand of a directive which holds a single value, such as `.byte`, and returns it, or an error
+ * giving whatever follows the value if the operand holds more than one.
+ */
+fn single_value(directive:&str, operand:&str) -> Result<String, DataError> {
+    let values = split_values(operand);
+    match values.len() > 1 {
+        true => Err(DataError::TrailingTokensError(directive.to_string(), values[1..].join(" "))),
+        false => Ok(operand.trim().to_string())
+    }
+}
+
+
+/**
+ * Converts a decimal number such as `3.25` or `-0.5` to a 16-bit fixed point value with the given number of
+ * fraction bits, rounding to the nearest value it can hold. A negative value is stored in two's complement.
+ *
+ * Will return an error if the text is not a number or its value is outside the signed range of the format,
+ * which for Qm.n is -2^(m-1) to 2^(m-1) - 2^-n.
+ */
+fn to_fixed_point(text:&str, fraction_bits:u32) -> Result<u16, DataError> {
+    let value = match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => value,
+        _ => return Err(DataError::InvalidFixedError(text.to_string()))
+    };
+
+    let scaled = (value * (1u32 << fraction_bits) as f64).round();
+    match scaled >= i16::MIN as f64 && scaled <= i16::MAX as f64 {
+        true => Ok(scaled as i16 as u16),
+        false => Err(DataError::FixedOutOfRangeError(text.to_string(), fraction_bits))
+    }
+}
+
+
+/**
+ * Converts a decimal number such as `1234` to packed binary-coded decimal, with two digits in each byte and
+ * the most significant digits first, as in `0x12 0x34`. A number with an odd number of digits is padded with
+ * a leading zero. Returns `None` if the text is not made up of decimal digits.
  */
-fn convert_imm_str_to_unsigned<T: Num>(original:&str) -> Result<T, <T as Num>::FromStrRadixErr> {
-    let immediate:T;
-    if original.starts_with("0x") {
-        immediate = T::from_str_radix(original.strip_prefix("0x").unwrap(), 16)?;
-    } else if original.starts_with("0b") {
-        immediate = T::from_str_radix(original.strip_prefix("0b").unwrap(), 2)?;
-    } else {
-        immediate = T::from_str_radix(original, 10)?;
+fn to_packed_bcd(text:&str) -> Option<Vec<u8>> {
+    let digits = text.replace('_', "");
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
     }
 
-    Ok(immediate)
+    let padded = format!("{}{}", "0".repeat(digits.len() % 2), digits);
+    Some(padded.as_bytes().chunks(2).map(|pair| ((pair[0] - b'0') << 4) | (pair[1] - b'0')).collect())
 }
 
 
 /**
- * Takes a string representing an integer either in decimal, hex (with the prefix '0x'), or binary (with
- * the prefix '0b') and returns an `Opcode::LongImmediate` or an `Opcode::ShortImmediate` depending on the
- * opcode provided.
+ * Takes a string representing an immediate as a constant expression and returns an `Opcode::LongImmediate`
+ * or an `Opcode::ShortImmediate` depending on the opcode provided. A large immediate may be negative, such as
+ * the offset given by `@rel(label)` to an earlier label, and is stored in two's complement.
  */
 fn get_immediate_from_string(opcode:&Opcode, original:&str) -> Result<Operand, Box<dyn Error>> {
-    let immediate = convert_imm_str_to_unsigned(original)?;
-    match opcode {
-        Opcode::MovI => Ok(Operand::LargeImmediate(immediate)),
-        _ => Ok(Operand::ShortImmediate(immediate.try_into()?))
+    match opcode.format() {
+        OperandFormat::RegisterLargeImmediate => Ok(Operand::LargeImmediate(evaluate_signed_immediate(original, 16)? as u16)),
+        _ => Ok(Operand::ShortImmediate(evaluate_immediate(original)?))
     }
 }
 
@@ -173,67 +656,97 @@ pub struct Data {
     pub bytes:Vec<u8>
 }
 
-impl From<&str> for Data {
+impl TryFrom<&str> for Data {
+    type Error = Box<dyn Error>;
+
     /**
-     * Takes a string and converts it into a `Vec<u8>` for the `Data` struct.
+     * Takes a string and converts it into a `Vec<u8>` for the `Data` struct. The value of a `.byte` may be a
+     * constant expression, and an `.array`, `.word` or `.long` may hold several values separated by spaces, each
+     * of which may be an expression. A negative value is stored in two's complement.
+     *
+     * A `.fixed` holds decimal numbers as 16-bit fixed point values, in Q8.8 unless a format such as `Q4.12`
+     * is given first, and a `.bcd` holds a decimal number as packed binary-coded decimal.
+     *
+     * The text of an `.ascii`, `.asciiz` or `.asciip` may hold escape sequences such as `\n`, and an escaped
+     * quote does not end it. An `.asciiz` appends a null byte, and an `.asciip` is preceded by a byte holding
+     * its length, which must be at most 255.
+     *
+     * Will return an error naming the offending value if the line is not a valid data directive or a value
+     * does not fit in the space the directive gives it.
      */
-    fn from(line:&str) -> Data {
-        let index = line.find(":").unwrap_or(0);
+    fn try_from(line:&str) -> Result<Data, Box<dyn Error>> {
+        let index = find_label_end(line).map(|index| index + 1).unwrap_or(0);
         let tokens:Vec<&str> = line[index..].split_whitespace().collect();
+        let directive = match tokens.first() {
+            Some(directive) => *directive,
+            None => return Err(Box::new(DataError::UnknownDirectiveError(String::new())))
+        };
+        let value = match tokens.len() > 1 {
+            true => Ok(tokens[1..].join(" ")),
+            false => Err(Box::new(DataError::MissingValueError(directive.to_string())))
+        };
 
-        // first token in the kind of data expected, byte, 2 byte word, 4 byte long word, array of bytes
-        // or an ascii string with a null byte auto-appended.
-        match *tokens.get(0).expect(&format!("Insufficient tokens in data line: '{}'", line)) {
-            ".byte" => {
-                Data {
-                    bytes: vec![
-                        convert_imm_str_to_unsigned(
-                            tokens.get(1).expect(&format!("Insufficient tokens in data line: '{}'", line))
-                        ).unwrap()
-                    ]
-                }
-            },
-            
-            ".word" => {
-                let immediate:u16 = convert_imm_str_to_unsigned(
-                    tokens.get(1).expect(&format!("Insufficient tokens in data line: '{}'", line))
-                ).unwrap();
+        // the directive gives the kind of data expected: a byte, 2 byte word, 4 byte long word, array of bytes,
+        // fixed point or bcd number, reserved or filled bytes, or a string
+        let bytes = match directive {
+            ".byte" => vec![evaluate_data_value(&single_value(directive, &value?)?, 8)? as u8],
 
-                Data {
-                    bytes: immediate.to_be_bytes().to_vec()
-                }
+            ".word" => {
+                let words:Result<Vec<u64>, Box<dyn Error>> = split_values(&value?).iter().map(|v| evaluate_data_value(v, 16)).collect();
+                words?.iter().flat_map(|word| (*word as u16).to_be_bytes()).collect()
             },
 
             ".long" => {
-                let immediate:u32 = convert_imm_str_to_unsigned(
-                    tokens.get(1).expect(&format!("Insufficient tokens in data line: '{}'", line))
-                ).unwrap();
+                let longs:Result<Vec<u64>, Box<dyn Error>> = split_values(&value?).iter().map(|v| evaluate_data_value(v, 32)).collect();
+                longs?.iter().flat_map(|long| (*long as u32).to_be_bytes()).collect()
+            },
 
-                Data {
-                    bytes: immediate.to_be_bytes().to_vec()
-                }
+            ".fixed" => {
+                let (fraction_bits, values) = match tokens.get(1).and_then(|token| parse_q_format(token)) {
+                    Some(fraction_bits) => (fraction_bits, &tokens[2..]),
+                    None => (8, &tokens[1..])
+                };
+
+                let words:Result<Vec<u16>, DataError> = values.iter().map(|v| to_fixed_point(v, fraction_bits)).collect();
+                words?.iter().flat_map(|word| word.to_be_bytes()).collect()
+            },
+
+            ".bcd" => {
+                let value = single_value(directive, &value?)?;
+                to_packed_bcd(&value).ok_or(DataError::InvalidBcdError(value))?
             },
 
             ".array" => {
-                let bytes:Vec<u8> = tokens[1..].into_iter()
-                                               .map(|b| convert_imm_str_to_unsigned(b).unwrap())
-                                               .collect();
-                Data {
-                    bytes: bytes
-                }
+                let bytes:Result<Vec<u64>, Box<dyn Error>> = split_values(&value?).iter().map(|b| evaluate_data_value(b, 8)).collect();
+                bytes?.iter().map(|byte| *byte as u8).collect()
             },
 
-            ".asciiz" => {
-                let mut string = line[line.find("`").unwrap() + 1 .. line.len() - 1].as_bytes().to_vec();
-                string.push(0x00);
+            ".space" => vec![0; evaluate_data_count(&single_value(directive, &value?)?)?],
 
-                Data {
-                    bytes: string
+            ".fill" => {
+                let value = value?;
+                let (count, fill) = value.split_once(",").ok_or_else(|| DataError::FillOperandsError(value.to_string()))?;
+                vec![evaluate_data_value(&single_value(directive, fill)?, 8)? as u8; evaluate_data_count(&single_value(directive, count)?)?]
+            },
+
+            ".ascii" | ".asciiz" | ".asciip" => {
+                let operands = line[index..].trim()[directive.len()..].trim();
+                let text = string_literal(operands).ok_or_else(|| DataError::InvalidStringError(operands.to_string()))?;
+                let mut string = unescape(&text).ok_or_else(|| DataError::InvalidEscapeError(text.to_string()))?;
+                match directive {
+                    ".asciiz" => string.push(0x00),
+                    ".asciip" => string.insert(0, u8::try_from(string.len()).map_err(|_| DataError::StringTooLongError(string.len()))?),
+                    _ => {}
                 }
-            }
+                string
+            },
 
-            datatype => panic!("'{}' is not a valid data instruction type", datatype)
-        }
+            datatype => return Err(Box::new(DataError::UnknownDirectiveError(datatype.to_string())))
+        };
+
+        Ok(Data {
+            bytes: bytes
+        })
     }
 }
 
@@ -246,14 +759,14 @@ impl Display for Data {
 
 #[derive(Debug, Clone)]
 pub enum InstructionOrData {
-    Instruction(Instruction),
+    Instruction(Instruction, InstructionKind),
     Data(Data)
 }
 
 impl Display for InstructionOrData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            InstructionOrData::Instruction(instr) => write!(f, "{:?}", instr),
+            InstructionOrData::Instruction(instr, _) => write!(f, "{}", instr),
             InstructionOrData::Data(data) => write!(f, "{}", data)
         }
     }
@@ -262,7 +775,7 @@ impl Display for InstructionOrData {
 impl Into<Instruction> for InstructionOrData {
     fn into(self) -> Instruction {
         match self {
-            InstructionOrData::Instruction(instr) => instr,
+            InstructionOrData::Instruction(instr, _) => instr,
             InstructionOrData::Data(_) => panic!("{:?} is not an instruction", self)
         }
     }
@@ -275,51 +788,59 @@ mod tests {
     use super::*;
     use crate::repr::opcode::Opcode;
     use crate::repr::register::Register;
+    use crate::validation::validate_instruction;
+
+
+    fn encode(instr:&Instruction) -> InstrType {
+        instr.encode(&validate_instruction(instr).unwrap())
+    }
 
 
     #[test]
     fn test_gen_instrs() {
-        assert_eq!(Instruction::from("Nop"), Instruction::new(Opcode::Nop, Operand::Register(Register::None), Operand::Register(Register::None)));
-        assert_eq!(Instruction::from("ADD ax, bx"), Instruction::new(Opcode::Add, Operand::Register(Register::Ax), Operand::Register(Register::Bx)));
-        assert_eq!(Instruction::from("ADDC ax"), Instruction::new(Opcode::Addc, Operand::Register(Register::Ax), Operand::Register(Register::None)));
-        assert_eq!(Instruction::from("in dl, 5"), Instruction::new(Opcode::In, Operand::Register(Register::Dl), Operand::ShortImmediate(5)));
-        assert_eq!(Instruction::from("movi sp, 700"), Instruction::new(Opcode::MovI, Operand::Register(Register::Sp), Operand::LargeImmediate(700)));
+        assert_eq!(Instruction::try_from("Nop").unwrap(), Instruction::new(Opcode::Nop, Operand::Register(Register::None), Operand::Register(Register::None)));
+        assert_eq!(Instruction::try_from("ADD ax, bx").unwrap(), Instruction::new(Opcode::Add, Operand::Register(Register::Ax), Operand::Register(Register::Bx)));
+        assert_eq!(Instruction::try_from("ADDC ax").unwrap(), Instruction::new(Opcode::Addc, Operand::Register(Register::Ax), Operand::Register(Register::None)));
+        assert_eq!(Instruction::try_from("in dl, 5").unwrap(), Instruction::new(Opcode::In, Operand::Register(Register::Dl), Operand::ShortImmediate(5)));
+        assert_eq!(Instruction::try_from("movi sp, 700").unwrap(), Instruction::new(Opcode::MovI, Operand::Register(Register::Sp), Operand::LargeImmediate(700)));
+        assert_eq!(Instruction::try_from("movi ax, (16*2)+1").unwrap(), Instruction::new(Opcode::MovI, Operand::Register(Register::Ax), Operand::LargeImmediate(33)));
+        assert_eq!(Instruction::try_from("out al, 0x10 >> 2").unwrap(), Instruction::new(Opcode::Out, Operand::Register(Register::Al), Operand::ShortImmediate(4)));
     }
 
 
     #[test]
     fn test_gen_binary() {
-        let binary:InstrType = Instruction::new(Opcode::Nop, Operand::Register(Register::None), Operand::Register(Register::None)).into();
+        let binary = encode(&Instruction::new(Opcode::Nop, Operand::Register(Register::None), Operand::Register(Register::None)));
         match binary {
             InstrType::Regular(bin) => assert_eq!(bin, 0x0000),
             _ => panic!("Invalid")
         }
 
-        let binary:InstrType = Instruction::new(Opcode::Add, Operand::Register(Register::Ax), Operand::Register(Register::Bx)).into();
+        let binary = encode(&Instruction::new(Opcode::Add, Operand::Register(Register::Ax), Operand::Register(Register::Bx)));
         match binary {
             InstrType::Regular(bin) => assert_eq!(bin, 0x07C1),
             _ => panic!("Invalid")
         }
 
-        let binary:InstrType = Instruction::new(Opcode::Addc, Operand::Register(Register::Ax), Operand::Register(Register::None)).into();
+        let binary = encode(&Instruction::new(Opcode::Addc, Operand::Register(Register::Ax), Operand::Register(Register::None)));
         match binary {
             InstrType::Regular(bin) => assert_eq!(bin, 0x0F80),
             _ => panic!("Invalid")
         }
 
-        let binary:InstrType = Instruction::new(Opcode::In, Operand::Register(Register::Dl), Operand::ShortImmediate(5)).into();
+        let binary = encode(&Instruction::new(Opcode::In, Operand::Register(Register::Dl), Operand::ShortImmediate(5)));
         match binary {
             InstrType::Regular(bin) => assert_eq!(bin, 0x4D1D),
             _ => panic!("Invalid")
         }
 
-        let binary:InstrType = Instruction::new(Opcode::MovI, Operand::Register(Register::Sp), Operand::LargeImmediate(700)).into();
+        let binary = encode(&Instruction::new(Opcode::MovI, Operand::Register(Register::Sp), Operand::LargeImmediate(700)));
         match binary {
             InstrType::Long(bin) => assert_eq!(bin, 0x5B38_02BC),
             _ => panic!("Invalid")
         }
 
-        let binary:InstrType = Instruction::new(Opcode::Halt, Operand::Register(Register::None), Operand::Register(Register::None)).into();
+        let binary = encode(&Instruction::new(Opcode::Halt, Operand::Register(Register::None), Operand::Register(Register::None)));
         match binary {
             InstrType::Regular(bin) => assert_eq!(bin, 0xFC00),
             _ => panic!("Invalid")
@@ -327,6 +848,63 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_display_instrs() {
+        assert_eq!(Instruction::try_from("NOP").unwrap().to_string(), "nop");
+        assert_eq!(Instruction::try_from("ADD ax,   bx").unwrap().to_string(), "add ax, bx");
+        assert_eq!(Instruction::try_from("addc AX").unwrap().to_string(), "addc ax");
+        assert_eq!(Instruction::try_from("in dl 5").unwrap().to_string(), "in dl, 5");
+        assert_eq!(Instruction::try_from("movi sp, 700").unwrap().to_string(), "movi sp, 0x02BC");
+        assert_eq!(Instruction::try_from("pushA").unwrap().to_string(), "pusha");
+    }
+
+
+    #[test]
+    fn test_decode_round_trip() {
+        let instrs = vec![
+            "nop", "add ax, bx", "addc ax", "in dl, 5", "sub ah, bh", "mul al, cl", "push sp", "out rp, 7", "halt"
+        ];
+
+        for line in instrs {
+            let instr = Instruction::try_from(line).unwrap();
+            match encode(&instr) {
+                InstrType::Regular(bin) => assert_eq!(Instruction::try_from(bin).unwrap(), instr),
+                InstrType::Long(_) => panic!("Invalid")
+            }
+        }
+
+        let instr = Instruction::try_from("movi sp, 700").unwrap();
+        match encode(&instr) {
+            InstrType::Long(bin) => assert_eq!(Instruction::try_from(bin).unwrap(), instr),
+            InstrType::Regular(_) => panic!("Invalid")
+        }
+    }
+
+
+    #[test]
+    fn test_decode_short_immediates() {
+        for reg in ["al", "ah", "ax", "bl", "dl", "rp", "sp"] {
+            for imm in 0..8 {
+                let instr = Instruction::try_from(format!("in {}, {}", reg, imm).as_str()).unwrap();
+                match encode(&instr) {
+                    InstrType::Regular(bin) => assert_eq!(Instruction::try_from(bin).unwrap(), instr),
+                    InstrType::Long(_) => panic!("Invalid")
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Instruction::try_from(0xE800u16), Err(DecodeError::InvalidOpcodeError(58)));
+        assert_eq!(Instruction::try_from(0x0621u16), Err(DecodeError::InvalidRegisterError(4, true, false)));
+        assert_eq!(Instruction::try_from(0x0001u16), Err(DecodeError::UnusedOperandBitsError(Opcode::Nop, 1)));
+        assert_eq!(Instruction::try_from(0x5B38u16), Err(DecodeError::LongInstructionError(Opcode::MovI)));
+        assert_eq!(Instruction::try_from(0x07C1_0000u32), Err(DecodeError::NotLongInstructionError(Opcode::Add)));
+    }
+
+
     #[test]
     fn test_get_immediate() {
         assert_eq!(get_immediate_from_string(&Opcode::Add, "0").unwrap(), Operand::ShortImmediate(0));
@@ -343,36 +921,164 @@ mod tests {
         assert_eq!(get_immediate_from_string(&Opcode::Add, "0x19").unwrap(), Operand::ShortImmediate(25));
         assert_eq!(get_immediate_from_string(&Opcode::Add, "0x1F").unwrap(), Operand::ShortImmediate(31));
         assert_eq!(get_immediate_from_string(&Opcode::MovI, "0xFFFF").unwrap(), Operand::LargeImmediate(0xFFFF));
+
+        assert_eq!(get_immediate_from_string(&Opcode::MovI, "(8*2)+1").unwrap(), Operand::LargeImmediate(17));
+        assert_eq!(get_immediate_from_string(&Opcode::Add, "1 << 3 | 0b11").unwrap(), Operand::ShortImmediate(11));
+        assert!(get_immediate_from_string(&Opcode::MovI, "0x10000").is_err());
+        assert_eq!(get_immediate_from_string(&Opcode::MovI, "-1").unwrap(), Operand::LargeImmediate(0xFFFF));
+        assert_eq!(get_immediate_from_string(&Opcode::MovI, "0x5800 - 0x5806").unwrap(), Operand::LargeImmediate(0xFFFA));
+        assert!(get_immediate_from_string(&Opcode::MovI, "-0x8001").is_err());
+        assert_eq!(get_immediate_from_string(&Opcode::MovI, "-0x8000").unwrap(), Operand::LargeImmediate(0x8000));
+        assert!(get_immediate_from_string(&Opcode::Add, "-1").is_err());
+        assert!(get_immediate_from_string(&Opcode::MovI, "2 +").is_err());
     }
 
 
     #[test]
-    fn test_get_valid_data() {
-        assert_eq!(Data::from(".byte 25"), Data { bytes: vec![25] });
-        assert_eq!(Data::from(".byte 0x50"), Data { bytes: vec![0x50] });
-        assert_eq!(Data::from(".word 0xAABB"), Data { bytes: vec![0xAA, 0xBB] });
-        assert_eq!(Data::from(".word 0b1010101010101010"), Data { bytes: vec![0xAA, 0xAA] });
-        assert_eq!(Data::from(".long 0x12345678"), Data { bytes: vec![0x12, 0x34, 0x56, 0x78] });
-        assert_eq!(Data::from(".array 25 40 32 18"), Data { bytes: vec![25, 40, 32, 18] });
-        assert_eq!(Data::from(".array 0xAC 40 0b11001100 18"), Data { bytes: vec![0xAC, 40, 0b11001100, 18] });
-        assert_eq!(Data::from(".asciiz `Hey you!`"), Data { bytes: vec![0x48, 0x65, 0x79, 0x20, 0x79, 0x6F, 0x75, 0x21, 0x00] });
+    fn test_tokenise_instruction() {
+        assert_eq!(tokenise_instruction("add ax,bx"), vec!["add", "ax", "bx"]);
+        assert_eq!(tokenise_instruction("add\tax ,\t bx"), vec!["add", "ax", "bx"]);
+        assert_eq!(tokenise_instruction("movi al, ','"), vec!["movi", "al", "','"]);
+        assert_eq!(tokenise_instruction("movi ax,1 << 4"), vec!["movi", "ax", "1", "<<", "4"]);
+        assert_eq!(Instruction::try_from("add ax,bx").unwrap(), Instruction::try_from("add ax, bx").unwrap());
+        assert_eq!(Instruction::try_from("JMP cx").unwrap(), Instruction::try_from("jump cx").unwrap());
+        assert_eq!(Instruction::try_from("mov ax, bx").unwrap().to_string(), "move ax, bx");
+        assert_eq!(split_operands(" sp,,, '\\'', 0"), vec![" sp", "", "", " '\\''", " 0"]);
     }
 
+
     #[test]
-    #[should_panic]
-    fn test_invalid_data_type() {
-        _ = Data::from(".bad 70");
+    fn test_too_many_operands() {
+        let error = |line:&str| Instruction::try_from(line).unwrap_err().to_string();
+
+        assert_eq!(error("nop ax bx cx junk"), "'nop' takes at most 0 operand(s), but 4 were given");
+        assert_eq!(error("push ax, bx"), "'push' takes at most 1 operand(s), but 2 were given");
+        assert_eq!(error("add ax, bx, cx"), "'add' takes at most 2 operand(s), but 3 were given");
+        assert_eq!(error("movi ax, 5 junk"), "'movi' takes at most 2 operand(s), but 3 were given");
+        assert!(Instruction::try_from("out al, 0x100").is_err());
+        assert!(Instruction::try_from("movi ax, (1 << 12) | 0x0F").is_ok());
+
+        assert_eq!(error("foo ax"), "'foo' is not an instruction mnemonic");
+        assert_eq!(error("Foo.nf ax"), "'Foo.nf' is not an instruction mnemonic");
+        assert_eq!(error("inc foo"), "'foo' is not a register");
     }
 
+
+    #[test]
+    fn test_flag_suffixes() {
+        assert!(!Instruction::try_from("add.nf ax, bx").unwrap().set_flags);
+        assert!(Instruction::try_from("ADD.F ax, bx").unwrap().set_flags);
+        assert!(!Instruction::try_from("shl.nf ax, bx").unwrap().set_flags);
+        assert_eq!(Instruction::try_from("xor.nf al, bl").unwrap().to_string(), "xor.nf al, bl");
+        assert_eq!(Instruction::try_from("xor.f al, bl").unwrap().to_string(), "xor al, bl");
+
+        let error = |line:&str| Instruction::try_from(line).unwrap_err().to_string();
+        assert_eq!(error("move.f ax, bx"), "'move' cannot take the '.f' suffix, as whether it updates the flags is fixed");
+        assert_eq!(error("cmp.nf ax, bx"), "'cmp' cannot take the '.nf' suffix, as whether it updates the flags is fixed");
+        assert_eq!(error("add.x ax, bx"), "'add.x' has an unknown suffix, expected '.f' or '.nf', and '.b' or '.w'");
+        assert_eq!(error("add.f.nf ax, bx"), "'add.f.nf' has an unknown suffix, expected '.f' or '.nf', and '.b' or '.w'");
+    }
+
+
+    #[test]
+    fn test_bracketed_memory_operands() {
+        assert_eq!(Instruction::try_from("load ax, [bx]").unwrap(), Instruction::try_from("load ax, bx").unwrap());
+        assert_eq!(Instruction::try_from("store [bx], ax").unwrap(), Instruction::try_from("store ax, bx").unwrap());
+        assert_eq!(Instruction::try_from("STORE al,[ cx ]").unwrap(), Instruction::try_from("store al, cx").unwrap());
+
+        let error = |line:&str| Instruction::try_from(line).unwrap_err().to_string();
+        assert_eq!(error("load [ax], [bx]"), "expected a register and an address in brackets, as in 'load ax, [bx]', found '[ax], [bx]'");
+        assert_eq!(error("store [bx"), "expected a register and an address in brackets, as in 'load ax, [bx]', found '[bx'");
+        assert_eq!(error("load ax, []"), "expected a register and an address in brackets, as in 'load ax, [bx]', found 'ax, []'");
+    }
+
+
     #[test]
-    #[should_panic]
-    fn test_data_pos_overflow() {
-        _ = Data::from(".long 7000000000");
+    fn test_size_suffixes() {
+        assert_eq!(Instruction::try_from("move.b ah, bh").unwrap(), Instruction::try_from("move ah, bh").unwrap());
+        assert_eq!(Instruction::try_from("MOVI.W ax, 0x1234").unwrap(), Instruction::try_from("movi ax, 0x1234").unwrap());
+        assert!(!Instruction::try_from("add.nf.b al, bl").unwrap().set_flags);
+        assert!(Instruction::try_from("push.b al").is_ok());
+
+        let error = |line:&str| Instruction::try_from(line).unwrap_err().to_string();
+        assert_eq!(error("move.b ax, bx"), "'move.b' is a byte operation, but ax is a 16-bit register");
+        assert_eq!(error("add.w al, bl"), "'add.w' is a word operation, but al is a byte register");
+        assert_eq!(error("out.w dl, 5"), "'out.w' is a word operation, but dl is a byte register");
+        assert_eq!(error("halt.b"), "'halt' has no register operands, so it cannot take the '.b' suffix");
+        assert_eq!(error("move.b.w al, bl"), "'move.b.w' has an unknown suffix, expected '.f' or '.nf', and '.b' or '.w'");
+    }
+
+
+    #[test]
+    fn test_get_valid_data() {
+        assert_eq!(Data::try_from(".byte 25").unwrap(), Data { bytes: vec![25] });
+        assert_eq!(Data::try_from(".byte 0x50").unwrap(), Data { bytes: vec![0x50] });
+        assert_eq!(Data::try_from(".word 0xAABB").unwrap(), Data { bytes: vec![0xAA, 0xBB] });
+        assert_eq!(Data::try_from(".word 0b1010101010101010").unwrap(), Data { bytes: vec![0xAA, 0xAA] });
+        assert_eq!(Data::try_from(".long 0x12345678").unwrap(), Data { bytes: vec![0x12, 0x34, 0x56, 0x78] });
+        assert_eq!(Data::try_from(".array 25 40 32 18").unwrap(), Data { bytes: vec![25, 40, 32, 18] });
+        assert_eq!(Data::try_from(".array 0xAC 40 0b11001100 18").unwrap(), Data { bytes: vec![0xAC, 40, 0b11001100, 18] });
+        assert_eq!(Data::try_from(".word 1<<12 | 0x0F").unwrap(), Data { bytes: vec![0x10, 0x0F] });
+        assert_eq!(Data::try_from(".byte (0x20 + 1) * 2").unwrap(), Data { bytes: vec![0x42] });
+        assert_eq!(Data::try_from(".array 1<<4 0xF0|1 7").unwrap(), Data { bytes: vec![0x10, 0xF1, 7] });
+        assert_eq!(Data::try_from(".space 2 * 2").unwrap(), Data { bytes: vec![0; 4] });
+        assert_eq!(Data::try_from(".fill 3, 0xFF").unwrap(), Data { bytes: vec![0xFF; 3] });
+        assert_eq!(Data::try_from(".asciiz `Hey you!`").unwrap(), Data { bytes: vec![0x48, 0x65, 0x79, 0x20, 0x79, 0x6F, 0x75, 0x21, 0x00] });
+        assert_eq!(Data::try_from(".byte -5").unwrap(), Data { bytes: vec![0xFB] });
+        assert_eq!(Data::try_from(".word -2").unwrap(), Data { bytes: vec![0xFF, 0xFE] });
+        assert_eq!(Data::try_from(".long -1").unwrap(), Data { bytes: vec![0xFF; 4] });
+        assert_eq!(Data::try_from(".word -300").unwrap(), Data { bytes: vec![0xFE, 0xD4] });
+        assert_eq!(Data::try_from(".long -70000").unwrap(), Data { bytes: vec![0xFF, 0xFE, 0xEE, 0x90] });
+        assert_eq!(Data::try_from(".word -32768 65535").unwrap(), Data { bytes: vec![0x80, 0x00, 0xFF, 0xFF] });
+        assert_eq!(Data::try_from(".long -2147483648").unwrap(), Data { bytes: vec![0x80, 0x00, 0x00, 0x00] });
+        assert_eq!(Data::try_from(".array -128 255 -1").unwrap(), Data { bytes: vec![0x80, 0xFF, 0xFF] });
+        assert_eq!(Data::try_from(".fill 2, -1").unwrap(), Data { bytes: vec![0xFF; 2] });
+        assert_eq!(Data::try_from(".byte '\\n'").unwrap(), Data { bytes: vec![0x0A] });
+        assert_eq!(Data::try_from(".word 0xFF_00").unwrap(), Data { bytes: vec![0xFF, 0x00] });
+        assert_eq!(Data::try_from(".word 1 2 3 400").unwrap(), Data { bytes: vec![0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x01, 0x90] });
+        assert_eq!(Data::try_from(".long 0x10000 0x20000").unwrap(), Data { bytes: vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00] });
+        assert_eq!(Data::try_from(".word 1 + 2 -1").unwrap(), Data { bytes: vec![0x00, 0x03, 0xFF, 0xFF] });
+        assert_eq!(Data::try_from(".fixed 3.25 -1.5 0.001").unwrap(), Data { bytes: vec![0x03, 0x40, 0xFE, 0x80, 0x00, 0x00] });
+        assert_eq!(Data::try_from(".fixed Q4.12 1.0625 7").unwrap(), Data { bytes: vec![0x11, 0x00, 0x70, 0x00] });
+        assert_eq!(Data::try_from(".fixed -128 127.99609375").unwrap(), Data { bytes: vec![0x80, 0x00, 0x7F, 0xFF] });
+        assert_eq!(Data::try_from(".bcd 1234").unwrap(), Data { bytes: vec![0x12, 0x34] });
+        assert_eq!(Data::try_from(".bcd 9_87_65").unwrap(), Data { bytes: vec![0x09, 0x87, 0x65] });
+        assert_eq!(Data::try_from(".array 'h' 'i'").unwrap(), Data { bytes: vec![0x68, 0x69] });
+        assert_eq!(Data::try_from(".array 'a' ' ' 'c'").unwrap(), Data { bytes: vec![0x61, 0x20, 0x63] });
+        assert_eq!(Data::try_from(".asciiz `a\\tb\\``").unwrap(), Data { bytes: vec![0x61, 0x09, 0x62, 0x60, 0x00] });
+        assert_eq!(Data::try_from(".ascii \"Hey\"").unwrap(), Data { bytes: vec![0x48, 0x65, 0x79] });
+        assert_eq!(Data::try_from(".asciip `Hey\\n`").unwrap(), Data { bytes: vec![0x04, 0x48, 0x65, 0x79, 0x0A] });
+        assert_eq!(Data::try_from(".asciip ``").unwrap(), Data { bytes: vec![0x00] });
     }
 
     #[test]
-    #[should_panic]
-    fn test_invalid_int_prefix() {
-        _ = Data::from(".byte 0c55");
+    fn test_invalid_data() {
+        let error = |line:&str| Data::try_from(line).unwrap_err().to_string();
+
+        assert_eq!(error(".bad 70"), "'.bad' is not a data directive");
+        assert_eq!(error(".word"), "'.word' expects a value");
+        assert_eq!(error(".long 7000000000"), "'7000000000' is 7000000000, which does not fit in 32 bits, as they hold -2147483648 to 4294967295");
+        assert_eq!(error(".byte -129"), "'-129' is -129, which does not fit in 8 bits, as they hold -128 to 255");
+        assert_eq!(error(".word 1 -32769"), "'-32769' is -32769, which does not fit in 16 bits, as they hold -32768 to 65535");
+        assert_eq!(error(".long -2147483649"), "'-2147483649' is -2147483649, which does not fit in 32 bits, as they hold -2147483648 to 4294967295");
+        assert_eq!(error(".array 1 0x100"), "'0x100' is 256, which does not fit in 8 bits, as they hold -128 to 255");
+        assert_eq!(error(".fixed Q4.12 16.0"), "'16.0' does not fit in Q4.12, which holds -8 to 7.999755859375");
+        assert_eq!(error(".fixed Q4.12 8.5"), "'8.5' does not fit in Q4.12, which holds -8 to 7.999755859375");
+        assert_eq!(error(".fixed 200.0"), "'200.0' does not fit in Q8.8, which holds -128 to 127.99609375");
+        assert_eq!(error(".fixed -128.01"), "'-128.01' does not fit in Q8.8, which holds -128 to 127.99609375");
+        assert_eq!(error(".fixed 1.5 x"), "'x' is not a decimal number");
+        assert_eq!(error(".bcd 0x12"), "'.bcd' expects a decimal number, found '0x12'");
+        assert_eq!(error(".byte 0c55"), "'0c55' is not a valid expression");
+        assert_eq!(error(".space 2 - 3"), "'2 - 3' is not a valid number of bytes");
+        assert_eq!(error(".fill 3"), "'.fill' expects a count and a value, as in '.fill 16, 0xFF', found '3'");
+        assert_eq!(error(".asciiz `open"), "Expected a string between backticks or double quotes, found '`open'");
+        assert_eq!(error(".ascii hello"), "Expected a string between backticks or double quotes, found 'hello'");
+        assert_eq!(error(".asciiz `a\\q`"), "'a\\q' holds an invalid escape sequence");
+        assert_eq!(error(&format!(".asciip `{}`", "a".repeat(256))), "An '.asciip' string can be at most 255 bytes long, but this one is 256");
+        assert_eq!(error(".byte 5 junk"), "'.byte' takes a single value, but found 'junk' after it");
+        assert_eq!(error(".space 4 8 16"), "'.space' takes a single value, but found '8 16' after it");
+        assert_eq!(error(".fill 3, 0xFF 1"), "'.fill' takes a single value, but found '1' after it");
+        assert_eq!(error(".bcd 12 34"), "'.bcd' takes a single value, but found '34' after it");
+        assert_eq!(Data::try_from(".byte 2 + 3").unwrap().bytes, vec![5]);
     }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 /**
  * Represents the full range of opcodes available to the Sim6 processor
  */
@@ -64,148 +66,231 @@ pub enum Opcode {
     Halt // halt process execution and yield
 }
 
+/**
+ * The operands an opcode takes and how they are laid out in the operand bits of the instruction: none, one
+ * register, two registers, a register and a 5-bit immediate, or a register and a 16-bit immediate held in a
+ * second word.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandFormat {
+    NoOperands,
+    OneRegister,
+    TwoRegisters,
+    RegisterShortImmediate,
+    RegisterLargeImmediate
+}
+
+impl OperandFormat {
+    /**
+     * Returns the number of operands an instruction of this format may be given.
+     */
+    pub fn max_operands(&self) -> usize {
+        match self {
+            OperandFormat::NoOperands => 0,
+            OperandFormat::OneRegister => 1,
+            _ => 2
+        }
+    }
+
+
+    /**
+     * Returns the number of bytes an instruction of this format is encoded in.
+     */
+    pub fn size(&self) -> usize {
+        match self {
+            OperandFormat::RegisterLargeImmediate => 4,
+            _ => 2
+        }
+    }
+}
+
+
+/**
+ * The mnemonic, 6-bit code and operand format of every opcode. The mnemonics are reserved and cannot be used as
+ * labels.
+ */
+pub const OPCODES:&[(Opcode, &str, u16, OperandFormat)] = &[
+    (Opcode::Nop,    "nop",   0,  OperandFormat::NoOperands),
+    (Opcode::Add,    "add",   1,  OperandFormat::TwoRegisters),
+    (Opcode::Addu,   "addu",  2,  OperandFormat::TwoRegisters),
+    (Opcode::Addc,   "addc",  3,  OperandFormat::OneRegister),
+    (Opcode::Inc,    "inc",   4,  OperandFormat::OneRegister),
+    (Opcode::Sub,    "sub",   5,  OperandFormat::TwoRegisters),
+    (Opcode::Subu,   "subu",  6,  OperandFormat::TwoRegisters),
+    (Opcode::Subb,   "subb",  7,  OperandFormat::OneRegister),
+    (Opcode::Dec,    "dec",   8,  OperandFormat::OneRegister),
+    (Opcode::Cmp,    "cmp",   9,  OperandFormat::TwoRegisters),
+    (Opcode::Neg,    "neg",   10, OperandFormat::OneRegister),
+    (Opcode::Move,   "move",  11, OperandFormat::TwoRegisters),
+    (Opcode::Push,   "push",  12, OperandFormat::OneRegister),
+    (Opcode::Pop,    "pop",   13, OperandFormat::OneRegister),
+    (Opcode::PushA,  "pusha", 14, OperandFormat::NoOperands),
+    (Opcode::PopA,   "popa",  15, OperandFormat::NoOperands),
+    (Opcode::PushF,  "pushf", 16, OperandFormat::NoOperands),
+    (Opcode::PopF,   "popf",  17, OperandFormat::NoOperands),
+    (Opcode::Swap,   "swap",  18, OperandFormat::TwoRegisters),
+    (Opcode::In,     "in",    19, OperandFormat::RegisterShortImmediate),
+    (Opcode::Out,    "out",   20, OperandFormat::RegisterShortImmediate),
+    (Opcode::Lda,    "lda",   21, OperandFormat::TwoRegisters),
+    (Opcode::MovI,   "movi",  22, OperandFormat::RegisterLargeImmediate),
+    (Opcode::Mul,    "mul",   23, OperandFormat::TwoRegisters),
+    (Opcode::Mulu,   "mulu",  24, OperandFormat::TwoRegisters),
+    (Opcode::Div,    "div",   25, OperandFormat::TwoRegisters),
+    (Opcode::Divu,   "divu",  26, OperandFormat::TwoRegisters),
+    (Opcode::Csign,  "csign", 27, OperandFormat::OneRegister),
+    (Opcode::Not,    "not",   28, OperandFormat::OneRegister),
+    (Opcode::And,    "and",   29, OperandFormat::TwoRegisters),
+    (Opcode::Or,     "or",    30, OperandFormat::TwoRegisters),
+    (Opcode::Xor,    "xor",   31, OperandFormat::TwoRegisters),
+    (Opcode::Sra,    "sra",   32, OperandFormat::TwoRegisters),
+    (Opcode::Srl,    "srl",   33, OperandFormat::TwoRegisters),
+    (Opcode::Sll,    "sll",   34, OperandFormat::TwoRegisters),
+    (Opcode::Clear,  "clear", 35, OperandFormat::OneRegister),
+    (Opcode::Call,   "call",  36, OperandFormat::OneRegister),
+    (Opcode::Ret,    "ret",   37, OperandFormat::NoOperands),
+    (Opcode::Jump,   "jump",  38, OperandFormat::OneRegister),
+    (Opcode::Jeq,    "jeq",   39, OperandFormat::OneRegister),
+    (Opcode::Jne,    "jne",   40, OperandFormat::OneRegister),
+    (Opcode::Jgt,    "jgt",   41, OperandFormat::OneRegister),
+    (Opcode::Jle,    "jle",   42, OperandFormat::OneRegister),
+    (Opcode::Jgte,   "jgte",  43, OperandFormat::OneRegister),
+    (Opcode::Jlte,   "jlte",  44, OperandFormat::OneRegister),
+    (Opcode::Jzro,   "jzro",  45, OperandFormat::TwoRegisters),
+    (Opcode::Jnzro,  "jnzro", 46, OperandFormat::TwoRegisters),
+    (Opcode::Jovf,   "jovf",  47, OperandFormat::OneRegister),
+    (Opcode::Jcry,   "jcry",  48, OperandFormat::OneRegister),
+    (Opcode::Scry,   "scry",  49, OperandFormat::NoOperands),
+    (Opcode::Ccry,   "ccry",  50, OperandFormat::NoOperands),
+    (Opcode::Eitr,   "eitr",  51, OperandFormat::NoOperands),
+    (Opcode::Ditr,   "ditr",  52, OperandFormat::NoOperands),
+    (Opcode::Intr,   "intr",  53, OperandFormat::RegisterShortImmediate),
+    (Opcode::Into,   "into",  54, OperandFormat::RegisterShortImmediate),
+    (Opcode::Iret,   "iret",  55, OperandFormat::NoOperands),
+    (Opcode::Load,   "load",  56, OperandFormat::TwoRegisters),
+    (Opcode::Store,  "store", 57, OperandFormat::TwoRegisters),
+    (Opcode::Halt,   "halt",  63, OperandFormat::NoOperands)
+];
+
+/**
+ * The mnemonics of other assemblers which are accepted in place of the Sim6 mnemonic for the same operation, so
+ * code ported from them assembles unchanged. Like the mnemonics themselves, they cannot be used as labels.
+ */
+pub const MNEMONIC_ALIASES:&[(&str, &str)] = &[
+    ("mov", "move"), ("jmp", "jump"), ("shl", "sll"), ("shr", "srl"), ("sar", "sra"), ("je", "jeq"), ("jz", "jeq"),
+    ("jnz", "jne"), ("jge", "jgte"), ("jo", "jovf"), ("jc", "jcry"), ("stc", "scry"), ("clc", "ccry"), ("hlt", "halt")
+];
+
+
+/**
+ * Splits a mnemonic such as `add.nf` into its name and the suffix after the '.', if it has one. A name starting
+ * with a '.', such as that of a directive, has no suffix.
+ */
+pub fn split_mnemonic_suffix(name:&str) -> (&str, Option<&str>) {
+    match name.split_once('.') {
+        Some((mnemonic, suffix)) if !mnemonic.is_empty() => (mnemonic, Some(suffix)),
+        _ => (name, None)
+    }
+}
+
+
+/**
+ * Returns the lowercase mnemonic the given name stands for, which is the name itself unless it is one of the
+ * `MNEMONIC_ALIASES`. A suffix such as the `.nf` of `shl.nf` is kept after the mnemonic the alias stands for.
+ */
+pub fn resolve_mnemonic(name:&str) -> String {
+    let name = name.to_lowercase();
+    let (name, suffix) = split_mnemonic_suffix(&name);
+    let mnemonic = match MNEMONIC_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        Some((_, mnemonic)) => mnemonic.to_string(),
+        None => name.to_string()
+    };
+
+    match suffix {
+        Some(suffix) => format!("{}.{}", mnemonic, suffix),
+        None => mnemonic
+    }
+}
+
+
+/**
+ * Returns true if the given name is a mnemonic or an alias of one, ignoring case and any suffix.
+ */
+pub fn is_mnemonic(name:&str) -> bool {
+    Opcode::from_mnemonic(split_mnemonic_suffix(name).0).is_some()
+}
+
+
+/**
+ * Returns true if the given name is the mnemonic of a jump or call, or an alias of one, ignoring case and any
+ * suffix.
+ */
+pub fn is_jump_mnemonic(name:&str) -> bool {
+    is_mnemonic(name) && Opcode::from(&split_mnemonic_suffix(&resolve_mnemonic(name)).0.to_string()).is_jump()
+}
+
 impl Into<u16> for Opcode {
     /**
      * Converts an opcode to its 6-bit integer representation
      */
     fn into(self) -> u16 {
-        match self {
-            Opcode::Nop    => 0,
-            Opcode::Add    => 1,
-            Opcode::Addu   => 2,
-            Opcode::Addc   => 3,
-            Opcode::Inc    => 4,
-            Opcode::Sub    => 5,
-            Opcode::Subu   => 6,
-            Opcode::Subb   => 7,
-            Opcode::Dec    => 8,
-            Opcode::Cmp    => 9,
-            Opcode::Neg    => 10,
-            Opcode::Move   => 11,
-            Opcode::Push   => 12,
-            Opcode::Pop    => 13,
-            Opcode::PushA  => 14,
-            Opcode::PopA   => 15,
-            Opcode::PushF  => 16,
-            Opcode::PopF   => 17,
-            Opcode::Swap   => 18,
-            Opcode::In     => 19,
-            Opcode::Out    => 20,
-            Opcode::Lda    => 21,
-            Opcode::MovI   => 22,
-            Opcode::Mul    => 23,
-            Opcode::Mulu   => 24,
-            Opcode::Div    => 25,
-            Opcode::Divu   => 26,
-            Opcode::Csign  => 27,
-            Opcode::Not    => 28,
-            Opcode::And    => 29,
-            Opcode::Or     => 30,
-            Opcode::Xor    => 31,
-            Opcode::Sra    => 32,
-            Opcode::Srl    => 33,
-            Opcode::Sll    => 34,
-            Opcode::Clear  => 35,
-            Opcode::Call   => 36,
-            Opcode::Ret    => 37,
-            Opcode::Jump   => 38,
-            Opcode::Jeq    => 39,
-            Opcode::Jne    => 40,
-            Opcode::Jgt    => 41,
-            Opcode::Jle    => 42,
-            Opcode::Jgte   => 43,
-            Opcode::Jlte   => 44,
-            Opcode::Jzro   => 45,
-            Opcode::Jnzro  => 46,
-            Opcode::Jovf   => 47,
-            Opcode::Jcry   => 48,
-            Opcode::Scry   => 49,
-            Opcode::Ccry   => 50,
-            Opcode::Eitr   => 51,
-            Opcode::Ditr   => 52,
-            Opcode::Intr   => 53,
-            Opcode::Into   => 54,
-            Opcode::Iret   => 55,
-            Opcode::Load   => 56,
-            Opcode::Store  => 57,
-            Opcode::Halt   => 63
-        } 
+        self.entry().2
+    }
+}
+
+impl TryFrom<u16> for Opcode {
+    type Error = u16;
+
+    /**
+     * Converts a 6-bit integer back into the opcode it represents, returning the integer as the error if
+     * no opcode uses it.
+     */
+    fn try_from(code:u16) -> Result<Opcode, u16> {
+        match OPCODES.iter().find(|(_, _, opcode_code, _)| *opcode_code == code) {
+            Some((opcode, _, _, _)) => Ok(opcode.clone()),
+            None => Err(code)
+        }
     }
 }
 
 impl From<&String> for Opcode {
     /**
-     * Translates a string to the opcode it represents, is case-insensitive, panics if
-     * it finds an invalid opcode.
+     * Translates a string to the opcode it represents, is case-insensitive and accepts the
+     * `MNEMONIC_ALIASES`, panics if it finds an invalid opcode.
      */
     fn from(code:&String) -> Opcode {
-        match code.to_lowercase().as_str() {
-            "nop"   => Opcode::Nop,
-            "add"   => Opcode::Add,
-            "addu"  => Opcode::Addu,
-            "addc"  => Opcode::Addc,
-            "inc"   => Opcode::Inc,
-            "sub"   => Opcode::Sub,
-            "subu"  => Opcode::Subu,
-            "subb"  => Opcode::Subb,
-            "dec"   => Opcode::Dec,
-            "cmp"   => Opcode::Cmp,
-            "neg"   => Opcode::Neg,
-            "move"  => Opcode::Move,
-            "push"  => Opcode::Push,
-            "pop"   => Opcode::Pop,
-            "pusha" => Opcode::PushA,
-            "popa"  => Opcode::PopA,
-            "pushf" => Opcode::PushF,
-            "popf"  => Opcode::PopF,
-            "swap"  => Opcode::Swap,
-            "in"    => Opcode::In,
-            "out"   => Opcode::Out,
-            "lda"   => Opcode::Lda,
-            "movi"  => Opcode::MovI,
-            "mul"   => Opcode::Mul,
-            "mulu"  => Opcode::Mulu,
-            "div"   => Opcode::Div,
-            "divu"  => Opcode::Divu,
-            "csign" => Opcode::Csign,
-            "not"   => Opcode::Not,
-            "and"   => Opcode::And,
-            "or"    => Opcode::Or,
-            "xor"   => Opcode::Xor,
-            "sra"   => Opcode::Sra,
-            "srl"   => Opcode::Srl,
-            "sll"   => Opcode::Sll,
-            "clear" => Opcode::Clear,
-            "call"  => Opcode::Call,
-            "ret"   => Opcode::Ret,
-            "jump"  => Opcode::Jump,
-            "jeq"   => Opcode::Jeq,
-            "jne"   => Opcode::Jne,
-            "jgt"   => Opcode::Jgt,
-            "jle"   => Opcode::Jle,
-            "jgte"  => Opcode::Jgte,
-            "jlte"  => Opcode::Jlte,
-            "jzro"  => Opcode::Jzro,
-            "jnzro" => Opcode::Jnzro,
-            "jovf"  => Opcode::Jovf,
-            "jcry"  => Opcode::Jcry,
-            "scry"  => Opcode::Scry,
-            "ccry"  => Opcode::Ccry,
-            "eitr"  => Opcode::Eitr,
-            "ditr"  => Opcode::Ditr,
-            "intr"  => Opcode::Intr,
-            "into"  => Opcode::Into,
-            "iret"  => Opcode::Iret,
-            "load"  => Opcode::Load,
-            "store" => Opcode::Store,
-            "halt"  => Opcode::Halt,  
-            _ => panic!("Invalid opcode found")
-        }
+        Opcode::from_mnemonic(code).unwrap_or_else(|| panic!("Invalid opcode found"))
+    }
+}
+
+impl fmt::Display for Opcode {
+    /**
+     * Writes the opcode as its lowercase mnemonic.
+     */
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.entry().1)
     }
 }
 
 
 impl Opcode {
+    /**
+     * Returns the entry of the opcode in `OPCODES`.
+     */
+    fn entry(&self) -> &'static (Opcode, &'static str, u16, OperandFormat) {
+        OPCODES.iter().find(|(opcode, _, _, _)| opcode == self).expect("Every opcode is in OPCODES")
+    }
+
+
+    /**
+     * Returns the opcode of the given mnemonic, ignoring case and accepting the `MNEMONIC_ALIASES`, or `None`
+     * if it is not a mnemonic.
+     */
+    pub fn from_mnemonic(name:&str) -> Option<Opcode> {
+        let name = resolve_mnemonic(name);
+        OPCODES.iter().find(|(_, mnemonic, _, _)| *mnemonic == name).map(|(opcode, _, _, _)| opcode.clone())
+    }
+
+
     pub fn is_signed(&self) -> bool {
         match self {
             Opcode::Add | Opcode::Dec | Opcode::Inc | Opcode::Div | Opcode::Mul => true,
@@ -223,4 +308,58 @@ impl Opcode {
             _ => false
         }
     }
+
+
+    /**
+     * Returns the operand format of the opcode, which decides how its operands are parsed, validated, encoded
+     * and decoded.
+     */
+    pub fn format(&self) -> OperandFormat {
+        self.entry().3
+    }
+
+
+    /**
+     * Returns true if the opcode is a jump or a call, which takes the address it transfers control to from the
+     * register in its 1st operand.
+     */
+    pub fn is_jump(&self) -> bool {
+        match self {
+            Opcode::Call | Opcode::Jump | Opcode::Jeq | Opcode::Jne | Opcode::Jgt | Opcode::Jle | Opcode::Jgte
+             | Opcode::Jlte | Opcode::Jzro | Opcode::Jnzro | Opcode::Jovf | Opcode::Jcry => true,
+            _ => false
+        }
+    }
+
+
+    /**
+     * Returns true if the programmer may choose whether the opcode updates the flags, with an `.f` or `.nf`
+     * suffix. This is every opcode which updates them by default apart from `cmp`, which does nothing else.
+     */
+    pub fn flags_optional(&self) -> bool {
+        self.set_flags() && *self != Opcode::Cmp
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_opcode_table() {
+        for (opcode, mnemonic, code, format) in OPCODES {
+            assert_eq!(Opcode::from_mnemonic(mnemonic), Some(opcode.clone()));
+            assert_eq!(Opcode::try_from(*code), Ok(opcode.clone()));
+            assert_eq!(Into::<u16>::into(opcode.clone()), *code);
+            assert_eq!(opcode.to_string(), *mnemonic);
+            assert_eq!(opcode.format(), *format);
+        }
+
+        assert_eq!(Opcode::from_mnemonic("JZ"), Some(Opcode::Jeq));
+        assert_eq!(Opcode::from_mnemonic("foo"), None);
+        assert_eq!(Opcode::try_from(58), Err(58));
+    }
 }
@@ -14,6 +14,13 @@ pub enum Register {
     Pc  // program counter
 }
 
+/**
+ * The name of every register, which are reserved and cannot be used as labels.
+ */
+pub const REGISTER_NAMES:&[&str] = &[
+    "none", "ax", "al", "ah", "bx", "bl", "bh", "cx", "cl", "ch", "dx", "dl", "dh", "rp", "fp", "bp", "sp", "st", "pc"
+];
+
 impl Into<u16> for Register {
     fn into(self) -> u16 {
         match self {
@@ -61,31 +68,68 @@ impl Into<String> for Register {
 
 impl From<&String> for Register {
     fn from(reg:&String) -> Register {
-        match reg.to_lowercase().as_str() {
-            "none" => Register::None,
-            "ax" => Register::Ax,
-            "ah" => Register::Ah,
-            "al" => Register::Al,
-            "bx" => Register::Bx,
-            "bh" => Register::Bh,
-            "bl" => Register::Bl,
-            "cx" => Register::Cx,
-            "ch" => Register::Ch,
-            "cl" => Register::Cl,
-            "dx" => Register::Dx,
-            "dh" => Register::Dh,
-            "dl" => Register::Dl,
-            "rp" => Register::Rp,
-            "fp" => Register::Fp,
-            "bp" => Register::Bp,
-            "sp" => Register::Sp,
-            "pc" => Register::Pc,
-            _ => panic!("Invalid register {} found", reg)
-        }
+        Register::from_name(reg).unwrap_or_else(|| panic!("Invalid register {} found", reg))
     }
 }
 
 impl Register {
+    /**
+     * Returns the register with the given name, ignoring case, or `None` if it does not name a register.
+     */
+    pub fn from_name(name:&str) -> Option<Register> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(Register::None),
+            "ax" => Some(Register::Ax),
+            "ah" => Some(Register::Ah),
+            "al" => Some(Register::Al),
+            "bx" => Some(Register::Bx),
+            "bh" => Some(Register::Bh),
+            "bl" => Some(Register::Bl),
+            "cx" => Some(Register::Cx),
+            "ch" => Some(Register::Ch),
+            "cl" => Some(Register::Cl),
+            "dx" => Some(Register::Dx),
+            "dh" => Some(Register::Dh),
+            "dl" => Some(Register::Dl),
+            "rp" => Some(Register::Rp),
+            "fp" => Some(Register::Fp),
+            "bp" => Some(Register::Bp),
+            "sp" => Some(Register::Sp),
+            "st" => Some(Register::St),
+            "pc" => Some(Register::Pc),
+            _ => None
+        }
+    }
+
+
+    /**
+     * Takes a 3-bit register code and the high and low bits of an instruction and returns the register they
+     * represent, or `None` if the combination does not describe a register.
+     */
+    pub fn from_code(code:u16, high:bool, low:bool) -> Option<Register> {
+        match (code, high, low) {
+            (0, false, false) => Some(Register::None),
+            (0, true, true) => Some(Register::Ax),
+            (0, true, false) => Some(Register::Ah),
+            (0, false, true) => Some(Register::Al),
+            (1, true, true) => Some(Register::Bx),
+            (1, true, false) => Some(Register::Bh),
+            (1, false, true) => Some(Register::Bl),
+            (2, true, true) => Some(Register::Cx),
+            (2, true, false) => Some(Register::Ch),
+            (2, false, true) => Some(Register::Cl),
+            (3, true, true) => Some(Register::Dx),
+            (3, true, false) => Some(Register::Dh),
+            (3, false, true) => Some(Register::Dl),
+            (4, true, true) => Some(Register::Rp),
+            (5, true, true) => Some(Register::Fp),
+            (6, true, true) => Some(Register::Bp),
+            (7, true, true) => Some(Register::Sp),
+            _ => None
+        }
+    }
+
+
     /**
      * Returns true if the register requires the high bit of the instruction to be set.
      */
@@ -0,0 +1,350 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::error::AssemblerError;
+use crate::expr::ExprError;
+use crate::repr::opcode::is_mnemonic;
+
+
+/**
+ * A line of source as seen by the assembler passes, along with the number of the line in the source file it
+ * came from, the path of that file if it was included rather than being the input file, and, if it was
+ * produced by expanding macros, the name of each macro from outermost to innermost.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub text: String,
+    pub line_num: usize,
+    pub file: Option<PathBuf>,
+    pub expansion: Vec<String>
+}
+
+impl SourceLine {
+    pub fn new(text:&str, line_num:usize) -> SourceLine {
+        SourceLine {
+            text: text.to_string(),
+            line_num: line_num,
+            file: None,
+            expansion: vec![]
+        }
+    }
+
+
+    /**
+     * Creates an error reported against this line, naming the file it came from if it was included.
+     */
+    pub fn error(&self, err:Box<dyn Error>) -> AssemblerError {
+        match &self.file {
+            Some(path) => AssemblerError::IncludedSourceError(path.to_owned(), self.line_num, err),
+            None => AssemblerError::SourceError(self.line_num, err)
+        }
+    }
+}
+
+
+/**
+ * Calls `replace` with each name in the text outside of backtick and double quoted strings and character
+ * literals, along with the character before it and the text after it, and replaces the name with the text it
+ * returns, if any. Names directly after a `.` are directives and are left as they are, and `$`, the current
+ * address, is a name. A quote character after a backslash does not end a string.
+ */
+pub fn replace_names(text:&str, replace:impl Fn(&str, char, &str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut quote:Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let starts_name = c == '$' || ((c.is_ascii_alphabetic() || c == '_') && !(previous.is_ascii_alphanumeric() || previous == '_'));
+        if quote.is_some() || !starts_name || previous == '.' {
+            quote = match (quote, c) {
+                (Some(open), _) if escaped => Some(open),
+                (None, '`') | (None, '"') | (None, '\'') => Some(c),
+                (Some(open), c) if c == open => None,
+                (quote, _) => quote
+            };
+            escaped = quote.is_some() && !escaped && c == '\\';
+            result.push(c);
+            previous = c;
+            continue;
+        }
+
+        let mut end = index + c.len_utf8();
+        while let Some((next_index, next)) = chars.peek() {
+            if c == '$' || !(next.is_ascii_alphanumeric() || *next == '_') {
+                break;
+            }
+            end = next_index + next.len_utf8();
+            chars.next();
+        }
+
+        let name = &text[index..end];
+        match replace(name, previous, &text[end..]) {
+            Some(replacement) => result.push_str(&replacement),
+            None => result.push_str(name)
+        }
+        previous = name.chars().last().unwrap();
+    }
+
+    result
+}
+
+
+/**
+ * Returns the index of the ':' ending the label defined on a line, if any. A ':' inside the string of a
+ * string directive such as `.asciiz`, between backticks or double quotes, does not end a label, and neither
+ * does one after the mnemonic of an instruction, such as that of the register pair in `add32 dx:ax, cx:bx`.
+ */
+pub fn find_label_end(line:&str) -> Option<usize> {
+    line.find(':').filter(|index| !line[..*index].contains(['`', '"']) && !line[..*index].trim().contains(char::is_whitespace))
+}
+
+
+/**
+ * Splits the text of a source file into lines numbered from 1. A line ending in a `\` is continued on the
+ * next line, which is joined to it in place of the `\`, so a long line such as an `.asciiz` of adjacent strings
+ * can be split across several. The joined line takes the number of its first line.
+ */
+pub fn to_source_lines(text:&str) -> Vec<SourceLine> {
+    let mut source_lines:Vec<SourceLine> = vec![];
+    let mut continued = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let (line, continues) = match line.trim_end().strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false)
+        };
+
+        match (continued, source_lines.last_mut()) {
+            (true, Some(source_line)) => source_line.text.push_str(line),
+            _ => source_lines.push(SourceLine::new(line, index + 1))
+        }
+        continued = continues;
+    }
+
+    source_lines
+}
+
+
+/**
+ * Rewrites the numbers in a line written in the notation of other 8 and 16 bit assemblers, `$FF` for hex and
+ * `%1010` for binary, as `0xFF` and `0b1010`, leaving strings and character literals as they are. A `$` not
+ * followed by a hex digit is still the current address, and a `%` after an operand is still the modulo
+ * operator, so `a %10` is a remainder while `.byte %10` and `, %10` are binary.
+ */
+fn convert_compat_number_text(text:&str) -> String {
+    let chars:Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut quote:Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    let mut word = String::new();
+
+    for (index, c) in chars.iter().copied().enumerate() {
+        let next = chars.get(index + 1).copied().unwrap_or(' ');
+
+        // a directive or mnemonic is followed by its operands rather than ending one
+        let keyword = word.starts_with('.') || is_mnemonic(&word);
+        let after_operand = (previous.is_ascii_alphanumeric() && !keyword) || matches!(previous, '_' | ')' | '\'' | '$');
+
+        match (quote, c) {
+            (None, '$') if next.is_ascii_hexdigit() => result.push_str("0x"),
+            (None, '%') if (next == '0' || next == '1') && !after_operand => result.push_str("0b"),
+            _ => result.push(c)
+        }
+
+        quote = match (quote, c) {
+            (Some(open), _) if escaped => Some(open),
+            (None, '`') | (None, '"') | (None, '\'') => Some(c),
+            (Some(open), c) if c == open => None,
+            (quote, _) => quote
+        };
+        escaped = quote.is_some() && !escaped && c == '\\';
+
+        let continues_word = index > 0 && (chars[index - 1].is_ascii_alphanumeric() || matches!(chars[index - 1], '_' | '.'));
+        match c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            true if continues_word => word.push(c),
+            true => word = c.to_string(),
+            false => {}
+        }
+
+        if !c.is_whitespace() {
+            previous = c;
+        }
+    }
+
+    result
+}
+
+
+/**
+ * Rewrites `$FF` and `%1010` style numbers in the line as `0xFF` and `0b1010`, so that code written for other
+ * assemblers can be ported with fewer edits.
+ */
+pub fn convert_compat_numbers(source_line:SourceLine) -> SourceLine {
+    SourceLine { text: convert_compat_number_text(&source_line.text), ..source_line }
+}
+
+
+/**
+ * Rewrites the mnemonic of the line if it uses one of the given aliases, such as `bra` for `jump` when given
+ * `--alias bra=jump`, as the mnemonic it stands for. The label and operands of the line are left as they are.
+ */
+pub fn apply_mnemonic_alias(source_line:SourceLine, aliases:&[(String, String)]) -> SourceLine {
+    let text = &source_line.text;
+    let label_end = find_label_end(text).map(|index| index + 1).unwrap_or(0);
+    let start = label_end + text[label_end..].len() - text[label_end..].trim_start().len();
+    let end = text[start..].find(|c:char| c.is_whitespace() || c == ',').map(|index| start + index).unwrap_or(text.len());
+
+    let name = text[start..end].to_lowercase();
+    match aliases.iter().find(|(alias, _)| *alias == name) {
+        Some((_, mnemonic)) => SourceLine { text: format!("{}{}{}", &text[..start], mnemonic, &text[end..]), ..source_line },
+        None => source_line
+    }
+}
+
+
+/**
+ * Adds the prefix for the given radix to every number in the line without one, leaving strings and character
+ * literals as they are. A number is a run of letters, digits and underscores which starts with a digit and is
+ * not part of a name, so hex numbers in radix 16 must start with a digit, as in `0FFh` style assemblers.
+ */
+fn convert_radix_text(text:&str, prefix:&str) -> String {
+    let mut result = String::new();
+    let mut quote:Option<char> = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let starts_number = c.is_ascii_digit() && !(previous.is_ascii_alphanumeric() || matches!(previous, '_' | '.' | '$'));
+        if quote.is_some() || !starts_number {
+            quote = match (quote, c) {
+                (Some(open), _) if escaped => Some(open),
+                (None, '`') | (None, '"') | (None, '\'') => Some(c),
+                (Some(open), c) if c == open => None,
+                (quote, _) => quote
+            };
+            escaped = quote.is_some() && !escaped && c == '\\';
+            result.push(c);
+            previous = c;
+            continue;
+        }
+
+        let mut end = index + 1;
+        while let Some((next_index, next)) = chars.peek() {
+            if !(next.is_ascii_alphanumeric() || *next == '_') {
+                break;
+            }
+            end = next_index + next.len_utf8();
+            chars.next();
+        }
+
+        let number = &text[index..end];
+        if !matches!(number.to_lowercase().get(..2), Some("0x") | Some("0o") | Some("0b")) {
+            result.push_str(prefix);
+        }
+        result.push_str(number);
+        previous = number.chars().last().unwrap();
+    }
+
+    result
+}
+
+
+/**
+ * Returns the part of the line after its label, or the whole line if it has no label.
+ */
+fn get_contents(source_line:&SourceLine) -> &str {
+    find_label_end(&source_line.text).map(|index| &source_line.text[index + 1..]).unwrap_or(&source_line.text)
+}
+
+
+/**
+ * Returns the prefix for the radix set by the line if it is a `.radix <2|8|10|16>` directive, which sets the
+ * radix of the numbers without a prefix on the lines after it until the next. Numbers are decimal before the
+ * first `.radix`.
+ */
+pub fn get_radix_prefix(source_line:&SourceLine) -> Result<Option<&'static str>, AssemblerError> {
+    let mut tokens = get_contents(source_line).split_whitespace();
+    if tokens.next() != Some(".radix") {
+        return Ok(None);
+    }
+
+    let radix = tokens.collect::<Vec<&str>>().join(" ");
+    match radix.as_str() {
+        "2" => Ok(Some("0b")),
+        "8" => Ok(Some("0o")),
+        "10" => Ok(Some("")),
+        "16" => Ok(Some("0x")),
+        _ => Err(source_line.error(Box::new(ExprError::InvalidRadixError(radix))))
+    }
+}
+
+
+/**
+ * Adds the prefix of the radix in effect to every number in the line without one. The decimal numbers of a
+ * `.fixed` or `.bcd` are left as they are, as is the radix of a `.radix` directive.
+ */
+pub fn apply_radix(source_line:SourceLine, prefix:&str) -> SourceLine {
+    match get_contents(&source_line).split_whitespace().next() {
+        Some(".fixed") | Some(".bcd") | Some(".radix") => source_line,
+        _ => SourceLine { text: convert_radix_text(&source_line.text, prefix), ..source_line }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::constants::ConstantTable;
+    use crate::preprocess::preprocess;
+    use super::*;
+
+
+    #[test]
+    fn test_compat_numbers() {
+        let source_lines = to_source_lines("movi ax, $FF00\n.byte %1010_1010\nmovi bx, ($ + 4) % 2\nmovi cx, (%11 | $1f)");
+        let texts:Vec<String> = source_lines.into_iter().map(|line| convert_compat_numbers(line).text).collect();
+        assert_eq!(texts, vec!["movi ax, 0xFF00", ".byte 0b1010_1010", "movi bx, ($ + 4) % 2", "movi cx, (0b11 | 0x1f)"]);
+
+        assert_eq!(convert_compat_number_text("rem: .word COUNT %10"), "rem: .word COUNT %10");
+        assert_eq!(convert_compat_number_text("msg: .asciiz `costs $5 or %10`"), "msg: .asciiz `costs $5 or %10`");
+        assert_eq!(convert_compat_number_text(".byte '$' + $01"), ".byte '$' + 0x01");
+        assert_eq!(convert_compat_number_text("MASK: .equ %1111 % 3"), "MASK: .equ 0b1111 % 3");
+        assert_eq!(convert_compat_number_text("push %1"), "push 0b1");
+    }
+
+
+    #[test]
+    fn test_mnemonic_aliases() {
+        let aliases = vec![(String::from("bra"), String::from("jump")), (String::from("ldr"), String::from("move"))];
+        let source_lines = to_source_lines("loop: BRA cx\n  ldr,ax bx\nbra: .word 1\nlabel:\n.ascii `bra`");
+        let texts:Vec<String> = source_lines.into_iter().map(|line| apply_mnemonic_alias(line, &aliases).text).collect();
+        assert_eq!(texts, vec!["loop: jump cx", "  move,ax bx", "bra: .word 1", "label:", ".ascii `bra`"]);
+    }
+
+
+    #[test]
+    fn test_line_continuation() {
+        let source = "msg: .asciiz \"Hello, \" \\\n    \"world\" \\  \n    `!`\nhalt\n.ascii `a\\\\`";
+        let source_lines = to_source_lines(source);
+        let lines:Vec<(&str, usize)> = source_lines.iter().map(|line| (line.text.as_str(), line.line_num)).collect();
+        assert_eq!(lines, vec![("msg: .asciiz \"Hello, \"     \"world\"     `!`", 1), ("halt", 4), (".ascii `a\\\\`", 5)]);
+    }
+
+
+    #[test]
+    fn test_radix() {
+        let source = ".data:\n.array 10 20\n.radix 16\nbuf2: .array 10 0FF 0b11 `10` '1'\n.radix 2\n.byte 1010_1010\n.radix 10\n.word 10\n.radix 16\nhalf: .fixed 3.25 10";
+        let texts:Vec<String> = preprocess(to_source_lines(source), Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::default()).unwrap().into_iter().map(|line| line.text).collect();
+        assert_eq!(texts, vec![".data:", ".array 10 20", "buf2: .array 0x10 0x0FF 0b11 `10` '1'", ".byte 0b1010_1010", ".word 10",
+            "half: .fixed 3.25 10"]);
+
+        let err = preprocess(to_source_lines(".radix 12"), Path::new("prog.asm"), &[], &[], false, &mut ConstantTable::default()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: '.radix' expects 2, 8, 10 or 16, found '12'");
+    }
+}
@@ -0,0 +1,515 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::messages::{message, MessageId};
+use crate::object::{write_name, ObjectFormatError, ObjectReader, ObjectWriteError};
+
+
+const SSE_MAGIC:&[u8] = b"SSE\0";
+const SSE_VERSION:u8 = 2;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SseFormatError {
+    MissingMagicError,
+    UnsupportedVersionError(u8),
+    TruncatedError,
+    InvalidSectionError(u8),
+    InvalidMetadataError(String),
+    ChecksumMismatchError(u32, u32),
+    UnknownLineFileError(usize)
+}
+
+impl Error for SseFormatError {}
+
+impl fmt::Display for SseFormatError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SseFormatError::MissingMagicError => write!(f, "{}", message(MessageId::MissingSseMagic, &[])),
+            SseFormatError::UnsupportedVersionError(version) => write!(f, "{}", message(MessageId::UnsupportedSseVersion, &[version])),
+            SseFormatError::TruncatedError => write!(f, "{}", message(MessageId::TruncatedSse, &[])),
+            SseFormatError::InvalidSectionError(code) => write!(f, "{}", message(MessageId::InvalidObjectSection, &[code])),
+            SseFormatError::InvalidMetadataError(entry) => write!(f, "{}", message(MessageId::InvalidMetadata, &[entry])),
+            SseFormatError::ChecksumMismatchError(stored, computed) => {
+                write!(f, "{}", message(MessageId::SseChecksumMismatch, &[&format!("0x{:08X}", stored), &format!("0x{:08X}", computed)]))
+            },
+            SseFormatError::UnknownLineFileError(index) => write!(f, "{}", message(MessageId::UnknownLineFile, &[index]))
+        }
+    }
+}
+
+
+/**
+ * The kind of a section in a .sse file. The bss section has a size but no contents, as it is zeroed when the
+ * program is loaded, and a named section is one started in the source with `.section`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SseSectionKind {
+    Data,
+    Code,
+    Bss,
+    Named(String)
+}
+
+impl SseSectionKind {
+    pub fn name(&self) -> &str {
+        match self {
+            SseSectionKind::Data => "data",
+            SseSectionKind::Code => "code",
+            SseSectionKind::Bss => "bss",
+            SseSectionKind::Named(name) => name
+        }
+    }
+}
+
+
+/**
+ * A section of a .sse file along with the address it is loaded at and the number of bytes it takes up in memory,
+ * which is the length of its contents for every section but the bss section.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseSection {
+    pub kind: SseSectionKind,
+    pub address: usize,
+    pub size: usize,
+    pub contents: Vec<u8>
+}
+
+impl SseSection {
+    pub fn new(kind:SseSectionKind, address:usize, contents:Vec<u8>) -> SseSection {
+        SseSection { kind: kind, address: address, size: contents.len(), contents: contents }
+    }
+}
+
+
+/**
+ * An entry of the debug line table, giving the file and line of source which produced the bytes starting at
+ * an address.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEntry {
+    pub address: usize,
+    pub file: String,
+    pub line: usize
+}
+
+
+/**
+ * The contents of a .sse output file: the address execution starts at, the sections to load, key/value
+ * metadata describing how the file was built, the debug line table, which is empty unless the program was
+ * assembled with debug information, and the address of each label kept in the symbol table.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseFile {
+    pub entry: usize,
+    pub sections: Vec<SseSection>,
+    pub metadata: Vec<(String, String)>,
+    pub lines: Vec<LineEntry>,
+    pub symbols: Vec<(String, usize)>
+}
+
+
+/**
+ * The section type codes used in the section table, where the metadata, the debug line table and the symbol
+ * table are stored as sections of their own.
+ */
+const SECTION_DATA:u8 = 0;
+const SECTION_CODE:u8 = 1;
+const SECTION_BSS:u8 = 2;
+const SECTION_NAMED:u8 = 3;
+const SECTION_METADATA:u8 = 4;
+const SECTION_LINES:u8 = 5;
+const SECTION_SYMBOLS:u8 = 6;
+
+
+/**
+ * The offset of the first byte covered by the checksum, which is the byte after the checksum itself.
+ */
+const CHECKSUMMED_START:usize = 9;
+
+
+/**
+ * Computes the CRC-32 (as used by zip and PNG) of the given bytes.
+ */
+pub fn crc32(bytes:&[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB88320,
+                _ => crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+
+/**
+ * Checks the magic number and format version at the start of a .sse file, returning a reader positioned
+ * at the checksum which follows them.
+ */
+fn read_header(bytes:&[u8]) -> Result<ObjectReader<'_>, SseFormatError> {
+    let mut reader = ObjectReader::new(bytes);
+    if reader.take(SSE_MAGIC.len()).map_err(|_| SseFormatError::MissingMagicError)? != SSE_MAGIC {
+        return Err(SseFormatError::MissingMagicError);
+    }
+
+    let version = reader.read_u8().map_err(truncated)?;
+    if version != SSE_VERSION {
+        return Err(SseFormatError::UnsupportedVersionError(version));
+    }
+
+    Ok(reader)
+}
+
+
+/**
+ * Recomputes the checksum of the bytes of a .sse file and compares it to the one stored in its header,
+ * returning the checksum if they match.
+ */
+pub fn verify(bytes:&[u8]) -> Result<u32, SseFormatError> {
+    let mut reader = read_header(bytes)?;
+    let stored = reader.read_u32().map_err(truncated)?;
+    let computed = crc32(&bytes[CHECKSUMMED_START..]);
+    match stored == computed {
+        true => Ok(stored),
+        false => Err(SseFormatError::ChecksumMismatchError(stored, computed))
+    }
+}
+
+
+/**
+ * Every read from a .sse file fails the same way when it runs off the end of the file.
+ */
+fn truncated(_:ObjectFormatError) -> SseFormatError {
+    SseFormatError::TruncatedError
+}
+
+
+impl SseFile {
+    /**
+     * Returns the section of the given kind, if the file has one.
+     */
+    pub fn section(&self, kind:&SseSectionKind) -> Option<&SseSection> {
+        self.sections.iter().find(|section| section.kind == *kind)
+    }
+
+
+    /**
+     * Returns the contents of the code section, which is empty if the file has none.
+     */
+    pub fn code(&self) -> &[u8] {
+        self.section(&SseSectionKind::Code).map(|section| section.contents.as_slice()).unwrap_or(&[])
+    }
+
+
+    /**
+     * Serialises the file. After the magic number and format version come a CRC-32 of the rest of the file,
+     * the 16-bit entry address and an 8-bit count of the entries in the section table. Each entry gives the
+     * section's type, its 16-bit load address, its 32-bit size and the 32-bit file offset of its contents,
+     * followed by its name if it is a named section. The contents of the sections follow the table, with each
+     * metadata entry written as `key=value` followed by a null byte. The debug line table starts with a 16-bit
+     * count of source files and their names, followed by the 16-bit address, 16-bit file index and 32-bit line
+     * number of each entry, and the symbol table holds the 16-bit address and name of each symbol. All integers
+     * are big-endian and names are prefixed by an 8-bit length.
+     */
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectWriteError> {
+        let mut metadata_bytes = vec![];
+        for (key, value) in &self.metadata {
+            metadata_bytes.extend_from_slice(format!("{}={}", key, value).as_bytes());
+            metadata_bytes.push(0x00);
+        }
+
+        let mut entries:Vec<(u8, &str, usize, usize, &[u8])> = self.sections.iter().map(|section| {
+            let section_type = match &section.kind {
+                SseSectionKind::Data => SECTION_DATA,
+                SseSectionKind::Code => SECTION_CODE,
+                SseSectionKind::Bss => SECTION_BSS,
+                SseSectionKind::Named(_) => SECTION_NAMED
+            };
+
+            let name = match &section.kind {
+                SseSectionKind::Named(name) => name.as_str(),
+                _ => ""
+            };
+
+            (section_type, name, section.address, section.size, section.contents.as_slice())
+        }).collect();
+
+        if !metadata_bytes.is_empty() {
+            entries.push((SECTION_METADATA, "", 0, metadata_bytes.len(), &metadata_bytes));
+        }
+
+        let line_bytes = lines_to_bytes(&self.lines)?;
+        if !self.lines.is_empty() {
+            entries.push((SECTION_LINES, "", 0, line_bytes.len(), &line_bytes));
+        }
+
+        let mut symbol_bytes = vec![];
+        for (name, address) in &self.symbols {
+            symbol_bytes.extend_from_slice(&(*address as u16).to_be_bytes());
+            write_name(&mut symbol_bytes, name)?;
+        }
+
+        if !symbol_bytes.is_empty() {
+            entries.push((SECTION_SYMBOLS, "", 0, symbol_bytes.len(), &symbol_bytes));
+        }
+
+        let mut bytes = SSE_MAGIC.to_vec();
+        bytes.push(SSE_VERSION);
+        bytes.extend_from_slice(&[0x00; 4]);
+        bytes.extend_from_slice(&(self.entry as u16).to_be_bytes());
+        bytes.push(entries.len() as u8);
+
+        let table_length:usize = entries.iter().map(|(_, name, _, _, _)| 12 + name.len()).sum();
+        let mut offset = bytes.len() + table_length;
+        for (section_type, name, address, size, contents) in &entries {
+            bytes.push(*section_type);
+            bytes.extend_from_slice(&(*address as u16).to_be_bytes());
+            bytes.extend_from_slice(&(*size as u32).to_be_bytes());
+            bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+            write_name(&mut bytes, name)?;
+            offset += contents.len();
+        }
+
+        for (_, _, _, _, contents) in &entries {
+            bytes.extend_from_slice(contents);
+        }
+
+        let checksum = crc32(&bytes[CHECKSUMMED_START..]);
+        bytes[CHECKSUMMED_START - 4..CHECKSUMMED_START].copy_from_slice(&checksum.to_be_bytes());
+        Ok(bytes)
+    }
+
+
+    /**
+     * Parses the bytes of a .sse file. The checksum is not checked, so that a damaged file can still be inspected;
+     * `verify` checks it.
+     */
+    pub fn parse(bytes:&[u8]) -> Result<SseFile, SseFormatError> {
+        let mut reader = read_header(bytes)?;
+        reader.read_u32().map_err(truncated)?;
+
+        let mut file = SseFile { entry: reader.read_u16().map_err(truncated)? as usize, ..SseFile::default() };
+        for _ in 0..reader.read_u8().map_err(truncated)? {
+            let section_type = reader.read_u8().map_err(truncated)?;
+            let address = reader.read_u16().map_err(truncated)? as usize;
+            let size = reader.read_u32().map_err(truncated)? as usize;
+            let offset = reader.read_u32().map_err(truncated)? as usize;
+            let name = reader.read_name().map_err(truncated)?;
+
+            let stored = match section_type {
+                SECTION_BSS => 0,
+                _ => size
+            };
+            let contents = bytes.get(offset..offset + stored).ok_or(SseFormatError::TruncatedError)?.to_vec();
+
+            let kind = match section_type {
+                SECTION_DATA => SseSectionKind::Data,
+                SECTION_CODE => SseSectionKind::Code,
+                SECTION_BSS => SseSectionKind::Bss,
+                SECTION_NAMED => SseSectionKind::Named(name),
+                SECTION_METADATA => {
+                    file.metadata = parse_metadata(&contents)?;
+                    continue;
+                },
+                SECTION_LINES => {
+                    file.lines = parse_lines(&contents)?;
+                    continue;
+                },
+                SECTION_SYMBOLS => {
+                    file.symbols = parse_symbols(&contents)?;
+                    continue;
+                },
+                _ => return Err(SseFormatError::InvalidSectionError(section_type))
+            };
+
+            file.sections.push(SseSection { kind: kind, address: address, size: size, contents: contents });
+        }
+
+        Ok(file)
+    }
+}
+
+
+/**
+ * Parses the contents of the metadata section, a series of `key=value` entries each followed by a null byte.
+ */
+fn parse_metadata(bytes:&[u8]) -> Result<Vec<(String, String)>, SseFormatError> {
+    let mut metadata = vec![];
+    for entry in bytes.split(|byte| *byte == 0x00).filter(|entry| !entry.is_empty()) {
+        let entry = String::from_utf8_lossy(entry).to_string();
+        match entry.split_once('=') {
+            Some((key, value)) => metadata.push((key.to_string(), value.to_string())),
+            None => return Err(SseFormatError::InvalidMetadataError(entry))
+        }
+    }
+
+    Ok(metadata)
+}
+
+
+/**
+ * Serialises the debug line table, writing the name of each source file once and referring to it by index.
+ */
+fn lines_to_bytes(lines:&[LineEntry]) -> Result<Vec<u8>, ObjectWriteError> {
+    let mut files:Vec<&str> = vec![];
+    for entry in lines {
+        if !files.contains(&entry.file.as_str()) {
+            files.push(&entry.file);
+        }
+    }
+
+    let mut bytes = (files.len() as u16).to_be_bytes().to_vec();
+    for file in &files {
+        write_name(&mut bytes, file)?;
+    }
+
+    for entry in lines {
+        let file_index = files.iter().position(|file| *file == entry.file).unwrap_or(0);
+        bytes.extend_from_slice(&(entry.address as u16).to_be_bytes());
+        bytes.extend_from_slice(&(file_index as u16).to_be_bytes());
+        bytes.extend_from_slice(&(entry.line as u32).to_be_bytes());
+    }
+
+    Ok(bytes)
+}
+
+
+/**
+ * Parses the contents of the debug line table section.
+ */
+fn parse_lines(bytes:&[u8]) -> Result<Vec<LineEntry>, SseFormatError> {
+    let mut reader = ObjectReader::new(bytes);
+    let mut files = vec![];
+    for _ in 0..reader.read_u16().map_err(truncated)? {
+        files.push(reader.read_name().map_err(truncated)?);
+    }
+
+    let mut lines = vec![];
+    while !reader.is_at_end() {
+        let address = reader.read_u16().map_err(truncated)? as usize;
+        let file_index = reader.read_u16().map_err(truncated)? as usize;
+        let line = reader.read_u32().map_err(truncated)? as usize;
+        let file = files.get(file_index).ok_or(SseFormatError::UnknownLineFileError(file_index))?.to_owned();
+        lines.push(LineEntry { address: address, file: file, line: line });
+    }
+
+    Ok(lines)
+}
+
+
+/**
+ * Parses the contents of the symbol table section.
+ */
+fn parse_symbols(bytes:&[u8]) -> Result<Vec<(String, usize)>, SseFormatError> {
+    let mut reader = ObjectReader::new(bytes);
+    let mut symbols = vec![];
+    while !reader.is_at_end() {
+        let address = reader.read_u16().map_err(truncated)? as usize;
+        symbols.push((reader.read_name().map_err(truncated)?, address));
+    }
+
+    Ok(symbols)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_to_bytes() {
+        let file = SseFile {
+            entry: 0x5800,
+            sections: vec![SseSection::new(SseSectionKind::Data, 0x9000, vec![0x55]), SseSection::new(SseSectionKind::Code, 0x5800, vec![0x07, 0xC1])],
+            metadata: vec![(String::from("march"), String::from("sim6"))],
+            lines: vec![],
+            symbols: vec![]
+        };
+
+        let mut expected = b"SSE\0\x02\xEF\x05\xAC\x52\x58\x00\x03".to_vec();
+        expected.extend_from_slice(b"\x00\x90\x00\x00\x00\x00\x01\x00\x00\x00\x30\x00");
+        expected.extend_from_slice(b"\x01\x58\x00\x00\x00\x00\x02\x00\x00\x00\x31\x00");
+        expected.extend_from_slice(b"\x04\x00\x00\x00\x00\x00\x0B\x00\x00\x00\x33\x00");
+        expected.extend_from_slice(b"\x55\x07\xC1march=sim6\x00");
+        assert_eq!(file.to_bytes().unwrap(), expected);
+        assert_eq!(SseFile::default().to_bytes().unwrap(), b"SSE\0\x02\xFF\x41\xD9\x12\x00\x00\x00".to_vec());
+
+        let long_name = "a".repeat(256);
+        let file = SseFile { symbols: vec![(long_name.to_owned(), 0x5800)], ..SseFile::default() };
+        assert_eq!(file.to_bytes(), Err(ObjectWriteError::NameTooLongError(long_name)));
+    }
+
+
+    #[test]
+    fn test_parse() {
+        let file = SseFile {
+            entry: 0x5804,
+            sections: vec![
+                SseSection::new(SseSectionKind::Data, 0x9000, b".code:".to_vec()),
+                SseSection::new(SseSectionKind::Code, 0x5800, vec![0x07, 0xC1, 0x5B, 0x38, 0x02, 0xBC]),
+                SseSection { kind: SseSectionKind::Bss, address: 0x9006, size: 0x100, contents: vec![] },
+                SseSection::new(SseSectionKind::Named(String::from("fast_ram")), 0x0100, vec![0xAA])
+            ],
+            metadata: vec![(String::from("march"), String::from("sim6")), (String::from("isa_revision"), String::from("1"))],
+            lines: vec![
+                LineEntry { address: 0x5800, file: String::from("prog.asm"), line: 3 },
+                LineEntry { address: 0x5802, file: String::from("lib/util.asm"), line: 12 },
+                LineEntry { address: 0x5804, file: String::from("prog.asm"), line: 4 }
+            ],
+            symbols: vec![(String::from("start"), 0x5800), (String::from("table"), 0x0100)]
+        };
+
+        let parsed = SseFile::parse(&file.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, file);
+        assert_eq!(parsed.code(), &[0x07, 0xC1, 0x5B, 0x38, 0x02, 0xBC]);
+        assert_eq!(SseFile::parse(&SseFile::default().to_bytes().unwrap()).unwrap(), SseFile::default());
+    }
+
+
+    #[test]
+    fn test_invalid_files() {
+        assert_eq!(SseFile::parse(b".data:\x55"), Err(SseFormatError::MissingMagicError));
+        assert_eq!(SseFile::parse(b"SSE\0\x01\x58\x00\x00"), Err(SseFormatError::UnsupportedVersionError(1)));
+        assert_eq!(SseFile::parse(b"SSE\0\x02\x00\x00"), Err(SseFormatError::TruncatedError));
+        assert_eq!(SseFile::parse(b"SSE\0\x02\x00\x00\x00\x00\x58\x00\x01\x00\x90\x00"), Err(SseFormatError::TruncatedError));
+        assert_eq!(SseFile::parse(b"SSE\0\x02\x00\x00\x00\x00\x58\x00\x01\x00\x90\x00\x00\x00\x00\x04\x00\x00\x00\x18\x00\x55"), Err(SseFormatError::TruncatedError));
+        assert_eq!(SseFile::parse(b"SSE\0\x02\x00\x00\x00\x00\x58\x00\x01\x05\x00\x00\x00\x00\x00\x0A\x00\x00\x00\x18\x00\x00\x00\x58\x00\x00\x01\x00\x00\x00\x03"),
+                   Err(SseFormatError::UnknownLineFileError(1)));
+        assert_eq!(SseFile::parse(b"SSE\0\x02\x00\x00\x00\x00\x58\x00\x01\x09\x90\x00\x00\x00\x00\x00\x00\x00\x00\x18\x00"), Err(SseFormatError::InvalidSectionError(9)));
+    }
+
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0x00000000);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+
+    #[test]
+    fn test_verify() {
+        let mut bytes = SseFile {
+            entry: 0x5800,
+            sections: vec![SseSection::new(SseSectionKind::Code, 0x5800, vec![0x07, 0xC1])],
+            metadata: vec![],
+            lines: vec![],
+            symbols: vec![]
+        }.to_bytes().unwrap();
+
+        assert_eq!(verify(&bytes), Ok(crc32(&bytes[9..])));
+
+        let checksum = crc32(&bytes[9..]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert_eq!(verify(&bytes), Err(SseFormatError::ChecksumMismatchError(checksum, crc32(&bytes[9..]))));
+        assert_eq!(verify(b"SSE\0\x02\x00"), Err(SseFormatError::TruncatedError));
+        assert_eq!(verify(b".data:"), Err(SseFormatError::MissingMagicError));
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use crate::label_table::LabelDefinition;
+use crate::messages::{message, MessageId};
+use crate::object::Relocation;
+
+
+/**
+ * Logs how symbols are defined and resolved while a file is assembled, enabled by `--trace-symbols`. Every
+ * entry is written to stderr so that it does not mix with anything the assembler prints to stdout.
+ */
+pub struct SymbolTrace {
+    definitions: HashMap<String, LabelDefinition>
+}
+
+impl SymbolTrace {
+    pub fn new(definitions:HashMap<String, LabelDefinition>) -> SymbolTrace {
+        SymbolTrace { definitions: definitions }
+    }
+
+
+    /**
+     * Logs every label found by the first pass in the order they appear in the source.
+     */
+    pub fn log_definitions(&self) {
+        let mut definitions:Vec<(&String, &LabelDefinition)> = self.definitions.iter().collect();
+        definitions.sort_by_key(|(_, definition)| definition.line_num);
+
+        for (label, definition) in definitions {
+            let section = format!("{:?}", definition.section).to_lowercase();
+            let address = format!("0x{:04X}", definition.address);
+            eprintln!("trace: {}", message(MessageId::TraceDefinition, &[&definition.line_num, label, &address, &section]));
+        }
+    }
+
+
+    /**
+     * Logs a reference to `label` on the given line which the second pass resolved to `value`, along with the
+     * relocation recorded for it if the label was left for the linker to fill in.
+     */
+    pub fn log_reference(&self, line_num:usize, label:&str, value:usize, relocation:Option<&Relocation>) {
+        let defined_on = match self.definitions.get(label) {
+            Some(definition) => definition.line_num.to_string(),
+            None => String::from("?")
+        };
+
+        let text = match relocation {
+            Some(relocation) => {
+                let section = format!("{:?}", relocation.section).to_lowercase();
+                let kind = format!("{:?}", relocation.kind);
+                let offset = format!("0x{:04X}", relocation.offset);
+                message(MessageId::TraceRelocated, &[&line_num, &label, &defined_on, &kind, &section, &offset])
+            },
+
+            None => message(MessageId::TraceResolved, &[&line_num, &label, &defined_on, &format!("0x{:04X}", value)])
+        };
+
+        eprintln!("trace: {}", text);
+    }
+}
@@ -1,5 +1,10 @@
-use crate::repr::instruction::{Instruction, Operand};
-use crate::repr::{opcode::Opcode, register::Register};
+use crate::error::AssemblerError;
+use crate::messages::{message, MessageId};
+use crate::object::MAX_NAME_LENGTH;
+use crate::repr::instruction::{split_operands, Instruction, InstructionKind, Operand};
+use crate::repr::opcode::{is_mnemonic, Opcode, OperandFormat};
+use crate::repr::register::{Register, REGISTER_NAMES};
+use crate::source::{find_label_end, SourceLine};
 use std::{fmt, error::Error};
 
 
@@ -12,7 +17,15 @@ enum ValidationError {
     OperandNotShortImmediateError(Operand),
     OperandNotLongImmediateError(Operand),
     ImmediateTooLargeError(u16),
-    LabelInvalidFormat(String)
+    ImmediateOverlapsRegisterError(u8),
+    ImmediateOutOfRangeError(i64, u32),
+    LabelInvalidFormat(String),
+    ReservedLabelError(String),
+    LabelTooLongError(String),
+    OperandSeparatorError(String),
+    UncodedRegisterError(Register),
+    MulDivSourceError(Opcode, Register),
+    SelfDivisionError(Opcode, Register)
 }
 
 impl Error for ValidationError {}
@@ -20,14 +33,24 @@ impl Error for ValidationError {}
 impl fmt::Display for ValidationError {
     fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
         match self {
-            ValidationError::RegisterNotNoneError(reg) => write!(f, "Register {:?} should be None", reg),
-            ValidationError::MixedRegisterTypesError(reg_a, reg_b) => write!(f, "Register {:?} and {:?} are either of different sizes or mixed high/low", reg_a, reg_b),
-            ValidationError::RegisterIsNoneError(reg) => write!(f, "Register {:?} must not be None", reg),
-            ValidationError::OperandNotRegisterError(operand) => write!(f, "Operand {:?} should be a register", operand),
-            ValidationError::OperandNotShortImmediateError(operand) => write!(f, "Operand {:?} should be a short immediate", operand),
-            ValidationError::OperandNotLongImmediateError(operand) => write!(f, "Operand {:?} should be a long immediate", operand),
-            ValidationError::ImmediateTooLargeError(imm) => write!(f, "Immediate {} is too large", imm),
-            ValidationError::LabelInvalidFormat(label) => write!(f, "Label '{:?}' is in an invalid format", label)
+            ValidationError::RegisterNotNoneError(reg) => write!(f, "{}", message(MessageId::RegisterNotNone, &[&format!("{:?}", reg)])),
+            ValidationError::MixedRegisterTypesError(reg_a, reg_b) => write!(f, "{}", message(MessageId::MixedRegisterTypes, &[&format!("{:?}", reg_a), &format!("{:?}", reg_b)])),
+            ValidationError::RegisterIsNoneError(reg) => write!(f, "{}", message(MessageId::RegisterIsNone, &[&format!("{:?}", reg)])),
+            ValidationError::OperandNotRegisterError(operand) => write!(f, "{}", message(MessageId::OperandNotRegister, &[&format!("{:?}", operand)])),
+            ValidationError::OperandNotShortImmediateError(operand) => write!(f, "{}", message(MessageId::OperandNotShortImmediate, &[&format!("{:?}", operand)])),
+            ValidationError::OperandNotLongImmediateError(operand) => write!(f, "{}", message(MessageId::OperandNotLongImmediate, &[&format!("{:?}", operand)])),
+            ValidationError::ImmediateTooLargeError(imm) => write!(f, "{}", message(MessageId::ImmediateTooLarge, &[imm])),
+            ValidationError::ImmediateOverlapsRegisterError(imm) => write!(f, "{}", message(MessageId::ImmediateOverlapsRegister, &[imm])),
+            ValidationError::ImmediateOutOfRangeError(imm, bits) => {
+                write!(f, "{}", message(MessageId::ImmediateOutOfRange, &[imm, bits, &-(1i64 << (bits - 1)), &((1i64 << bits) - 1)]))
+            },
+            ValidationError::LabelInvalidFormat(label) => write!(f, "{}", message(MessageId::LabelInvalidFormat, &[label])),
+            ValidationError::ReservedLabelError(label) => write!(f, "{}", message(MessageId::ReservedLabel, &[label])),
+            ValidationError::LabelTooLongError(label) => write!(f, "{}", message(MessageId::LabelTooLong, &[label, &MAX_NAME_LENGTH])),
+            ValidationError::OperandSeparatorError(line) => write!(f, "{}", message(MessageId::OperandSeparators, &[line])),
+            ValidationError::UncodedRegisterError(reg) => write!(f, "{}", message(MessageId::UncodedRegister, &[&Into::<String>::into(reg.clone())])),
+            ValidationError::MulDivSourceError(opcode, reg) => write!(f, "{}", message(MessageId::MulDivSource, &[opcode, &Into::<String>::into(reg.clone())])),
+            ValidationError::SelfDivisionError(opcode, reg) => write!(f, "{}", message(MessageId::SelfDivision, &[opcode, &Into::<String>::into(reg.clone())]))
         }
     }
 }
@@ -43,7 +66,7 @@ pub fn validate_label(label:&str) -> Result<(), Box<dyn Error>> {
         return Ok(());
     }
 
-    if !(label.chars().nth(0).unwrap().is_ascii_alphabetic() || label.chars().nth(0).unwrap() == '_') {
+    if !(label.chars().next().unwrap().is_ascii_alphabetic() || label.starts_with('_')) {
         return Err(Box::new(ValidationError::LabelInvalidFormat(label.to_string())));
     }
 
@@ -51,233 +74,263 @@ pub fn validate_label(label:&str) -> Result<(), Box<dyn Error>> {
         return Err(Box::new(ValidationError::LabelInvalidFormat(label.to_string())));
     }
 
+    // operands are classified by name, so a label named after a register or mnemonic would be misread
+    let lowercase = label.to_lowercase();
+    if REGISTER_NAMES.contains(&lowercase.as_str()) || is_mnemonic(&lowercase) {
+        return Err(Box::new(ValidationError::ReservedLabelError(label.to_string())));
+    }
+
+    // symbol tables store names behind an 8-bit length
+    if label.len() > MAX_NAME_LENGTH {
+        return Err(Box::new(ValidationError::LabelTooLongError(label.to_string())));
+    }
+
     Ok(())
 }
 
 
 /**
- * Takes a pair of `Operand`s which should represent `Operand::Register`s and returns `Ok(())` if they
- * match or a `ValidationError` if they are either of mixed sizes (16 and 8 bits) or if a high register
- * is paired with a low register.
+ * Checks that the operands of every instruction in the source are separated by a single comma, for the
+ * `--strict` option. Otherwise stray commas are ignored like spaces, so degenerate input such as
+ * `into, sp,,, 0` is accepted.
  */
-fn validate_register_operand_pair(operand_a:&Operand, operand_b:&Operand) -> Result<(), Box<dyn Error>> {
-    let reg_a = match operand_a {
-        Operand::Register(reg_a) => reg_a,
-        _ => return Err(Box::new(ValidationError::OperandNotRegisterError(operand_a.clone())))
-    };
-
-    let reg_b = match operand_b {
-        Operand::Register(reg_b) => reg_b,
-        _ => return Err(Box::new(ValidationError::OperandNotRegisterError(operand_b.clone())))
-    };
-
-    match reg_a {
-        Register::Ax | Register::Bx | Register::Cx | Register::Dx | Register::Sp | Register::Fp
-         | Register::Bp | Register::Rp => {
-            match reg_b {
-                Register::Ax | Register::Bx | Register::Cx | Register::Dx | Register::Sp | Register::Fp
-                 | Register::Bp | Register::Rp => return Ok(()),
-                _ => return Err(Box::new(ValidationError::MixedRegisterTypesError(reg_a.clone(), reg_b.clone()))),
-            }
-         }
-        
-        Register::Ah | Register::Bh | Register::Ch | Register::Dh => {
-            match reg_b {
-                Register::Ah | Register::Bh | Register::Ch | Register::Dh => return Ok(()),
-                _ => return Err(Box::new(ValidationError::MixedRegisterTypesError(reg_a.clone(), reg_b.clone()))),
-            }
+pub fn check_operand_separators(source_lines:&[SourceLine]) -> Result<(), AssemblerError> {
+    for source_line in source_lines {
+        let text = &source_line.text;
+        let contents = find_label_end(text).map(|end| &text[end + 1..]).unwrap_or(text).trim();
+        let mnemonic_end = contents.find(|c:char| c.is_whitespace() || c == ',').unwrap_or(contents.len());
+        if !is_mnemonic(&contents[..mnemonic_end]) {
+            continue;
         }
 
-        Register::Al | Register::Bl | Register::Cl | Register::Dl => {
-            match reg_b {
-                Register::Al | Register::Bl | Register::Cl | Register::Dl => return Ok(()),
-                _ => return Err(Box::new(ValidationError::MixedRegisterTypesError(reg_a.clone(), reg_b.clone()))),
-            }
+        let parts = split_operands(&contents[mnemonic_end..]);
+        if parts.len() > 2 || (parts.len() == 2 && parts.iter().any(|part| part.trim().is_empty())) {
+            return Err(source_line.error(Box::new(ValidationError::OperandSeparatorError(contents.to_string()))));
         }
+    }
+
+    Ok(())
+}
 
-        Register::None | Register::Pc | Register::St => return Err(Box::new(ValidationError::RegisterIsNoneError(reg_a.clone())))
+
+/**
+ * Takes the value of an immediate and the width in bits of the field it is stored in, and returns the value as
+ * stored in the field. A negative value is stored in two's complement, so a field of `n` bits holds values from
+ * -2^(n-1) up to 2^n - 1, and any other value gives an error.
+ */
+pub fn encode_immediate(value:i64, bits:u32) -> Result<u64, Box<dyn Error>> {
+    let max = (1i64 << bits) - 1;
+    match (-(1i64 << (bits - 1))..=max).contains(&value) {
+        true => Ok((value & max) as u64),
+        false => Err(Box::new(ValidationError::ImmediateOutOfRangeError(value, bits)))
     }
 }
 
 
 /**
- * Takes an instruction and validates the register code and the operand types and values
+ * Returns the operands of an instruction as the kind its operand format lays them out in, or a
+ * `ValidationError` naming the first operand which is not of the type the format has in its place.
  */
-pub fn validate_instruction(instr:&Instruction) -> Result<(), Box<dyn Error>> {
-    match instr.opcode {
-        // No operands
-        Opcode::Nop | Opcode::PopA | Opcode::PushA | Opcode::PopF | Opcode::PushF | Opcode::Ret | Opcode::Ccry | Opcode::Scry 
-         | Opcode::Eitr | Opcode::Ditr | Opcode::Iret | Opcode::Halt => {
-            // validate operand a
-            match &instr.operand_a {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                Operand::Register(reg) => {
-                    match reg {
-                        Register::None => {},
-                        _ => return Err(Box::new(ValidationError::RegisterNotNoneError(reg.clone())))
-                    }
-                }
-            }
+fn get_instruction_kind(instr:&Instruction) -> Result<InstructionKind, ValidationError> {
+    let register = |operand:&Operand| match operand {
+        Operand::Register(reg) => Ok(reg.clone()),
+        _ => Err(ValidationError::OperandNotRegisterError(operand.clone()))
+    };
+    let unused = |operand:&Operand| match operand {
+        Operand::Register(Register::None) => Ok(()),
+        Operand::Register(reg) => Err(ValidationError::RegisterNotNoneError(reg.clone())),
+        _ => Err(ValidationError::OperandNotRegisterError(operand.clone()))
+    };
 
-            // validate operand b
-            match &instr.operand_b {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                Operand::Register(reg) => {
-                    match reg {
-                        Register::None => {},
-                        _ => return Err(Box::new(ValidationError::RegisterNotNoneError(reg.clone())))
-                    }
-                }
-            }
+    match instr.opcode.format() {
+        OperandFormat::NoOperands => {
+            unused(&instr.operand_a)?;
+            unused(&instr.operand_b)?;
+            Ok(InstructionKind::NoOperands)
+        },
+
+        OperandFormat::OneRegister => {
+            let rd = register(&instr.operand_a)?;
+            unused(&instr.operand_b)?;
+            Ok(InstructionKind::OneRegister { rd: rd })
+        },
+
+        OperandFormat::TwoRegisters => Ok(InstructionKind::TwoRegisters { rd: register(&instr.operand_a)?, rt: register(&instr.operand_b)? }),
+
+        OperandFormat::RegisterShortImmediate => match instr.operand_b {
+            Operand::ShortImmediate(imm) => Ok(InstructionKind::RegisterShortImmediate { rd: register(&instr.operand_a)?, imm: imm }),
+            _ => Err(ValidationError::OperandNotShortImmediateError(instr.operand_b.clone()))
+        },
+
+        OperandFormat::RegisterLargeImmediate => match instr.operand_b {
+            Operand::LargeImmediate(imm) => Ok(InstructionKind::RegisterLargeImmediate { rd: register(&instr.operand_a)?, imm: imm }),
+            _ => Err(ValidationError::OperandNotLongImmediateError(instr.operand_b.clone()))
         }
+    }
+}
 
-        // two register operands
-        Opcode::Add | Opcode::Sub | Opcode::Cmp | Opcode::Move | Opcode::Swap | Opcode::Mul | Opcode::Mulu 
-         | Opcode::Div | Opcode::Divu | Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Sra | Opcode::Srl 
-         | Opcode::Sll | Opcode::Lda | Opcode::Load | Opcode::Store | Opcode::Addu | Opcode::Subu
-         | Opcode::Jzro | Opcode::Jnzro => {
-            match instr.operand_a {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                _ => {}
-            }
 
-            match instr.operand_b {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                _ => {}
-            }
+/**
+ * Checks that a register operand has a register code it can be encoded with, which `none`, `st` and `pc` do not.
+ */
+fn validate_register_code(reg:&Register) -> Result<(), ValidationError> {
+    match reg {
+        Register::None => Err(ValidationError::RegisterIsNoneError(reg.clone())),
+        Register::St | Register::Pc => Err(ValidationError::UncodedRegisterError(reg.clone())),
+        _ => Ok(())
+    }
+}
 
-            validate_register_operand_pair(&instr.operand_a, &instr.operand_b)?;
-        }
 
-        // one register operand
-        Opcode::Addc | Opcode::Inc | Opcode::Subb | Opcode::Dec | Opcode::Neg | Opcode::Push | Opcode::Pop | Opcode::Csign 
-         | Opcode::Not | Opcode::Clear | Opcode::Call | Opcode::Jump | Opcode::Jeq | Opcode::Jne | Opcode::Jgt | Opcode::Jle 
-         | Opcode::Jgte | Opcode::Jlte | Opcode::Jovf | Opcode::Jcry => {
-            match &instr.operand_a {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                Operand::Register(reg) => {
-                    match reg {
-                        Register::None => return Err(Box::new(ValidationError::RegisterIsNoneError(reg.clone()))),
-                        _ => {}
-                    }
-                }
-            }
+/**
+ * Takes the two register operands of an instruction and returns `Ok(())` if they can be encoded and match, or a
+ * `ValidationError` if they are either of mixed sizes (16 and 8 bits) or if a high register is paired with a
+ * low register, as both share the high and low bits of the instruction.
+ */
+fn validate_register_pair(rd:&Register, rt:&Register) -> Result<(), ValidationError> {
+    validate_register_code(rd)?;
+    validate_register_code(rt)?;
 
-            match &instr.operand_b {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                Operand::Register(reg) => {
-                    match reg {
-                        Register::None => {},
-                        _ => return Err(Box::new(ValidationError::RegisterNotNoneError(reg.clone())))
-                    }
-                }
-            }
-        }
+    match (rd.is_high_reg(), rd.is_low_reg()) == (rt.is_high_reg(), rt.is_low_reg()) {
+        true => Ok(()),
+        false => Err(ValidationError::MixedRegisterTypesError(rd.clone(), rt.clone()))
+    }
+}
 
-        // one register and one 5-bit immediate
-        Opcode::In | Opcode::Out | Opcode::Intr | Opcode::Into => {
-            match instr.operand_a {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                _ => {}
-            }
 
-            match instr.operand_b {
-                Operand::Register(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotShortImmediateError(instr.operand_b.clone()))),
-                Operand::ShortImmediate(imm) => {
-                    if imm > 0x001F {
-                        return Err(Box::new(ValidationError::ImmediateTooLargeError(imm as u16)))
-                    }
-                }
-            }
-        }
+/**
+ * Checks the registers of a `mul`, `mulu`, `div` or `divu`. The 16-bit form operates on the high and low bytes
+ * of its source, as in `mul ax, bx` setting ax to bh * bl, so the source must be a register with byte halves.
+ * The byte form divides its destination by its source, so dividing a byte register by itself is rejected, as it
+ * is always 1 or undefined.
+ */
+fn validate_mul_div_registers(opcode:&Opcode, rd:&Register, rt:&Register) -> Result<(), ValidationError> {
+    if !matches!(opcode, Opcode::Mul | Opcode::Mulu | Opcode::Div | Opcode::Divu) {
+        return Ok(());
+    }
+
+    match rt {
+        Register::Rp | Register::Fp | Register::Bp | Register::Sp => Err(ValidationError::MulDivSourceError(opcode.clone(), rt.clone())),
+        _ if rd == rt && rt.is_high_reg() != rt.is_low_reg() && matches!(opcode, Opcode::Div | Opcode::Divu) => {
+            Err(ValidationError::SelfDivisionError(opcode.clone(), rt.clone()))
+        },
+        _ => Ok(())
+    }
+}
 
-        // one register and one 16 bit immediate
-        Opcode::MovI => {
-            match instr.operand_a {
-                Operand::ShortImmediate(_) | Operand::LargeImmediate(_) => return Err(Box::new(ValidationError::OperandNotRegisterError(instr.operand_a.clone()))),
-                _ => {}
+
+/**
+ * Takes an instruction and returns its operands as the kind its operand format gives them, after validating the
+ * register codes and the values of the operands. The instruction is encoded from the kind returned.
+ */
+pub fn validate_instruction(instr:&Instruction) -> Result<InstructionKind, Box<dyn Error>> {
+    let kind = get_instruction_kind(instr)?;
+
+    match &kind {
+        InstructionKind::NoOperands => {},
+        InstructionKind::OneRegister { rd } => validate_register_code(rd)?,
+        InstructionKind::TwoRegisters { rd, rt } => {
+            validate_register_pair(rd, rt)?;
+            validate_mul_div_registers(&instr.opcode, rd, rt)?;
+        },
+
+        // the register code takes bits 3 to 5 of the operand, so an immediate which sets bit 3 or 4 would be
+        // encoded the same as a different register with a smaller immediate
+        InstructionKind::RegisterShortImmediate { rd, imm } => {
+            validate_register_code(rd)?;
+            if *imm > 0x001F {
+                return Err(Box::new(ValidationError::ImmediateTooLargeError(*imm as u16)));
             }
 
-            // large immediate cannot be out of range due to u16 type limits
-            match instr.operand_b {
-                Operand::Register(_) | Operand::ShortImmediate(_) => return Err(Box::new(ValidationError::OperandNotLongImmediateError(instr.operand_b.clone()))),
-                _ => {}
+            if *imm & 0x0018 != 0 {
+                return Err(Box::new(ValidationError::ImmediateOverlapsRegisterError(*imm)));
+            }
+        },
+
+        // a 16 bit immediate cannot be out of range due to u16 type limits, but an 8 bit register only holds a
+        // byte, which may be given as a negative value stored in two's complement
+        InstructionKind::RegisterLargeImmediate { rd, imm } => {
+            validate_register_code(rd)?;
+            if rd.is_high_reg() != rd.is_low_reg() {
+                encode_immediate(*imm as i16 as i64, 8)?;
             }
         }
     }
 
-    Ok(())
+    Ok(kind)
 }
 
 
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
     use crate::assembler::process_line;
-    use super::validate_label;
+    use crate::source::to_source_lines;
+    use super::{check_operand_separators, encode_immediate, validate_label};
 
 
     #[test]
     fn test_valid_nn_instrs() {
-        process_line("  NOP", &HashMap::new(), &mut false);
-        process_line("my_label: POPA", &HashMap::new(), &mut false);
-        process_line("pusha", &HashMap::new(), &mut false);
-        process_line("ret", &HashMap::new(), &mut false);
-        process_line("scry", &HashMap::new(), &mut false);
-        process_line("CcRy", &HashMap::new(), &mut false);
-        process_line("__hello:      Eitr    ", &HashMap::new(), &mut false);
-        process_line("Ditr", &HashMap::new(), &mut false);
-        process_line("Iret", &HashMap::new(), &mut false);
+        process_line("  NOP", &HashMap::new(), &mut false).unwrap();
+        process_line("my_label: POPA", &HashMap::new(), &mut false).unwrap();
+        process_line("pusha", &HashMap::new(), &mut false).unwrap();
+        process_line("ret", &HashMap::new(), &mut false).unwrap();
+        process_line("scry", &HashMap::new(), &mut false).unwrap();
+        process_line("CcRy", &HashMap::new(), &mut false).unwrap();
+        process_line("__hello:      Eitr    ", &HashMap::new(), &mut false).unwrap();
+        process_line("Ditr", &HashMap::new(), &mut false).unwrap();
+        process_line("Iret", &HashMap::new(), &mut false).unwrap();
     }
 
 
     #[test]
     fn test_valid_rn_instrs() {
-        process_line("ADDC  ax", &HashMap::new(), &mut false);
-        process_line("inc bl", &HashMap::new(), &mut false);
-        process_line("Subb bh", &HashMap::new(), &mut false);
-        process_line("Dec    dx", &HashMap::new(), &mut false);
-        process_line("label:  Neg DX", &HashMap::new(), &mut false);
-        process_line("_l_a_b_e_l: Push  aH", &HashMap::new(), &mut false);
-        process_line("Pop Ah", &HashMap::new(), &mut false);
-        process_line("Csign        ah", &HashMap::new(), &mut false);
-        process_line("CLEAR rp", &HashMap::new(), &mut false);
+        process_line("ADDC  ax", &HashMap::new(), &mut false).unwrap();
+        process_line("inc bl", &HashMap::new(), &mut false).unwrap();
+        process_line("Subb bh", &HashMap::new(), &mut false).unwrap();
+        process_line("Dec    dx", &HashMap::new(), &mut false).unwrap();
+        process_line("label:  Neg DX", &HashMap::new(), &mut false).unwrap();
+        process_line("_l_a_b_e_l: Push  aH", &HashMap::new(), &mut false).unwrap();
+        process_line("Pop Ah", &HashMap::new(), &mut false).unwrap();
+        process_line("Csign        ah", &HashMap::new(), &mut false).unwrap();
+        process_line("CLEAR rp", &HashMap::new(), &mut false).unwrap();
    }
 
 
     #[test]
     fn test_valid_ri_instrs() {
-        process_line("  in rp, 10", &HashMap::new(), &mut false);
-        process_line("out ax 10", &HashMap::new(), &mut false);
-        process_line("InTr rp, 0", &HashMap::new(), &mut false);
-        process_line("lbl: Into, sp,,, 0", &HashMap::new(), &mut false);
+        process_line("  in rp, 6", &HashMap::new(), &mut false).unwrap();
+        process_line("out ax 7", &HashMap::new(), &mut false).unwrap();
+        process_line("InTr rp, 0", &HashMap::new(), &mut false).unwrap();
+        process_line("lbl: Into, sp,,, 0", &HashMap::new(), &mut false).unwrap();
     }
 
     #[test]
     fn test_valid_rl_instrs() {
-        process_line("mOvi ax   700", &HashMap::new(), &mut false);
-        process_line("mOvi ax   0", &HashMap::new(), &mut false);
+        process_line("mOvi ax   700", &HashMap::new(), &mut false).unwrap();
+        process_line("mOvi ax   0", &HashMap::new(), &mut false).unwrap();
     }
 
 
     #[test]
     fn test_valid_rr_instrs() {
-        process_line("ADD ax bx", &HashMap::new(), &mut false);
-        process_line("sub ax bx", &HashMap::new(), &mut false);
-        process_line("ADDu ax bx", &HashMap::new(), &mut false);
-        process_line("subu ax bx", &HashMap::new(), &mut false);
-        process_line("move ah bh", &HashMap::new(), &mut false);
-        process_line("And al bl", &HashMap::new(), &mut false);
-        process_line("SRa al bl", &HashMap::new(), &mut false);
-        process_line("Load ax bx", &HashMap::new(), &mut false);
-        process_line("Store ax bx", &HashMap::new(), &mut false);
-        process_line("Mul ax bx", &HashMap::new(), &mut false);
-        process_line("mulu ax bx", &HashMap::new(), &mut false);
-        process_line("div ax, bx", &HashMap::new(), &mut false);
-        process_line("divu ax, bx", &HashMap::new(), &mut false);
-        process_line("jzro ax, bx", &HashMap::new(), &mut false);
-        process_line("jnzro ax, bx", &HashMap::new(), &mut false);
+        process_line("ADD ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("sub ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("ADDu ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("subu ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("move ah bh", &HashMap::new(), &mut false).unwrap();
+        process_line("And al bl", &HashMap::new(), &mut false).unwrap();
+        process_line("SRa al bl", &HashMap::new(), &mut false).unwrap();
+        process_line("Load ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("Store ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("Mul ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("mulu ax bx", &HashMap::new(), &mut false).unwrap();
+        process_line("div ax, bx", &HashMap::new(), &mut false).unwrap();
+        process_line("divu ax, bx", &HashMap::new(), &mut false).unwrap();
+        process_line("jzro ax, bx", &HashMap::new(), &mut false).unwrap();
+        process_line("jnzro ax, bx", &HashMap::new(), &mut false).unwrap();
     }
 
     #[test]
@@ -337,6 +390,32 @@ mod tests {
         process_line("addc 1000", &HashMap::new(), &mut false).unwrap();
     }
 
+    #[test]
+    fn test_operand_format_errors() {
+        let error = |line:&str| process_line(line, &HashMap::new(), &mut false).unwrap_err().to_string();
+        assert_eq!(error("add ax, 10"), "Operand ShortImmediate(10) should be a register");
+        assert_eq!(error("inc"), "Register None must not be None");
+        assert_eq!(error("out al, bl"), "Operand Register(Bl) should be a short immediate");
+        assert_eq!(error("in al, 29"), "Immediate 29 overlaps the register code, which takes bits 3 and 4 of the operand, so it must be from 0 to 7");
+        assert_eq!(error("push pc"), "Register pc has no register code, so it cannot be an operand");
+        assert_eq!(error("move st, ax"), "Register st has no register code, so it cannot be an operand");
+        assert_eq!(error("movi pc, 5"), "Register pc has no register code, so it cannot be an operand");
+    }
+
+
+    #[test]
+    fn test_mul_div_registers() {
+        process_line("mul ax, ax", &HashMap::new(), &mut false).unwrap();
+        process_line("divu bx, dx", &HashMap::new(), &mut false).unwrap();
+        process_line("mul al, al", &HashMap::new(), &mut false).unwrap();
+        process_line("div ah, bh", &HashMap::new(), &mut false).unwrap();
+
+        let error = |line:&str| process_line(line, &HashMap::new(), &mut false).unwrap_err().to_string();
+        assert_eq!(error("mul ax, bp"), "'mul' operates on the high and low bytes of its source, but bp has none, so it must be ax, bx, cx or dx");
+        assert_eq!(error("divu cx, sp"), "'divu' operates on the high and low bytes of its source, but sp has none, so it must be ax, bx, cx or dx");
+        assert_eq!(error("div cl, cl"), "'div' divides cl by itself, which is always 1 or undefined");
+    }
+
     #[test]
     #[should_panic]
     fn test_mixed_high_low_reg() {
@@ -362,6 +441,23 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_signed_immediates() {
+        assert_eq!(encode_immediate(-1, 8).unwrap(), 0xFF);
+        assert_eq!(encode_immediate(-128, 8).unwrap(), 0x80);
+        assert_eq!(encode_immediate(255, 8).unwrap(), 0xFF);
+        assert_eq!(encode_immediate(-0x8000, 16).unwrap(), 0x8000);
+        assert_eq!(encode_immediate(256, 8).unwrap_err().to_string(), "Immediate 256 does not fit in 8 bits, which hold -128 to 255");
+        assert!(encode_immediate(-129, 8).is_err());
+
+        process_line("movi cx, -1", &HashMap::new(), &mut false).unwrap();
+        process_line("movi al, -128", &HashMap::new(), &mut false).unwrap();
+        process_line("movi bh, 0xFF", &HashMap::new(), &mut false).unwrap();
+        assert!(process_line("movi al, 300", &HashMap::new(), &mut false).is_err());
+        assert!(process_line("movi ah, -129", &HashMap::new(), &mut false).is_err());
+    }
+
+
     #[test]
     fn test_valid_labels() {
         validate_label("label").unwrap();
@@ -394,4 +490,31 @@ mod tests {
     fn label_contains_non_ascii() {
         validate_label("aБcd").unwrap();
     }
+
+    #[test]
+    fn label_is_reserved() {
+        assert_eq!(validate_label("ax").unwrap_err().to_string(), "Label 'ax' is reserved as a register name or mnemonic");
+        assert!(validate_label("ADD").is_err());
+        assert!(validate_label("movi").is_err());
+        assert!(validate_label("JMP").is_err());
+        validate_label("axis").unwrap();
+        validate_label("add_one").unwrap();
+    }
+
+    #[test]
+    fn label_is_too_long() {
+        validate_label(&"a".repeat(255)).unwrap();
+        assert_eq!(validate_label(&"a".repeat(256)).unwrap_err().to_string(), format!("Label '{}' is longer than 255 bytes", "a".repeat(256)));
+    }
+
+
+    #[test]
+    fn test_operand_separators() {
+        assert!(check_operand_separators(&to_source_lines("add ax, bx\nadd ax,bx\nmovi ax 1 << 4\nstart: nop\nmovi al, ','\n.array 1, 2,, 3")).is_ok());
+
+        let err = check_operand_separators(&to_source_lines("nop\ninto, sp,,, 0")).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: Operands must be separated by a single comma in strict mode, found 'into, sp,,, 0'");
+        assert!(check_operand_separators(&to_source_lines("add ax,, bx")).is_err());
+        assert!(check_operand_separators(&to_source_lines("loop: add ax, bx,")).is_err());
+    }
 }
@@ -0,0 +1,40 @@
+pub const ASSEMBLER_NAME:&str = env!("CARGO_PKG_NAME");
+pub const ASSEMBLER_VERSION:&str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH:&str = env!("S16_GIT_HASH");
+
+/**
+ * Revision of the Sim6 instruction set the encoder implements.
+ */
+pub const ISA_REVISION:u32 = 1;
+
+/**
+ * The architecture levels accepted by `--march`, the first being the default.
+ */
+pub const SUPPORTED_MARCH:&[&str] = &["sim6"];
+
+/**
+ * The output formats the assembler can produce.
+ */
+pub const SUPPORTED_FORMATS:&[&str] = &["sse", "sso", "memh", "srec", "bin", "ihex", "readmemh", "logisim", "coe", "mif"];
+
+
+/**
+ * Returns the text printed by `--version`.
+ */
+pub fn version_text() -> String {
+    format!("{} {} (git {})\nISA revision: {}\nSupported --march levels: {}\nSupported output formats: {}",
+            ASSEMBLER_NAME, ASSEMBLER_VERSION, GIT_HASH, ISA_REVISION, SUPPORTED_MARCH.join(", "), SUPPORTED_FORMATS.join(", "))
+}
+
+
+/**
+ * Returns the toolchain information recorded in the metadata section of every output file.
+ */
+pub fn toolchain_metadata(march:&str) -> Vec<(String, String)> {
+    vec![
+        (String::from("assembler"), format!("{} {}", ASSEMBLER_NAME, ASSEMBLER_VERSION)),
+        (String::from("git_hash"), String::from(GIT_HASH)),
+        (String::from("isa_revision"), ISA_REVISION.to_string()),
+        (String::from("march"), String::from(march))
+    ]
+}
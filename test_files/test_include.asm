@@ -0,0 +1,5 @@
+.include "data.inc"
+
+.code:
+    .include "regs.inc"
+    start: add ax bx
@@ -0,0 +1 @@
+.include "include/cycle_a.inc"